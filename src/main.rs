@@ -1,4 +1,7 @@
 mod zune_core;
+// the firmware doesn't call into the image processing API yet
+#[allow(dead_code, unused_imports)]
+mod zune_imageprocs;
 mod zune_jpeg;
 
 use embedded_svc::http::server::{Connection, Request};
@@ -34,7 +37,7 @@ use esp_idf_sys::{
 };
 use log::*;
 use std::time::Duration;
-use zune_jpeg::JpegDecoder;
+use zune_jpeg::{HashKind, JpegDecoder};
 
 const CAMERA_PWDN_GPIO_NUM: i32 = -1;
 const CAMERA_RESET_GPIO_NUM: i32 = -1;
@@ -209,7 +212,11 @@ fn test_camera_framerate() -> Result<(), EspError> {
         let mut reported_frames = 0;
         let mut fb_len_sum = 0;
         let mut fb_len_max = 0;
+        let mut previous_hash: Option<zune_jpeg::PerceptualHash> = None;
+        let mut duplicates = 0;
         const REPORT_DT_USEC: u64 = 5000000;
+        // frames whose hashes are at most this many bits apart show the same scene
+        const DUPLICATE_DISTANCE: u32 = 4;
 
         for _ in 0..1000 {
             let fb = esp_camera_fb_get();
@@ -222,9 +229,32 @@ fn test_camera_framerate() -> Result<(), EspError> {
                     //     error!("error decoding frame: {}", err);
                     // }
 
-                    let mut decoder = Box::new(JpegDecoder::new(bytes));
-                    if let Err(err) = decoder.decode() {
-                        error!("error decoding frame: {}", err);
+                    // cheap, only needs entropy decoding, compared with the last
+                    // decoded frame so slow changes still add up
+                    let duplicate =
+                        match JpegDecoder::new(bytes).perceptual_hash(HashKind::Difference) {
+                            Ok(hash) => {
+                                let duplicate = previous_hash.is_some_and(|previous| {
+                                    previous.distance(hash) <= DUPLICATE_DISTANCE
+                                });
+                                if !duplicate {
+                                    previous_hash = Some(hash);
+                                }
+                                duplicate
+                            }
+                            Err(err) => {
+                                error!("error hashing frame: {}", err);
+                                false
+                            }
+                        };
+
+                    if duplicate {
+                        duplicates += 1;
+                    } else {
+                        let mut decoder = Box::new(JpegDecoder::new(bytes));
+                        if let Err(err) = decoder.decode() {
+                            error!("error decoding frame: {}", err);
+                        }
                     }
 
                     let t = timeval_usec(fb.timestamp);
@@ -249,14 +279,21 @@ fn test_camera_framerate() -> Result<(), EspError> {
                                 (0, 0, 0.0)
                             };
                             info!(
-                                "skipped {} count {} dt {} (fr {}, len avg {} max {})",
-                                skipped, reported_frames, frame_dt_avg, fr, fb_len_avg, fb_len_max
+                                "skipped {} duplicates {} count {} dt {} (fr {}, len avg {} max {})",
+                                skipped,
+                                duplicates,
+                                reported_frames,
+                                frame_dt_avg,
+                                fr,
+                                fb_len_avg,
+                                fb_len_max
                             );
                             reported_frames = 0;
                             fb_len_sum = 0;
                             fb_len_max = 0;
 
                             skipped = 0;
+                            duplicates = 0;
                         }
                     } else {
                         skipped += 1;
@@ -15,9 +15,13 @@
 //! Useful for a lot of image readers and writers, it's put
 //! here to minimize code reuse
 pub use reader::ZByteReader;
+pub use sinks::{ZIoWriter, ZSvcWriter};
+pub use sources::{ZChunkedBuffer, ZRingBuffer, ZSeekReader};
 pub use traits::*;
 pub use writer::ZByteWriter;
 
 mod reader;
+mod sinks;
+mod sources;
 mod traits;
 mod writer;
@@ -181,6 +181,42 @@ impl<T: ZReaderTrait> ZByteReader<T> {
             None => Err(ERROR_MSG),
         }
     }
+    /// Copy the bytes `position` bytes ahead into `buf`, or return an
+    /// error if the stream ends before `buf` is filled.
+    ///
+    /// Unlike [`peek_at`], this works on sources that aren't contiguous
+    /// in memory.
+    ///
+    /// This doesn't increment the position.
+    ///
+    /// [`peek_at`]: Self::peek_at
+    #[inline]
+    pub fn peek_into(&self, position: usize, buf: &mut [u8]) -> Result<(), &'static str> {
+        if self.stream.read_at(self.position + position, buf) {
+            Ok(())
+        } else {
+            Err(ERROR_MSG)
+        }
+    }
+    /// Copy `N` bytes `position` bytes ahead, or return an error if the
+    /// stream ends before them.
+    ///
+    /// This doesn't increment the position.
+    ///
+    /// # Example
+    /// ```rust
+    /// use zune_core::bytestream::ZByteReader;
+    /// let stream = ZByteReader::new([0x0,0x5,0x3,0x2].as_slice());
+    /// assert_eq!(stream.peek_fixed_bytes::<2>(1), Ok([0x5,0x3]));
+    /// ```
+    #[inline]
+    pub fn peek_fixed_bytes<const N: usize>(&self, position: usize) -> Result<[u8; N], &'static str> {
+        let mut byte_store: [u8; N] = [0; N];
+
+        self.peek_into(position, &mut byte_store)?;
+
+        Ok(byte_store)
+    }
     /// Get a fixed amount of bytes or return an error if we cant
     /// satisfy the read
     ///
@@ -205,14 +241,12 @@ impl<T: ZReaderTrait> ZByteReader<T> {
     pub fn get_fixed_bytes_or_err<const N: usize>(&mut self) -> Result<[u8; N], &'static str> {
         let mut byte_store: [u8; N] = [0; N];
 
-        match self.stream.get_slice(self.position..self.position + N) {
-            Some(bytes) => {
-                self.position += N;
-                byte_store.copy_from_slice(bytes);
+        if self.stream.read_at(self.position, &mut byte_store) {
+            self.position += N;
 
-                Ok(byte_store)
-            }
-            None => Err(ERROR_MSG),
+            Ok(byte_store)
+        } else {
+            Err(ERROR_MSG)
         }
     }
 
@@ -238,15 +272,10 @@ impl<T: ZReaderTrait> ZByteReader<T> {
     pub fn get_fixed_bytes_or_zero<const N: usize>(&mut self) -> [u8; N] {
         let mut byte_store: [u8; N] = [0; N];
 
-        match self.stream.get_slice(self.position..self.position + N) {
-            Some(bytes) => {
-                self.position += N;
-                byte_store.copy_from_slice(bytes);
-
-                byte_store
-            }
-            None => byte_store,
+        if self.stream.read_at(self.position, &mut byte_store) {
+            self.position += N;
         }
+        byte_store
     }
     #[inline]
     /// Skip bytes until a condition becomes false or the stream runs out of bytes
@@ -272,6 +301,10 @@ impl<T: ZReaderTrait> ZByteReader<T> {
         }
     }
     /// Return the remaining unread bytes in this byte reader
+    ///
+    /// # Panics
+    /// If the source isn't contiguous in memory, use
+    /// [`peek_at`](Self::peek_at) to handle that
     pub fn remaining_bytes(&self) -> &[u8] {
         &self.stream.get_slice(self.position..self.len()).unwrap()
     }
@@ -282,8 +315,9 @@ impl<T: ZReaderTrait> ZByteReader<T> {
         let end = min(self.len(), self.position + buf_length);
         let diff = end - start;
 
-        buf[0..diff].copy_from_slice(&self.stream.get_slice(start..end).unwrap());
-
+        if !self.stream.read_at(start, &mut buf[0..diff]) {
+            return Err(ERROR_MSG);
+        }
         self.skip(diff);
 
         Ok(diff)
@@ -319,20 +353,16 @@ macro_rules! get_single_type {
 
                 let mut space = [0; SIZE_OF_VAL];
 
-                match self.stream.get_slice(self.position..self.position + SIZE_OF_VAL)
+                if !self.stream.read_at(self.position, &mut space)
+                {
+                    return 0;
+                }
+                self.position += SIZE_OF_VAL;
+
+                match mode
                 {
-                    Some(position) =>
-                    {
-                        space.copy_from_slice(position);
-                        self.position += SIZE_OF_VAL;
-
-                        match mode
-                        {
-                            Mode::LE => $int_type::from_le_bytes(space),
-                            Mode::BE => $int_type::from_be_bytes(space),
-                        }
-                    }
-                    None => 0,
+                    Mode::LE => $int_type::from_le_bytes(space),
+                    Mode::BE => $int_type::from_be_bytes(space),
                 }
             }
 
@@ -343,20 +373,16 @@ macro_rules! get_single_type {
 
                 let mut space = [0; SIZE_OF_VAL];
 
-                match self.stream.get_slice(self.position..self.position + SIZE_OF_VAL)
+                if !self.stream.read_at(self.position, &mut space)
+                {
+                    return Err(ERROR_MSG);
+                }
+                self.position += SIZE_OF_VAL;
+
+                match mode
                 {
-                    Some(position) =>
-                    {
-                        space.copy_from_slice(position);
-                        self.position += SIZE_OF_VAL;
-
-                        match mode
-                        {
-                            Mode::LE => Ok($int_type::from_le_bytes(space)),
-                            Mode::BE => Ok($int_type::from_be_bytes(space)),
-                        }
-                    }
-                    None => Err(ERROR_MSG),
+                    Mode::LE => Ok($int_type::from_le_bytes(space)),
+                    Mode::BE => Ok($int_type::from_be_bytes(space)),
                 }
             }
             #[doc=concat!("Read ",stringify!($int_type)," as a big endian integer")]
@@ -0,0 +1,195 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+//! Writers for sinks that aren't a buffer
+//!
+//! - [`ZIoWriter`]: anything implementing [`std::io::Write`], e.g a file
+//! - [`ZSvcWriter`]: anything implementing `embedded_svc::io::Write`, e.g an
+//!   HTTP response
+//!
+//! Neither buffers, wrap small writes in a buffered writer if the sink is
+//! slow to call. Neither can seek either, writes always go to the end.
+
+use crate::zune_core::bytestream::traits::{ZByteIoError, ZWriterTrait};
+
+/// Writes to a [`std::io::Write`] sink
+///
+/// # Example
+/// ```rust
+/// use zune_core::bytestream::{ZByteWriter, ZIoWriter};
+///
+/// let mut file = std::io::Cursor::new(Vec::new());
+/// let mut writer = ZByteWriter::new(ZIoWriter::new(&mut file));
+///
+/// writer.write_u32_be_err(0x1234_5678).unwrap();
+/// assert_eq!(file.into_inner(), [0x12, 0x34, 0x56, 0x78]);
+/// ```
+pub struct ZIoWriter<W: std::io::Write> {
+    inner: W,
+}
+
+impl<W: std::io::Write> ZIoWriter<W> {
+    /// Create a writer over `inner`
+    pub const fn new(inner: W) -> ZIoWriter<W> {
+        ZIoWriter { inner }
+    }
+
+    /// Return the underlying sink
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: std::io::Write> ZWriterTrait for ZIoWriter<W> {
+    fn write_bytes(&mut self, _position: usize, buf: &[u8]) -> Result<usize, ZByteIoError> {
+        Ok(self.inner.write(buf)?)
+    }
+
+    fn write_all_bytes(&mut self, _position: usize, buf: &[u8]) -> Result<(), ZByteIoError> {
+        Ok(self.inner.write_all(buf)?)
+    }
+
+    fn flush_bytes(&mut self) -> Result<(), ZByteIoError> {
+        Ok(self.inner.flush()?)
+    }
+}
+
+/// Writes to an `embedded_svc::io::Write` sink
+///
+/// Errors of the sink are kept formatted in
+/// [`ZByteIoError::SinkError`].
+pub struct ZSvcWriter<W: embedded_svc::io::Write> {
+    inner: W,
+}
+
+impl<W: embedded_svc::io::Write> ZSvcWriter<W> {
+    /// Create a writer over `inner`
+    pub const fn new(inner: W) -> ZSvcWriter<W> {
+        ZSvcWriter { inner }
+    }
+
+    /// Return the underlying sink
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: embedded_svc::io::Write> ZWriterTrait for ZSvcWriter<W> {
+    fn write_bytes(&mut self, _position: usize, buf: &[u8]) -> Result<usize, ZByteIoError> {
+        self.inner
+            .write(buf)
+            .map_err(|e| ZByteIoError::SinkError(format!("{e:?}")))
+    }
+
+    fn write_all_bytes(&mut self, _position: usize, buf: &[u8]) -> Result<(), ZByteIoError> {
+        self.inner
+            .write_all(buf)
+            .map_err(|e| ZByteIoError::SinkError(format!("{e:?}")))
+    }
+
+    fn flush_bytes(&mut self) -> Result<(), ZByteIoError> {
+        self.inner
+            .flush()
+            .map_err(|e| ZByteIoError::SinkError(format!("{e:?}")))
+    }
+}
+
+#[test]
+fn writer_sinks() {
+    use crate::zune_core::bytestream::ZByteWriter;
+
+    fn write<T: ZWriterTrait>(writer: &mut ZByteWriter<T>) -> Result<(), ZByteIoError> {
+        writer.write_u8_err(1)?;
+        writer.write_u16_be_err(0x0203)?;
+        writer.write_u32_le_err(0x0706_0504)?;
+        writer.write_u64_be_err(0x0809_0A0B_0C0D_0E0F)?;
+        writer.write_all(&[16, 17])
+    }
+    let expected: Vec<u8> = (1..18).collect();
+
+    // fixed buffers
+    let mut storage = [0; 17];
+    let mut writer = ZByteWriter::new(&mut storage[..]);
+    write(&mut writer).unwrap();
+    assert_eq!(writer.position(), 17);
+    assert!(writer.eof());
+    assert!(matches!(
+        writer.write_u16_be_err(1),
+        Err(ZByteIoError::NotEnoughBuffer(2, 0))
+    ));
+    assert_eq!(storage[..], expected[..]);
+
+    // too small, the failed write leaves the buffer as it was
+    let mut storage = [0; 10];
+    let mut writer = ZByteWriter::new(&mut storage[..]);
+    assert!(write(&mut writer).is_err());
+    assert_eq!(writer.position(), 7);
+    assert_eq!(writer.bytes_left(), 3);
+    assert_eq!(writer.write(&[20, 21, 22, 23]).unwrap(), 3);
+    assert_eq!(storage, [1, 2, 3, 4, 5, 6, 7, 20, 21, 22]);
+
+    // growable buffers
+    let mut writer = ZByteWriter::new(Vec::new());
+    writer.reserve(17);
+    write(&mut writer).unwrap();
+    assert_eq!(writer.bytes_left(), usize::MAX);
+    assert_eq!(writer.into_inner(), expected);
+
+    let mut vec = vec![0];
+    write(&mut ZByteWriter::new(&mut vec)).unwrap();
+    assert_eq!(vec[1..], expected[..]);
+
+    // io sinks
+    let mut cursor = std::io::Cursor::new(Vec::new());
+    let mut writer = ZByteWriter::new(ZIoWriter::new(&mut cursor));
+    write(&mut writer).unwrap();
+    writer.flush().unwrap();
+    assert_eq!(cursor.into_inner(), expected);
+
+    let mut full = [0; 4];
+    let mut writer = ZByteWriter::new(ZIoWriter::new(&mut full[..]));
+    assert!(matches!(
+        write(&mut writer),
+        Err(ZByteIoError::StdIoError(_))
+    ));
+
+    // buffers can go back to fill in a length, other sinks can't
+    fn back_patch<T: ZWriterTrait>(writer: &mut ZByteWriter<T>) -> Result<(), ZByteIoError> {
+        writer.write_u16_be_err(0)?;
+        writer.write_all(&[1, 2, 3])?;
+        let end = writer.position();
+
+        writer.rewind(end)?;
+        writer.write_u16_be_err(end as u16)?;
+        writer.set_position(end)
+    }
+    let mut storage = [0; 8];
+    let mut writer = ZByteWriter::new(&mut storage[..]);
+    back_patch(&mut writer).unwrap();
+    assert_eq!(writer.peek_at(0, 3).unwrap(), [0; 3]);
+    assert_eq!(writer.len(), 8);
+    writer.skip(2).unwrap();
+    writer.write_u8(9);
+    assert_eq!(storage, [0, 5, 1, 2, 3, 0, 0, 9]);
+
+    let mut vec = vec![7];
+    let mut writer = ZByteWriter::new(&mut vec);
+    back_patch(&mut writer).unwrap();
+    assert_eq!(writer.len(), 5);
+    writer.skip(1).unwrap();
+    writer.write_u8(9);
+    assert_eq!(vec, [7, 0, 5, 1, 2, 3, 0, 9]);
+
+    let mut cursor = std::io::Cursor::new(Vec::new());
+    let mut writer = ZByteWriter::new(ZIoWriter::new(&mut cursor));
+    assert!(matches!(
+        back_patch(&mut writer),
+        Err(ZByteIoError::NotSeekable)
+    ));
+    assert_eq!(writer.len(), 5);
+    assert!(writer.peek_at(0, 1).is_err());
+}
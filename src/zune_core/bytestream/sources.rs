@@ -0,0 +1,445 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+//! Readers for sources that aren't a single buffer
+//!
+//! - [`ZChunkedBuffer`]: a list of chunks, e.g the chunks of an HTTP body
+//! - [`ZRingBuffer`]: a view of data in a ring buffer, which may wrap around
+//!   its end
+//! - [`ZSeekReader`]: anything implementing [`Read`] and [`Seek`], e.g a file,
+//!   read in blocks as they are needed
+//!
+//! These return `None` from [`get_slice`](ZReaderTrait::get_slice) for ranges
+//! spanning two segments, everything else works like on a contiguous buffer.
+
+use core::cell::{Cell, OnceCell, RefCell};
+use core::ops::Range;
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::zune_core::bytestream::traits::ZReaderTrait;
+
+/// A stream made of a list of chunks
+///
+/// # Example
+/// ```rust
+/// use zune_core::bytestream::{ZByteReader, ZChunkedBuffer};
+///
+/// let mut chunks = ZChunkedBuffer::new(vec![vec![0x12], vec![0x34, 0x56]]);
+/// chunks.push(vec![0x78]);
+///
+/// let mut reader = ZByteReader::new(chunks);
+/// assert_eq!(reader.get_u32_be(), 0x1234_5678);
+/// ```
+pub struct ZChunkedBuffer<C: AsRef<[u8]>> {
+    chunks: Vec<C>,
+    /// Offset of every chunk in the stream, then the stream length
+    offsets: Vec<usize>,
+    len: usize,
+    /// Chunk of the last access, reads are mostly sequential
+    last: Cell<usize>,
+}
+
+impl<C: AsRef<[u8]>> ZChunkedBuffer<C> {
+    /// Create a stream reading `chunks` one after the other
+    pub fn new(chunks: Vec<C>) -> ZChunkedBuffer<C> {
+        let mut stream = ZChunkedBuffer {
+            chunks: Vec::with_capacity(chunks.len()),
+            offsets: vec![0],
+            len: 0,
+            last: Cell::new(0),
+        };
+        for chunk in chunks {
+            stream.push(chunk);
+        }
+        stream
+    }
+
+    /// Add a chunk at the end of the stream
+    pub fn push(&mut self, chunk: C) {
+        self.len += chunk.as_ref().len();
+
+        self.chunks.push(chunk);
+        self.offsets.push(self.len);
+    }
+
+    /// Return the chunks
+    pub fn into_inner(self) -> Vec<C> {
+        self.chunks
+    }
+
+    /// Find the chunk holding byte `index`
+    #[inline(always)]
+    fn locate(&self, index: usize) -> Option<usize> {
+        let last = self.last.get();
+
+        if last < self.chunks.len() && self.offsets[last] <= index && index < self.offsets[last + 1]
+        {
+            return Some(last);
+        }
+        if index >= self.get_len() {
+            return None;
+        }
+        // last chunk starting at or before index, skipping empty ones
+        let chunk = self.offsets.partition_point(|x| *x <= index) - 1;
+        self.last.set(chunk);
+
+        Some(chunk)
+    }
+}
+
+impl<C: AsRef<[u8]>> ZReaderTrait for ZChunkedBuffer<C> {
+    #[inline(always)]
+    fn get_byte(&self, index: usize) -> Option<&u8> {
+        let chunk = self.locate(index)?;
+
+        self.chunks[chunk].as_ref().get(index - self.offsets[chunk])
+    }
+
+    fn get_slice(&self, index: Range<usize>) -> Option<&[u8]> {
+        if index.start >= index.end {
+            return (index.start == index.end && index.end <= self.get_len()).then_some(&[][..]);
+        }
+        let chunk = self.locate(index.start)?;
+        let offset = self.offsets[chunk];
+
+        self.chunks[chunk]
+            .as_ref()
+            .get(index.start - offset..index.end - offset)
+    }
+
+    #[inline(always)]
+    fn get_len(&self) -> usize {
+        self.len
+    }
+
+    fn read_at(&self, index: usize, buf: &mut [u8]) -> bool {
+        if index.saturating_add(buf.len()) > self.get_len() {
+            return false;
+        }
+        let mut position = index;
+        let mut written = 0;
+
+        while written < buf.len() {
+            let Some(chunk) = self.locate(position) else {
+                return false;
+            };
+            let data = &self.chunks[chunk].as_ref()[position - self.offsets[chunk]..];
+            let size = data.len().min(buf.len() - written);
+
+            buf[written..written + size].copy_from_slice(&data[..size]);
+            written += size;
+            position += size;
+        }
+        true
+    }
+}
+
+/// A view of data in a ring buffer
+///
+/// The data starts at `start` and may wrap around the end of the buffer.
+///
+/// # Example
+/// ```rust
+/// use zune_core::bytestream::{ZByteReader, ZRingBuffer};
+///
+/// let ring = [0x56, 0x78, 0x00, 0x12, 0x34];
+///
+/// let mut reader = ZByteReader::new(ZRingBuffer::new(&ring, 3, 4));
+/// assert_eq!(reader.get_u32_be(), 0x1234_5678);
+/// ```
+#[derive(Copy, Clone)]
+pub struct ZRingBuffer<'a> {
+    buffer: &'a [u8],
+    start: usize,
+    len: usize,
+}
+
+impl<'a> ZRingBuffer<'a> {
+    /// Create a view of the `len` bytes of `buffer` starting at `start`
+    ///
+    /// `start` wraps around the buffer and `len` is clamped to its length.
+    pub fn new(buffer: &'a [u8], start: usize, len: usize) -> ZRingBuffer<'a> {
+        ZRingBuffer {
+            buffer,
+            start: start.checked_rem(buffer.len()).unwrap_or(0),
+            len: len.min(buffer.len()),
+        }
+    }
+
+    /// Return the data as two slices, the second one empty unless the
+    /// data wraps around
+    pub fn as_slices(&self) -> (&'a [u8], &'a [u8]) {
+        let first = self.first_len();
+
+        (
+            &self.buffer[self.start..self.start + first],
+            &self.buffer[..self.len - first],
+        )
+    }
+
+    /// Number of bytes before the end of the buffer
+    #[inline(always)]
+    fn first_len(&self) -> usize {
+        (self.buffer.len() - self.start).min(self.len)
+    }
+}
+
+impl<'a> ZReaderTrait for ZRingBuffer<'a> {
+    #[inline(always)]
+    fn get_byte(&self, index: usize) -> Option<&u8> {
+        if index >= self.len {
+            return None;
+        }
+        let first = self.buffer.len() - self.start;
+
+        if index < first {
+            self.buffer.get(self.start + index)
+        } else {
+            self.buffer.get(index - first)
+        }
+    }
+
+    fn get_slice(&self, index: Range<usize>) -> Option<&[u8]> {
+        if index.start > index.end || index.end > self.len {
+            return None;
+        }
+        let first = self.buffer.len() - self.start;
+
+        if index.end <= first {
+            self.buffer
+                .get(self.start + index.start..self.start + index.end)
+        } else if index.start >= first {
+            self.buffer.get(index.start - first..index.end - first)
+        } else {
+            None
+        }
+    }
+
+    #[inline(always)]
+    fn get_len(&self) -> usize {
+        self.len
+    }
+
+    fn read_at(&self, index: usize, buf: &mut [u8]) -> bool {
+        if index.saturating_add(buf.len()) > self.len {
+            return false;
+        }
+        let (first, second) = self.as_slices();
+
+        if index >= first.len() {
+            let start = index - first.len();
+
+            buf.copy_from_slice(&second[start..start + buf.len()]);
+        } else {
+            let size = (first.len() - index).min(buf.len());
+            let (head, tail) = buf.split_at_mut(size);
+
+            head.copy_from_slice(&first[index..index + size]);
+            tail.copy_from_slice(&second[..tail.len()]);
+        }
+        true
+    }
+}
+
+/// Reads a seekable source in blocks as they are needed
+///
+/// Blocks stay in memory once read, so this saves reading the source up
+/// front rather than memory. I/O errors end the stream early, making the
+/// decoder report truncated data; the error can be recovered with
+/// [`take_error`](Self::take_error).
+///
+/// # Example
+/// ```rust
+/// use std::io::Cursor;
+/// use zune_core::bytestream::{ZByteReader, ZSeekReader};
+///
+/// let source = ZSeekReader::new(Cursor::new(vec![0x12, 0x34])).unwrap();
+///
+/// let mut reader = ZByteReader::new(source);
+/// assert_eq!(reader.get_u16_be(), 0x1234);
+/// ```
+pub struct ZSeekReader<R: Read + Seek> {
+    reader: RefCell<R>,
+    len: usize,
+    /// Log2 of the block size
+    block_shift: u32,
+    blocks: Vec<OnceCell<Box<[u8]>>>,
+    error: RefCell<Option<std::io::Error>>,
+}
+
+impl<R: Read + Seek> ZSeekReader<R> {
+    /// Default size of the blocks read from the source
+    pub const DEFAULT_BLOCK_SIZE: usize = 16 * 1024;
+
+    /// Create a reader over `reader`, reading blocks of
+    /// [`DEFAULT_BLOCK_SIZE`](Self::DEFAULT_BLOCK_SIZE) bytes
+    ///
+    /// # Errors
+    /// If the length of the source can't be found
+    pub fn new(reader: R) -> std::io::Result<ZSeekReader<R>> {
+        ZSeekReader::with_block_size(reader, Self::DEFAULT_BLOCK_SIZE)
+    }
+
+    /// Create a reader over `reader`, reading blocks of `block_size` bytes,
+    /// rounded up to a power of two
+    ///
+    /// The source is read from its start, whatever its current position.
+    ///
+    /// # Errors
+    /// If the length of the source can't be found
+    pub fn with_block_size(mut reader: R, block_size: usize) -> std::io::Result<ZSeekReader<R>> {
+        let len = usize::try_from(reader.seek(SeekFrom::End(0))?).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "Source too large")
+        })?;
+        let block_shift = block_size.max(1).next_power_of_two().trailing_zeros();
+        let blocks = (len + (1 << block_shift) - 1) >> block_shift;
+
+        Ok(ZSeekReader {
+            reader: RefCell::new(reader),
+            len,
+            block_shift,
+            blocks: (0..blocks).map(|_| OnceCell::new()).collect(),
+            error: RefCell::new(None),
+        })
+    }
+
+    /// Return the first I/O error met while reading, if any
+    pub fn take_error(&self) -> Option<std::io::Error> {
+        self.error.borrow_mut().take()
+    }
+
+    /// Return the underlying source
+    pub fn into_inner(self) -> R {
+        self.reader.into_inner()
+    }
+
+    /// Get block `number`, reading it if needed
+    #[inline(always)]
+    fn block(&self, number: usize) -> Option<&[u8]> {
+        let cell = self.blocks.get(number)?;
+
+        if let Some(block) = cell.get() {
+            return Some(block);
+        }
+        self.read_block(cell, number)
+    }
+
+    #[cold]
+    fn read_block<'b>(&self, cell: &'b OnceCell<Box<[u8]>>, number: usize) -> Option<&'b [u8]> {
+        let start = number << self.block_shift;
+        let mut block = vec![0; (self.len - start).min(1 << self.block_shift)];
+
+        let mut reader = self.reader.borrow_mut();
+        let result = reader
+            .seek(SeekFrom::Start(start as u64))
+            .and_then(|_| reader.read_exact(&mut block));
+
+        if let Err(error) = result {
+            self.error.borrow_mut().get_or_insert(error);
+            return None;
+        }
+        Some(cell.get_or_init(|| block.into_boxed_slice()))
+    }
+}
+
+impl<R: Read + Seek> ZReaderTrait for ZSeekReader<R> {
+    #[inline(always)]
+    fn get_byte(&self, index: usize) -> Option<&u8> {
+        let block = self.block(index >> self.block_shift)?;
+
+        block.get(index & ((1 << self.block_shift) - 1))
+    }
+
+    fn get_slice(&self, index: Range<usize>) -> Option<&[u8]> {
+        if index.start > index.end || index.end > self.len {
+            return None;
+        }
+        if index.start == index.end {
+            return Some(&[]);
+        }
+        let number = index.start >> self.block_shift;
+
+        if (index.end - 1) >> self.block_shift != number {
+            return None;
+        }
+        let start = number << self.block_shift;
+
+        self.block(number)?
+            .get(index.start - start..index.end - start)
+    }
+
+    #[inline(always)]
+    fn get_len(&self) -> usize {
+        self.len
+    }
+
+    fn read_at(&self, index: usize, buf: &mut [u8]) -> bool {
+        if index.saturating_add(buf.len()) > self.len {
+            return false;
+        }
+        let mut written = 0;
+
+        while written < buf.len() {
+            let position = index + written;
+            let Some(block) = self.block(position >> self.block_shift) else {
+                return false;
+            };
+            let data = &block[position & ((1 << self.block_shift) - 1)..];
+            let size = data.len().min(buf.len() - written);
+
+            buf[written..written + size].copy_from_slice(&data[..size]);
+            written += size;
+        }
+        true
+    }
+}
+
+#[test]
+fn segmented_reads() {
+    use std::io::Cursor;
+
+    let data: Vec<u8> = (0..200).map(|x| (x * 7) as u8).collect();
+
+    // empty chunks are skipped
+    let chunks = ZChunkedBuffer::new(vec![&data[..50], &[], &data[50..51], &data[51..]]);
+    let mut ring = data[120..].to_vec();
+    ring.extend_from_slice(&data[..120]);
+    let ring = ZRingBuffer::new(&ring, 80, 200);
+    let seek = ZSeekReader::with_block_size(Cursor::new(&data), 30).unwrap();
+
+    let sources: [&dyn ZReaderTrait; 3] = [&chunks, &ring, &seek];
+
+    for source in sources {
+        assert_eq!(source.get_len(), 200);
+        assert_eq!(source.get_byte(200), None);
+
+        for (i, byte) in data.iter().enumerate() {
+            assert_eq!(source.get_byte(i), Some(byte));
+        }
+        for start in 0..=200 {
+            for end in start..=200 {
+                if let Some(slice) = source.get_slice(start..end) {
+                    assert_eq!(slice, &data[start..end]);
+                }
+                let mut buf = vec![0; end - start];
+                assert!(source.read_at(start, &mut buf));
+                assert_eq!(buf, &data[start..end]);
+            }
+        }
+        assert!(source.get_slice(190..201).is_none());
+        assert!(!source.read_at(190, &mut [0; 11]));
+    }
+    // ranges within a segment are slices of it
+    assert!(chunks.get_slice(51..200).is_some());
+    assert!(chunks.get_slice(49..51).is_none());
+    assert_eq!(ring.as_slices(), (&data[..120], &data[120..]));
+    assert!(ring.get_slice(119..121).is_none());
+    // blocks are rounded up to 32 bytes
+    assert!(seek.get_slice(64..96).is_some());
+    assert!(seek.get_slice(63..65).is_none());
+    assert!(seek.take_error().is_none());
+}
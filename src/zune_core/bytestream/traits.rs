@@ -11,6 +11,7 @@
 //! This exposes the traits and implementations for readers
 //! and writers in the zune family of decoders and encoders.
 
+use core::fmt::{Debug, Display, Formatter};
 use core::ops::Range;
 
 /// The underlying reader trait
@@ -32,6 +33,10 @@ pub trait ZReaderTrait {
 
     /// Get a slice of bytes from a range of start..end
     ///
+    /// Sources that aren't contiguous in memory return `None` for ranges
+    /// spanning two of their segments, use [`read_at`](Self::read_at)
+    /// to read those.
+    ///
     /// # Arguments
     ///
     /// * `index`:  The range of the bytes to read
@@ -63,6 +68,24 @@ pub trait ZReaderTrait {
     /// For files, this includes the file  length.
     /// For buffers this includes the internal buffer length
     fn get_len(&self) -> usize;
+
+    /// Copy the bytes starting at `index` into `buf`
+    ///
+    /// Returns false if the source ends before `buf` is filled.
+    ///
+    /// The default implementation goes through [`get_slice`](Self::get_slice),
+    /// sources that aren't contiguous in memory override it to copy across
+    /// their segments.
+    #[inline(always)]
+    fn read_at(&self, index: usize, buf: &mut [u8]) -> bool {
+        match self.get_slice(index..index + buf.len()) {
+            Some(bytes) => {
+                buf.copy_from_slice(bytes);
+                true
+            }
+            None => false,
+        }
+    }
 }
 
 impl ZReaderTrait for &[u8] {
@@ -129,3 +152,247 @@ impl<const N: usize> ZReaderTrait for &[u8; N] {
         N
     }
 }
+
+/// Errors from writing to a [`ZWriterTrait`] sink
+pub enum ZByteIoError {
+    /// The sink is full, contains the number of bytes that had to be
+    /// written and the number of bytes that were left
+    NotEnoughBuffer(usize, usize),
+    /// The sink can't go back to bytes already written
+    NotSeekable,
+    /// An error from a [`std::io::Write`] sink
+    StdIoError(std::io::Error),
+    /// An error from another kind of sink, formatted
+    SinkError(String),
+}
+
+impl Debug for ZByteIoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NotEnoughBuffer(needed, left) => {
+                write!(f, "Not enough space, needed {needed} bytes but {left} were left")
+            }
+            Self::NotSeekable => write!(f, "The sink can't go back to bytes already written"),
+            Self::StdIoError(error) => write!(f, "{error}"),
+            Self::SinkError(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl Display for ZByteIoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl std::error::Error for ZByteIoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::StdIoError(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ZByteIoError {
+    fn from(error: std::io::Error) -> Self {
+        ZByteIoError::StdIoError(error)
+    }
+}
+
+/// The underlying writer trait
+///
+/// This is implemented for fixed buffers (`&mut [u8]`), for growable
+/// `Vec<u8>` buffers, which are appended to, and through wrappers for
+/// [`std::io::Write`] and `embedded_svc::io::Write` sinks, see [`ZIoWriter`]
+/// and [`ZSvcWriter`].
+///
+/// Writes go to a `position` in the sink. Buffers can seek, so writes to
+/// positions before the end of the data overwrite it, e.g to fill in a
+/// length once it is known. Other sinks can't, they are only ever asked to
+/// write where the last write ended and ignore the position.
+///
+/// [`ZIoWriter`]: super::ZIoWriter
+/// [`ZSvcWriter`]: super::ZSvcWriter
+pub trait ZWriterTrait {
+    /// Write as many bytes of `buf` as the sink takes at `position`,
+    /// returning how many were written
+    ///
+    /// # Errors
+    /// If the sink fails
+    fn write_bytes(&mut self, position: usize, buf: &[u8]) -> Result<usize, ZByteIoError>;
+
+    /// Write all of `buf` at `position`
+    ///
+    /// # Errors
+    /// If the sink fails or fills up, part of `buf` may have been written
+    /// in that case
+    fn write_all_bytes(&mut self, mut position: usize, mut buf: &[u8]) -> Result<(), ZByteIoError> {
+        while !buf.is_empty() {
+            let written = self.write_bytes(position, buf)?;
+
+            if written == 0 {
+                return Err(ZByteIoError::NotEnoughBuffer(buf.len(), 0));
+            }
+            position += written;
+            buf = &buf[written..];
+        }
+        Ok(())
+    }
+
+    /// Write the bytes of a fixed size array, e.g an integer, at `position`
+    ///
+    /// # Errors
+    /// If the sink fails or fills up
+    #[inline(always)]
+    fn write_const_bytes<const N: usize>(
+        &mut self,
+        position: usize,
+        buf: &[u8; N],
+    ) -> Result<(), ZByteIoError> {
+        self.write_all_bytes(position, buf)
+    }
+
+    /// Push buffered bytes to their destination
+    ///
+    /// # Errors
+    /// If the sink fails
+    fn flush_bytes(&mut self) -> Result<(), ZByteIoError> {
+        Ok(())
+    }
+
+    /// Number of bytes the sink can still take from `position`, `None` if
+    /// it has no limit
+    fn bytes_left(&self, position: usize) -> Option<usize> {
+        let _ = position;
+        None
+    }
+
+    /// Hint that `additional` bytes are about to be written, growable sinks
+    /// may allocate them in one go
+    fn reserve_capacity(&mut self, additional: usize) {
+        let _ = additional;
+    }
+
+    /// Position of the first write, e.g the end of a `Vec` that already
+    /// holds data
+    fn start_position(&self) -> usize {
+        0
+    }
+
+    /// Whether writes can go to positions other than the end of the data
+    fn can_seek(&self) -> bool {
+        false
+    }
+
+    /// The bytes of the sink, for sinks that keep them
+    fn sink_bytes(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+impl ZWriterTrait for &mut [u8] {
+    #[inline(always)]
+    fn write_bytes(&mut self, position: usize, buf: &[u8]) -> Result<usize, ZByteIoError> {
+        let Some(space) = self.get_mut(position..) else {
+            return Ok(0);
+        };
+        let size = buf.len().min(space.len());
+
+        space[..size].copy_from_slice(&buf[..size]);
+
+        Ok(size)
+    }
+
+    #[inline(always)]
+    fn write_all_bytes(&mut self, position: usize, buf: &[u8]) -> Result<(), ZByteIoError> {
+        let left = self.len().saturating_sub(position);
+
+        if buf.len() > left {
+            return Err(ZByteIoError::NotEnoughBuffer(buf.len(), left));
+        }
+        self[position..position + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn bytes_left(&self, position: usize) -> Option<usize> {
+        Some(self.len().saturating_sub(position))
+    }
+
+    fn can_seek(&self) -> bool {
+        true
+    }
+
+    fn sink_bytes(&self) -> Option<&[u8]> {
+        Some(self)
+    }
+}
+
+impl ZWriterTrait for Vec<u8> {
+    #[inline(always)]
+    fn write_bytes(&mut self, position: usize, buf: &[u8]) -> Result<usize, ZByteIoError> {
+        self.write_all_bytes(position, buf)?;
+        Ok(buf.len())
+    }
+
+    #[inline(always)]
+    fn write_all_bytes(&mut self, position: usize, buf: &[u8]) -> Result<(), ZByteIoError> {
+        if position == self.len() {
+            self.extend_from_slice(buf);
+        } else {
+            // positions skipped past the end are zero filled
+            let end = position + buf.len();
+
+            if end > self.len() {
+                self.resize(end, 0);
+            }
+            self[position..end].copy_from_slice(buf);
+        }
+        Ok(())
+    }
+
+    fn reserve_capacity(&mut self, additional: usize) {
+        self.reserve(additional);
+    }
+
+    fn start_position(&self) -> usize {
+        self.len()
+    }
+
+    fn can_seek(&self) -> bool {
+        true
+    }
+
+    fn sink_bytes(&self) -> Option<&[u8]> {
+        Some(self)
+    }
+}
+
+impl ZWriterTrait for &mut Vec<u8> {
+    #[inline(always)]
+    fn write_bytes(&mut self, position: usize, buf: &[u8]) -> Result<usize, ZByteIoError> {
+        (**self).write_bytes(position, buf)
+    }
+
+    #[inline(always)]
+    fn write_all_bytes(&mut self, position: usize, buf: &[u8]) -> Result<(), ZByteIoError> {
+        (**self).write_all_bytes(position, buf)
+    }
+
+    fn reserve_capacity(&mut self, additional: usize) {
+        self.reserve(additional);
+    }
+
+    fn start_position(&self) -> usize {
+        self.len()
+    }
+
+    fn can_seek(&self) -> bool {
+        true
+    }
+
+    fn sink_bytes(&self) -> Option<&[u8]> {
+        Some(self)
+    }
+}
@@ -1,5 +1,7 @@
 use core::mem::size_of;
 
+use crate::zune_core::bytestream::traits::{ZByteIoError, ZWriterTrait};
+
 enum Mode
 {
     // Big endian
@@ -8,17 +10,21 @@ enum Mode
     LE
 }
 
-static ERROR_MSG: &str = "No more space";
-
 /// Encapsulates a simple Byte writer with
 /// support for Endian aware writes
-pub struct ZByteWriter<'a>
+///
+/// Bytes are written to a [`ZWriterTrait`] sink, e.g a fixed `&mut [u8]`,
+/// a growable `Vec<u8>` or a [`ZIoWriter`](super::ZIoWriter) over a file.
+pub struct ZByteWriter<T: ZWriterTrait>
 {
-    buffer:   &'a mut [u8],
+    sink:     T,
+    /// Position of the first write in the sink
+    start:    usize,
+    /// Position of the next write in the sink
     position: usize
 }
 
-impl<'a> ZByteWriter<'a>
+impl<T: ZWriterTrait> ZByteWriter<T>
 {
     /// Write bytes from the buf into the bytestream
     /// and return how many bytes were written
@@ -29,77 +35,80 @@ impl<'a> ZByteWriter<'a>
     /// # Returns
     /// - `Ok(usize)` - Number of bytes written
     /// This number may be less than `buf.len()` if the length of the buffer is greater
-    /// than the internal bytestream length
-    ///  
+    /// than the space left in the sink
+    ///
     /// If you want to be sure that all bytes were written, see [`write_all`](Self::write_all)
     ///
     #[inline]
-    pub fn write(&mut self, buf: &[u8]) -> Result<usize, &'static str>
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize, ZByteIoError>
     {
-        let min = buf.len().min(self.bytes_left());
-        // write
-        self.buffer[self.position..self.position + min].copy_from_slice(&buf[0..min]);
-        self.position += min;
+        let size = self.sink.write_bytes(self.position, buf)?;
+        self.position += size;
 
-        Ok(min)
+        Ok(size)
     }
-    /// Write all bytes from `buf` into the bytestream and return
-    /// and panic if not all bytes were written to the bytestream
+    /// Write all bytes from `buf` into the bytestream
     ///
     /// # Arguments
     /// - `buf`: The bytes to be written into the bytestream
     ///
     ///# Returns
     /// - `Ok(())`: Indicates all bytes were written into the bytestream
-    /// - `Err(&static str)`: In case all the bytes could not be written
-    /// to the stream
-    pub fn write_all(&mut self, buf: &[u8]) -> Result<(), &'static str>
+    /// - `Err(ZByteIoError)`: In case all the bytes could not be written
+    /// to the stream, fixed buffers are left untouched then
+    pub fn write_all(&mut self, buf: &[u8]) -> Result<(), ZByteIoError>
     {
-        let size = self.write(buf)?;
+        self.sink.write_all_bytes(self.position, buf)?;
+        self.position += buf.len();
 
-        if size != buf.len()
-        {
-            return Err("Could not write the whole buffer");
-        }
         Ok(())
     }
     /// Create a new bytestream writer
     /// Bytes are written from the start to the end and not assumptions
     /// are made of the nature of the underlying stream
     ///
+    /// Growable buffers are appended to.
+    ///
     /// # Arguments
-    pub fn new(data: &'a mut [u8]) -> ZByteWriter<'a>
+    /// - `sink`: Where bytes are written
+    pub fn new(sink: T) -> ZByteWriter<T>
     {
+        let start = sink.start_position();
+
         ZByteWriter {
-            buffer:   data,
-            position: 0
+            sink,
+            start,
+            position: start
         }
     }
-    /// Return number of unwritten bytes in this stream
+    /// Return number of bytes the sink can still take,
+    /// `usize::MAX` for sinks without a limit
     ///
     /// # Example
     /// ```
     /// use zune_core::bytestream::ZByteWriter;
     /// let mut storage = [0;10];
     ///
-    /// let writer = ZByteWriter::new(&mut storage);
+    /// let writer = ZByteWriter::new(&mut storage[..]);
     /// assert_eq!(writer.bytes_left(),10); // no bytes were written
     /// ```
-    pub const fn bytes_left(&self) -> usize
+    pub fn bytes_left(&self) -> usize
     {
-        self.buffer.len().saturating_sub(self.position)
+        self.sink.bytes_left(self.position).unwrap_or(usize::MAX)
     }
 
-    /// Return the number of bytes the writer has written
+    /// Return the position of the writer, the number of bytes written
+    /// unless it was moved
     ///
     /// ```
     /// use zune_core::bytestream::ZByteWriter;
-    /// let mut stream = ZByteWriter::new(&mut []);
-    /// assert_eq!(stream.position(),0);
+    /// let mut stream = ZByteWriter::new(Vec::new());
+    /// stream.write_u32_be(1);
+    /// assert_eq!(stream.position(),4);
     /// ```
     pub const fn position(&self) -> usize
     {
-        self.position
+        self.position - self.start
     }
 
     /// Write a single byte into the bytestream or error out
@@ -109,42 +118,31 @@ impl<'a> ZByteWriter<'a>
     /// ```
     /// use zune_core::bytestream::ZByteWriter;
     /// let mut buf = [0;10];
-    /// let mut stream  =  ZByteWriter::new(&mut buf);
+    /// let mut stream  =  ZByteWriter::new(&mut buf[..]);
     /// assert!(stream.write_u8_err(34).is_ok());
     /// ```
     /// No space
     /// ```
     /// use zune_core::bytestream::ZByteWriter;
-    /// let mut stream = ZByteWriter::new(&mut []);
+    /// let mut stream = ZByteWriter::new(&mut [][..]);
     /// assert!(stream.write_u8_err(32).is_err());
     /// ```
     ///
-    pub fn write_u8_err(&mut self, byte: u8) -> Result<(), &'static str>
+    pub fn write_u8_err(&mut self, byte: u8) -> Result<(), ZByteIoError>
     {
-        match self.buffer.get_mut(self.position)
-        {
-            Some(m_byte) =>
-            {
-                self.position += 1;
-                *m_byte = byte;
+        self.sink.write_const_bytes(self.position, &[byte])?;
+        self.position += 1;
 
-                Ok(())
-            }
-            None => Err(ERROR_MSG)
-        }
+        Ok(())
     }
 
     /// Write a single byte in the stream or don't write
-    /// anything if the buffer is full and cannot support the byte read
+    /// anything if the sink is full or fails
     ///
     /// Should be combined with [`has`](Self::has)
     pub fn write_u8(&mut self, byte: u8)
     {
-        if let Some(m_byte) = self.buffer.get_mut(self.position)
-        {
-            self.position += 1;
-            *m_byte = byte;
-        }
+        let _ = self.write_u8_err(byte);
     }
     /// Check if the byte writer can support
     /// the following write
@@ -153,70 +151,78 @@ impl<'a> ZByteWriter<'a>
     /// ```
     /// use zune_core::bytestream::ZByteWriter;
     /// let mut data = [0;10];
-    /// let mut stream = ZByteWriter::new(&mut data);
+    /// let mut stream = ZByteWriter::new(&mut data[..]);
     /// assert!(stream.has(5));
     /// assert!(!stream.has(100));
     /// ```
-    pub const fn has(&self, bytes: usize) -> bool
+    pub fn has(&self, bytes: usize) -> bool
     {
-        self.position.saturating_add(bytes) <= self.buffer.len()
+        bytes <= self.bytes_left()
     }
 
-    /// Get length of the underlying buffer.
+    /// Get length of the underlying buffer from where the writer started,
+    /// for sinks that aren't buffers the number of bytes written
     #[inline]
-    pub const fn len(&self) -> usize
+    pub fn len(&self) -> usize
     {
-        self.buffer.len()
+        self.sink
+            .sink_bytes()
+            .map_or(self.position, <[u8]>::len)
+            .saturating_sub(self.start)
     }
     /// Return true if the underlying buffer stream is empty
     #[inline]
-    pub const fn is_empty(&self) -> bool
+    pub fn is_empty(&self) -> bool
     {
         self.len() == 0
     }
 
-    /// Return true whether or not we read to the end of the
-    /// buffer and have no more bytes left.
+    /// Return true whether or not the sink is full
     ///
     /// If this is true, all non error variants will silently discard the
     /// byte and all error variants will return an error on writing a byte
     /// if any write occurs
-    ///
-    ///
     #[inline]
-    pub const fn eof(&self) -> bool
+    pub fn eof(&self) -> bool
     {
-        self.position >= self.len()
+        self.bytes_left() == 0
     }
 
     /// Rewind the position of the internal cursor back by `by` bytes
     ///
-    /// The position saturates at zero
+    /// The position saturates at zero, later writes overwrite the bytes
+    /// written after it.
+    ///
+    /// # Errors
+    /// If the sink can't seek
     ///
     /// # Example
     /// ```
     /// use zune_core::bytestream::ZByteWriter;
     /// let bytes = &mut [1,2,4];
-    /// let mut stream = ZByteWriter::new(bytes);
+    /// let mut stream = ZByteWriter::new(&mut bytes[..]);
     /// stream.write_u16_be(23);
     /// // now internal cursor is at position 2.
     /// // lets rewind it
-    /// stream.rewind(usize::MAX);
+    /// stream.rewind(usize::MAX).unwrap();
     /// assert_eq!(stream.position(),0);
     /// ```
     #[inline]
-    pub fn rewind(&mut self, by: usize)
+    pub fn rewind(&mut self, by: usize) -> Result<(), ZByteIoError>
     {
-        self.position = self.position.saturating_sub(by);
+        self.set_position(self.position().saturating_sub(by))
     }
     /// Move the internal cursor forward some bytes
     ///
-    ///
     /// This saturates at maximum value of usize in your platform.
+    /// Growable buffers are zero filled up to the next byte written.
+    ///
+    /// # Errors
+    /// If the sink can't seek
     #[inline]
-    pub fn skip(&mut self, by: usize)
+    pub fn skip(&mut self, by: usize) -> Result<(), ZByteIoError>
     {
-        self.position = self.position.saturating_add(by);
+        self.set_position(self.position().saturating_add(by))
     }
 
     /// Look ahead position bytes and return a reference
@@ -225,100 +231,135 @@ impl<'a> ZByteWriter<'a>
     ///
     /// This doesn't increment the position, bytes would have to be discarded
     /// at a later point.
+    ///
+    /// # Errors
+    /// If the sink doesn't keep its bytes, or the bytes are out of bounds
     #[inline]
-    pub fn peek_at(&'a self, position: usize, num_bytes: usize) -> Result<&'a [u8], &'static str>
+    pub fn peek_at(&self, position: usize, num_bytes: usize) -> Result<&[u8], ZByteIoError>
     {
-        let start = self.position + position;
-        let end = self.position + position + num_bytes;
+        let bytes = self.sink.sink_bytes().ok_or(ZByteIoError::NotSeekable)?;
+        let start = self.position.saturating_add(position);
+        let end = start.saturating_add(num_bytes);
 
-        match self.buffer.get(start..end)
-        {
-            Some(bytes) => Ok(bytes),
-            None => Err(ERROR_MSG)
-        }
+        bytes
+            .get(start..end)
+            .ok_or(ZByteIoError::NotEnoughBuffer(num_bytes, bytes.len().saturating_sub(start)))
     }
 
     /// Set position for the internal cursor
     ///
     /// Further calls to write bytes will proceed from the
-    /// position set
-    pub fn set_position(&mut self, position: usize)
+    /// position set, overwriting bytes already written, e.g to
+    /// fill in a length once it is known.
+    ///
+    /// # Errors
+    /// If the sink can't seek
+    ///
+    /// # Example
+    /// ```
+    /// use zune_core::bytestream::ZByteWriter;
+    /// let mut stream = ZByteWriter::new(Vec::new());
+    /// stream.write_u16_be(0);
+    /// stream.write_all(b"segment").unwrap();
+    ///
+    /// let length = stream.position() as u16;
+    /// stream.set_position(0).unwrap();
+    /// stream.write_u16_be(length);
+    /// assert_eq!(stream.into_inner()[..2], [0, 9]);
+    /// ```
+    pub fn set_position(&mut self, position: usize) -> Result<(), ZByteIoError>
+    {
+        if !self.sink.can_seek()
+        {
+            return Err(ZByteIoError::NotSeekable);
+        }
+        self.position = self.start.saturating_add(position);
+
+        Ok(())
+    }
+
+    /// Hint that `additional` bytes are about to be written, growable sinks
+    /// may allocate them in one go
+    pub fn reserve(&mut self, additional: usize)
+    {
+        self.sink.reserve_capacity(additional);
+    }
+
+    /// Push bytes buffered by the sink to their destination
+    pub fn flush(&mut self) -> Result<(), ZByteIoError>
+    {
+        self.sink.flush_bytes()
+    }
+
+    /// Return a mutable reference to the sink
+    pub fn inner_mut(&mut self) -> &mut T
     {
-        self.position = position;
+        &mut self.sink
+    }
+
+    /// Return the sink
+    ///
+    /// # Example
+    /// ```
+    /// use zune_core::bytestream::ZByteWriter;
+    /// let mut stream = ZByteWriter::new(Vec::new());
+    /// stream.write_u16_le(0x1234);
+    /// assert_eq!(stream.into_inner(),[0x34,0x12]);
+    /// ```
+    pub fn into_inner(self) -> T
+    {
+        self.sink
     }
 }
 
 macro_rules! write_single_type {
     ($name:tt,$name2:tt,$name3:tt,$name4:tt,$name5:tt,$name6:tt,$int_type:tt) => {
-        impl<'a> ZByteWriter<'a>
+        impl<T: ZWriterTrait> ZByteWriter<T>
         {
             #[inline(always)]
-            fn $name(&mut self, byte: $int_type, mode: Mode) -> Result<(), &'static str>
+            fn $name(&mut self, byte: $int_type, mode: Mode) -> Result<(), ZByteIoError>
             {
                 const SIZE: usize = size_of::<$int_type>();
 
-                match self.buffer.get_mut(self.position..self.position + SIZE)
+                // get bits, depending on mode.
+                // This should be inlined and not visible in
+                // the generated binary since mode is a compile
+                // time constant.
+                let bytes = match mode
                 {
-                    Some(m_byte) =>
-                    {
-                        self.position += SIZE;
-                        // get bits, depending on mode.
-                        // This should be inlined and not visible in
-                        // the generated binary since mode is a compile
-                        // time constant.
-                        let bytes = match mode
-                        {
-                            Mode::BE => byte.to_be_bytes(),
-                            Mode::LE => byte.to_le_bytes()
-                        };
-
-                        m_byte.copy_from_slice(&bytes);
-
-                        Ok(())
-                    }
-                    None => Err(ERROR_MSG)
-                }
+                    Mode::BE => byte.to_be_bytes(),
+                    Mode::LE => byte.to_le_bytes()
+                };
+
+                self.sink.write_const_bytes(self.position, &bytes)?;
+                self.position += SIZE;
+
+                Ok(())
             }
             #[inline(always)]
             fn $name2(&mut self, byte: $int_type, mode: Mode)
             {
-                const SIZE: usize = size_of::<$int_type>();
-
-                if let Some(m_byte) = self.buffer.get_mut(self.position..self.position + SIZE)
-                {
-                    self.position += SIZE;
-                    // get bits, depending on mode.
-                    // This should be inlined and not visible in
-                    // the generated binary since mode is a compile
-                    // time constant.
-                    let bytes = match mode
-                    {
-                        Mode::BE => byte.to_be_bytes(),
-                        Mode::LE => byte.to_le_bytes()
-                    };
-
-                    m_byte.copy_from_slice(&bytes);
-                }
+                let _ = self.$name(byte, mode);
             }
 
             #[doc=concat!("Write ",stringify!($int_type)," as a big endian integer")]
-            #[doc=concat!("Returning an error if the underlying buffer cannot support a ",stringify!($int_type)," write.")]
+            #[doc=concat!("Returning an error if the underlying sink cannot support a ",stringify!($int_type)," write.")]
             #[inline]
-            pub fn $name3(&mut self, byte: $int_type) -> Result<(), &'static str>
+            pub fn $name3(&mut self, byte: $int_type) -> Result<(), ZByteIoError>
             {
                 self.$name(byte, Mode::BE)
             }
 
             #[doc=concat!("Write ",stringify!($int_type)," as a little endian integer")]
-            #[doc=concat!("Returning an error if the underlying buffer cannot support a ",stringify!($int_type)," write.")]
+            #[doc=concat!("Returning an error if the underlying sink cannot support a ",stringify!($int_type)," write.")]
             #[inline]
-            pub fn $name4(&mut self, byte: $int_type) -> Result<(), &'static str>
+            pub fn $name4(&mut self, byte: $int_type) -> Result<(), ZByteIoError>
             {
                 self.$name(byte, Mode::LE)
             }
 
             #[doc=concat!("Write ",stringify!($int_type)," as a big endian integer")]
-            #[doc=concat!("Or don't write anything if the reader cannot support a ",stringify!($int_type)," write.")]
+            #[doc=concat!("Or don't write anything if the sink cannot support a ",stringify!($int_type)," write.")]
             #[doc=concat!("\nShould be combined with the [`has`](Self::has) method to ensure a write succeeds")]
             #[inline]
             pub fn $name5(&mut self, byte: $int_type)
@@ -326,7 +367,7 @@ macro_rules! write_single_type {
                 self.$name2(byte, Mode::BE)
             }
             #[doc=concat!("Write ",stringify!($int_type)," as a little endian integer")]
-            #[doc=concat!("Or don't write anything if the reader cannot support a ",stringify!($int_type)," write.")]
+            #[doc=concat!("Or don't write anything if the sink cannot support a ",stringify!($int_type)," write.")]
             #[doc=concat!("Should be combined with the [`has`](Self::has) method to ensure a write succeeds")]
             #[inline]
             pub fn $name6(&mut self, byte: $int_type)
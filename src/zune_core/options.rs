@@ -6,8 +6,8 @@
 //! All supported options are put into one _Options to allow for global configurations
 //! options e.g the same  `DecoderOption` can be reused for all other decoders
 //!
-pub use decoder::DecoderOptions;
-pub use encoder::EncoderOptions;
+pub use decoder::{DecoderOptions, JpegColorMatrix, JpegColorRange, JpegConcealment};
+pub use encoder::{EncoderOptions, JpegSubsampling};
 
 mod decoder;
 mod encoder;
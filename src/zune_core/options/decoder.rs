@@ -26,6 +26,9 @@ fn decoder_strict_mode() -> DecoderFlags {
     flags.set(DecoderFlags::ZUNE_USE_SSE3, true);
     flags.set(DecoderFlags::ZUNE_USE_SSE41, true);
     flags.set(DecoderFlags::PNG_ADD_ALPHA_CHANNEL, false);
+    flags.set(DecoderFlags::JPG_FANCY_UPSAMPLING, true);
+    flags.set(DecoderFlags::JPG_EXACT, false);
+    flags.set(DecoderFlags::JPG_DERINGING, false);
 
     flags
 }
@@ -49,6 +52,9 @@ fn fast_options() -> DecoderFlags {
     flags.set(DecoderFlags::ZUNE_USE_SSE3, true);
     flags.set(DecoderFlags::ZUNE_USE_SSE41, true);
     flags.set(DecoderFlags::PNG_ADD_ALPHA_CHANNEL, false);
+    flags.set(DecoderFlags::JPG_FANCY_UPSAMPLING, true);
+    flags.set(DecoderFlags::JPG_EXACT, false);
+    flags.set(DecoderFlags::JPG_DERINGING, false);
 
     flags
 }
@@ -73,6 +79,9 @@ fn cmd_options() -> DecoderFlags {
     flags.set(DecoderFlags::ZUNE_USE_SSE3, true);
     flags.set(DecoderFlags::ZUNE_USE_SSE41, true);
     flags.set(DecoderFlags::PNG_ADD_ALPHA_CHANNEL, false);
+    flags.set(DecoderFlags::JPG_FANCY_UPSAMPLING, true);
+    flags.set(DecoderFlags::JPG_EXACT, false);
+    flags.set(DecoderFlags::JPG_DERINGING, false);
 
     flags
 }
@@ -109,9 +118,54 @@ bitflags! {
         const ZUNE_USE_AVX2                 =  0b0000_0000_0000_0000_0000_0001_0000_0000;
         /// Whether the png decoder should add alpha channel where possible.
         const PNG_ADD_ALPHA_CHANNEL         =  0b0000_0000_0000_0000_0000_0010_0000_0000;
+        /// Whether the jpeg decoder should interpolate sub-sampled components
+        /// instead of replicating samples
+        const JPG_FANCY_UPSAMPLING          =  0b0000_0000_0000_0000_0000_0100_0000_0000;
+        /// Whether the jpeg decoder should reproduce libjpeg-turbo's output
+        /// bit for bit instead of using faster approximations
+        const JPG_EXACT                     =  0b0000_0000_0000_0000_0000_1000_0000_0000;
+        /// Whether the jpeg decoder should smooth ringing around edges
+        /// inside blocks when deblocking
+        const JPG_DERINGING                 =  0b0000_0000_0000_0000_0001_0000_0000_0000;
     }
 }
 
+/// How the jpeg decoder fills MCUs it couldn't decode because
+/// of corrupt entropy coded data
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum JpegConcealment {
+    /// Don't conceal, corrupt data is an error
+    None,
+    /// Fill lost MCUs with mid grey
+    Grey,
+    /// Extend the last pixel row of the MCU row above downwards
+    ///
+    /// Lost MCUs in the first row are filled with mid grey
+    CopyAbove,
+    /// Fill lost blocks with the last DC value decoded for their component
+    DcOnly,
+}
+
+/// Matrix the jpeg decoder uses to convert YCbCr to RGB
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum JpegColorMatrix {
+    /// ITU-R BT.601, used by JFIF and nearly all jpeg images
+    Bt601,
+    /// ITU-R BT.709, used by HD video
+    Bt709,
+    /// ITU-R BT.2020, used by UHD video
+    Bt2020,
+}
+
+/// Range of YCbCr samples in jpeg images
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum JpegColorRange {
+    /// Samples use the whole 0..=255 range, as JFIF specifies
+    Full,
+    /// Luma uses 16..=235 and chroma 16..=240, as video does
+    Studio,
+}
+
 /// Decoder options
 ///
 /// Not all options are respected by decoders all decoders
@@ -131,6 +185,17 @@ pub struct DecoderOptions {
     /// - Default value: 16384
     /// - Respected by: `all decoders`
     max_height: usize,
+    /// Maximum number of bytes decoders may allocate
+    /// for decoding an image
+    ///
+    /// - Default value: `usize::MAX` (no limit)
+    /// - Respected by: `jpeg`
+    max_memory: usize,
+    /// Number of threads decoders may use
+    ///
+    /// - Default value: 1
+    /// - Respected by: `jpeg` (baseline images with restart markers)
+    num_threads: u8,
     /// Output colorspace
     ///
     /// The jpeg decoder allows conversion to a separate colorspace
@@ -151,6 +216,27 @@ pub struct DecoderOptions {
     /// - Default value:100
     /// - Respected by: `jpeg`
     max_scans: usize,
+    /// How lost MCUs are filled when entropy coded data
+    /// is corrupt
+    ///
+    /// - Default value: `JpegConcealment::None`
+    /// - Respected by: `jpeg` (baseline images with restart markers)
+    concealment: JpegConcealment,
+    /// Matrix used to convert YCbCr to RGB
+    ///
+    /// - Default value: `JpegColorMatrix::Bt601`
+    /// - Respected by: `jpeg`
+    color_matrix: JpegColorMatrix,
+    /// Range of YCbCr samples
+    ///
+    /// - Default value: `JpegColorRange::Full`
+    /// - Respected by: `jpeg`
+    color_range: JpegColorRange,
+    /// Strength of the deblocking filter, 0 disables it
+    ///
+    /// - Default value: 0
+    /// - Respected by: `jpeg`
+    deblock_strength: u8,
     /// Maximum size for deflate.
     /// Respected by all decoders that use inflate/deflate
     deflate_limit: usize,
@@ -209,6 +295,17 @@ impl DecoderOptions {
         self.max_height
     }
 
+    /// Get maximum number of bytes the decoder may allocate
+    /// when decoding an image
+    pub const fn get_max_memory(&self) -> usize {
+        self.max_memory
+    }
+
+    /// Return number of threads decoders may use
+    pub const fn get_num_threads(&self) -> u8 {
+        self.num_threads
+    }
+
     /// Return true whether the decoder should be in strict mode
     /// And reject most errors
     pub fn get_strict_mode(&self) -> bool {
@@ -250,6 +347,35 @@ impl DecoderOptions {
         self
     }
 
+    /// Set maximum number of bytes the decoder may allocate
+    /// when decoding an image
+    ///
+    /// This covers the output (when the decoder allocates it), coefficient
+    /// buffers, component rows and up-sampler scratch space. Decoding fails
+    /// before allocating anything if the image needs more than this.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`: The maximum number of bytes allowed
+    ///
+    /// returns: DecoderOptions
+    pub fn set_max_memory(mut self, bytes: usize) -> Self {
+        self.max_memory = bytes;
+        self
+    }
+
+    /// Set the number of threads decoders may use where supported
+    ///
+    /// The jpeg decoder splits baseline images with restart markers into
+    /// groups of restart intervals and decodes them on separate threads, output
+    /// is the same as when decoding on a single thread.
+    ///
+    /// Zero and one mean use a single thread
+    pub fn set_num_threads(mut self, threads: u8) -> Self {
+        self.num_threads = threads;
+        self
+    }
+
     /// Whether the routines can use unsafe platform specific
     /// intrinsics when necessary
     ///
@@ -387,6 +513,23 @@ impl DecoderOptions {
         self.max_scans = max_scans;
         self
     }
+    /// Get how the jpeg decoder conceals MCUs lost to corrupt data
+    pub const fn jpeg_get_concealment(&self) -> JpegConcealment {
+        self.concealment
+    }
+    /// Set how the jpeg decoder conceals MCUs lost to corrupt data
+    ///
+    /// When this isn't `JpegConcealment::None` and the decoder finds corrupt
+    /// data in a restart interval, it skips forward to the next restart marker
+    /// and fills the MCUs it lost instead of returning an error.
+    ///
+    /// This only works for baseline images with restart markers, other images
+    /// return an error as before.
+    #[must_use]
+    pub fn jpeg_set_concealment(mut self, concealment: JpegConcealment) -> Self {
+        self.concealment = concealment;
+        self
+    }
     /// Get expected output colorspace set by the user for which the image
     /// is expected to be reconstructed into.
     ///
@@ -403,6 +546,97 @@ impl DecoderOptions {
         self.out_colorspace = colorspace;
         self
     }
+    /// Return true if the jpeg decoder interpolates sub-sampled
+    /// components when up-sampling them
+    pub const fn jpeg_get_fancy_upsampling(&self) -> bool {
+        self.flags.contains(DecoderFlags::JPG_FANCY_UPSAMPLING)
+    }
+    /// Set whether the jpeg decoder should interpolate sub-sampled components
+    /// (triangle filter) or replicate their samples (nearest neighbour).
+    ///
+    /// Nearest neighbour is faster and keeps chroma edges sharp, at the cost
+    /// of blockier color transitions. Default is true
+    #[must_use]
+    pub fn jpeg_set_fancy_upsampling(mut self, yes: bool) -> Self {
+        self.flags.set(DecoderFlags::JPG_FANCY_UPSAMPLING, yes);
+        self
+    }
+    /// Get the matrix the jpeg decoder uses to convert YCbCr to RGB
+    pub const fn jpeg_get_color_matrix(&self) -> JpegColorMatrix {
+        self.color_matrix
+    }
+    /// Set the matrix the jpeg decoder uses to convert YCbCr to RGB
+    ///
+    /// Jpeg images don't say which matrix they were encoded with, JFIF
+    /// mandates BT.601 but frames grabbed from video may use another one.
+    /// Default is `JpegColorMatrix::Bt601`
+    #[must_use]
+    pub fn jpeg_set_color_matrix(mut self, matrix: JpegColorMatrix) -> Self {
+        self.color_matrix = matrix;
+        self
+    }
+    /// Get the range of YCbCr samples the jpeg decoder expects
+    pub const fn jpeg_get_color_range(&self) -> JpegColorRange {
+        self.color_range
+    }
+    /// Set the range of YCbCr samples the jpeg decoder expects
+    ///
+    /// With `JpegColorRange::Studio`, samples are stretched to the full range
+    /// when converting, including luma for grayscale output.
+    /// Default is `JpegColorRange::Full`
+    #[must_use]
+    pub fn jpeg_set_color_range(mut self, range: JpegColorRange) -> Self {
+        self.color_range = range;
+        self
+    }
+    /// Return true if the jpeg decoder reproduces libjpeg-turbo's output
+    pub const fn jpeg_get_exact(&self) -> bool {
+        self.flags.contains(DecoderFlags::JPG_EXACT)
+    }
+    /// Set whether the jpeg decoder should reproduce libjpeg-turbo's output
+    /// bit for bit, with its default (`JDCT_ISLOW`, fancy up-sampling)
+    /// settings
+    ///
+    /// This switches the IDCT, up-sampling and color conversion to ports
+    /// of libjpeg's, which are slower than the default approximations.
+    /// Other color matrices and ranges keep libjpeg's rounding.
+    /// Default is false
+    #[must_use]
+    pub fn jpeg_set_exact(mut self, yes: bool) -> Self {
+        self.flags.set(DecoderFlags::JPG_EXACT, yes);
+        self
+    }
+    /// Get the strength of the jpeg decoder's deblocking filter
+    pub const fn jpeg_get_deblocking(&self) -> u8 {
+        self.deblock_strength
+    }
+    /// Set the strength of the jpeg decoder's deblocking filter
+    ///
+    /// The filter smooths discontinuities across block edges that are small
+    /// enough to come from quantization, judged from the image's quantization
+    /// tables, and leaves larger ones which are likely real edges.
+    ///
+    /// 0 disables the filter, 8 uses thresholds matched to the quantization
+    /// step and higher values smooth more. Default is 0
+    #[must_use]
+    pub fn jpeg_set_deblocking(mut self, strength: u8) -> Self {
+        self.deblock_strength = strength;
+        self
+    }
+    /// Return true if the jpeg decoder smooths ringing inside blocks
+    pub const fn jpeg_get_deringing(&self) -> bool {
+        self.flags.contains(DecoderFlags::JPG_DERINGING)
+    }
+    /// Set whether the jpeg decoder should also smooth ringing around edges
+    /// inside blocks
+    ///
+    /// This uses the deblocking strength and does nothing when deblocking
+    /// is disabled. Default is false
+    #[must_use]
+    pub fn jpeg_set_deringing(mut self, yes: bool) -> Self {
+        self.flags.set(DecoderFlags::JPG_DERINGING, yes);
+        self
+    }
 }
 
 /// Intrinsics support
@@ -588,7 +822,13 @@ impl Default for DecoderOptions {
             out_colorspace: ColorSpace::RGB,
             max_width: 1 << 14,
             max_height: 1 << 14,
+            max_memory: usize::MAX,
+            num_threads: 1,
             max_scans: 100,
+            concealment: JpegConcealment::None,
+            color_matrix: JpegColorMatrix::Bt601,
+            color_range: JpegColorRange::Full,
+            deblock_strength: 0,
             deflate_limit: 1 << 30,
             flags: decoder_strict_mode(),
             endianness: ByteEndian::BE,
@@ -35,6 +35,20 @@ impl Default for EncoderFlags {
     }
 }
 
+/// Chroma subsampling used by the jpeg encoder
+///
+/// Luma is kept at full resolution, the ratios give the resolution of the
+/// chroma components
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum JpegSubsampling {
+    /// No subsampling, chroma at full resolution
+    S444,
+    /// Chroma at half the horizontal resolution
+    S422,
+    /// Chroma at half the horizontal and vertical resolution
+    S420,
+}
+
 /// Options shared by some of the encoders in
 /// the `zune-` family of image crates
 #[derive(Debug, Copy, Clone)]
@@ -47,6 +61,7 @@ pub struct EncoderOptions {
     num_threads: u8,
     effort: u8,
     flags: EncoderFlags,
+    jpeg_subsampling: JpegSubsampling,
 }
 
 impl Default for EncoderOptions {
@@ -60,6 +75,7 @@ impl Default for EncoderOptions {
             num_threads: 4,
             effort: 4,
             flags: EncoderFlags::default(),
+            jpeg_subsampling: JpegSubsampling::S420,
         }
     }
 }
@@ -97,12 +113,8 @@ impl EncoderOptions {
 
     /// Get height for which the image will be encoded in
     ///
-    /// returns: usize
-    ///
-    /// # Panics
-    /// If height is zero
-    pub fn get_height(&self) -> usize {
-        assert_ne!(self.height, 0);
+    /// Encoders reject a zero height when encoding
+    pub const fn get_height(&self) -> usize {
         self.height
     }
     /// Get the depth for which the image will be encoded in
@@ -225,11 +237,34 @@ impl EncoderOptions {
         self.flags.contains(EncoderFlags::JPEG_OPTIMIZED_HUFFMAN)
     }
 
+    /// The chroma subsampling the jpeg encoder uses for color images
+    ///
+    /// Default is [`JpegSubsampling::S420`]
+    pub const fn jpeg_subsampling(&self) -> JpegSubsampling {
+        self.jpeg_subsampling
+    }
+
     /// Set whether the jpeg encoder should encode the imagei in progressive mode
     ///
     /// Default is `false`
     pub fn set_jpeg_encode_progressive(mut self, yes: bool) -> Self {
+        self.flags.set(EncoderFlags::JPEG_ENCODE_PROGRESSIVE, yes);
+        self
+    }
+
+    /// Set whether the jpeg encoder should optimize huffman tables
+    ///
+    /// Default is `false`
+    pub fn set_jpeg_optimized_huffman_tables(mut self, yes: bool) -> Self {
         self.flags.set(EncoderFlags::JPEG_OPTIMIZED_HUFFMAN, yes);
         self
     }
+
+    /// Set the chroma subsampling the jpeg encoder uses for color images
+    ///
+    /// Grayscale images have no chroma and ignore this
+    pub fn set_jpeg_subsampling(mut self, subsampling: JpegSubsampling) -> Self {
+        self.jpeg_subsampling = subsampling;
+        self
+    }
 }
@@ -0,0 +1,287 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+//! Colorspace conversion
+//!
+//! Pixels are converted to RGBA and from RGBA to the output colorspace, a
+//! batch at a time through a buffer on the stack.
+//!
+//! - Luma is computed with the BT.601 weights, like JPEG does, and YCbCr is
+//!   the full range JFIF flavour
+//! - CMYK and YCCK follow the Adobe convention used by the JPEG decoder,
+//!   where samples are inverted, 255 meaning no ink
+//! - Alpha is dropped when converting to a colorspace without it, and is
+//!   255 when converting from one without it
+#![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+
+use crate::zune_core::colorspace::ColorSpace;
+
+use super::errors::ImageErrors;
+use super::image::Image;
+
+/// Pixels converted at a time
+const BATCH: usize = 64;
+
+/// `a * b / 255`, rounded
+#[inline(always)]
+fn mul_255(a: u8, b: u8) -> u8 {
+    let t = u32::from(a) * u32::from(b) + 128;
+
+    ((t + (t >> 8)) >> 8) as u8
+}
+
+#[inline(always)]
+fn luma(r: u8, g: u8, b: u8) -> u8 {
+    ((19595 * u32::from(r) + 38470 * u32::from(g) + 7471 * u32::from(b) + 32768) >> 16) as u8
+}
+
+#[inline(always)]
+fn rgb_to_ycbcr(r: u8, g: u8, b: u8) -> [u8; 3] {
+    let (r, g, b) = (i32::from(r), i32::from(g), i32::from(b));
+    let offset = (128 << 16) + 32767;
+
+    [
+        luma(r as u8, g as u8, b as u8),
+        ((-11059 * r - 21709 * g + 32768 * b + offset) >> 16).clamp(0, 255) as u8,
+        ((32768 * r - 27439 * g - 5329 * b + offset) >> 16).clamp(0, 255) as u8,
+    ]
+}
+
+#[inline(always)]
+fn ycbcr_to_rgb(y: u8, cb: u8, cr: u8) -> [u8; 3] {
+    let y = i32::from(y);
+    let (cb, cr) = (i32::from(cb) - 128, i32::from(cr) - 128);
+
+    [
+        (y + ((91881 * cr + 32768) >> 16)).clamp(0, 255) as u8,
+        (y + ((-22554 * cb - 46802 * cr + 32768) >> 16)).clamp(0, 255) as u8,
+        (y + ((116_130 * cb + 32768) >> 16)).clamp(0, 255) as u8,
+    ]
+}
+
+#[inline(always)]
+fn rgb_to_cmyk(r: u8, g: u8, b: u8) -> [u8; 4] {
+    let k = r.max(g).max(b);
+
+    if k == 0 {
+        return [255, 255, 255, 0];
+    }
+    let scale = |x: u8| ((u32::from(x) * 255 + u32::from(k) / 2) / u32::from(k)) as u8;
+
+    [scale(r), scale(g), scale(b), k]
+}
+
+/// Convert pixels of `from` to RGBA
+///
+/// When `luma_only` is set only the first component of `rgba` pixels is
+/// used, YCbCr pixels then keep their luma as it is.
+fn to_rgba(from: ColorSpace, input: &[u8], rgba: &mut [[u8; 4]], luma_only: bool) {
+    let pixels = input
+        .chunks_exact(from.num_components())
+        .zip(rgba.iter_mut());
+
+    match from {
+        ColorSpace::RGB => pixels.for_each(|(p, o)| *o = [p[0], p[1], p[2], 255]),
+        ColorSpace::RGBA => pixels.for_each(|(p, o)| *o = [p[0], p[1], p[2], p[3]]),
+        ColorSpace::BGR => pixels.for_each(|(p, o)| *o = [p[2], p[1], p[0], 255]),
+        ColorSpace::BGRA => pixels.for_each(|(p, o)| *o = [p[2], p[1], p[0], p[3]]),
+        ColorSpace::Luma => pixels.for_each(|(p, o)| *o = [p[0], p[0], p[0], 255]),
+        ColorSpace::LumaA => pixels.for_each(|(p, o)| *o = [p[0], p[0], p[0], p[1]]),
+        ColorSpace::YCbCr if luma_only => pixels.for_each(|(p, o)| *o = [p[0], 0, 0, 255]),
+        ColorSpace::YCbCr => pixels.for_each(|(p, o)| {
+            let [r, g, b] = ycbcr_to_rgb(p[0], p[1], p[2]);
+            *o = [r, g, b, 255];
+        }),
+        ColorSpace::CMYK => pixels.for_each(|(p, o)| {
+            *o = [
+                mul_255(p[0], p[3]),
+                mul_255(p[1], p[3]),
+                mul_255(p[2], p[3]),
+                255,
+            ];
+        }),
+        ColorSpace::YCCK => pixels.for_each(|(p, o)| {
+            let [r, g, b] = ycbcr_to_rgb(p[0], p[1], p[2]);
+            *o = [
+                mul_255(255 - r, p[3]),
+                mul_255(255 - g, p[3]),
+                mul_255(255 - b, p[3]),
+                255,
+            ];
+        }),
+        _ => unreachable!(),
+    }
+}
+
+/// Convert RGBA pixels to `to`, with only their first component used
+/// for luma when `luma_only` is set
+fn from_rgba(to: ColorSpace, rgba: &[[u8; 4]], output: &mut [u8], luma_only: bool) {
+    let pixels = rgba
+        .iter()
+        .zip(output.chunks_exact_mut(to.num_components()));
+
+    match to {
+        ColorSpace::RGB => pixels.for_each(|(p, o)| o.copy_from_slice(&p[..3])),
+        ColorSpace::RGBA => pixels.for_each(|(p, o)| o.copy_from_slice(p)),
+        ColorSpace::BGR => pixels.for_each(|(p, o)| o.copy_from_slice(&[p[2], p[1], p[0]])),
+        ColorSpace::BGRA => pixels.for_each(|(p, o)| o.copy_from_slice(&[p[2], p[1], p[0], p[3]])),
+        ColorSpace::Luma if luma_only => pixels.for_each(|(p, o)| o[0] = p[0]),
+        ColorSpace::Luma => pixels.for_each(|(p, o)| o[0] = luma(p[0], p[1], p[2])),
+        ColorSpace::LumaA if luma_only => {
+            pixels.for_each(|(p, o)| o.copy_from_slice(&[p[0], p[3]]));
+        }
+        ColorSpace::LumaA => {
+            pixels.for_each(|(p, o)| o.copy_from_slice(&[luma(p[0], p[1], p[2]), p[3]]));
+        }
+        ColorSpace::YCbCr => {
+            pixels.for_each(|(p, o)| o.copy_from_slice(&rgb_to_ycbcr(p[0], p[1], p[2])));
+        }
+        ColorSpace::CMYK => {
+            pixels.for_each(|(p, o)| o.copy_from_slice(&rgb_to_cmyk(p[0], p[1], p[2])));
+        }
+        ColorSpace::YCCK => pixels.for_each(|(p, o)| {
+            let [c, m, y, k] = rgb_to_cmyk(p[0], p[1], p[2]);
+            let [y, cb, cr] = rgb_to_ycbcr(255 - c, 255 - m, 255 - y);
+            o.copy_from_slice(&[y, cb, cr, k]);
+        }),
+        _ => unreachable!(),
+    }
+}
+
+/// Convert `input` pixels in `from` to `output` pixels in `to`
+///
+/// Any pair of colorspaces except [`ColorSpace::Unknown`] is supported.
+///
+/// # Errors
+/// If a colorspace isn't supported, `input` isn't made of whole pixels
+/// or `output` doesn't have room for exactly as many pixels
+pub fn convert(
+    input: &[u8],
+    from: ColorSpace,
+    output: &mut [u8],
+    to: ColorSpace,
+) -> Result<(), ImageErrors> {
+    for colorspace in [from, to] {
+        if colorspace.num_components() == 0 {
+            return Err(ImageErrors::UnsupportedColorspace(colorspace));
+        }
+    }
+    let (in_components, out_components) = (from.num_components(), to.num_components());
+    let pixels = input.len() / in_components;
+
+    if !input.len().is_multiple_of(in_components) {
+        return Err(ImageErrors::BadLength(
+            (pixels + 1) * in_components,
+            input.len(),
+        ));
+    }
+    if output.len() != pixels * out_components {
+        return Err(ImageErrors::BadLength(
+            pixels * out_components,
+            output.len(),
+        ));
+    }
+    if from == to {
+        output.copy_from_slice(input);
+        return Ok(());
+    }
+    let luma_only = from == ColorSpace::YCbCr && to.is_grayscale();
+    let mut rgba = [[0; 4]; BATCH];
+
+    for (input, output) in input
+        .chunks(BATCH * in_components)
+        .zip(output.chunks_mut(BATCH * out_components))
+    {
+        let rgba = &mut rgba[..input.len() / in_components];
+
+        to_rgba(from, input, rgba, luma_only);
+        from_rgba(to, rgba, output, luma_only);
+    }
+    Ok(())
+}
+
+impl Image {
+    /// Convert the image to `colorspace`, see [`convert`]
+    ///
+    /// # Errors
+    /// If `colorspace` is [`ColorSpace::Unknown`]
+    pub fn convert(&mut self, colorspace: ColorSpace) -> Result<(), ImageErrors> {
+        if colorspace == self.colorspace {
+            return Ok(());
+        }
+        let mut out = vec![0; self.width * self.height * colorspace.num_components()];
+
+        convert(&self.pixels, self.colorspace, &mut out, colorspace)?;
+
+        self.pixels = out;
+        self.colorspace = colorspace;
+
+        Ok(())
+    }
+}
+
+#[test]
+fn convert_colorspaces() {
+    use crate::zune_core::colorspace::ALL_COLORSPACES;
+
+    let rgb: Vec<u8> = (0..200_u32)
+        .flat_map(|i| [(i * 37) as u8, (i * 11 + 40) as u8, (i * 5) as u8])
+        .collect();
+    let gray: Vec<u8> = (0..=255).collect();
+
+    for from in ALL_COLORSPACES {
+        for to in ALL_COLORSPACES {
+            // gray survives any round trip
+            let mut image = Image::new(gray.clone(), 256, 1, ColorSpace::Luma).unwrap();
+            image.convert(from).unwrap();
+            image.convert(to).unwrap();
+            assert_eq!(image.pixels.len(), 256 * to.num_components());
+            image.convert(ColorSpace::Luma).unwrap();
+            assert_eq!(image.pixels, gray, "{from:?} -> {to:?}");
+        }
+        if from.is_grayscale() {
+            continue;
+        }
+        // and colors come back close
+        let mut image = Image::new(rgb.clone(), 200, 1, ColorSpace::RGB).unwrap();
+        image.convert(from).unwrap();
+        image.convert(ColorSpace::RGB).unwrap();
+
+        let error = image
+            .pixels
+            .iter()
+            .zip(&rgb)
+            .map(|(a, b)| a.abs_diff(*b))
+            .max()
+            .unwrap();
+        let expected = match from {
+            ColorSpace::YCbCr | ColorSpace::YCCK | ColorSpace::CMYK => 2,
+            _ => 0,
+        };
+        assert!(error <= expected, "{from:?} is off by {error}");
+    }
+
+    let mut out = [0; 6];
+    convert(
+        &[1, 2, 3, 4, 5, 6, 7, 8],
+        ColorSpace::RGBA,
+        &mut out,
+        ColorSpace::BGR,
+    )
+    .unwrap();
+    assert_eq!(out, [3, 2, 1, 7, 6, 5]);
+
+    let mut out = [0; 4];
+    let primaries = [255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255];
+    convert(&primaries, ColorSpace::RGB, &mut out, ColorSpace::Luma).unwrap();
+    assert_eq!(out, [76, 150, 29, 255]);
+
+    let mut out = [0; 3];
+    assert!(convert(&[0; 4], ColorSpace::RGB, &mut out, ColorSpace::Luma).is_err());
+    assert!(convert(&[0; 3], ColorSpace::RGB, &mut out, ColorSpace::Unknown).is_err());
+}
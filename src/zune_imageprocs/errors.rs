@@ -0,0 +1,56 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+//! Errors possible when processing images
+
+use core::fmt::{Debug, Display, Formatter};
+
+use crate::zune_core::colorspace::ColorSpace;
+
+/// Errors possible when processing images
+pub enum ImageErrors {
+    /// Width and height, one of them is zero or their product overflows
+    BadDimensions(usize, usize),
+    /// Expected and found length of the pixels
+    BadLength(usize, usize),
+    /// The colorspace isn't supported
+    UnsupportedColorspace(ColorSpace),
+    /// The crop rectangle, x, y, width and height, isn't inside the image
+    /// or is empty
+    BadCrop(usize, usize, usize, usize),
+}
+
+impl Debug for ImageErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::BadDimensions(width, height) => {
+                write!(f, "Bad image dimensions {width}x{height}")
+            }
+            Self::BadLength(expected, found) => {
+                write!(f, "Expected {expected} bytes of pixels but found {found}")
+            }
+            Self::UnsupportedColorspace(colorspace) => {
+                write!(f, "Unsupported colorspace {colorspace:?}")
+            }
+            Self::BadCrop(x, y, width, height) => {
+                write!(
+                    f,
+                    "Crop of {width}x{height} pixels at ({x}, {y}) is outside the image"
+                )
+            }
+        }
+    }
+}
+
+impl Display for ImageErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl std::error::Error for ImageErrors {}
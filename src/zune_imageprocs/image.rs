@@ -0,0 +1,93 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+use crate::zune_core::colorspace::ColorSpace;
+
+use super::errors::ImageErrors;
+
+/// A decoded image, 8 bit interleaved pixels row by row
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub(crate) pixels: Vec<u8>,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) colorspace: ColorSpace,
+}
+
+/// Number of bytes of a `width` by `height` image in `colorspace`
+pub(crate) fn image_size(
+    width: usize,
+    height: usize,
+    colorspace: ColorSpace,
+) -> Result<usize, ImageErrors> {
+    if colorspace == ColorSpace::Unknown {
+        return Err(ImageErrors::UnsupportedColorspace(colorspace));
+    }
+    if width == 0 || height == 0 {
+        return Err(ImageErrors::BadDimensions(width, height));
+    }
+    width
+        .checked_mul(height)
+        .and_then(|x| x.checked_mul(colorspace.num_components()))
+        .ok_or(ImageErrors::BadDimensions(width, height))
+}
+
+impl Image {
+    /// Create an image from its pixels
+    ///
+    /// # Errors
+    /// If the colorspace is unknown, the dimensions are zero or the pixels
+    /// aren't `width * height * colorspace.num_components()` bytes
+    pub fn new(
+        pixels: Vec<u8>,
+        width: usize,
+        height: usize,
+        colorspace: ColorSpace,
+    ) -> Result<Image, ImageErrors> {
+        let size = image_size(width, height, colorspace)?;
+
+        if pixels.len() != size {
+            return Err(ImageErrors::BadLength(size, pixels.len()));
+        }
+        Ok(Image {
+            pixels,
+            width,
+            height,
+            colorspace,
+        })
+    }
+
+    /// Return the pixels
+    #[must_use]
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Return the width and height
+    #[must_use]
+    pub const fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Return the colorspace
+    #[must_use]
+    pub const fn colorspace(&self) -> ColorSpace {
+        self.colorspace
+    }
+
+    /// Return the pixels, width, height and colorspace
+    #[must_use]
+    pub fn into_parts(self) -> (Vec<u8>, usize, usize, ColorSpace) {
+        (self.pixels, self.width, self.height, self.colorspace)
+    }
+
+    /// Number of bytes per pixel
+    pub(crate) const fn components(&self) -> usize {
+        self.colorspace.num_components()
+    }
+}
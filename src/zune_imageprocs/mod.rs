@@ -0,0 +1,64 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+//! Processing of decoded images
+//!
+//! This works on [`Image`]s, 8 bit interleaved pixels in any colorspace of
+//! [`ALL_COLORSPACES`](crate::zune_core::colorspace::ALL_COLORSPACES), as
+//! returned by the decoders.
+//!
+//! - Resizing with nearest neighbour, bilinear or area average resampling
+//! - Cropping
+//! - Rotating by 90, 180 and 270 degrees and flipping
+//! - Converting between colorspaces
+//!
+//! Everything is done in fixed point, so it stays fast on chips without a
+//! fast FPU, and operations that don't change the size of the image work in
+//! place.
+//!
+//! # Example
+//! ```no_run
+//! use zune_core::colorspace::ColorSpace;
+//! use zune_imageprocs::{Image, ResizeMethod, Rotation};
+//!
+//! let pixels = vec![0; 320 * 240 * 3];
+//! let mut image = Image::new(pixels, 320, 240, ColorSpace::RGB).unwrap();
+//!
+//! image.rotate(Rotation::Rotate90);
+//! image.resize(120, 160, ResizeMethod::Area).unwrap();
+//! image.convert(ColorSpace::Luma).unwrap();
+//!
+//! let (pixels, width, height, colorspace) = image.into_parts();
+//! ```
+#![warn(
+    clippy::correctness,
+    clippy::perf,
+    clippy::pedantic,
+    clippy::inline_always,
+    clippy::missing_errors_doc,
+    clippy::panic
+)]
+#![allow(
+    clippy::inline_always,
+    clippy::similar_names,
+    clippy::doc_markdown,
+    clippy::module_name_repetitions,
+    clippy::missing_panics_doc
+)]
+
+pub use convert::convert;
+pub use errors::ImageErrors;
+pub use image::Image;
+pub use resize::ResizeMethod;
+pub use transform::{Flip, Rotation};
+
+mod convert;
+mod errors;
+mod image;
+mod resize;
+mod transform;
@@ -0,0 +1,282 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+//! Resampling
+//!
+//! Bilinear and area resampling share a separable filter: every output
+//! column is a weighted sum of a few input columns, then every output row
+//! of a few intermediate rows. Weights are 14 bit fixed point summing to
+//! one, and the intermediate rows keep 6 fractional bits in 16 bit values.
+#![allow(clippy::cast_possible_truncation)]
+
+use super::errors::ImageErrors;
+use super::image::{image_size, Image};
+
+/// How pixels are computed when resizing
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResizeMethod {
+    /// The pixel closest to the center of the output pixel, the fastest
+    Nearest,
+    /// Interpolate the four pixels around the center of the output pixel,
+    /// smooth when enlarging but aliases when shrinking more than 2x
+    Bilinear,
+    /// Average the pixels covered by the output pixel, weighted by how
+    /// much of them it covers. The best for shrinking
+    Area,
+}
+
+/// Fractional bits of filter weights
+const WEIGHT_BITS: u32 = 14;
+
+/// Fractional bits kept by the horizontal pass
+const INTERMEDIATE_BITS: u32 = 6;
+
+/// Weights of the input pixels making every output pixel along an axis
+struct Filter {
+    /// Taps of output pixel `i` are `taps[offsets[i]..offsets[i + 1]]`
+    offsets: Vec<usize>,
+    /// Input pixel and its weight
+    taps: Vec<(usize, u32)>,
+}
+
+impl Filter {
+    fn new(input: usize, output: usize, method: ResizeMethod) -> Filter {
+        let mut filter = Filter {
+            offsets: Vec::with_capacity(output + 1),
+            taps: Vec::new(),
+        };
+        let (input, output) = (input as u64, output as u64);
+        let mut weights = Vec::new();
+
+        filter.offsets.push(0);
+
+        for i in 0..output {
+            weights.clear();
+
+            match method {
+                ResizeMethod::Nearest => unreachable!(),
+                ResizeMethod::Bilinear => {
+                    // center of the output pixel in input pixels, in 1/256ths
+                    let center = (((2 * i + 1) * input * 256) / (2 * output)).saturating_sub(128);
+                    let (pixel, fraction) = (center >> 8, center & 255);
+
+                    if pixel + 1 >= input {
+                        weights.push((input - 1, 1));
+                    } else {
+                        weights.push((pixel, 256 - fraction));
+                        weights.push((pixel + 1, fraction));
+                    }
+                }
+                ResizeMethod::Area => {
+                    // in units of 1/(input * output), input pixel j covers
+                    // j*output..(j+1)*output and output pixel i covers
+                    // i*input..(i+1)*input
+                    let (start, end) = (i * input, (i + 1) * input);
+
+                    for j in start / output..=(end - 1) / output {
+                        let covered = end.min((j + 1) * output) - start.max(j * output);
+                        weights.push((j, covered));
+                    }
+                }
+            }
+            let total: u64 = weights.iter().map(|(_, w)| w).sum();
+            let first = filter.taps.len();
+
+            for &(pixel, weight) in &weights {
+                let weight = ((weight << WEIGHT_BITS) + total / 2) / total;
+
+                if weight != 0 {
+                    filter.taps.push((pixel as usize, weight as u32));
+                }
+            }
+            // put rounding errors on the heaviest tap, so weights sum to one
+            let taps = &mut filter.taps[first..];
+            let sum: u32 = taps.iter().map(|(_, w)| w).sum();
+            let heaviest = taps
+                .iter_mut()
+                .max_by_key(|(_, w)| *w)
+                .expect("No taps for output pixel");
+            heaviest.1 = heaviest.1 + (1 << WEIGHT_BITS) - sum;
+
+            filter.offsets.push(filter.taps.len());
+        }
+        filter
+    }
+
+    fn taps(&self, output: usize) -> &[(usize, u32)] {
+        &self.taps[self.offsets[output]..self.offsets[output + 1]]
+    }
+}
+
+/// Resize with the nearest pixels, taking the center of output pixels
+fn resize_nearest(image: &Image, width: usize, height: usize, out: &mut [u8]) {
+    let components = image.components();
+    let columns: Vec<usize> = (0..width)
+        .map(|x| (2 * x + 1) * image.width / (2 * width) * components)
+        .collect();
+    let in_stride = image.width * components;
+
+    for (y, out_row) in out.chunks_exact_mut(width * components).enumerate() {
+        let row_y = (2 * y + 1) * image.height / (2 * height);
+        let row = &image.pixels[row_y * in_stride..(row_y + 1) * in_stride];
+
+        for (pixel, column) in out_row.chunks_exact_mut(components).zip(&columns) {
+            pixel.copy_from_slice(&row[*column..*column + components]);
+        }
+    }
+}
+
+/// Resize with a separable filter, horizontally then vertically
+fn resize_filtered(
+    image: &Image,
+    width: usize,
+    height: usize,
+    method: ResizeMethod,
+    out: &mut [u8],
+) {
+    let components = image.components();
+    let horizontal = Filter::new(image.width, width, method);
+    let vertical = Filter::new(image.height, height, method);
+    let out_stride = width * components;
+
+    let mut intermediate = vec![0_u16; out_stride * image.height];
+
+    for (row, inter_row) in image
+        .pixels
+        .chunks_exact(image.width * components)
+        .zip(intermediate.chunks_exact_mut(out_stride))
+    {
+        for (x, pixel) in inter_row.chunks_exact_mut(components).enumerate() {
+            let taps = horizontal.taps(x);
+
+            for (c, value) in pixel.iter_mut().enumerate() {
+                let sum: u32 = taps
+                    .iter()
+                    .map(|(column, weight)| u32::from(row[column * components + c]) * weight)
+                    .sum();
+                let shift = WEIGHT_BITS - INTERMEDIATE_BITS;
+
+                *value = ((sum + (1 << (shift - 1))) >> shift) as u16;
+            }
+        }
+    }
+    let mut sums = vec![0_u32; out_stride];
+
+    for (y, out_row) in out.chunks_exact_mut(out_stride).enumerate() {
+        sums.fill(0);
+
+        for (row, weight) in vertical.taps(y) {
+            let inter_row = &intermediate[row * out_stride..(row + 1) * out_stride];
+
+            for (sum, value) in sums.iter_mut().zip(inter_row) {
+                *sum += u32::from(*value) * weight;
+            }
+        }
+        let shift = WEIGHT_BITS + INTERMEDIATE_BITS;
+
+        for (value, sum) in out_row.iter_mut().zip(&sums) {
+            *value = ((sum + (1 << (shift - 1))) >> shift).min(255) as u8;
+        }
+    }
+}
+
+impl Image {
+    /// Resize the image to `width` by `height` pixels
+    ///
+    /// Channels are resampled independently, alpha isn't premultiplied.
+    ///
+    /// # Errors
+    /// If the new dimensions are zero
+    pub fn resize(
+        &mut self,
+        width: usize,
+        height: usize,
+        method: ResizeMethod,
+    ) -> Result<(), ImageErrors> {
+        let size = image_size(width, height, self.colorspace)?;
+
+        if (width, height) == (self.width, self.height) {
+            return Ok(());
+        }
+        let mut out = vec![0; size];
+
+        match method {
+            ResizeMethod::Nearest => resize_nearest(self, width, height, &mut out),
+            _ => resize_filtered(self, width, height, method, &mut out),
+        }
+        self.pixels = out;
+        self.width = width;
+        self.height = height;
+
+        Ok(())
+    }
+}
+
+#[test]
+fn resize_methods() {
+    use crate::zune_core::colorspace::ColorSpace;
+
+    let (width, height) = (12, 8);
+    let pixels: Vec<u8> = (0..height)
+        .flat_map(|y| (0..width).flat_map(move |x| [(x * 20) as u8, (y * 30) as u8, 200]))
+        .collect();
+    let image = Image::new(pixels, width, height, ColorSpace::RGB).unwrap();
+
+    let resized = |w, h, method| {
+        let mut image = image.clone();
+        image.resize(w, h, method).unwrap();
+        assert_eq!(image.dimensions(), (w, h));
+        image.pixels
+    };
+
+    for method in [
+        ResizeMethod::Nearest,
+        ResizeMethod::Bilinear,
+        ResizeMethod::Area,
+    ] {
+        // a constant channel stays constant
+        for size in [(1, 1), (5, 3), (12, 8), (30, 17)] {
+            assert!(resized(size.0, size.1, method)
+                .chunks_exact(3)
+                .all(|p| p[2] == 200));
+        }
+    }
+    // enlarging by an integer factor replicates pixels, so shrinking back
+    // gives the image back
+    for method in [ResizeMethod::Nearest, ResizeMethod::Area] {
+        let mut enlarged = image.clone();
+        enlarged.resize(36, 16, method).unwrap();
+        enlarged.resize(12, 8, ResizeMethod::Area).unwrap();
+        assert_eq!(enlarged, image, "{method:?}");
+    }
+
+    // halving averages 2x2 blocks, that's also bilinear at the center of
+    // the block
+    for method in [ResizeMethod::Bilinear, ResizeMethod::Area] {
+        let half = resized(6, 4, method);
+
+        for y in 0..4 {
+            for x in 0..6 {
+                let pixel = &half[(y * 6 + x) * 3..];
+                assert_eq!(pixel[0], (x * 40 + 10) as u8);
+                assert_eq!(pixel[1], (y * 60 + 15) as u8);
+            }
+        }
+    }
+    // nearest takes the pixels whose centers are closest
+    let third = resized(4, 2, ResizeMethod::Nearest);
+    assert_eq!(&third[..3], &[20, 60, 200]);
+
+    // bilinear is linear between pixel centers
+    let mut ramp = Image::new(vec![0, 100], 2, 1, ColorSpace::Luma).unwrap();
+    ramp.resize(8, 1, ResizeMethod::Bilinear).unwrap();
+    assert_eq!(ramp.pixels, [0, 0, 13, 38, 63, 88, 100, 100]);
+
+    let mut image = image.clone();
+    assert!(image.resize(0, 5, ResizeMethod::Area).is_err());
+}
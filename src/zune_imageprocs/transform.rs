@@ -0,0 +1,225 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+//! Cropping, rotating and flipping
+
+use super::errors::ImageErrors;
+use super::image::Image;
+
+/// Clockwise rotation
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Rotation {
+    /// Quarter turn clockwise
+    Rotate90,
+    /// Half turn
+    Rotate180,
+    /// Quarter turn counter clockwise
+    Rotate270,
+}
+
+/// Mirroring
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Flip {
+    /// Swap left and right
+    Horizontal,
+    /// Swap top and bottom
+    Vertical,
+}
+
+impl Image {
+    /// Keep the `width` by `height` pixels at `x`, `y`
+    ///
+    /// This works in place.
+    ///
+    /// # Errors
+    /// If the rectangle is empty or not inside the image
+    pub fn crop(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<(), ImageErrors> {
+        if width == 0
+            || height == 0
+            || x.saturating_add(width) > self.width
+            || y.saturating_add(height) > self.height
+        {
+            return Err(ImageErrors::BadCrop(x, y, width, height));
+        }
+        let components = self.components();
+        let (in_stride, out_stride) = (self.width * components, width * components);
+
+        // rows only move towards the start
+        for row in 0..height {
+            let start = (y + row) * in_stride + x * components;
+
+            self.pixels
+                .copy_within(start..start + out_stride, row * out_stride);
+        }
+        self.pixels.truncate(out_stride * height);
+        self.width = width;
+        self.height = height;
+
+        Ok(())
+    }
+
+    /// Rotate the image clockwise
+    ///
+    /// Half turns work in place.
+    pub fn rotate(&mut self, rotation: Rotation) {
+        let components = self.components();
+        let (width, height) = (self.width, self.height);
+
+        if rotation == Rotation::Rotate180 {
+            // reversing the bytes also reverses the components of pixels
+            self.pixels.reverse();
+
+            if components > 1 {
+                for pixel in self.pixels.chunks_exact_mut(components) {
+                    pixel.reverse();
+                }
+            }
+            return;
+        }
+        let mut out = vec![0; self.pixels.len()];
+
+        // output rows are input columns, going up for a quarter turn
+        // clockwise and down otherwise
+        for (out_y, out_row) in out.chunks_exact_mut(height * components).enumerate() {
+            let column = if rotation == Rotation::Rotate90 {
+                out_y
+            } else {
+                width - 1 - out_y
+            };
+            for (out_x, pixel) in out_row.chunks_exact_mut(components).enumerate() {
+                let row = if rotation == Rotation::Rotate90 {
+                    height - 1 - out_x
+                } else {
+                    out_x
+                };
+                let start = (row * width + column) * components;
+
+                pixel.copy_from_slice(&self.pixels[start..start + components]);
+            }
+        }
+        self.pixels = out;
+        self.width = height;
+        self.height = width;
+    }
+
+    /// Mirror the image, in place
+    pub fn flip(&mut self, flip: Flip) {
+        let components = self.components();
+        let stride = self.width * components;
+
+        match flip {
+            Flip::Horizontal => {
+                let half = self.width / 2 * components;
+
+                for row in self.pixels.chunks_exact_mut(stride) {
+                    let (left, right) = row.split_at_mut(half);
+                    // for odd widths the middle pixel stays
+                    let right = &mut right[(self.width % 2) * components..];
+
+                    for (a, b) in left
+                        .chunks_exact_mut(components)
+                        .zip(right.chunks_exact_mut(components).rev())
+                    {
+                        a.swap_with_slice(b);
+                    }
+                }
+            }
+            Flip::Vertical => {
+                let (top, bottom) = self.pixels.split_at_mut(stride * (self.height / 2));
+                let bottom = &mut bottom[stride * (self.height % 2)..];
+
+                for (a, b) in top
+                    .chunks_exact_mut(stride)
+                    .zip(bottom.chunks_exact_mut(stride).rev())
+                {
+                    a.swap_with_slice(b);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn crop_rotate_flip() {
+    use crate::zune_core::colorspace::ColorSpace;
+
+    // 3x2 image with two components per pixel
+    let image = Image::new(
+        vec![1, 10, 2, 20, 3, 30, 4, 40, 5, 50, 6, 60],
+        3,
+        2,
+        ColorSpace::LumaA,
+    )
+    .unwrap();
+    let first = |image: &Image| -> Vec<u8> { image.pixels.iter().step_by(2).copied().collect() };
+
+    let mut rotated = image.clone();
+    rotated.rotate(Rotation::Rotate90);
+    assert_eq!(rotated.dimensions(), (2, 3));
+    assert_eq!(first(&rotated), [4, 1, 5, 2, 6, 3]);
+    assert_eq!(rotated.pixels[..4], [4, 40, 1, 10]);
+
+    let mut rotated = image.clone();
+    rotated.rotate(Rotation::Rotate270);
+    assert_eq!(first(&rotated), [3, 6, 2, 5, 1, 4]);
+
+    let mut rotated = image.clone();
+    rotated.rotate(Rotation::Rotate180);
+    assert_eq!(first(&rotated), [6, 5, 4, 3, 2, 1]);
+    assert_eq!(rotated.pixels[..2], [6, 60]);
+
+    // four quarter turns, or two half turns, do nothing
+    for rotation in [Rotation::Rotate90, Rotation::Rotate180, Rotation::Rotate270] {
+        let mut rotated = image.clone();
+        let turns = if rotation == Rotation::Rotate180 {
+            2
+        } else {
+            4
+        };
+
+        for _ in 0..turns {
+            rotated.rotate(rotation);
+        }
+        assert_eq!(rotated, image);
+    }
+
+    let mut flipped = image.clone();
+    flipped.flip(Flip::Horizontal);
+    assert_eq!(first(&flipped), [3, 2, 1, 6, 5, 4]);
+    assert_eq!(flipped.pixels[..2], [3, 30]);
+
+    let mut flipped = image.clone();
+    flipped.flip(Flip::Vertical);
+    assert_eq!(first(&flipped), [4, 5, 6, 1, 2, 3]);
+
+    // both flips are a half turn
+    flipped.flip(Flip::Horizontal);
+    let mut rotated = image.clone();
+    rotated.rotate(Rotation::Rotate180);
+    assert_eq!(flipped, rotated);
+
+    let mut cropped = image.clone();
+    cropped.crop(1, 0, 2, 2).unwrap();
+    assert_eq!(cropped.dimensions(), (2, 2));
+    assert_eq!(first(&cropped), [2, 3, 5, 6]);
+
+    let mut cropped = image.clone();
+    cropped.crop(0, 1, 3, 1).unwrap();
+    assert_eq!(first(&cropped), [4, 5, 6]);
+
+    let mut cropped = image.clone();
+    assert!(cropped.crop(2, 0, 2, 1).is_err());
+    assert!(cropped.crop(0, 0, 0, 1).is_err());
+    assert_eq!(cropped, image);
+}
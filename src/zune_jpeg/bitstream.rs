@@ -12,7 +12,8 @@
     clippy::inline_always,
     clippy::doc_markdown,
     clippy::cast_sign_loss,
-    clippy::cast_possible_truncation
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap
 )]
 
 //! This file exposes a single struct that can decode a huffman encoded
@@ -33,7 +34,8 @@
 //!
 //! Speed-wise: It is probably the fastest JPEG BitStream decoder to ever sail the seven seas because of
 //! a couple of optimization tricks.
-//! 1. Fast refills from libjpeg-turbo
+//! 1. Fast refills from libjpeg-turbo, into a 64 bit accumulator, or a 32 bit one on targets
+//!    where 64 bit shifts are expensive (see [`BitAccumulator`]).
 //! 2. As few as possible branches in decoder fast paths.
 //! 3. Accelerated AC table decoding borrowed from stb_image.h written by Fabian Gissen (@ rygorous),
 //! improved by me to handle more cases.
@@ -54,6 +56,7 @@ use super::mcu::DCT_BLOCK;
 use super::misc::UN_ZIGZAG;
 use crate::zune_core::bytestream::{ZByteReader, ZReaderTrait};
 use std::cmp::min;
+use std::ops::{BitOr, Shl, Shr};
 
 macro_rules! decode_huff {
     ($stream:tt,$symbol:tt,$table:tt) => {
@@ -98,16 +101,106 @@ macro_rules! decode_huff {
     };
 }
 
+/// An integer a [`BitStream`] accumulates bits in
+///
+/// Refills add `REFILL_BYTES` bytes once fewer than `REFILL_BELOW` bits are
+/// left, so unless a marker stops it, a refill leaves at least `REFILL_BELOW`
+/// bits to decode.
+pub(crate) trait BitAccumulator:
+    Copy + Shl<u8, Output = Self> + Shr<u8, Output = Self> + BitOr<Output = Self> + From<u8>
+{
+    /// Width of the accumulator in bits
+    const BITS: u8;
+    /// Number of bytes a refill adds
+    const REFILL_BYTES: u8;
+    /// Refill when fewer bits than this are left
+    const REFILL_BELOW: u8 = Self::BITS - 8 * Self::REFILL_BYTES;
+    /// An empty accumulator
+    const ZERO: Self;
+
+    /// Read `REFILL_BYTES` bytes at once if none of them is 0xFF, which may
+    /// start a marker or be followed by a stuffed zero
+    fn read_bytes<T: ZReaderTrait>(reader: &mut ZByteReader<T>) -> Option<Self>;
+
+    /// The lowest 32 bits
+    fn low_bits(self) -> u32;
+}
+
+impl BitAccumulator for u64 {
+    const BITS: u8 = 64;
+    const REFILL_BYTES: u8 = 4;
+    const ZERO: u64 = 0;
+
+    #[inline(always)]
+    fn read_bytes<T: ZReaderTrait>(reader: &mut ZByteReader<T>) -> Option<u64> {
+        if reader.has(4) {
+            let msb_buf = reader.get_u32_be();
+
+            if !has_byte(msb_buf, 255) {
+                return Some(u64::from(msb_buf));
+            }
+            // not there, rewind the read
+            reader.rewind(4);
+        }
+        None
+    }
+
+    #[inline(always)]
+    fn low_bits(self) -> u32 {
+        self as u32
+    }
+}
+
+impl BitAccumulator for u32 {
+    const BITS: u8 = 32;
+    const REFILL_BYTES: u8 = 2;
+    const ZERO: u32 = 0;
+
+    #[inline(always)]
+    fn read_bytes<T: ZReaderTrait>(reader: &mut ZByteReader<T>) -> Option<u32> {
+        if reader.has(2) {
+            let msb_buf = reader.get_u16_be();
+
+            if msb_buf >> 8 != 0xFF && msb_buf & 0xFF != 0xFF {
+                return Some(u32::from(msb_buf));
+            }
+            reader.rewind(2);
+        }
+        None
+    }
+
+    #[inline(always)]
+    fn low_bits(self) -> u32 {
+        self
+    }
+}
+
+// Huffman codes are up to 16 bits, a refill of any accumulator has to leave
+// enough bits to decode one, which covers peeking HUFF_LOOKAHEAD bits too
+const _: () = assert!(<u32 as BitAccumulator>::REFILL_BELOW >= 16 && HUFF_LOOKAHEAD <= 16);
+
+/// Accumulator of the [`BitStream`] used to decode images
+///
+/// 64 bit shifts are several instructions on 32 bit targets like Xtensa, so
+/// these accumulate 32 bits, refilled two bytes at a time.
+#[cfg(target_pointer_width = "64")]
+pub(crate) type Accumulator = u64;
+#[cfg(not(target_pointer_width = "64"))]
+pub(crate) type Accumulator = u32;
+
 /// A `BitStream` struct, a bit by bit reader with super powers
 ///
-pub(crate) struct BitStream {
+pub(crate) struct BitStream<A: BitAccumulator = Accumulator> {
     /// A MSB type buffer that is used for some certain operations
-    pub buffer: u64,
+    pub buffer: A,
     /// A TOP  aligned MSB type buffer that is used to accelerate some operations like
     /// peek_bits and get_bits.
     ///
-    /// By top aligned, I mean the top bit (63) represents the top bit in the buffer.
-    aligned_buffer: u64,
+    /// By top aligned, I mean the top bit (`A::BITS - 1`) represents the top bit in the buffer.
+    ///
+    /// Bits below the ones left are zero, they are what is decoded once a marker
+    /// ends the data.
+    aligned_buffer: A,
     /// Tell us the bits left the two buffer
     pub(crate) bits_left: u8,
     /// Did we find a marker(RST/EOF) during decoding?
@@ -120,14 +213,17 @@ pub(crate) struct BitStream {
     spec_end: u8,
     pub eob_run: i32,
     pub overread_by: usize,
+    /// Number of decoded coefficients outside the range allowed for 8 bit
+    /// images, or past the end of a block
+    pub(crate) out_of_range: usize,
 }
 
-impl BitStream {
+impl<A: BitAccumulator> BitStream<A> {
     /// Create a new BitStream
-    pub(crate) const fn new() -> BitStream {
+    pub(crate) const fn new() -> BitStream<A> {
         BitStream {
-            buffer: 0,
-            aligned_buffer: 0,
+            buffer: A::ZERO,
+            aligned_buffer: A::ZERO,
             bits_left: 0,
             marker: None,
             successive_high: 0,
@@ -136,15 +232,16 @@ impl BitStream {
             spec_end: 0,
             eob_run: 0,
             overread_by: 0,
+            out_of_range: 0,
         }
     }
 
     /// Create a new Bitstream for progressive decoding
     #[allow(clippy::redundant_field_names)]
-    pub(crate) fn new_progressive(ah: u8, al: u8, spec_start: u8, spec_end: u8) -> BitStream {
+    pub(crate) fn new_progressive(ah: u8, al: u8, spec_start: u8, spec_end: u8) -> BitStream<A> {
         BitStream {
-            buffer: 0,
-            aligned_buffer: 0,
+            buffer: A::ZERO,
+            aligned_buffer: A::ZERO,
             bits_left: 0,
             marker: None,
             successive_high: ah,
@@ -153,16 +250,17 @@ impl BitStream {
             spec_end: spec_end,
             eob_run: 0,
             overread_by: 0,
+            out_of_range: 0,
         }
     }
 
-    /// Refill the bit buffer by (a maximum of) 32 bits
+    /// Refill the bit buffer by (a maximum of) `A::REFILL_BYTES` bytes
     ///
     /// # Arguments
     ///  - `reader`:`&mut BufReader<R>`: A mutable reference to an underlying
     ///    File/Memory buffer containing a valid JPEG stream
     ///
-    /// This function will only refill if `self.count` is less than 32
+    /// This function will only refill if `self.bits_left` is less than `A::REFILL_BELOW`
     #[inline(always)] // to many call sites? ( perf improvement by 4%)
     fn refill<T>(&mut self, reader: &mut ZByteReader<T>) -> Result<bool, DecodeErrors>
     where
@@ -175,37 +273,37 @@ impl BitStream {
         /// to full refill
         macro_rules! refill {
             ($buffer:expr,$byte:expr,$bits_left:expr) => {
-                // read a byte from the stream
-                $byte = u64::from(reader.get_u8());
+                // read a byte from the stream, zero past its end
                 self.overread_by += usize::from(reader.eof());
+                $byte = reader.get_u8();
                 // append to the buffer
                 // JPEG is a MSB type buffer so that means we append this
                 // to the lower end (0..8) of the buffer and push the rest bits above..
-                $buffer = ($buffer << 8) | $byte;
+                $buffer = ($buffer << 8) | A::from($byte);
                 // Increment bits left
                 $bits_left += 8;
                 // Check for special case  of OxFF, to see if it's a stream or a marker
                 if $byte == 0xff {
                     // read next byte
-                    let mut next_byte = u64::from(reader.get_u8());
+                    let mut next_byte = reader.get_u8();
                     // Byte snuffing, if we encounter byte snuff, we skip the byte
                     if next_byte != 0x00 {
                         // skip that byte we read
                         while next_byte == 0xFF {
-                            next_byte = u64::from(reader.get_u8());
+                            next_byte = reader.get_u8();
                         }
 
                         if next_byte != 0x00 {
                             // Undo the byte append and return
-                            $buffer >>= 8;
+                            $buffer = $buffer >> 8;
                             $bits_left -= 8;
 
                             if $bits_left != 0 {
-                                self.aligned_buffer = $buffer << (64 - $bits_left);
+                                self.aligned_buffer = $buffer << (A::BITS - $bits_left);
                             }
 
                             self.marker =
-                                Some(Marker::from_u8(next_byte as u8).ok_or_else(|| {
+                                Some(Marker::from_u8(next_byte).ok_or_else(|| {
                                     DecodeErrors::Format(format!(
                                         "Unknown marker 0xFF{:X}",
                                         next_byte
@@ -218,39 +316,29 @@ impl BitStream {
             };
         }
 
-        // 32 bits is enough for a decode(16 bits) and receive_extend(max 16 bits)
-        // If we have less than 32 bits we refill
-        if self.bits_left < 32 && self.marker.is_none() {
+        // With a 64 bit accumulator, 32 bits is enough for a decode(16 bits) and
+        // receive_extend(max 16 bits), smaller ones refill again before receive_extend
+        // (see ensure_bits)
+        if self.bits_left < A::REFILL_BELOW && self.marker.is_none() {
             // So before we do anything, check if we have a 0xFF byte
-
-            if reader.has(4) {
-                // we have 4 bytes to spare, read the 4 bytes into a temporary buffer
-                // create buffer
-                let msb_buf = reader.get_u32_be();
-                // check if we have 0xff
-                if !has_byte(msb_buf, 255) {
-                    self.bits_left += 32;
-                    self.buffer <<= 32;
-                    self.buffer |= u64::from(msb_buf);
-                    self.aligned_buffer = self.buffer << (64 - self.bits_left);
-                    return Ok(true);
-                }
-                // not there, rewind the read
-                reader.rewind(4);
+            if let Some(bytes) = A::read_bytes(reader) {
+                self.bits_left += 8 * A::REFILL_BYTES;
+                self.buffer = (self.buffer << (8 * A::REFILL_BYTES)) | bytes;
+                self.aligned_buffer = self.buffer << (A::BITS - self.bits_left);
+                return Ok(true);
             }
             // This serves two reasons,
             // 1: Make clippy shut up
             // 2: Favour register reuse
             let mut byte;
 
-            // 4 refills, if all succeed the stream should contain enough bits to decode a
+            // if all refills succeed the stream should contain enough bits to decode a
             // value
-            refill!(self.buffer, byte, self.bits_left);
-            refill!(self.buffer, byte, self.bits_left);
-            refill!(self.buffer, byte, self.bits_left);
-            refill!(self.buffer, byte, self.bits_left);
+            for _ in 0..A::REFILL_BYTES {
+                refill!(self.buffer, byte, self.bits_left);
+            }
             // Construct an MSB buffer whose top bits are the bitstream we are currently holding.
-            self.aligned_buffer = self.buffer << (64 - self.bits_left);
+            self.aligned_buffer = self.buffer << (A::BITS - self.bits_left);
         }
 
         return Ok(true);
@@ -276,9 +364,9 @@ impl BitStream {
     {
         let (mut symbol, r);
 
-        if self.bits_left < 32 {
+        if self.bits_left < A::REFILL_BELOW {
             self.refill(reader)?;
-        };
+        }
         // look a head HUFF_LOOKAHEAD bits into the bitstream
         symbol = self.peek_bits::<HUFF_LOOKAHEAD>();
         symbol = dc_table.lookup[symbol as usize];
@@ -286,11 +374,14 @@ impl BitStream {
         decode_huff!(self, symbol, dc_table);
 
         if symbol != 0 {
+            self.ensure_bits(reader, symbol as u8)?;
             r = self.get_bits(symbol as u8);
             symbol = huff_extend(r, symbol);
         }
         // Update DC prediction
         *dc_prediction = dc_prediction.wrapping_add(symbol);
+        // 8 bit images have DC coefficients within 11 bits
+        self.out_of_range += usize::from(!(-2047..=2047).contains(dc_prediction));
 
         return Ok(true);
     }
@@ -344,6 +435,7 @@ impl BitStream {
             if fast_ac != 0 {
                 //  FAST AC path
                 pos += ((fast_ac >> 4) & 15) as usize; // run
+                self.out_of_range += usize::from(pos > 63);
                 let t_pos = UN_ZIGZAG[min(pos, 63)] & 63;
 
                 block[t_pos] = i32::from(fast_ac >> 8) * (qt_table[t_pos]); // Value
@@ -357,6 +449,9 @@ impl BitStream {
 
                 if symbol != 0 {
                     pos += r as usize;
+                    // 8 bit images have AC coefficients within 10 bits
+                    self.out_of_range += usize::from(symbol > 10 || pos > 63);
+                    self.ensure_bits(reader, symbol as u8)?;
                     r = self.get_bits(symbol as u8);
                     symbol = huff_extend(r, symbol);
                     let t_pos = UN_ZIGZAG[pos & 63] & 63;
@@ -374,28 +469,47 @@ impl BitStream {
         return Ok(());
     }
 
+    /// Refill if fewer than `n_bits` are left after decoding a Huffman code
+    ///
+    /// A refill of a 64 bit accumulator leaves enough bits for a code and
+    /// the bits following it, so this does nothing for those.
+    #[inline(always)]
+    fn ensure_bits<T>(
+        &mut self,
+        reader: &mut ZByteReader<T>,
+        n_bits: u8,
+    ) -> Result<(), DecodeErrors>
+    where
+        T: ZReaderTrait,
+    {
+        if A::REFILL_BELOW < 32 && self.bits_left < n_bits {
+            self.refill(reader)?;
+        }
+        Ok(())
+    }
+
     /// Peek `look_ahead` bits ahead without discarding them from the buffer
     #[inline(always)]
     #[allow(clippy::cast_possible_truncation)]
-    const fn peek_bits<const LOOKAHEAD: u8>(&self) -> i32 {
-        (self.aligned_buffer >> (64 - LOOKAHEAD)) as i32
+    fn peek_bits<const LOOKAHEAD: u8>(&self) -> i32 {
+        (self.aligned_buffer >> (A::BITS - LOOKAHEAD)).low_bits() as i32
     }
 
     /// Discard the next `N` bits without checking
     #[inline]
     fn drop_bits(&mut self, n: u8) {
         self.bits_left = self.bits_left.saturating_sub(n);
-        self.aligned_buffer <<= n;
+        self.aligned_buffer = self.aligned_buffer << n;
     }
 
     /// Read `n_bits` from the buffer  and discard them
     #[inline(always)]
     #[allow(clippy::cast_possible_truncation)]
     fn get_bits(&mut self, n_bits: u8) -> i32 {
-        let mask = (1_u64 << n_bits) - 1;
+        // shift in two steps so reading 0 bits doesn't shift by the whole width
+        let bits = (self.aligned_buffer >> 1 >> (A::BITS - 1 - n_bits)).low_bits() as i32;
 
-        self.aligned_buffer = self.aligned_buffer.rotate_left(u32::from(n_bits));
-        let bits = (self.aligned_buffer & mask) as i32;
+        self.aligned_buffer = self.aligned_buffer << n_bits;
         self.bits_left = self.bits_left.wrapping_sub(n_bits);
         bits
     }
@@ -440,7 +554,7 @@ impl BitStream {
 
     /// Get a single bit from the bitstream
     fn get_bit(&mut self) -> u8 {
-        let k = (self.aligned_buffer >> 63) as u8;
+        let k = (self.aligned_buffer >> (A::BITS - 1)).low_bits() as u8;
         // discard a bit
         self.drop_bits(1);
         return k;
@@ -482,12 +596,14 @@ impl BitStream {
 
                 if symbol != 0 {
                     k += r as usize;
+                    self.ensure_bits(reader, symbol as u8)?;
                     r = self.get_bits(symbol as u8);
                     symbol = huff_extend(r, symbol);
                     block[UN_ZIGZAG[k & 63] & 63] = (symbol as i16).wrapping_mul(1 << shift);
                     k += 1;
                 } else {
                     if r != 15 {
+                        self.ensure_bits(reader, r as u8)?;
                         self.eob_run = 1 << r;
                         self.eob_run += self.get_bits(r as u8);
                         self.eob_run -= 1;
@@ -535,6 +651,7 @@ impl BitStream {
                 if symbol == 0 {
                     if r != 15 {
                         // EOB run is 2^r + bits
+                        self.ensure_bits(reader, r as u8)?;
                         self.eob_run = 1 << r;
                         self.eob_run += self.get_bits(r as u8);
                         // EOB runs are handled by the eob logic
@@ -547,8 +664,8 @@ impl BitStream {
                         ));
                     }
                     // get sign bit
-                    // We assume we have enough bits, which should be correct for sane images
-                    // since we refill by 32 above
+                    self.ensure_bits(reader, 1)?;
+
                     if self.get_bit() == 1 {
                         symbol = i32::from(bit);
                     } else {
@@ -561,6 +678,8 @@ impl BitStream {
                 // A correction bit is 1 if the absolute value of the coefficient must be increased
 
                 if k <= self.spec_end {
+                    self.ensure_bits(reader, 1)?;
+
                     'advance_nonzero: loop {
                         let coefficient = &mut block[UN_ZIGZAG[k as usize & 63] & 63];
 
@@ -645,6 +764,40 @@ impl BitStream {
         self.spec_end = spec_end;
     }
 
+    /// Return the marker ending the current entropy coded segment if only
+    /// padding bits are left before it, otherwise `None`
+    ///
+    /// Padding is less than a byte, anything more is still entropy coded data.
+    pub(crate) fn segment_end_marker<T>(
+        &mut self,
+        reader: &mut ZByteReader<T>,
+    ) -> Result<Option<Marker>, DecodeErrors>
+    where
+        T: ZReaderTrait,
+    {
+        if self.bits_left < 8 {
+            self.refill(reader)?;
+        }
+        if self.bits_left < 8 {
+            return Ok(self.marker);
+        }
+        Ok(None)
+    }
+
+    /// Number of bytes past the end of the data that were decoded
+    ///
+    /// Zeroes read past the end that are still buffered aren't counted, so
+    /// unlike `overread_by` this doesn't depend on how far refills read ahead.
+    pub(crate) fn overread(&self) -> usize {
+        self.overread_by.saturating_sub(usize::from(self.bits_left / 8))
+    }
+
+    /// Return true if all entropy coded data up to an EOI marker was consumed,
+    /// anything decoded after that is decoded from zeroes
+    pub(crate) fn data_exhausted(&self) -> bool {
+        self.bits_left == 0 && self.eob_run == 0 && self.marker == Some(Marker::EOI)
+    }
+
     /// Reset the stream if we have a restart marker
     ///
     /// Restart markers indicate drop those bits in the stream and zero out
@@ -653,8 +806,8 @@ impl BitStream {
     pub fn reset(&mut self) {
         self.bits_left = 0;
         self.marker = None;
-        self.buffer = 0;
-        self.aligned_buffer = 0;
+        self.buffer = A::ZERO;
+        self.aligned_buffer = A::ZERO;
         self.eob_run = 0;
     }
 }
@@ -677,3 +830,494 @@ fn has_byte(b: u32, val: u8) -> bool {
     // @ https://graphics.stanford.edu/~seander/bithacks.html#ZeroInWord
     has_zero(b ^ ((!0_u32 / 255) * u32::from(val)))
 }
+
+/// A stream and the data it reads, decoding block by block like the decoder
+/// does, to check accumulators decode the same
+#[cfg(test)]
+struct StepDecoder<'a, A: BitAccumulator> {
+    stream: BitStream<A>,
+    reader: ZByteReader<&'a [u8]>,
+    progressive: Option<[u8; 4]>,
+    block: [i32; 64],
+    dc_prediction: i32,
+}
+
+#[cfg(test)]
+impl<'a, A: BitAccumulator> StepDecoder<'a, A> {
+    /// Decode entropy coded data from `start`, baseline data if there are
+    /// no progressive parameters
+    fn new(data: &'a [u8], start: usize, progressive: Option<[u8; 4]>, block: [i32; 64]) -> Self {
+        let mut reader = ZByteReader::new(data);
+        reader.set_position(start);
+
+        let stream = match progressive {
+            Some([ah, al, spec_start, spec_end]) => {
+                BitStream::new_progressive(ah, al, spec_start, spec_end)
+            }
+            None => BitStream::new(),
+        };
+        StepDecoder {
+            stream,
+            reader,
+            progressive,
+            block,
+            dc_prediction: 0,
+        }
+    }
+
+    /// Decode a block with the stream like the decoder does
+    fn decode_block(
+        &mut self,
+        (dc_table, ac_table): (&HuffmanTable, &HuffmanTable),
+    ) -> Result<(), DecodeErrors> {
+        let (stream, reader) = (&mut self.stream, &mut self.reader);
+
+        let Some([ah, _, spec_start, _]) = self.progressive else {
+            self.block = [0; 64];
+            return stream.decode_mcu_block(
+                reader,
+                dc_table,
+                ac_table,
+                &[1; 64],
+                &mut self.block,
+                &mut self.dc_prediction,
+            );
+        };
+        let mut coefficients = self.block.map(|x| x as i16);
+
+        let result = if spec_start == 0 {
+            if ah == 0 {
+                stream.decode_prog_dc_first(
+                    reader,
+                    dc_table,
+                    &mut coefficients[0],
+                    &mut self.dc_prediction,
+                )
+            } else {
+                stream.decode_prog_dc_refine(reader, &mut coefficients[0])
+            }
+        } else if ah == 0 {
+            // EOB runs are handled by the caller, like in mcu_prog.rs
+            if stream.eob_run > 0 {
+                stream.eob_run -= 1;
+                Ok(())
+            } else {
+                stream
+                    .decode_mcu_ac_first(reader, ac_table, &mut coefficients)
+                    .map(drop)
+            }
+        } else {
+            stream
+                .decode_mcu_ac_refine(reader, ac_table, &mut coefficients)
+                .map(drop)
+        };
+        self.block = coefficients.map(i32::from);
+        result
+    }
+
+    /// Decode a block, and describe what was decoded and the state of the
+    /// stream after it, including the bytes decoded past the end
+    fn step(&mut self, tables: (&HuffmanTable, &HuffmanTable)) -> String {
+        let result = self.decode_block(tables);
+        // markers are found at different times, depending on how far refills
+        // read ahead, so check where the segment ends instead
+        let end = self.stream.segment_end_marker(&mut self.reader);
+
+        if let Ok(Some(Marker::RST(_))) = end {
+            self.stream.reset();
+            self.dc_prediction = 0;
+        }
+        format!(
+            "{result:?} {:?} dc {} eob {} out of range {} end {end:?} overread {} {}",
+            self.block,
+            self.dc_prediction,
+            self.stream.eob_run,
+            self.stream.out_of_range,
+            self.stream.overread(),
+            self.stream.data_exhausted()
+        )
+    }
+}
+
+/// Baseline decoding reading a bit at a time, simple enough to check the
+/// accumulators against
+#[cfg(test)]
+struct BitByBit<'a> {
+    data: &'a [u8],
+    /// Position in `data` after the current segment
+    next: usize,
+    /// Data of the current segment, without stuffed bytes
+    segment: Vec<u8>,
+    /// Number of bits of the segment read, bits past its end are zero
+    position: usize,
+    /// Marker ending the segment
+    marker: Option<Marker>,
+    block: [i32; 64],
+    dc_prediction: i32,
+}
+
+#[cfg(test)]
+impl<'a> BitByBit<'a> {
+    fn new(data: &'a [u8], start: usize) -> Self {
+        let mut decoder = BitByBit {
+            data,
+            next: start,
+            segment: vec![],
+            position: 0,
+            marker: None,
+            block: [0; 64],
+            dc_prediction: 0,
+        };
+        decoder.start_segment();
+        decoder
+    }
+
+    /// Read the data up to the next marker, bytes past the end of the data
+    /// are zero
+    fn start_segment(&mut self) {
+        let data = self.data;
+        let mut i = self.next;
+
+        self.segment.clear();
+        self.position = 0;
+        self.marker = None;
+
+        while i < data.len() {
+            let byte = data[i];
+            i += 1;
+
+            if byte == 0xFF {
+                // skip fill bytes
+                while data.get(i) == Some(&0xFF) {
+                    i += 1;
+                }
+                match data.get(i) {
+                    Some(0) | None => i += 1,
+                    Some(&marker) => {
+                        self.marker = Marker::from_u8(marker);
+                        i += 1;
+                        break;
+                    }
+                }
+            }
+            self.segment.push(byte);
+        }
+        self.next = i;
+    }
+
+    fn bits(&mut self, n: i32) -> i32 {
+        (0..n).fold(0, |bits, _| {
+            let byte = self.segment.get(self.position / 8).copied().unwrap_or(0);
+            let bit = (byte >> (7 - self.position % 8)) & 1;
+
+            self.position += 1;
+            (bits << 1) | i32::from(bit)
+        })
+    }
+
+    /// Read `size` bits and extend them to a signed value
+    fn extend(&mut self, size: i32) -> i32 {
+        let bits = self.bits(size);
+
+        if size != 0 && bits < 1 << (size - 1) {
+            bits - (1 << size) + 1
+        } else {
+            bits
+        }
+    }
+
+    /// Decode a Huffman code a bit at a time, like figure F.16 of the spec
+    fn decode(&mut self, table: &HuffmanTable) -> Option<i32> {
+        let mut code = 0;
+
+        for length in 1..=16 {
+            code = (code << 1) | self.bits(1);
+
+            // maxcode is one more than the largest code of each length, shifted to 16 bits
+            if code << (16 - length) < table.maxcode[length] {
+                let index = (code + table.offset[length]) & 0xFF;
+
+                return Some(i32::from(table.values[index as usize]));
+            }
+        }
+        None
+    }
+
+    /// Decode a block and restart after the marker ending its segment, if
+    /// it's a restart marker
+    ///
+    /// Returns `None` if the data can't be decoded, and `Some(false)` if
+    /// coefficients are past the end of the block, where decoders put them
+    /// differently.
+    fn step(&mut self, (dc_table, ac_table): (&HuffmanTable, &HuffmanTable)) -> Option<bool> {
+        self.block = [0; 64];
+
+        let size = self.decode(dc_table)?;
+        self.dc_prediction = self.dc_prediction.wrapping_add(self.extend(size));
+        self.block[0] = self.dc_prediction;
+
+        let mut pos = 1;
+        let mut in_block = true;
+
+        while pos < 64 {
+            let symbol = self.decode(ac_table)?;
+            let (run, size) = ((symbol >> 4) as usize, symbol & 15);
+
+            if size == 0 && run != 15 {
+                break;
+            }
+            let value = self.extend(size);
+            pos += run;
+
+            if size != 0 {
+                in_block &= pos < 64;
+                self.block[UN_ZIGZAG[pos.min(63)]] = value;
+            }
+            pos += 1;
+        }
+        // padding is less than a byte
+        if !self.overran()
+            && self.position + 8 > self.segment.len() * 8
+            && matches!(self.marker, Some(Marker::RST(_)))
+        {
+            self.start_segment();
+            self.dc_prediction = 0;
+        }
+        Some(in_block)
+    }
+
+    /// Return true if bits past the marker ending the segment were read
+    ///
+    /// Accumulators read zeroes past it too, but lose track of where the
+    /// segment ends, so only the block reading them decodes the same.
+    fn overran(&self) -> bool {
+        self.marker.is_some() && self.position > self.segment.len() * 8
+    }
+}
+
+/// Decode `data` from `start` with both accumulators until it ends or can't
+/// be decoded, checking they agree after every block, and agree with
+/// [`BitByBit`] on baseline data until it reads past a marker
+///
+/// Blocks cycle through `tables`, and decoding goes on a bit past the end of
+/// the data.
+#[cfg(test)]
+fn check_accumulators(
+    data: &[u8],
+    start: usize,
+    tables: &[(&HuffmanTable, &HuffmanTable)],
+    progressive: Option<[u8; 4]>,
+    block: [i32; 64],
+) {
+    let mut wide = StepDecoder::<u64>::new(data, start, progressive, block);
+    let mut narrow = StepDecoder::<u32>::new(data, start, progressive, block);
+    let mut reference = progressive.is_none().then(|| BitByBit::new(data, start));
+
+    for (step, tables) in tables.iter().copied().cycle().enumerate() {
+        let expected = wide.step(tables);
+        let found = narrow.step(tables);
+
+        assert_eq!(expected, found, "block {step} of {data:X?}");
+
+        if let Some(decoder) = &mut reference {
+            let decoded = decoder.step(tables);
+
+            assert_eq!(
+                decoded.is_some(),
+                expected.starts_with("Ok"),
+                "block {step} of {data:X?}: {expected}"
+            );
+            if decoded == Some(true) {
+                assert_eq!(decoder.block, wide.block, "block {step} of {data:X?}");
+            }
+            if decoder.overran() {
+                reference = None;
+            } else {
+                assert_eq!(
+                    decoder.dc_prediction, wide.dc_prediction,
+                    "block {step} of {data:X?}"
+                );
+            }
+        }
+        if expected.starts_with("Err") || wide.stream.data_exhausted() || wide.stream.overread() > 8
+        {
+            return;
+        }
+    }
+}
+
+#[test]
+fn accumulators_decode_the_same() {
+    use super::decoder::JpegDecoder;
+    use super::writer::{AC_LUMA_BITS, AC_LUMA_VALUES, DC_LUMA_BITS, DC_VALUES};
+    use super::JpegEncoder;
+    use crate::zune_core::bit_depth::BitDepth;
+    use crate::zune_core::colorspace::ColorSpace;
+    use crate::zune_core::options::EncoderOptions;
+
+    // images, blocks are decoded in MCU order
+    let pixels: Vec<u8> = (0..96 * 64).map(|i| (i * i / 7 % 251) as u8).collect();
+    let mut images = vec![];
+
+    for quality in [5, 50, 100] {
+        let options =
+            EncoderOptions::new(96, 64, ColorSpace::Luma, BitDepth::Eight).set_quality(quality);
+        images.push(JpegEncoder::new(&pixels, options).encode().unwrap());
+    }
+    images.extend([
+        include_bytes!("../../tests/inputs/restart_420.jpg").to_vec(),
+        include_bytes!("../../tests/inputs/restart_422_partial.jpg").to_vec(),
+        include_bytes!("../../tests/inputs/sampling_mixed.jpg").to_vec(),
+    ]);
+    for data in &images {
+        for end in [data.len(), data.len() * 2 / 3] {
+            let data = &data[..end];
+            let mut decoder = JpegDecoder::new(data);
+            decoder.decode_headers().unwrap();
+
+            let mut tables = vec![];
+
+            for component in &decoder.components {
+                let dc_table = decoder.dc_huffman_tables[component.dc_huff_table].as_ref();
+                let ac_table = decoder.ac_huffman_tables[component.ac_huff_table].as_ref();
+                let blocks = component.horizontal_sample * component.vertical_sample;
+
+                tables
+                    .extend(std::iter::repeat((dc_table.unwrap(), ac_table.unwrap())).take(blocks));
+            }
+            check_accumulators(data, decoder.stream.get_position(), &tables, None, [0; 64]);
+        }
+    }
+
+    // random data, with markers and stuffed bytes, baseline and progressive
+    let table = |bits: &[u8; 16], values: &[u8], is_dc| {
+        let mut codes = [0; 17];
+        codes[1..].copy_from_slice(bits);
+        let mut symbols = [0; 256];
+        symbols[..values.len()].copy_from_slice(values);
+
+        HuffmanTable::new(&codes, symbols, is_dc, true).unwrap()
+    };
+    let dc_table = table(&DC_LUMA_BITS, &DC_VALUES, true);
+    let ac_table = table(&AC_LUMA_BITS, &AC_LUMA_VALUES, false);
+
+    let mut state = 0x2545_F491_u32;
+    let mut random = move |n: u32| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state % n
+    };
+    for i in 0..4000 {
+        let mut data = vec![];
+
+        while data.len() < 16 + random(256) as usize {
+            match random(64) {
+                0 => data.extend([0xFF, 0x00]),
+                1 => data.extend([0xFF, 0xD0 + random(8) as u8]),
+                // bytes following 0xFF are markers, keep them to the known ones
+                _ => data.push(random(255) as u8),
+            }
+        }
+        // end with a marker, or not at all
+        match random(3) {
+            0 => data.extend([0xFF, 0xD9]),
+            1 => data.extend([0xFF, 0xFF, 0xD9]),
+            _ => {}
+        }
+        let progressive = (i % 2 == 1).then(|| {
+            let spec_start = random(64) as u8;
+            let spec_end = if spec_start == 0 {
+                0
+            } else {
+                spec_start + random(64 - u32::from(spec_start)) as u8
+            };
+            [random(3) as u8, random(4) as u8, spec_start, spec_end]
+        });
+        // refinements need coefficients to refine
+        let mut block = [0; 64];
+
+        for coefficient in &mut block {
+            if random(3) == 0 {
+                *coefficient = random(64) as i32 - 32;
+            }
+        }
+        check_accumulators(&data, 0, &[(&dc_table, &ac_table)], progressive, block);
+    }
+}
+
+/// Throughput of `BitStream::refill` over each source, rates are logged at
+/// info level
+///
+/// Run with `cargo test --release bench_refill -- --ignored`
+#[test]
+#[ignore = "benchmark"]
+#[allow(clippy::cast_precision_loss)]
+fn bench_refill() {
+    use std::io::Cursor;
+    use std::time::Instant;
+
+    use crate::zune_core::bytestream::{ZChunkedBuffer, ZRingBuffer, ZSeekReader};
+
+    const ROUNDS: usize = 20;
+
+    /// Read all bits of the source a Huffman code at a time, returning
+    /// their sum
+    fn bench<T: ZReaderTrait>(name: &str, source: T, len: usize) -> u64 {
+        let mut reader = ZByteReader::new(source);
+        let mut sum = 0;
+        let start = Instant::now();
+
+        for _ in 0..ROUNDS {
+            let mut stream = BitStream::<Accumulator>::new();
+            reader.set_position(0);
+
+            while stream.overread_by == 0 {
+                stream.refill(&mut reader).unwrap();
+                let n_bits = stream.bits_left.min(16);
+                sum += stream.get_bits(n_bits) as u64;
+            }
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+        log::info!(
+            "{name:>16}: {:>8.1} MiB/s",
+            (len * ROUNDS) as f64 / elapsed / f64::from(1 << 20)
+        );
+        sum
+    }
+
+    // entropy coded data, 0xFF bytes are followed by a stuffed zero
+    let mut data = vec![];
+    let mut state = 0x2545_F491_u32;
+
+    while data.len() < 1 << 20 {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        data.push(state as u8);
+
+        if state as u8 == 0xFF {
+            data.push(0);
+        }
+    }
+    let len = data.len();
+    let mut ring = data[len / 3..].to_vec();
+    ring.extend_from_slice(&data[..len / 3]);
+
+    let sums = [
+        bench("slice", data.as_slice(), len),
+        bench(
+            "chunks 1 KiB",
+            ZChunkedBuffer::new(data.chunks(1024).collect()),
+            len,
+        ),
+        bench(
+            "chunks 64 KiB",
+            ZChunkedBuffer::new(data.chunks(65536).collect()),
+            len,
+        ),
+        bench("ring", ZRingBuffer::new(&ring, len - len / 3, len), len),
+        bench("seek", ZSeekReader::new(Cursor::new(&data)).unwrap(), len),
+    ];
+    assert!(sums.iter().all(|&sum| sum == sums[0]));
+}
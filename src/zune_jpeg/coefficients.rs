@@ -0,0 +1,292 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+//! Access to quantized DCT coefficients
+//!
+//! Coefficients are returned as they are stored in the file, before
+//! dequantization, for every block of every component, including blocks
+//! that pad components to whole MCUs. They can be edited and rendered back
+//! to pixels with the decoder they came from.
+use crate::zune_core::bytestream::ZReaderTrait;
+use crate::zune_core::colorspace::ColorSpace;
+
+use super::decoder::{JpegDecoder, MAX_COMPONENTS};
+use super::errors::DecodeErrors;
+use super::misc::setup_component_params;
+use super::scratch::ScratchBuf;
+
+/// Quantized DCT coefficients of a component
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ComponentCoefficients {
+    /// Component identifier from the frame header
+    pub id: u8,
+    /// Horizontal sampling factor
+    pub horizontal_sample: usize,
+    /// Vertical sampling factor
+    pub vertical_sample: usize,
+    /// Identifier of the quantization table of this component, an index
+    /// into [`JpegCoefficients::quantization_tables`]
+    pub quantization_table: u8,
+    /// Number of blocks in a row
+    pub blocks_wide: usize,
+    /// Number of rows of blocks
+    pub blocks_high: usize,
+    /// Coefficients, 64 per block in natural (not zigzag) order, blocks
+    /// in raster order
+    pub coefficients: Vec<i16>,
+}
+
+impl ComponentCoefficients {
+    /// Coefficients of the block at column `x` and row `y`
+    ///
+    /// # Panics
+    /// If the block is outside the grid
+    #[must_use]
+    pub fn block(&self, x: usize, y: usize) -> &[i16] {
+        assert!(x < self.blocks_wide && y < self.blocks_high);
+        let start = (y * self.blocks_wide + x) * 64;
+
+        &self.coefficients[start..start + 64]
+    }
+    /// Mutable coefficients of the block at column `x` and row `y`
+    ///
+    /// # Panics
+    /// If the block is outside the grid
+    pub fn block_mut(&mut self, x: usize, y: usize) -> &mut [i16] {
+        assert!(x < self.blocks_wide && y < self.blocks_high);
+        let start = (y * self.blocks_wide + x) * 64;
+
+        &mut self.coefficients[start..start + 64]
+    }
+}
+
+/// Quantized DCT coefficients of an image
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JpegCoefficients {
+    /// Image width
+    pub width: usize,
+    /// Image height
+    pub height: usize,
+    /// Colorspace of the components
+    pub colorspace: ColorSpace,
+    /// Quantization tables in natural order, indexed by their identifier
+    pub quantization_tables: [Option<[u16; 64]>; MAX_COMPONENTS],
+    /// Coefficients of every component
+    pub components: Vec<ComponentCoefficients>,
+}
+
+impl<T: ZReaderTrait> JpegDecoder<T> {
+    /// Decode the quantized DCT coefficients of the image, without
+    /// reconstructing pixels
+    ///
+    /// Baseline and progressive images are supported, except baseline images
+    /// whose height is defined by a DNL segment.
+    ///
+    /// # Errors
+    /// If the image can't be decoded, or its coefficients exceed the memory limit
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn decode_coefficients(&mut self) -> Result<JpegCoefficients, DecodeErrors> {
+        self.decode_headers_internal()?;
+
+        let h_max = self
+            .components
+            .iter()
+            .map(|c| c.horizontal_sample)
+            .max()
+            .unwrap_or(1);
+        let v_max = self
+            .components
+            .iter()
+            .map(|c| c.vertical_sample)
+            .max()
+            .unwrap_or(1);
+        let mcu_x = usize::from(self.info.width).div_ceil(8 * h_max);
+        let mcu_y = usize::from(self.info.height).div_ceil(8 * v_max);
+
+        let coefficients_size: usize = self
+            .components
+            .iter()
+            .map(|c| mcu_x * c.horizontal_sample * mcu_y * c.vertical_sample * 64)
+            .sum();
+        self.check_memory_limit(coefficients_size * core::mem::size_of::<i16>())?;
+
+        let mut blocks = if self.is_progressive {
+            self.decode_progressive_coefficients()?
+                .map(ScratchBuf::into_vec)
+        } else {
+            self.decode_baseline_coefficients()?
+        };
+
+        let mut quantization_tables = [None; MAX_COMPONENTS];
+        let mut components = Vec::with_capacity(self.components.len());
+
+        for (component, coefficients) in self.components.iter().zip(&mut blocks) {
+            let blocks_wide = component.width_stride / 8;
+
+            quantization_tables[usize::from(component.quantization_table_number)] =
+                Some(component.quantization_table.map(|x| x as u16));
+
+            components.push(ComponentCoefficients {
+                id: component.id,
+                horizontal_sample: component.horizontal_sample,
+                vertical_sample: component.vertical_sample,
+                quantization_table: component.quantization_table_number,
+                blocks_wide,
+                blocks_high: coefficients.len() / 64 / blocks_wide.max(1),
+                coefficients: core::mem::take(coefficients),
+            });
+        }
+        Ok(JpegCoefficients {
+            width: usize::from(self.info.width),
+            height: usize::from(self.info.height),
+            colorspace: self.input_colorspace,
+            quantization_tables,
+            components,
+        })
+    }
+
+    /// Reconstruct pixels from coefficients returned by
+    /// [`decode_coefficients`](Self::decode_coefficients), which may have
+    /// been edited
+    ///
+    /// The decoder has to be the one the coefficients came from, or one of
+    /// the same image, and pixels are output as [`decode`](Self::decode)
+    /// does. Coefficients are dequantized with the tables in `coefficients`.
+    ///
+    /// # Errors
+    /// If the components, their sampling factors or block grids don't match
+    /// the image, or a quantization table is missing
+    pub fn render_coefficients(
+        &mut self,
+        coefficients: &JpegCoefficients,
+    ) -> Result<Vec<u8>, DecodeErrors> {
+        self.decode_headers_internal()?;
+
+        if self.awaiting_dnl {
+            return Err(DecodeErrors::FormatStatic(
+                "Images with a DNL segment have no coefficients to read",
+            ));
+        }
+        setup_component_params(self)?;
+
+        if self.input_colorspace == ColorSpace::Luma && self.is_interleaved {
+            self.reset_params();
+        }
+        if self.is_interleaved {
+            self.set_upsampling()?;
+        }
+        if coefficients.components.len() != self.components.len() {
+            return Err(DecodeErrors::Format(format!(
+                "Expected coefficients for {} components but found {}",
+                self.components.len(),
+                coefficients.components.len()
+            )));
+        }
+        let mcu_rows = if self.is_interleaved {
+            self.mcu_y
+        } else {
+            usize::from(self.info.height).div_ceil(8)
+        };
+
+        for (component, input) in self.components.iter_mut().zip(&coefficients.components) {
+            let blocks_wide = component.width_stride / 8;
+            let blocks_high = mcu_rows * component.vertical_sample;
+
+            if input.horizontal_sample != component.horizontal_sample
+                || input.vertical_sample != component.vertical_sample
+                || input.blocks_wide != blocks_wide
+                || input.blocks_high != blocks_high
+                || input.coefficients.len() != blocks_wide * blocks_high * 64
+            {
+                return Err(DecodeErrors::Format(format!(
+                    "Coefficients of component {:?} don't match the image",
+                    component.component_id
+                )));
+            }
+            let table = coefficients
+                .quantization_tables
+                .get(usize::from(input.quantization_table))
+                .copied()
+                .flatten()
+                .ok_or_else(|| {
+                    DecodeErrors::DqtError(format!(
+                        "No quantization table for component {:?}",
+                        component.component_id
+                    ))
+                })?;
+            component.quantization_table = table.map(i32::from);
+        }
+        let blocks: Vec<&[i16]> = coefficients
+            .components
+            .iter()
+            .map(|c| c.coefficients.as_slice())
+            .collect();
+
+        let size = self.output_buffer_size().ok_or(DecodeErrors::FormatStatic(
+            "Image size isn't known, headers weren't decoded",
+        ))?;
+        self.check_memory_limit(size)?;
+
+        let mut out = vec![0; size];
+        self.finish_progressive_decoding(&blocks, &mut out)?;
+
+        Ok(out)
+    }
+}
+
+#[test]
+fn coefficients_round_trip() {
+    use crate::zune_core::bit_depth::BitDepth;
+    use crate::zune_core::options::EncoderOptions;
+
+    use super::JpegEncoder;
+
+    let data = include_bytes!("../../tests/inputs/restart_420.jpg");
+    let pixels = JpegDecoder::new(&data[..]).decode().unwrap();
+
+    let options = EncoderOptions::new(96, 64, ColorSpace::RGB, BitDepth::Eight)
+        .set_jpeg_encode_progressive(true);
+    let progressive = JpegEncoder::new(&pixels, options).encode().unwrap();
+
+    for data in [&data[..], &progressive] {
+        let expected = JpegDecoder::new(data).decode().unwrap();
+
+        let mut decoder = JpegDecoder::new(data);
+        let mut coefficients = decoder.decode_coefficients().unwrap();
+
+        // 96x64 4:2:0, 6x4 MCUs
+        let luma = &coefficients.components[0];
+        assert_eq!((luma.blocks_wide, luma.blocks_high), (12, 8));
+        assert_eq!(luma.coefficients.len(), 12 * 8 * 64);
+        let chroma = &coefficients.components[1];
+        assert_eq!((chroma.blocks_wide, chroma.blocks_high), (6, 4));
+        assert!(coefficients.quantization_tables[usize::from(chroma.quantization_table)].is_some());
+
+        assert!(decoder.render_coefficients(&coefficients).unwrap() == expected);
+
+        // removing AC coefficients of luma leaves flat blocks
+        for coefficients in coefficients.components[0].coefficients.chunks_exact_mut(64) {
+            coefficients[1..].fill(0);
+        }
+        let options = crate::zune_core::options::DecoderOptions::default()
+            .jpeg_set_out_colorspace(ColorSpace::Luma);
+        let mut decoder = JpegDecoder::new_with_options(data, options);
+        let flat = decoder.render_coefficients(&coefficients).unwrap();
+
+        for row in flat.chunks_exact(96 * 8) {
+            for x in (0..96).step_by(8) {
+                let first = row[x];
+                assert!((0..8).all(|y| row[y * 96 + x..y * 96 + x + 8].iter().all(|p| *p == first)));
+            }
+        }
+
+        // coefficients have to fit the image
+        coefficients.components[2].coefficients.pop();
+        assert!(decoder.render_coefficients(&coefficients).is_err());
+    }
+}
@@ -17,60 +17,57 @@
 //!
 //! - `YCbCr` to `RGB,RGBA,GRAYSCALE,RGBX`.
 //!
+//! The default conversion is a fast BT.601 approximation. Other matrices,
+//! studio range and exact mode use the 16 bit fixed point conversion, which
+//! rounds like libjpeg.
 //!
-//! Hey there, if your reading this it means you probably need something, so let me help you.
+//! All conversions are scalar, the targets we run on (e.g Xtensa) have no
+//! vector extensions worth a separate path.
 //!
-//! There are 3 supported cpu extensions here.
-//! 1. Scalar
-//! 2. SSE
-//! 3. AVX
-//!
-//! There are two types of the color convert functions
-//!
-//! 1. Acts on 16 pixels.
-//! 2. Acts on 8 pixels.
-//!
-//! The reason for this is because when implementing the AVX part it occurred to me that we can actually
-//! do better and process 2 MCU's if we change IDCT return type to be `i16's`, since a lot of
-//! CPU's these days support AVX extensions, it becomes nice if we optimize for that path ,
-//! therefore AVX routines can process 16 pixels directly and SSE and Scalar just compensate.
-//!
-//! By compensating, I mean I wrote the 16 pixels version operating on the 8 pixel version twice.
-//!
-//! Therefore if your looking to optimize some routines, probably start there.
+//! Conversions act on 16 pixels at a time.
 
 use crate::zune_core::colorspace::ColorSpace;
-use crate::zune_core::options::DecoderOptions;
-pub use scalar::ycbcr_to_grayscale;
+use crate::zune_core::options::{DecoderOptions, JpegColorMatrix, JpegColorRange};
+pub use scalar::{expand_studio_luma, ycbcr_to_grayscale};
 
 use super::decoder::ColorConvert16Ptr;
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-#[cfg(feature = "x86")]
-pub use crate::color_convert::avx::{ycbcr_to_rgb_avx2, ycbcr_to_rgba_avx2};
 
-mod avx;
 mod scalar;
+
+/// Choose the 16 bit precision conversion using coefficients `M`
+fn choose_precise_func<const M: usize>(type_need: ColorSpace) -> Option<ColorConvert16Ptr> {
+    match type_need {
+        ColorSpace::RGB => Some(scalar::ycbcr_to_rgb_precise_16::<3, false, M>),
+        ColorSpace::RGBA => Some(scalar::ycbcr_to_rgb_precise_16::<4, false, M>),
+        ColorSpace::BGR => Some(scalar::ycbcr_to_rgb_precise_16::<3, true, M>),
+        ColorSpace::BGRA => Some(scalar::ycbcr_to_rgb_precise_16::<4, true, M>),
+        _ => None,
+    }
+}
+
 #[allow(unused_variables)]
 pub fn choose_ycbcr_to_rgb_convert_func(
     type_need: ColorSpace,
     options: &DecoderOptions,
 ) -> Option<ColorConvert16Ptr> {
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-    #[cfg(feature = "x86")]
-    {
-        if options.use_avx2() {
-            debug!("Using AVX optimised color conversion functions");
+    let matrix = match options.jpeg_get_color_matrix() {
+        JpegColorMatrix::Bt601 => 0,
+        JpegColorMatrix::Bt709 => 1,
+        JpegColorMatrix::Bt2020 => 2,
+    };
+    let studio = options.jpeg_get_color_range() == JpegColorRange::Studio;
 
-            // I believe avx2 means sse4 is also available
-            // match colorspace
-            match type_need {
-                ColorSpace::RGB => return Some(ycbcr_to_rgb_avx2),
-                ColorSpace::RGBA => return Some(ycbcr_to_rgba_avx2),
-                _ => (), // fall through to scalar, which has more types
-            };
-        }
+    if matrix != 0 || studio || options.jpeg_get_exact() {
+        // index in scalar::YCBCR_COEFFICIENTS
+        return match matrix * 2 + usize::from(studio) {
+            0 => choose_precise_func::<0>(type_need),
+            1 => choose_precise_func::<1>(type_need),
+            2 => choose_precise_func::<2>(type_need),
+            3 => choose_precise_func::<3>(type_need),
+            4 => choose_precise_func::<4>(type_need),
+            _ => choose_precise_func::<5>(type_need),
+        };
     }
-    // when there is no x86 or we haven't returned by here, resort to scalar
     return match type_need {
         ColorSpace::RGB => Some(scalar::ycbcr_to_rgb_inner_16_scalar::<false>),
         ColorSpace::RGBA => Some(scalar::ycbcr_to_rgba_inner_16_scalar::<false>),
@@ -79,3 +76,86 @@ pub fn choose_ycbcr_to_rgb_convert_func(
         _ => None,
     };
 }
+
+#[test]
+#[allow(clippy::cast_precision_loss)]
+fn matrices_match_their_equations() {
+    // Kr and Kb of each matrix
+    let matrices = [
+        (JpegColorMatrix::Bt601, 0.299, 0.114),
+        (JpegColorMatrix::Bt709, 0.2126, 0.0722),
+        (JpegColorMatrix::Bt2020, 0.2627, 0.0593),
+    ];
+
+    for (matrix, kr, kb) in matrices {
+        for range in [JpegColorRange::Full, JpegColorRange::Studio] {
+            for exact in [false, true] {
+                let options = DecoderOptions::default()
+                    .jpeg_set_color_matrix(matrix)
+                    .jpeg_set_color_range(range)
+                    .jpeg_set_exact(exact);
+                let convert = choose_ycbcr_to_rgb_convert_func(ColorSpace::RGB, &options).unwrap();
+                let (offset, y_scale, c_scale) = match range {
+                    JpegColorRange::Full => (0.0, 1.0, 1.0),
+                    JpegColorRange::Studio => (16.0, 255.0 / 219.0, 255.0 / 224.0),
+                };
+                let kg = 1.0 - kr - kb;
+                // the fast BT.601 approximation is off by up to 2
+                let tolerance =
+                    if exact || matrix != JpegColorMatrix::Bt601 || range != JpegColorRange::Full {
+                        1.0
+                    } else {
+                        2.0
+                    };
+                // Cb takes 0, 17, .. 255 across the 16 pixels
+                let cb: [i16; 16] = core::array::from_fn(|i| (i * 17) as i16);
+
+                for y_value in (0..=255).step_by(5) {
+                    for cr_value in (0..=255).step_by(5) {
+                        let y = [y_value; 16];
+                        let cr = [cr_value; 16];
+                        let mut output = [0; 48];
+                        let mut pos = 0;
+
+                        convert(&y, &cb, &cr, &mut output, &mut pos);
+                        assert_eq!(pos, 48);
+
+                        for (i, pixel) in output.chunks_exact(3).enumerate() {
+                            let luma = (f64::from(y_value) - offset) * y_scale;
+                            let pb = f64::from(cb[i] - 128) * c_scale;
+                            let pr = f64::from(cr[i] - 128) * c_scale;
+                            let expected = [
+                                luma + 2.0 * (1.0 - kr) * pr,
+                                luma - (2.0 * kb * (1.0 - kb) * pb + 2.0 * kr * (1.0 - kr) * pr)
+                                    / kg,
+                                luma + 2.0 * (1.0 - kb) * pb,
+                            ];
+                            for (actual, expected) in pixel.iter().zip(expected) {
+                                let expected = expected.round().clamp(0.0, 255.0);
+                                assert!(
+                                    (f64::from(*actual) - expected).abs() <= tolerance,
+                                    "{matrix:?} {range:?} exact={exact} Y={y_value} Cb={} Cr={cr_value}: {pixel:?} != {expected}",
+                                    cb[i]
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn studio_luma_matches_its_equation() {
+    let mut pixels: [u8; 256] = core::array::from_fn(|i| i as u8);
+
+    expand_studio_luma(&mut pixels);
+
+    for (y, actual) in (0..=255).zip(pixels) {
+        let expected = ((f64::from(y) - 16.0) * 255.0 / 219.0)
+            .round()
+            .clamp(0.0, 255.0);
+        assert_eq!(actual, expected as u8, "{y}");
+    }
+}
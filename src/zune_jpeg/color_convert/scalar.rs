@@ -1,5 +1,7 @@
 use core::convert::TryInto;
 
+use crate::zune_jpeg::worker::output_rows;
+
 /// Limit values to 0 and 255
 #[inline]
 #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, dead_code)]
@@ -105,11 +107,122 @@ pub fn ycbcr_to_rgb_inner_16_scalar<const BGRA: bool>(
     *pos += 48;
 }
 
-pub fn ycbcr_to_grayscale(y: &[i16], width: usize, padded_width: usize, output: &mut [u8])
+/// 16 bit fixed point coefficients converting YCbCr to RGB
+///
+/// `R = ((Y - y_offset) * y_scale + cr_r * Cr) >> 16` and so on, with
+/// chroma centered on zero.
+pub struct YCbCrCoefficients
+{
+    pub y_offset: i32,
+    pub y_scale:  i32,
+    pub cr_r:     i32,
+    pub cb_b:     i32,
+    pub cb_g:     i32,
+    pub cr_g:     i32
+}
+
+impl YCbCrCoefficients
+{
+    const fn new(studio: bool, cr_r: i32, cb_b: i32, cb_g: i32, cr_g: i32) -> YCbCrCoefficients
+    {
+        YCbCrCoefficients {
+            y_offset: if studio { 16 } else { 0 },
+            y_scale: if studio { 76309 } else { 65536 },
+            cr_r,
+            cb_b,
+            cb_g,
+            cr_g
+        }
+    }
+}
+
+/// Coefficients of BT.601, BT.709 and BT.2020, each in full then studio
+/// range.
+///
+/// The full range BT.601 ones are libjpeg's, including its rounding of
+/// 0.34414 and 0.71414, so that converting with them matches `jdcolor.c`
+/// bit for bit. Studio ones are scaled by 255/219 for luma and 255/224 for
+/// chroma.
+pub const YCBCR_COEFFICIENTS: [YCbCrCoefficients; 6] = [
+    YCbCrCoefficients::new(false, 91881, 116_130, 22554, 46802),
+    YCbCrCoefficients::new(true, 104_597, 132_201, 25675, 53279),
+    YCbCrCoefficients::new(false, 103_206, 121_609, 12276, 30679),
+    YCbCrCoefficients::new(true, 117_489, 138_438, 13975, 34925),
+    YCbCrCoefficients::new(false, 96639, 123_299, 10784, 37444),
+    YCbCrCoefficients::new(true, 110_014, 140_363, 12277, 42626)
+];
+
+/// Convert YCbCr to RGB(A)/BGR(A) with the coefficients at index `M` of
+/// [`YCBCR_COEFFICIENTS`]
+///
+/// `N` is the number of output components, 3 or 4, BGR(A) is written if
+/// `BGRA` is true.
+///
+/// Unlike the functions above, this keeps 16 bits of precision and rounds
+/// once per component like libjpeg does.
+pub fn ycbcr_to_rgb_precise_16<const N: usize, const BGRA: bool, const M: usize>(
+    y: &[i16; 16], cb: &[i16; 16], cr: &[i16; 16], output: &mut [u8], pos: &mut usize
+)
+{
+    let c = &YCBCR_COEFFICIENTS[M];
+
+    let opt = output
+        .get_mut(*pos..*pos + 16 * N)
+        .expect("Slice to small cannot write");
+
+    for ((y, (cb, cr)), out) in y
+        .iter()
+        .zip(cb.iter().zip(cr.iter()))
+        .zip(opt.chunks_exact_mut(N))
+    {
+        let base = (i32::from(*y) - c.y_offset) * c.y_scale + 32768;
+        let cb = i32::from(*cb) - 128;
+        let cr = i32::from(*cr) - 128;
+
+        let r = ((base + c.cr_r * cr) >> 16).clamp(0, 255) as u8;
+        let g = ((base - c.cb_g * cb - c.cr_g * cr) >> 16).clamp(0, 255) as u8;
+        let b = ((base + c.cb_b * cb) >> 16).clamp(0, 255) as u8;
+
+        if BGRA
+        {
+            out[0] = b;
+            out[2] = r;
+        }
+        else
+        {
+            out[0] = r;
+            out[2] = b;
+        }
+        out[1] = g;
+
+        if N == 4
+        {
+            out[3] = 255;
+        }
+    }
+    *pos += 16 * N;
+}
+
+/// Stretch studio range luma, 16..=235, to the full range
+pub fn expand_studio_luma(pixels: &mut [u8])
+{
+    let c = &YCBCR_COEFFICIENTS[1];
+
+    for pixel in pixels
+    {
+        let y = (i32::from(*pixel) - c.y_offset) * c.y_scale + 32768;
+
+        *pixel = (y >> 16).clamp(0, 255) as u8;
+    }
+}
+
+pub fn ycbcr_to_grayscale(
+    y: &[i16], width: usize, padded_width: usize, output: &mut [u8], output_stride: usize
+)
 {
     for (y_in, out) in y
         .chunks_exact(padded_width)
-        .zip(output.chunks_exact_mut(width))
+        .zip(output_rows(output, width, output_stride))
     {
         for (y, out) in y_in.iter().zip(out.iter_mut())
         {
@@ -5,7 +5,8 @@
 
 use super::decoder::MAX_COMPONENTS;
 use super::errors::DecodeErrors;
-use super::upsampler::upsample_no_op;
+use super::scratch::{ScratchArena, ScratchBuf, ScratchRegion};
+use super::upsampler::{upsample_generic_no_op, upsample_no_op};
 
 /// Represents an up-sampler function, this function will be called to upsample
 /// a down-sampled image
@@ -18,6 +19,19 @@ pub type UpSampler = fn(
     output: &mut [i16],
 );
 
+/// Represents an up-sampler for arbitrary integer sampling ratios.
+///
+/// It up-samples a whole MCU row of a component at once, `row_up` and `row_down`
+/// are the rows directly above and below that MCU row.
+pub type GenericUpSampler = fn(
+    input: &[i16],
+    row_up: &[i16],
+    row_down: &[i16],
+    ratio: (usize, usize),
+    scratch_space: &mut [i16],
+    output: &mut [i16],
+);
+
 /// Component Data from start of frame
 #[derive(Clone)]
 pub(crate) struct Components {
@@ -40,6 +54,15 @@ pub(crate) struct Components {
     /// An up-sampling function, can be basic or SSE, depending
     /// on the platform
     pub up_sampler: UpSampler,
+    /// Up-sampler used when the image layout isn't one of the
+    /// common (H, V, HV) layouts
+    pub generic_up_sampler: GenericUpSampler,
+    /// How many times this component has to be stretched horizontally
+    /// to reach the image resolution
+    pub h_ratio: usize,
+    /// How many times this component has to be stretched vertically
+    /// to reach the image resolution
+    pub v_ratio: usize,
     /// How pixels do we need to go to get to the next line?
     pub width_stride: usize,
     /// Component ID for progressive
@@ -47,13 +70,22 @@ pub(crate) struct Components {
     /// Whether we need to decode this image component.
     pub needed: bool,
     /// Upsample scanline
-    pub raw_coeff: Vec<i16>,
+    pub raw_coeff: ScratchBuf,
     /// Upsample destination, stores a scanline worth of sub sampled data
-    pub upsample_dest: Vec<i16>,
+    pub upsample_dest: ScratchBuf,
     /// previous row, used to handle MCU boundaries
-    pub prev_row: Vec<i16>,
+    pub prev_row: ScratchBuf,
     /// current row, used to handle MCU boundaries again
-    pub current_row: Vec<i16>,
+    pub current_row: ScratchBuf,
+    /// The previous MCU row, the generic up-sampler can only process it
+    /// after the row below it has been decoded
+    pub pending_coeff: ScratchBuf,
+    /// Last pixel row of the previous MCU row, used to conceal
+    /// MCUs lost to corrupt data
+    pub conceal_row: ScratchBuf,
+    /// Last pixel rows of the previous MCU row, the deblocking filter
+    /// reads them across the edge between MCU rows
+    pub deblock_rows: ScratchBuf,
     pub idct_pos: usize,
     pub x: usize,
     pub w2: usize,
@@ -92,17 +124,17 @@ impl Components {
                 "Too large quantization number :{quantization_table_number}, expected value between 0 and {MAX_COMPONENTS}"
             )));
         }
-        // check that upsampling ratios are powers of two
+        // check that sampling factors are between 1 and 4 as required by the spec
         // if these fail, it's probably a corrupt image.
-        if !horizontal_sample.is_power_of_two() {
+        if !(1..=4).contains(&horizontal_sample) {
             return Err(DecodeErrors::Format(format!(
-                "Horizontal sample is not a power of two({horizontal_sample}) cannot decode"
+                "Horizontal sample is not between 1 and 4({horizontal_sample}) cannot decode"
             )));
         }
 
-        if !vertical_sample.is_power_of_two() {
+        if !(1..=4).contains(&vertical_sample) {
             return Err(DecodeErrors::Format(format!(
-                "Vertical sub-sample is not power of two({vertical_sample}) cannot decode"
+                "Vertical sub-sample is not between 1 and 4({vertical_sample}) cannot decode"
             )));
         }
 
@@ -125,14 +157,20 @@ impl Components {
             quantization_table: [0; 64],
             dc_pred: 0,
             up_sampler: upsample_no_op,
+            generic_up_sampler: upsample_generic_no_op,
+            h_ratio: 1,
+            v_ratio: 1,
             // set later
             width_stride: horizontal_sample,
             id: a[0],
             needed: true,
-            raw_coeff: vec![],
-            upsample_dest: vec![],
-            prev_row: vec![],
-            current_row: vec![],
+            raw_coeff: ScratchBuf::default(),
+            upsample_dest: ScratchBuf::default(),
+            prev_row: ScratchBuf::default(),
+            current_row: ScratchBuf::default(),
+            pending_coeff: ScratchBuf::default(),
+            conceal_row: ScratchBuf::default(),
+            deblock_rows: ScratchBuf::default(),
             idct_pos: 0,
             x: 0,
             y: 0,
@@ -149,10 +187,55 @@ impl Components {
     ///
     /// # Requirements
     ///  - width stride of this element is set for the component.
-    pub fn setup_upsample_scanline(&mut self, h_max: usize, v_max: usize) {
-        self.current_row = vec![0; self.width_stride * self.vertical_sample];
-        self.prev_row = vec![0; self.width_stride * self.vertical_sample];
-        self.upsample_dest = vec![128; self.width_stride * h_max * v_max];
+    pub fn setup_upsample_scanline(
+        &mut self,
+        h_max: usize,
+        v_max: usize,
+        arena: &mut ScratchArena,
+    ) -> Result<(), DecodeErrors> {
+        let row_len = self.width_stride * self.vertical_sample;
+
+        self.current_row = arena.take(ScratchRegion::Hot, row_len, 0)?;
+        self.prev_row = arena.take(ScratchRegion::Hot, row_len, 0)?;
+        self.upsample_dest =
+            arena.take(ScratchRegion::Hot, self.width_stride * h_max * v_max, 128)?;
+
+        Ok(())
+    }
+    /// Setup space for the generic up-sampler
+    ///
+    /// The generic up-sampler works on whole MCU rows, so we keep the previous
+    /// MCU row, the row above it, and a full resolution MCU row as the destination.
+    ///
+    /// # Requirements
+    ///  - width stride and up-sampling ratios are set for the component.
+    pub fn setup_upsample_generic(&mut self, arena: &mut ScratchArena) -> Result<(), DecodeErrors> {
+        let mcu_row_len = self.width_stride * self.vertical_sample * 8;
+
+        self.prev_row = arena.take(ScratchRegion::Hot, self.width_stride, 0)?;
+        self.pending_coeff = arena.take(ScratchRegion::Hot, mcu_row_len, 0)?;
+        self.upsample_dest = arena.take(
+            ScratchRegion::Hot,
+            mcu_row_len * self.h_ratio * self.v_ratio,
+            128,
+        )?;
+
+        Ok(())
+    }
+    /// Drop buffers, which may be carved out of regions lent
+    /// to the decoder
+    pub fn release_scratch(&mut self) {
+        for buffer in [
+            &mut self.raw_coeff,
+            &mut self.upsample_dest,
+            &mut self.prev_row,
+            &mut self.current_row,
+            &mut self.pending_coeff,
+            &mut self.conceal_row,
+            &mut self.deblock_rows,
+        ] {
+            *buffer = ScratchBuf::default();
+        }
     }
 }
 
@@ -174,5 +257,8 @@ pub enum SampleRatios {
     HV,
     V,
     H,
+    /// Any other layout, carries the maximum horizontal and
+    /// vertical sampling factors
+    Generic(usize, usize),
     None,
 }
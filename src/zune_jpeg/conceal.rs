@@ -0,0 +1,265 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+//! Concealment of MCUs lost to corrupt entropy coded data
+//!
+//! Restart markers split the entropy coded data into intervals that can be
+//! decoded independently, so when an interval is corrupt we can skip to the next
+//! restart marker, fill the MCUs we lost and resume decoding from there.
+//!
+//! Restart markers are numbered modulo 8, so more than 7 lost intervals in a row
+//! are under-counted.
+use crate::zune_core::bytestream::ZReaderTrait;
+use crate::zune_core::options::JpegConcealment;
+
+use super::bitstream::BitStream;
+use super::decoder::JpegDecoder;
+use super::marker::Marker;
+use super::mcu::DCT_BLOCK;
+use super::report::DecodeWarning;
+
+impl<T: ZReaderTrait> JpegDecoder<T> {
+    /// Return true if corrupt data can be concealed instead of being an error
+    pub(crate) fn can_conceal(&self) -> bool {
+        self.restart_interval != 0 && self.options.jpeg_get_concealment() != JpegConcealment::None
+    }
+
+    /// Start concealing MCUs from `(row, column)` up to the next restart marker
+    /// that can be found in the stream
+    ///
+    /// Besides the rest of the current restart interval, this includes
+    /// whole intervals whose restart markers are missing.
+    pub(crate) fn start_concealment(
+        &mut self,
+        row: usize,
+        column: usize,
+        mcu_width: usize,
+        stream: &mut BitStream,
+    ) {
+        let expected = self.next_restart;
+
+        let mut lost = match self.find_next_restart(stream) {
+            Some(n) => {
+                self.next_restart = (n + 1) & 7;
+                let skipped = usize::from(n.wrapping_sub(expected) & 7);
+
+                self.todo + skipped * self.restart_interval
+            }
+            // no restart marker left, conceal the rest of the image
+            None => usize::MAX,
+        };
+        let index = row * mcu_width + column;
+        // the first lost MCU may start the next row
+        let (row, column) = (index / mcu_width, index % mcu_width);
+
+        if self.report.mcus_total != 0 {
+            lost = lost.min(self.report.mcus_total.saturating_sub(index));
+        }
+        if lost == 0 {
+            self.resume_after_concealment();
+            return;
+        }
+        self.report.warn(DecodeWarning::Concealed {
+            interval: index / self.restart_interval,
+            row,
+            column,
+            mcus: lost,
+            offset: self.stream.get_position(),
+        });
+        self.conceal_left = lost;
+    }
+
+    /// Skip the stream to the next restart marker and return its number
+    ///
+    /// Returns `None` if another marker or the end of the data comes first,
+    /// other markers are kept in the bitstream to be handled by the caller.
+    fn find_next_restart(&mut self, stream: &mut BitStream) -> Option<u8> {
+        let mut marker = stream.marker;
+
+        stream.reset();
+
+        while marker.is_none() && !self.stream.eof() {
+            if self.stream.get_u8() != 0xFF {
+                continue;
+            }
+            let mut next = self.stream.get_u8();
+
+            while next == 0xFF {
+                next = self.stream.get_u8();
+            }
+            // unknown markers are corrupt data too, keep looking
+            if next != 0 {
+                marker = Marker::from_u8(next);
+            }
+        }
+        match marker {
+            Some(Marker::RST(n)) => Some(n),
+            other => {
+                stream.marker = other;
+                None
+            }
+        }
+    }
+
+    /// Reset decoder state to resume decoding after a restart marker
+    fn resume_after_concealment(&mut self) {
+        self.todo = self.restart_interval;
+        self.components.iter_mut().for_each(|x| x.dc_pred = 0);
+    }
+
+    /// Fill the MCU at `(row, column)` using the concealment strategy set
+    /// in the decoder options
+    pub(crate) fn conceal_mcu(&mut self, row: usize, column: usize) {
+        let concealment = self.options.jpeg_get_concealment();
+        let mut tmp = [0_i32; DCT_BLOCK];
+
+        for component in self.components.iter_mut().filter(|c| c.needed) {
+            let stride = component.width_stride;
+
+            for v_samp in 0..component.vertical_sample {
+                for h_samp in 0..component.horizontal_sample {
+                    let x = ((column * component.horizontal_sample) + h_samp) * 8;
+                    let block = &mut component.raw_coeff[stride * v_samp * 8 + x..];
+
+                    match concealment {
+                        JpegConcealment::CopyAbove if row > 0 => {
+                            let above = &component.conceal_row[x..x + 8];
+
+                            for line in block.chunks_mut(stride).take(8) {
+                                line[..8].copy_from_slice(above);
+                            }
+                        }
+                        JpegConcealment::DcOnly => {
+                            tmp.fill(0);
+                            tmp[0] = component.dc_pred * component.quantization_table[0];
+                            (self.idct_func)(&mut tmp, block, stride);
+                        }
+                        _ => {
+                            for line in block.chunks_mut(stride).take(8) {
+                                line[..8].fill(128);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        self.report.mcus_concealed += 1;
+        self.conceal_left -= 1;
+
+        if self.conceal_left == 0 {
+            self.resume_after_concealment();
+        }
+    }
+
+    /// Keep the last pixel row of the MCU row just decoded, MCUs lost in
+    /// the next row are concealed from it
+    pub(crate) fn save_conceal_rows(&mut self) {
+        if !self.can_conceal() || self.options.jpeg_get_concealment() != JpegConcealment::CopyAbove
+        {
+            return;
+        }
+        for component in self.components.iter_mut().filter(|c| c.needed) {
+            let stride = component.width_stride;
+            let last_row = component.raw_coeff.len() - stride;
+
+            component
+                .conceal_row
+                .copy_from_slice(&component.raw_coeff[last_row..]);
+        }
+    }
+}
+
+#[test]
+fn conceal_restart_intervals() {
+    use crate::zune_core::colorspace::ColorSpace;
+    use crate::zune_core::options::DecoderOptions;
+
+    let data = include_bytes!("../../tests/inputs/restart_420.jpg");
+    // keep YCbCr output so the concealed samples can be checked directly
+    let options = DecoderOptions::default().jpeg_set_out_colorspace(ColorSpace::YCbCr);
+    let clean = JpegDecoder::new_with_options(&data[..], options)
+        .decode()
+        .unwrap();
+    let sample = |pixels: &[u8], x: usize, y: usize, c: usize| pixels[(y * 96 + x) * 3 + c];
+    // 96x64 image, 6x4 MCUs with 3 MCUs per restart interval
+    let restarts: Vec<usize> = (0..data.len() - 1)
+        .filter(|&i| data[i] == 0xFF && (0xD0..=0xD7).contains(&data[i + 1]))
+        .collect();
+
+    // corrupt data in the third interval
+    let mut corrupt = data.to_vec();
+    let middle = restarts[1] + (restarts[2] - restarts[1]) / 2;
+    corrupt[middle..middle + 4].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0x13]);
+
+    // restart marker and data of the third interval are lost
+    let mut lost = data[..restarts[1]].to_vec();
+    lost.extend_from_slice(&data[restarts[2]..]);
+
+    assert!(JpegDecoder::new(&corrupt[..]).decode().is_err());
+
+    for concealment in [
+        JpegConcealment::Grey,
+        JpegConcealment::CopyAbove,
+        JpegConcealment::DcOnly,
+    ] {
+        let options = options.jpeg_set_concealment(concealment);
+
+        for (input, column, mcus) in [(&corrupt, 1, 2), (&lost, 0, 3)] {
+            let mut decoder = JpegDecoder::new_with_options(&input[..], options);
+            let pixels = decoder.decode().unwrap();
+
+            assert!(matches!(
+                decoder.report().warnings[..],
+                [DecodeWarning::Concealed { interval: 2, row: 1, column: c, mcus: m, .. }]
+                    if c == column && m == mcus
+            ));
+            assert_eq!(decoder.report().mcus_concealed, mcus);
+            assert_eq!(decoder.report().mcus_decoded, 24 - mcus);
+            // decoding resumes after the restart marker, the last MCU row is intact
+            let last_row = 96 * 48 * 3;
+            assert_eq!(pixels[last_row..], clean[last_row..]);
+
+            // luma isn't resampled, the concealed MCUs hold exactly what was filled in
+            let (xs, ys) = (column * 16..(column + mcus) * 16, 16..32);
+            let dc = sample(&pixels, xs.start, ys.start, 0);
+
+            for y in ys.clone() {
+                for x in xs.clone() {
+                    let expected = match concealment {
+                        JpegConcealment::Grey => 128,
+                        JpegConcealment::CopyAbove => sample(&clean, x, 15, 0),
+                        _ => dc,
+                    };
+                    assert_eq!(sample(&pixels, x, y, 0), expected, "({x}, {y})");
+                }
+            }
+            if concealment == JpegConcealment::DcOnly && column == 0 {
+                // the DC predictor is left by the last block of the previous interval
+                let block = (8..16).flat_map(|y| (88..96).map(move |x| (x, y)));
+                let mean = block
+                    .map(|(x, y)| usize::from(sample(&clean, x, y, 0)))
+                    .sum::<usize>()
+                    / 64;
+
+                assert!(usize::from(dc).abs_diff(mean) <= 1, "{dc} vs {mean}");
+            }
+            // chroma is upsampled across MCU edges, check away from them
+            for y in ys.start + 2..ys.end - 2 {
+                for x in xs.start + 2..xs.end - 2 {
+                    for c in 1..3 {
+                        let expected = match concealment {
+                            JpegConcealment::Grey => 128,
+                            _ => sample(&pixels, x, ys.start + 2, c),
+                        };
+                        assert_eq!(sample(&pixels, x, y, c), expected, "({x}, {y})");
+                    }
+                }
+            }
+        }
+    }
+}
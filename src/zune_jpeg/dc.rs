@@ -0,0 +1,353 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+//! Working on DC coefficients
+//!
+//! The DC coefficient of a block is eight times the average of its samples,
+//! so the luma DC coefficients of an image make a grayscale thumbnail at an
+//! eighth of its size. Getting them only takes entropy decoding, no IDCT,
+//! upsampling or color conversion, which makes it a cheap way to compare
+//! frames.
+//!
+//! Perceptual hashes are computed from that thumbnail.
+#![allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss
+)]
+
+use crate::zune_core::bytestream::ZReaderTrait;
+
+use super::decoder::JpegDecoder;
+use super::errors::DecodeErrors;
+use super::misc::UN_ZIGZAG;
+
+/// A grayscale image made of the average of every luma block
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DcThumbnail {
+    /// Width in blocks, the image width divided by 8, rounded up
+    pub width: usize,
+    /// Height in blocks, the image height divided by 8, rounded up
+    pub height: usize,
+    /// Samples, row by row
+    pub pixels: Vec<u8>,
+}
+
+/// Luma blocks of an image, with their first few coefficients
+pub(crate) struct LumaBlocks {
+    /// Width in blocks
+    pub width: usize,
+    /// Height in blocks
+    pub height: usize,
+    /// Coefficients per block, in zigzag order
+    pub coefficients: usize,
+    /// Dequantized coefficients, block after block
+    pub values: Vec<i32>,
+}
+
+impl<T: ZReaderTrait> JpegDecoder<T> {
+    /// Entropy decode the image, keeping the first `coefficients` coefficients
+    /// (in zigzag order) of every luma block
+    ///
+    /// Baseline images are decoded block by block, progressive images need
+    /// all their coefficients in memory first.
+    pub(crate) fn decode_luma_blocks(
+        &mut self,
+        coefficients: usize,
+    ) -> Result<LumaBlocks, DecodeErrors> {
+        self.decode_headers_internal()?;
+
+        let coefficients = coefficients.clamp(1, 64);
+        let width = usize::from(self.info.width).div_ceil(8);
+        let height = usize::from(self.info.height).div_ceil(8);
+
+        self.check_memory_limit(width * height * coefficients * core::mem::size_of::<i32>())?;
+
+        let mut values = vec![0; width * height * coefficients];
+
+        if self.is_progressive {
+            let mcu_x = usize::from(self.info.width).div_ceil(8 * self.h_max);
+            let mcu_y = usize::from(self.info.height).div_ceil(8 * self.v_max);
+            let coefficients_size: usize = self
+                .components
+                .iter()
+                .map(|c| mcu_x * c.horizontal_sample * mcu_y * c.vertical_sample * 64)
+                .sum();
+            self.check_memory_limit(coefficients_size * core::mem::size_of::<i16>())?;
+
+            let blocks = self.decode_progressive_coefficients()?;
+            let luma = &self.components[0];
+            let stride = luma.width_stride / 8;
+
+            for (index, out) in values.chunks_exact_mut(coefficients).enumerate() {
+                let start = 64 * ((index / width) * stride + index % width);
+
+                for (k, value) in out.iter_mut().enumerate() {
+                    let pos = UN_ZIGZAG[k];
+
+                    *value = i32::from(blocks[0][start + pos]) * luma.quantization_table[pos];
+                }
+            }
+        } else {
+            let (mcu_width, mcu_height) = self.setup_baseline_blocks()?;
+            let table = self.components[0].quantization_table;
+
+            self.decode_baseline_blocks(mcu_width, mcu_height, |pos, x, y, block| {
+                if pos != 0 || x >= width || y >= height {
+                    return;
+                }
+                let start = (y * width + x) * coefficients;
+
+                for (k, value) in values[start..start + coefficients].iter_mut().enumerate() {
+                    let pos = UN_ZIGZAG[k];
+
+                    *value = block[pos] * table[pos];
+                }
+            })?;
+        }
+        Ok(LumaBlocks {
+            width,
+            height,
+            coefficients,
+            values,
+        })
+    }
+
+    /// Decode a grayscale thumbnail at an eighth of the image size from the
+    /// luma DC coefficients
+    ///
+    /// # Errors
+    /// If the image can't be decoded
+    pub fn decode_dc_thumbnail(&mut self) -> Result<DcThumbnail, DecodeErrors> {
+        let blocks = self.decode_luma_blocks(1)?;
+
+        // DC is eight times the block average, level shifted by 128
+        let pixels = blocks
+            .values
+            .iter()
+            .map(|dc| ((dc + 4).div_euclid(8) + 128).clamp(0, 255) as u8)
+            .collect();
+
+        Ok(DcThumbnail {
+            width: blocks.width,
+            height: blocks.height,
+            pixels,
+        })
+    }
+
+    /// Compute a perceptual hash of the image from its luma DC coefficients
+    ///
+    /// # Errors
+    /// If the image can't be decoded
+    pub fn perceptual_hash(&mut self, kind: HashKind) -> Result<PerceptualHash, DecodeErrors> {
+        Ok(self.decode_dc_thumbnail()?.perceptual_hash(kind))
+    }
+}
+
+/// The kind of perceptual hash to compute
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HashKind {
+    /// Average hash, whether each cell of an 8x8 grid is brighter than the
+    /// average. The cheapest, but sensitive to global brightness shifts
+    Average,
+    /// Difference hash, whether each cell of a 9x8 grid is brighter than the
+    /// cell to its right
+    Difference,
+    /// DCT hash, whether each of the 8x8 lowest frequencies of a 32x32 grid
+    /// is above their median. The most robust and the most expensive
+    Perceptual,
+}
+
+/// A 64 bit perceptual hash, similar images have hashes a small
+/// [`distance`](PerceptualHash::distance) apart
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PerceptualHash(pub u64);
+
+impl PerceptualHash {
+    /// Number of bits that differ between two hashes of the same kind
+    ///
+    /// Zero means the images are most likely the same, more than about 10
+    /// means they are different.
+    #[must_use]
+    pub const fn distance(self, other: PerceptualHash) -> u32 {
+        (self.0 ^ other.0).count_ones()
+    }
+}
+
+impl DcThumbnail {
+    /// Box filter the thumbnail to `width` by `height`, repeating samples
+    /// when it's smaller
+    fn resize(&self, width: usize, height: usize) -> Vec<f32> {
+        let mut out = Vec::with_capacity(width * height);
+
+        for y in 0..height {
+            let y0 = y * self.height / height;
+            let y1 = ((y + 1) * self.height / height).max(y0 + 1);
+
+            for x in 0..width {
+                let x0 = x * self.width / width;
+                let x1 = ((x + 1) * self.width / width).max(x0 + 1);
+                let mut sum = 0_u32;
+
+                for row in self.pixels[y0 * self.width..y1 * self.width].chunks_exact(self.width) {
+                    sum += row[x0..x1].iter().map(|x| u32::from(*x)).sum::<u32>();
+                }
+                out.push(sum as f32 / ((x1 - x0) * (y1 - y0)) as f32);
+            }
+        }
+        out
+    }
+
+    /// Compute a perceptual hash of the thumbnail
+    #[must_use]
+    pub fn perceptual_hash(&self, kind: HashKind) -> PerceptualHash {
+        let mut hash = 0_u64;
+
+        if self.pixels.is_empty() {
+            return PerceptualHash(hash);
+        }
+        match kind {
+            HashKind::Average => {
+                let grid = self.resize(8, 8);
+                let mean = grid.iter().sum::<f32>() / 64.0;
+
+                for value in grid {
+                    hash = (hash << 1) | u64::from(value > mean);
+                }
+            }
+            HashKind::Difference => {
+                let grid = self.resize(9, 8);
+
+                for row in grid.chunks_exact(9) {
+                    for pair in row.windows(2) {
+                        hash = (hash << 1) | u64::from(pair[0] > pair[1]);
+                    }
+                }
+            }
+            HashKind::Perceptual => {
+                const N: usize = 32;
+
+                let grid = self.resize(N, N);
+                // DCT-II basis, only the 8 lowest frequencies are needed
+                let basis: Vec<f32> = (0..8 * N)
+                    .map(|i| {
+                        let (u, x) = (i / N, i % N);
+                        ((2 * x + 1) as f32 * u as f32 * core::f32::consts::PI / (2 * N) as f32)
+                            .cos()
+                    })
+                    .collect();
+
+                // rows, then columns
+                let mut rows = vec![0.0_f32; N * 8];
+
+                for (y, row) in grid.chunks_exact(N).enumerate() {
+                    for u in 0..8 {
+                        rows[y * 8 + u] = row
+                            .iter()
+                            .zip(&basis[u * N..u * N + N])
+                            .map(|(a, b)| a * b)
+                            .sum();
+                    }
+                }
+                let mut low = [0.0_f32; 64];
+
+                for v in 0..8 {
+                    for u in 0..8 {
+                        low[v * 8 + u] = (0..N).map(|y| rows[y * 8 + u] * basis[v * N + y]).sum();
+                    }
+                }
+                // the DC term only carries overall brightness
+                let mut sorted = low[1..].to_vec();
+                sorted.sort_by(f32::total_cmp);
+                let median = sorted[sorted.len() / 2];
+
+                for value in low {
+                    hash = (hash << 1) | u64::from(value > median);
+                }
+            }
+        }
+        PerceptualHash(hash)
+    }
+}
+
+#[test]
+fn dc_perceptual_hash() {
+    use crate::zune_core::bit_depth::BitDepth;
+    use crate::zune_core::colorspace::ColorSpace;
+    use crate::zune_core::options::EncoderOptions;
+
+    use super::JpegEncoder;
+
+    let (width, height) = (128, 96);
+    let scene = |shift: usize, brightness: u8| {
+        let mut rgb = Vec::with_capacity(width * height * 3);
+
+        for y in 0..height {
+            for x in 0..width {
+                let x = x + shift;
+                let inside = (20..70).contains(&x) && (30..60).contains(&y);
+                let value = if inside { 200 } else { ((x + y) % 128) as u8 };
+
+                let value = value.saturating_add(brightness);
+                rgb.extend_from_slice(&[value, value / 2, 255 - value]);
+            }
+        }
+        rgb
+    };
+    let encode = |rgb: &[u8], quality, progressive| {
+        let options = EncoderOptions::new(width, height, ColorSpace::RGB, BitDepth::Eight)
+            .set_quality(quality)
+            .set_jpeg_encode_progressive(progressive);
+        JpegEncoder::new(rgb, options).encode().unwrap()
+    };
+
+    let rgb = scene(0, 0);
+    let original = encode(&rgb, 90, false);
+    let thumbnail = JpegDecoder::new(&original).decode_dc_thumbnail().unwrap();
+
+    assert_eq!((thumbnail.width, thumbnail.height), (16, 12));
+
+    // thumbnail samples are block averages of the luma of the source
+    for (i, sample) in thumbnail.pixels.iter().enumerate() {
+        let (bx, by) = (i % 16, i / 16);
+        let mut sum = 0.0;
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let p = &rgb[((by * 8 + y) * width + bx * 8 + x) * 3..];
+                sum += 0.299 * f32::from(p[0]) + 0.587 * f32::from(p[1]) + 0.114 * f32::from(p[2]);
+            }
+        }
+        assert!((f32::from(*sample) - sum / 64.0).abs() <= 2.0);
+    }
+
+    // progressive images give the same thumbnail
+    let progressive = encode(&scene(0, 0), 90, true);
+    assert_eq!(
+        JpegDecoder::new(&progressive).decode_dc_thumbnail().unwrap(),
+        thumbnail
+    );
+
+    let recompressed = encode(&scene(0, 0), 60, false);
+    let changed = encode(&scene(40, 0), 90, false);
+
+    for kind in [HashKind::Average, HashKind::Difference, HashKind::Perceptual] {
+        let hash = |jpeg: &[u8]| JpegDecoder::new(jpeg).perceptual_hash(kind).unwrap();
+
+        assert!(hash(&original).distance(hash(&recompressed)) <= 4, "{kind:?}");
+        assert!(hash(&original).distance(hash(&changed)) > 10, "{kind:?}");
+    }
+    // brightness shifts barely change the difference hash
+    let brighter = encode(&scene(0, 30), 90, false);
+    let hash = |jpeg: &[u8]| {
+        JpegDecoder::new(jpeg)
+            .perceptual_hash(HashKind::Difference)
+            .unwrap()
+    };
+    assert!(hash(&original).distance(hash(&brighter)) <= 6);
+}
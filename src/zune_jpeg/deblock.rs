@@ -0,0 +1,356 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+//! Deblocking and deringing post-filters
+//!
+//! Heavily quantized images show discontinuities at the edges of their 8x8
+//! blocks, and ripples around sharp edges inside them. Both are filtered on
+//! component samples after the IDCT, before up-sampling and color conversion.
+//!
+//! Deblocking follows the H.264 loop filter. An edge is only filtered if the
+//! step across it is small enough to come from quantization, with thresholds
+//! derived from the component's quantization table. Edges between smooth areas
+//! get the strong filter (boundary strength 4), other edges the normal filter
+//! whose changes are clipped. Vertical edges are filtered before horizontal ones.
+//!
+//! The decoder outputs an MCU row at a time, so across the edge between two
+//! MCU rows only the samples below it are changed.
+//!
+//! Deringing follows the MPEG-4 post-filter. Samples of a block are split into
+//! two classes around the middle of its range, and those whose 3x3 neighbourhood
+//! is in a single class are smoothed, changing them by at most about half
+//! of the quantization step.
+#![allow(clippy::cast_possible_truncation)]
+
+use crate::zune_core::bytestream::ZReaderTrait;
+
+use super::components::Components;
+use super::decoder::JpegDecoder;
+
+/// Samples on either side of an edge the filter reads
+const EDGE_TAPS: usize = 4;
+
+/// Filter thresholds of a component, in the direction across an edge
+#[derive(Copy, Clone)]
+struct Thresholds {
+    /// Largest step across an edge that is filtered
+    alpha: i32,
+    /// Largest step between neighbours on one side of a filtered edge
+    beta: i32,
+    /// Largest change made by the normal filter
+    tc: i32,
+}
+
+impl Thresholds {
+    fn new(q_dc: i32, q_ac: i32, strength: u8) -> Thresholds {
+        let strength = i32::from(strength);
+        let q = (q_dc + q_ac) * strength;
+
+        Thresholds {
+            alpha: q / 16,
+            beta: q_ac * strength / 32 + 1,
+            tc: q / 64 + 1,
+        }
+    }
+}
+
+#[inline(always)]
+fn clip(x: i32, limit: i32) -> i32 {
+    x.clamp(-limit, limit)
+}
+
+/// Filter the samples `p3 p2 p1 p0 | q0 q1 q2 q3` across an edge
+fn filter_edge(s: &mut [i32; 2 * EDGE_TAPS], t: Thresholds) {
+    let [p3, p2, p1, p0, q0, q1, q2, q3] = *s;
+
+    if (p0 - q0).abs() >= t.alpha || (p1 - p0).abs() >= t.beta || (q1 - q0).abs() >= t.beta {
+        // a real edge, or too much texture to tell
+        return;
+    }
+    let smooth_p = (p2 - p0).abs() < t.beta;
+    let smooth_q = (q2 - q0).abs() < t.beta;
+
+    if smooth_p && smooth_q && (p0 - q0).abs() < (t.alpha >> 2) + 2 {
+        s[1] = (2 * p3 + 3 * p2 + p1 + p0 + q0 + 4) >> 3;
+        s[2] = (p2 + p1 + p0 + q0 + 2) >> 2;
+        s[3] = (p2 + 2 * p1 + 2 * p0 + 2 * q0 + q1 + 4) >> 3;
+        s[4] = (p1 + 2 * p0 + 2 * q0 + 2 * q1 + q2 + 4) >> 3;
+        s[5] = (p0 + q0 + q1 + q2 + 2) >> 2;
+        s[6] = (2 * q3 + 3 * q2 + q1 + q0 + p0 + 4) >> 3;
+        return;
+    }
+    let tc = t.tc + i32::from(smooth_p) + i32::from(smooth_q);
+    let delta = clip((4 * (q0 - p0) + (p1 - q1) + 4) >> 3, tc);
+
+    s[3] = (p0 + delta).clamp(0, 255);
+    s[4] = (q0 - delta).clamp(0, 255);
+
+    if smooth_p {
+        s[2] = (p1 + clip((p2 + ((p0 + q0 + 1) >> 1) - 2 * p1) >> 1, t.tc)).clamp(0, 255);
+    }
+    if smooth_q {
+        s[5] = (q1 + clip((q2 + ((p0 + q0 + 1) >> 1) - 2 * q1) >> 1, t.tc)).clamp(0, 255);
+    }
+}
+
+/// Filter the edge before `pos` in `samples`, reading `step` apart
+#[inline(always)]
+fn filter_samples(samples: &mut [i16], pos: usize, step: usize, t: Thresholds) {
+    let start = pos - EDGE_TAPS * step;
+    let mut s = [0; 2 * EDGE_TAPS];
+
+    for (i, x) in s.iter_mut().enumerate() {
+        *x = i32::from(samples[start + i * step]);
+    }
+    filter_edge(&mut s, t);
+
+    for (i, x) in s.iter().enumerate() {
+        samples[start + i * step] = *x as i16;
+    }
+}
+
+/// Deblock an MCU row of a component, `above` holds the last rows of the
+/// MCU row above it, or is empty for the first MCU row
+fn deblock_component(samples: &mut [i16], above: &[i16], stride: usize, strength: u8, q: &[i32]) {
+    let rows = samples.len() / stride;
+    // quantization tables are in natural order, so the first horizontal and
+    // vertical frequencies are at 1 and 8
+    let across_columns = Thresholds::new(q[0], q[1], strength);
+    let across_rows = Thresholds::new(q[0], q[8], strength);
+
+    for row in samples.chunks_exact_mut(stride) {
+        for x in (8..stride).step_by(8) {
+            filter_samples(row, x, 1, across_columns);
+        }
+    }
+    for y in (8..rows).step_by(8) {
+        for x in 0..stride {
+            filter_samples(samples, y * stride + x, stride, across_rows);
+        }
+    }
+    if above.len() != EDGE_TAPS * stride {
+        return;
+    }
+    // the rows above have been output already, only change the ones below
+    for x in 0..stride {
+        let mut s = [0; 2 * EDGE_TAPS];
+
+        for i in 0..EDGE_TAPS {
+            s[i] = i32::from(above[i * stride + x]);
+            s[EDGE_TAPS + i] = i32::from(samples[i * stride + x]);
+        }
+        filter_edge(&mut s, across_rows);
+
+        for i in 0..EDGE_TAPS {
+            samples[i * stride + x] = s[EDGE_TAPS + i] as i16;
+        }
+    }
+}
+
+/// Dering an MCU row of a component, a block at a time
+#[allow(clippy::cast_possible_wrap, clippy::cast_sign_loss)]
+fn dering_component(samples: &mut [i16], stride: usize, strength: u8, q: &[i32]) {
+    let rows = samples.len() / stride;
+    let max_change = (q[1] + q[8]) * i32::from(strength) / 32;
+
+    if max_change == 0 {
+        return;
+    }
+    for by in (0..rows).step_by(8) {
+        for bx in (0..stride).step_by(8) {
+            // the block with a border of one sample, repeating edge samples
+            let mut block = [[0; 10]; 10];
+
+            for (dy, block_row) in block.iter_mut().enumerate() {
+                let y = (by + dy).saturating_sub(1).min(rows - 1);
+
+                for (dx, x) in block_row.iter_mut().enumerate() {
+                    let pos = (bx + dx).saturating_sub(1).min(stride - 1);
+                    *x = i32::from(samples[y * stride + pos]);
+                }
+            }
+            let inner = block[1..9].iter().flat_map(|row| &row[1..9]);
+            let (min, max) = inner.fold((255, 0), |(min, max), x| (min.min(*x), max.max(*x)));
+
+            if max - min < 16 {
+                // too flat to ring
+                continue;
+            }
+            let threshold = (max + min + 1) / 2;
+
+            for y in 1..9 {
+                for x in 1..9 {
+                    let class = block[y][x] >= threshold;
+                    let mut sum = 0;
+                    let mut uniform = true;
+
+                    for (dy, weights) in [[1, 2, 1], [2, 4, 2], [1, 2, 1]].iter().enumerate() {
+                        for (dx, weight) in weights.iter().enumerate() {
+                            let sample = block[y + dy - 1][x + dx - 1];
+
+                            uniform &= (sample >= threshold) == class;
+                            sum += weight * sample;
+                        }
+                    }
+                    if uniform && by + y <= rows && bx + x <= stride {
+                        let center = block[y][x];
+                        let filtered =
+                            ((sum + 8) >> 4).clamp(center - max_change, center + max_change);
+
+                        samples[(by + y - 1) * stride + bx + x - 1] = filtered as i16;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<T: ZReaderTrait> JpegDecoder<T> {
+    /// Deblock, and dering if enabled, MCU row `i` of all needed components
+    pub(crate) fn deblock_mcu_row(&mut self, i: usize) {
+        let strength = self.options.jpeg_get_deblocking();
+        let dering = self.options.jpeg_get_deringing();
+
+        for c in self.components.iter_mut().filter(|c| c.needed) {
+            let stride = c.width_stride;
+
+            if c.raw_coeff.len() < EDGE_TAPS * stride {
+                continue;
+            }
+            // the first MCU row has no row above it
+            let above: &[i16] = if i == 0 { &[] } else { &c.deblock_rows };
+
+            deblock_component(
+                &mut c.raw_coeff,
+                above,
+                stride,
+                strength,
+                &c.quantization_table,
+            );
+            if dering {
+                dering_component(&mut c.raw_coeff, stride, strength, &c.quantization_table);
+            }
+            save_deblock_rows(c);
+        }
+    }
+}
+
+fn save_deblock_rows(c: &mut Components) {
+    let start = c.raw_coeff.len() - EDGE_TAPS * c.width_stride;
+
+    c.deblock_rows.copy_from_slice(&c.raw_coeff[start..]);
+}
+
+#[test]
+fn deblock_edges() {
+    let t = Thresholds::new(40, 30, 8);
+
+    // a small step between two flat blocks is smoothed out
+    let mut s = [100, 100, 100, 100, 106, 106, 106, 106];
+    filter_edge(&mut s, t);
+    assert_eq!(s, [100, 101, 102, 102, 104, 105, 105, 106]);
+
+    // a larger one gets the normal filter
+    let mut s = [100, 100, 100, 100, 110, 110, 110, 110];
+    filter_edge(&mut s, t);
+    assert_eq!(s, [100, 100, 102, 104, 106, 107, 110, 110]);
+
+    // next to texture, the change is clipped
+    let mut s = [100, 90, 100, 100, 110, 110, 120, 110];
+    filter_edge(&mut s, t);
+    assert!((s[4] - s[3]).abs() < 10 && (s[3] - 100).abs() <= t.tc + 1);
+    assert_eq!([s[0], s[1], s[6], s[7]], [100, 90, 120, 110]);
+
+    // and stays in range next to black and white
+    let mut s = [0, 0, 0, 0, 12, 12, 12, 12];
+    filter_edge(&mut s, t);
+    assert_eq!(s, [0, 0, 3, 5, 7, 9, 12, 12]);
+
+    let mut s = [255, 255, 255, 255, 243, 243, 243, 243];
+    filter_edge(&mut s, t);
+    assert_eq!(s, [255, 255, 252, 251, 247, 246, 243, 243]);
+
+    // real edges are kept
+    let mut s = [20, 20, 20, 20, 200, 200, 200, 200];
+    filter_edge(&mut s, t);
+    assert_eq!(s, [20, 20, 20, 20, 200, 200, 200, 200]);
+
+    // and nothing is filtered at the highest quality
+    let mut s = [100, 100, 100, 100, 101, 101, 101, 101];
+    filter_edge(&mut s, Thresholds::new(1, 1, 8));
+    assert_eq!(s, [100, 100, 100, 100, 101, 101, 101, 101]);
+}
+
+#[test]
+fn deblock_low_quality_images() {
+    use crate::zune_core::bit_depth::BitDepth;
+    use crate::zune_core::colorspace::ColorSpace;
+    use crate::zune_core::options::{DecoderOptions, EncoderOptions};
+
+    use super::JpegEncoder;
+
+    let (width, height) = (96, 64);
+    // smooth gradients, with an edge
+    let gray: Vec<u8> = (0..width * height)
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            if x > 60 && y > 20 {
+                230
+            } else {
+                (x + y * 2) as u8
+            }
+        })
+        .collect();
+
+    let options =
+        EncoderOptions::new(width, height, ColorSpace::Luma, BitDepth::Eight).set_quality(12);
+    let jpeg = JpegEncoder::new(&gray, options).encode().unwrap();
+
+    let decode = |strength, dering| {
+        let options = DecoderOptions::default()
+            .jpeg_set_out_colorspace(ColorSpace::Luma)
+            .jpeg_set_deblocking(strength)
+            .jpeg_set_deringing(dering);
+        JpegDecoder::new_with_options(&jpeg, options)
+            .decode()
+            .unwrap()
+    };
+    // absolute difference to the original, and across block edges
+    let error = |pixels: &[u8]| -> usize {
+        pixels
+            .iter()
+            .zip(&gray)
+            .map(|(a, b)| usize::from(a.abs_diff(*b)))
+            .sum()
+    };
+    let blockiness = |pixels: &[u8]| {
+        let mut total = 0;
+
+        for y in 0..height {
+            for x in (8..width).step_by(8) {
+                total += usize::from(pixels[y * width + x].abs_diff(pixels[y * width + x - 1]));
+            }
+        }
+        for y in (8..height).step_by(8) {
+            for x in 0..width {
+                total += usize::from(pixels[y * width + x].abs_diff(pixels[(y - 1) * width + x]));
+            }
+        }
+        total
+    };
+
+    let plain = decode(0, false);
+    let deblocked = decode(8, false);
+    let deringed = decode(8, true);
+
+    assert!(blockiness(&deblocked) < blockiness(&plain));
+    assert!(error(&deblocked) < error(&plain));
+    assert!(error(&deringed) < error(&plain));
+    // deringing alone does nothing
+    assert!(decode(0, true) == plain);
+}
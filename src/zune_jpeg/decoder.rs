@@ -17,15 +17,20 @@ use super::color_convert::choose_ycbcr_to_rgb_convert_func;
 use super::components::{Components, SampleRatios};
 use super::errors::{DecodeErrors, UnsupportedSchemes};
 use super::headers::{
-    parse_app1, parse_app14, parse_app2, parse_dqt, parse_huffman, parse_sos, parse_start_of_frame,
+    parse_app1, parse_app14, parse_app2, parse_dnl, parse_dqt, parse_huffman, parse_sos,
+    parse_start_of_frame,
 };
 use super::huffman::HuffmanTable;
 use super::idct::{choose_idct_func, idct_int};
 use super::marker::Marker;
 use super::misc::SOFMarkers;
+use super::report::DecodeReport;
+use super::scratch::{upsampler_scratch_size, ScratchArena};
 use super::upsampler::{
-    choose_horizontal_samp_function, choose_hv_samp_function, choose_v_samp_function,
+    choose_generic_samp_function, choose_horizontal_samp_function, choose_hv_samp_function,
+    choose_v_samp_function,
 };
+use super::worker::output_rows;
 
 /// Maximum components
 pub(crate) const MAX_COMPONENTS: usize = 4;
@@ -63,6 +68,24 @@ pub type ColorConvert16Ptr = fn(&[i16; 16], &[i16; 16], &[i16; 16], &mut [u8], &
 /// Carry out IDCT (type 3 dct) on ach block of 64 i16's
 pub type IDCTPtr = fn(&mut [i32; 64], &mut [i16], usize);
 
+/// Hook called between MCU rows while decoding, see [`JpegDecoder::set_yield_hook`]
+pub type YieldHook = Box<dyn FnMut(DecodeProgress) + Send>;
+
+/// Decoding progress passed to a [`YieldHook`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DecodeProgress {
+    /// Decoding pass, baseline images are decoded in a single pass.
+    ///
+    /// Progressive images make a pass for every scan, and a last
+    /// one to reconstruct pixels from the coefficients
+    pub pass: usize,
+    /// Number of MCU rows decoded in this pass
+    pub mcu_rows_done: usize,
+    /// Number of MCU rows in this pass, or zero if unknown
+    /// (images whose height is defined by a DNL segment)
+    pub mcu_rows_total: usize,
+}
+
 /// An encapsulation of an ICC chunk
 pub(crate) struct ICCChunk {
     pub(crate) seq_no: u8,
@@ -130,6 +153,15 @@ pub struct JpegDecoder<T: ZReaderTrait> {
     /// restart markers
     pub(crate) restart_interval: usize,
     pub(crate) todo: usize,
+    /// Number of the next restart marker expected in the scan
+    pub(crate) next_restart: u8,
+    /// Number of MCUs left to conceal before decoding resumes
+    pub(crate) conceal_left: usize,
+    /// Hook called every `yield_every` MCU rows
+    pub(crate) yield_hook: Option<YieldHook>,
+    pub(crate) yield_every: usize,
+    /// Current decoding pass, passed to the yield hook
+    pub(crate) pass: usize,
     // decoder options
     pub(crate) options: DecoderOptions,
     // byte-stream
@@ -137,10 +169,19 @@ pub struct JpegDecoder<T: ZReaderTrait> {
     // Indicate whether headers have been decoded
     pub(crate) headers_decoded: bool,
     pub(crate) seen_sof: bool,
+    // The frame header had a height of zero, the height will be
+    // defined by a DNL segment after the first scan
+    pub(crate) awaiting_dnl: bool,
     // exif data, lifted from app2
     pub(crate) exif_data: Option<Vec<u8>>,
 
     pub(crate) icc_data: Vec<ICCChunk>,
+    // non-fatal issues found while decoding
+    pub(crate) report: DecodeReport,
+    // where component buffers come from
+    pub(crate) arena: ScratchArena,
+    // bytes between rows of the output, zero if rows are packed
+    pub(crate) output_stride: usize,
 }
 
 impl<T> JpegDecoder<T>
@@ -177,12 +218,21 @@ where
             z_order: [0; MAX_COMPONENTS],
             restart_interval: 0,
             todo: 0x7fff_ffff,
+            next_restart: 0,
+            conceal_left: 0,
+            yield_hook: None,
+            yield_every: 1,
+            pass: 0,
             options: options,
             stream: ZByteReader::new(buffer),
             headers_decoded: false,
             seen_sof: false,
+            awaiting_dnl: false,
             exif_data: None,
             icc_data: vec![],
+            report: DecodeReport::default(),
+            arena: ScratchArena::default(),
+            output_stride: 0,
         }
     }
     /// Decode a buffer already in memory
@@ -194,7 +244,11 @@ where
     /// See DecodeErrors for an explanation
     pub fn decode(&mut self) -> Result<Vec<u8>, DecodeErrors> {
         self.decode_headers()?;
+        if self.awaiting_dnl {
+            return self.decode_mcu_ycbcr_baseline_dnl();
+        }
         let size = self.output_buffer_size().unwrap();
+        self.check_memory_limit(size)?;
         let mut out = vec![0; size];
         self.decode_into(&mut out)?;
         Ok(out)
@@ -234,18 +288,66 @@ where
         return Some(self.info.clone());
     }
 
+    /// Call `hook` every `every_rows` MCU rows while decoding
+    ///
+    /// Decoding runs to completion without returning, which on cooperative
+    /// schedulers can starve other tasks for long enough to trip a task watchdog.
+    /// The hook can yield to them (e.g with a short task delay) or service other work
+    /// between rows.
+    ///
+    /// An MCU row is `8 * v_max` pixel rows (8 for non-interleaved scans), where `v_max` is the
+    /// largest vertical sampling factor.
+    ///
+    /// # Arguments
+    /// - `every_rows`: Call the hook after this many MCU rows, zero is treated as one
+    /// - `hook`: Function called with the decoding progress
+    pub fn set_yield_hook<F>(&mut self, every_rows: usize, hook: F)
+    where
+        F: FnMut(DecodeProgress) + Send + 'static,
+    {
+        self.yield_every = every_rows.max(1);
+        self.yield_hook = Some(Box::new(hook));
+    }
+
+    /// Call the yield hook if `mcu_rows_done` MCU rows in the current pass
+    /// is a multiple of the rows the hook was set up for
+    pub(crate) fn call_yield_hook(&mut self, mcu_rows_done: usize, mcu_rows_total: usize) {
+        if let Some(hook) = &mut self.yield_hook {
+            if mcu_rows_done.is_multiple_of(self.yield_every) {
+                hook(DecodeProgress {
+                    pass: self.pass,
+                    mcu_rows_done,
+                    mcu_rows_total,
+                });
+            }
+        }
+    }
+
+    /// Return the report of non-fatal issues found while decoding the image
+    ///
+    /// The decoder works around issues like truncated data instead of
+    /// returning an error (unless strict mode is set), this lists what was worked
+    /// around and how much of the image was actually decoded.
+    ///
+    /// The report is empty until an image is decoded.
+    #[must_use]
+    pub const fn report(&self) -> &DecodeReport {
+        &self.report
+    }
+
     /// Return the number of bytes required to hold a decoded image frame
     /// decoded using the given input transformations
     ///
     /// # Returns
     ///  - `Some(usize)`: Minimum size for a buffer needed to decode the image
-    ///  - `None`: Indicates the image was not decoded.
+    ///  - `None`: Indicates the image was not decoded, or the image height is
+    ///    defined by a DNL segment after the first scan, which hasn't been reached.
     ///
     /// # Panics
     /// In case `width*height*colorspace` calculation may overflow a usize
     #[must_use]
     pub fn output_buffer_size(&self) -> Option<usize> {
-        return if self.headers_decoded {
+        return if self.headers_decoded && !self.awaiting_dnl {
             Some(
                 usize::from(self.width())
                     .checked_mul(usize::from(self.height()))
@@ -339,7 +441,7 @@ where
     ///  - SOF(n) -> Decoder images which are not baseline/progressive
     ///  - DAC -> Images using Arithmetic tables
     ///  - JPG(n)
-    fn decode_headers_internal(&mut self) -> Result<(), DecodeErrors> {
+    pub(crate) fn decode_headers_internal(&mut self) -> Result<(), DecodeErrors> {
         if self.headers_decoded {
             // log::warn!("Headers decoded!");
             return Ok(());
@@ -362,6 +464,7 @@ where
             )
             .unwrap();
         }
+        self.idct_func = choose_idct_func(&self.options);
         // First two bytes should be jpeg soi marker
         let magic_bytes = self.stream.get_u16_be_err()?;
 
@@ -419,7 +522,9 @@ where
 
                     bytes_before_marker = 0;
 
-                    self.parse_marker_inner(n)?;
+                    let offset = self.stream.get_position().saturating_sub(2);
+                    self.parse_marker_inner(n)
+                        .map_err(|e| e.in_segment(n, offset))?;
 
                     if n == Marker::SOS {
                         self.headers_decoded = true;
@@ -514,7 +619,10 @@ where
             }
             Marker::EOI => return Err(DecodeErrors::FormatStatic("Premature End of image")),
 
-            Marker::DAC | Marker::DNL => {
+            Marker::DNL => {
+                parse_dnl(self)?;
+            }
+            Marker::DAC => {
                 return Err(DecodeErrors::Format(format!(
                     "Parsing of the following header `{m:?}` is not supported,\
                                 cannot continue"
@@ -659,6 +767,10 @@ where
     /// It is an error if the buffer size is smaller than
     /// [`output_buffer_size()`](Self::output_buffer_size)
     ///
+    /// For images whose height is defined by a DNL segment, the size is only
+    /// known after decoding, so the image is decoded into a temporary buffer first
+    /// and copied if it fits.
+    ///
     /// If the buffer is bigger than expected, we ignore the end padding bytes
     ///
     /// # Example
@@ -680,6 +792,19 @@ where
     ///
     pub fn decode_into(&mut self, out: &mut [u8]) -> Result<(), DecodeErrors> {
         self.decode_headers_internal()?;
+        self.check_memory_limit(0)?;
+
+        if self.awaiting_dnl {
+            // we only know how big the image is after decoding it
+            let pixels = self.decode_mcu_ycbcr_baseline_dnl()?;
+
+            if out.len() < pixels.len() {
+                return Err(DecodeErrors::TooSmallOutput(pixels.len(), out.len()));
+            }
+            out[..pixels.len()].copy_from_slice(&pixels);
+
+            return Ok(());
+        }
 
         let expected_size = self.output_buffer_size().unwrap();
 
@@ -699,6 +824,95 @@ where
         }
     }
 
+    /// Decode the image into a buffer whose rows are `stride` bytes apart
+    ///
+    /// This decodes into part of a larger buffer, like a tile of a framebuffer,
+    /// or into rows padded for alignment. The top left pixel of the image is
+    /// written at pixel `offset` (x, y) of `out`, or at its start if `offset`
+    /// is `None`, and bytes outside the image are left untouched.
+    ///
+    /// Pixels are in the output colorspace, so rows of the image take
+    /// `width * components` bytes, where `components` is the number of
+    /// components of the output colorspace.
+    ///
+    /// # Example
+    ///
+    /// - Decode into the bottom right corner of a 256x256 RGB canvas
+    ///
+    /// ```no_run
+    /// use zune_jpeg::JpegDecoder;
+    /// let mut decoder = JpegDecoder::new(&[]);
+    /// decoder.decode_headers().unwrap();
+    ///
+    /// let (width, height) = decoder.dimensions().unwrap();
+    /// let mut canvas = vec![0; 256 * 256 * 3];
+    /// let offset = (256 - usize::from(width), 256 - usize::from(height));
+    ///
+    /// decoder.decode_into_strided(&mut canvas, 256 * 3, Some(offset)).unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// If the image can't be decoded, a row of the image doesn't fit in
+    /// `stride` bytes past the offset, or `out` is too small to hold the rows
+    pub fn decode_into_strided(
+        &mut self,
+        out: &mut [u8],
+        stride: usize,
+        offset: Option<(usize, usize)>,
+    ) -> Result<(), DecodeErrors> {
+        self.decode_headers_internal()?;
+        self.check_memory_limit(0)?;
+
+        let components = self.options.jpeg_get_out_colorspace().num_components();
+        let row_len = usize::from(self.width()) * components;
+        let (x, y) = offset.unwrap_or((0, 0));
+
+        let start = x.saturating_mul(components);
+        let required = start.saturating_add(row_len);
+
+        if required > stride {
+            return Err(DecodeErrors::TooSmallStride(stride, required));
+        }
+        // the last row only needs its pixels
+        let start = y.saturating_mul(stride).saturating_add(start);
+        let out_len = out.len();
+        let region = |height: usize| {
+            let end = (height.max(1) - 1)
+                .saturating_mul(stride)
+                .saturating_add(start.saturating_add(row_len));
+
+            if end > out_len {
+                return Err(DecodeErrors::TooSmallStridedOutput(end, out_len, stride));
+            }
+            Ok(start..end)
+        };
+
+        if self.awaiting_dnl {
+            // we only know how big the image is after decoding it
+            let pixels = self.decode_mcu_ycbcr_baseline_dnl()?;
+            let out = &mut out[region(pixels.len() / row_len)?];
+
+            let rows = output_rows(out, row_len, stride);
+
+            for (row, pixels) in rows.zip(pixels.chunks_exact(row_len)) {
+                row.copy_from_slice(pixels);
+            }
+            return Ok(());
+        }
+        let out = &mut out[region(usize::from(self.height()))?];
+
+        self.output_stride = stride;
+
+        let result = if self.is_progressive {
+            self.decode_mcu_ycbcr_progressive(out)
+        } else {
+            self.decode_mcu_ycbcr_baseline(out)
+        };
+        self.output_stride = 0;
+
+        result
+    }
+
     /// Read only headers from a jpeg image buffer
     ///
     /// This allows you to extract important information like
@@ -732,59 +946,154 @@ where
         JpegDecoder::default(options, buf)
     }
 
-    /// Set up-sampling routines in case an image is down sampled
-    pub(crate) fn set_upsampling(&mut self) -> Result<(), DecodeErrors> {
+    /// Set up-sampling ratios of components and return the layout
+    /// of the image
+    pub(crate) fn upsampling_layout(&mut self) -> Result<SampleRatios, DecodeErrors> {
         // no sampling, return early
         // check if horizontal max ==1
         if self.h_max == self.v_max && self.h_max == 1 {
-            return Ok(());
+            return Ok(SampleRatios::None);
+        }
+
+        for comp in &mut self.components {
+            if !self.h_max.is_multiple_of(comp.horizontal_sample)
+                || !self.v_max.is_multiple_of(comp.vertical_sample)
+            {
+                return Err(DecodeErrors::Format(format!(
+                    "Non-integer up-sampling ratio for component {:?}, sampling factors {}x{} with maximum {}x{}",
+                    comp.component_id,
+                    comp.horizontal_sample,
+                    comp.vertical_sample,
+                    self.h_max,
+                    self.v_max
+                )));
+            }
+            comp.h_ratio = self.h_max / comp.horizontal_sample;
+            comp.v_ratio = self.v_max / comp.vertical_sample;
         }
+        Ok(self.sampling_layout(self.h_max, self.v_max))
+    }
+    /// Layout of an image whose MCUs are `h_max` by `v_max` blocks, without
+    /// checking the up-sampling ratios of components
+    pub(crate) fn sampling_layout(&self, h_max: usize, v_max: usize) -> SampleRatios {
+        if h_max == 1 && v_max == 1 {
+            return SampleRatios::None;
+        }
+        // The specialized routines handle full resolution Y with 1x1 chroma,
+        // everything else goes through the generic up-sampler
+        let common_layout = self.options.jpeg_get_fancy_upsampling()
+            && self.components[1..]
+                .iter()
+                .all(|x| x.horizontal_sample == 1 && x.vertical_sample == 1);
 
         // match for other ratios
-        match (self.h_max, self.v_max) {
-            (2, 1) => {
-                self.sub_sample_ratio = SampleRatios::H;
+        match (h_max, v_max) {
+            (2, 1) if common_layout => SampleRatios::H,
+            (1, 2) if common_layout => SampleRatios::V,
+            (2, 2) if common_layout => SampleRatios::HV,
+            (h_max, v_max) => SampleRatios::Generic(h_max, v_max),
+        }
+    }
+    /// Set up-sampling routines in case an image is down sampled
+    pub(crate) fn set_upsampling(&mut self) -> Result<(), DecodeErrors> {
+        let layout = self.upsampling_layout()?;
+
+        if layout == SampleRatios::None {
+            return Ok(());
+        }
+        self.sub_sample_ratio = layout;
+
+        let fancy_upsampling = self.options.jpeg_get_fancy_upsampling();
+        let exact = self.options.jpeg_get_exact();
+        let (h_max, v_max) = (self.h_max, self.v_max);
+
+        match layout {
+            SampleRatios::H => {
                 // horizontal sub-sampling
                 // log::info!("Horizontal sub-sampling (2,1)");
 
-                let up_sampler = choose_horizontal_samp_function(self.options.get_use_unsafe());
+                let up_sampler = choose_horizontal_samp_function(self.options.get_use_unsafe(), exact);
 
-                self.components[1..].iter_mut().for_each(|x| {
+                for x in &mut self.components[1..] {
                     x.up_sampler = up_sampler;
-                    x.setup_upsample_scanline(self.h_max, self.v_max);
-                });
+                    x.setup_upsample_scanline(h_max, v_max, &mut self.arena)?;
+                }
             }
-            (1, 2) => {
-                self.sub_sample_ratio = SampleRatios::V;
+            SampleRatios::V => {
                 // Vertical sub-sampling
                 // log::info!("Vertical sub-sampling (1,2)");
 
-                self.components[..].iter_mut().for_each(|x| {
-                    x.up_sampler = choose_v_samp_function(self.options.get_use_unsafe());
-                    x.setup_upsample_scanline(self.h_max, self.v_max);
-                });
+                for x in &mut self.components {
+                    x.up_sampler = choose_v_samp_function(self.options.get_use_unsafe(), exact);
+                    x.setup_upsample_scanline(h_max, v_max, &mut self.arena)?;
+                }
             }
-            (2, 2) => {
-                self.sub_sample_ratio = SampleRatios::HV;
+            SampleRatios::HV => {
                 // vertical and horizontal sub sampling
                 // log::info!("Vertical and horizontal sub-sampling(2,2)");
 
-                self.components[..].iter_mut().for_each(|x| {
-                    x.up_sampler = choose_hv_samp_function(self.options.get_use_unsafe());
-                    x.setup_upsample_scanline(self.h_max, self.v_max);
-                });
+                for x in &mut self.components {
+                    x.up_sampler = choose_hv_samp_function(self.options.get_use_unsafe(), exact);
+                    x.setup_upsample_scanline(h_max, v_max, &mut self.arena)?;
+                }
             }
-            (_, _) => {
-                // no op. Do nothing
-                // Jokes , panic...
-                return Err(DecodeErrors::Format(
-                    "Unknown down-sampling method, cannot continue".to_string(),
-                ));
+            SampleRatios::Generic(_, _) => {
+                let up_sampler = choose_generic_samp_function(fancy_upsampling, exact);
+
+                for x in &mut self.components {
+                    x.generic_up_sampler = up_sampler;
+                    x.setup_upsample_generic(&mut self.arena)?;
+                }
             }
+            SampleRatios::None => {}
         }
 
         return Ok(());
     }
+    /// Number of bytes the decoder allocates to decode the image, without
+    /// the output buffer
+    ///
+    /// This is an upper bound computed from the frame header, covering
+    /// coefficient buffers, component rows and up-sampler scratch space.
+    pub(crate) fn memory_required(&self) -> usize {
+        let h_max = self.components.iter().map(|x| x.horizontal_sample).max().unwrap_or(1);
+        let v_max = self.components.iter().map(|x| x.vertical_sample).max().unwrap_or(1);
+
+        let required = self.scratch_sizes(h_max, v_max, self.sampling_layout(h_max, v_max));
+
+        required
+            .hot
+            .saturating_add(required.large)
+            .saturating_mul(core::mem::size_of::<i16>())
+    }
+
+    /// Check that decoding fits in the memory limit set in the decoder options
+    ///
+    /// `output_size` is the size of the output buffer if the decoder allocates it
+    pub(crate) fn check_memory_limit(&self, output_size: usize) -> Result<(), DecodeErrors> {
+        let required = self.memory_required().saturating_add(output_size);
+        let allowed = self.options.get_max_memory();
+
+        if required > allowed {
+            return Err(DecodeErrors::MemoryLimitExceeded(required, allowed));
+        }
+        Ok(())
+    }
+    /// Bytes between rows of the output, for an image `width` pixels wide
+    pub(crate) fn output_row_stride(&self, width: usize) -> usize {
+        if self.output_stride == 0 {
+            width * self.options.jpeg_get_out_colorspace().num_components()
+        } else {
+            self.output_stride
+        }
+    }
+    /// Size of the scratch space needed by the up-sampling routines
+    pub(crate) fn upsampler_scratch_size(&self) -> usize {
+        upsampler_scratch_size(
+            self.sub_sample_ratio,
+            self.components.iter().map(|x| x.width_stride),
+        )
+    }
     #[must_use]
     /// Get the width of the image as a u16
     ///
@@ -886,3 +1195,321 @@ impl ImageInfo {
         self.y_density = sample;
     }
 }
+
+#[test]
+fn memory_limit() {
+    let data = include_bytes!("../../tests/inputs/sampling_411.jpg");
+
+    let options = DecoderOptions::default().set_max_memory(1000);
+    let required = match JpegDecoder::new_with_options(data, options).decode() {
+        Err(DecodeErrors::MemoryLimitExceeded(required, 1000)) => required,
+        result => unreachable!(
+            "expected MemoryLimitExceeded, got {:?}",
+            result.map(|x| x.len())
+        ),
+    };
+    // output alone is 50x37x3 bytes
+    assert!(required > 50 * 37 * 3);
+
+    let options = DecoderOptions::default().set_max_memory(required);
+    assert!(JpegDecoder::new_with_options(data, options).decode().is_ok());
+
+    // DNL images account for the output as it grows
+    let data = include_bytes!("../../tests/inputs/dnl_420.jpg");
+    let options = DecoderOptions::default().set_max_memory(5000);
+    assert!(matches!(
+        JpegDecoder::new_with_options(data, options).decode(),
+        Err(DecodeErrors::MemoryLimitExceeded(_, 5000))
+    ));
+    // and allocate whole MCU rows of it, nothing more
+    let mut decoder = JpegDecoder::new(&data[..]);
+    let pixels = decoder.decode().unwrap();
+    let (width, height) = decoder.dimensions().unwrap();
+    let mcu_rows = usize::from(height).div_ceil(16) * 16;
+    assert_eq!(pixels.capacity(), mcu_rows * usize::from(width) * 3);
+}
+
+#[test]
+fn error_positions() {
+    use super::errors::DecodeErrorKind;
+
+    let data = include_bytes!("../../tests/inputs/sampling_411.jpg");
+    let sos = data.windows(2).position(|x| x == [0xFF, 0xDA]).unwrap();
+
+    // bad DQT segment length
+    let dqt = data.windows(2).position(|x| x == [0xFF, 0xDB]).unwrap();
+    let mut corrupt = data.to_vec();
+    corrupt[dqt + 3] = 0x10;
+
+    let error = JpegDecoder::new(&corrupt).decode().unwrap_err();
+    assert!(matches!(
+        error,
+        DecodeErrors::Segment { marker: Marker::DQT, offset, .. } if offset == dqt
+    ));
+    assert_eq!(error.offset(), Some(dqt));
+
+    // invalid marker inside entropy coded data
+    let mut corrupt = data.to_vec();
+    let scan_start = sos + 2 + usize::from(u16::from_be_bytes([data[sos + 2], data[sos + 3]]));
+    corrupt[scan_start + 20] = 0xFF;
+    corrupt[scan_start + 21] = 0x01;
+
+    let error = JpegDecoder::new(&corrupt).decode().unwrap_err();
+    assert!(matches!(error, DecodeErrors::Entropy { row: 0, .. }));
+    assert_eq!(error.kind(), DecodeErrorKind::Format);
+    assert!(error.offset().unwrap() > scan_start);
+    assert!(std::error::Error::source(&error).is_some());
+
+    // known marker where it isn't expected, located at the marker
+    corrupt[scan_start + 21] = 0xFE;
+
+    let error = JpegDecoder::new(&corrupt).decode().unwrap_err();
+    assert!(matches!(error, DecodeErrors::Entropy { row: 0, .. }));
+    assert_eq!(error.offset(), Some(scan_start + 20));
+}
+
+#[test]
+fn yield_hook() {
+    use std::sync::{Arc, Mutex};
+
+    // 96x64 image with 16x16 MCUs, 4 MCU rows
+    let data = include_bytes!("../../tests/inputs/restart_420.jpg");
+
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let hook_calls = calls.clone();
+
+    let mut decoder = JpegDecoder::new(data);
+    decoder.set_yield_hook(1, move |progress| hook_calls.lock().unwrap().push(progress));
+    decoder.decode().unwrap();
+
+    let calls = calls.lock().unwrap();
+    assert_eq!(calls.len(), 4);
+    assert_eq!(
+        calls[3],
+        DecodeProgress {
+            pass: 0,
+            mcu_rows_done: 4,
+            mcu_rows_total: 4
+        }
+    );
+
+    let calls = Arc::new(Mutex::new(0));
+    let hook_calls = calls.clone();
+
+    let mut decoder = JpegDecoder::new(data);
+    decoder.set_yield_hook(3, move |_| *hook_calls.lock().unwrap() += 1);
+    decoder.decode().unwrap();
+
+    assert_eq!(*calls.lock().unwrap(), 1);
+}
+
+#[test]
+fn segmented_sources() {
+    use std::io::Cursor;
+
+    use crate::zune_core::bytestream::{ZChunkedBuffer, ZRingBuffer, ZSeekReader};
+
+    let data = include_bytes!("../../tests/inputs/restart_420.jpg");
+
+    // add an Exif segment, which is read through the byte reader
+    let exif = b"Exif\x00\x00MM\x00\x2A\x00\x00\x00\x08\x00\x00";
+    let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1, 0x00, 18];
+    jpeg.extend_from_slice(exif);
+    jpeg.extend_from_slice(&data[2..]);
+
+    let options = DecoderOptions::default().set_num_threads(4);
+    let expected = JpegDecoder::new_with_options(&jpeg, options).decode().unwrap();
+
+    let check = |decoder: &mut dyn FnMut() -> (Vec<u8>, Option<Vec<u8>>)| {
+        let (pixels, found) = decoder();
+        assert!(pixels == expected);
+        assert_eq!(found.as_deref(), Some(&exif[6..]));
+    };
+
+    for size in [1, 7, 100, 4096] {
+        check(&mut || {
+            let chunks = ZChunkedBuffer::new(jpeg.chunks(size).collect());
+            let mut decoder = JpegDecoder::new_with_options(chunks, options);
+            (decoder.decode().unwrap(), decoder.exif().cloned())
+        });
+        check(&mut || {
+            let source = ZSeekReader::with_block_size(Cursor::new(&jpeg), size).unwrap();
+            let mut decoder = JpegDecoder::new_with_options(source, options);
+            (decoder.decode().unwrap(), decoder.exif().cloned())
+        });
+    }
+    for start in [0, 3, 1000, jpeg.len() - 1] {
+        let mut ring = vec![0; jpeg.len() + 5];
+
+        for (i, byte) in jpeg.iter().enumerate() {
+            ring[(start + i) % (jpeg.len() + 5)] = *byte;
+        }
+        check(&mut || {
+            let source = ZRingBuffer::new(&ring, start, jpeg.len());
+            let mut decoder = JpegDecoder::new_with_options(source, options);
+            (decoder.decode().unwrap(), decoder.exif().cloned())
+        });
+    }
+}
+
+#[test]
+fn exact_decoding() {
+    // reference outputs from libjpeg-turbo 2.1.5, with the accurate integer
+    // IDCT and fancy up-sampling, made by stripping djpeg's PPM header:
+    //
+    // djpeg -dct int -ppm NAME.jpg | tail -c $((WIDTH * HEIGHT * 3)) > NAME.libjpeg.rgb
+    for (data, expected) in [
+        (
+            &include_bytes!("../../tests/inputs/restart_420.jpg")[..],
+            &include_bytes!("../../tests/inputs/restart_420.libjpeg.rgb")[..],
+        ),
+        (
+            &include_bytes!("../../tests/inputs/sampling_mixed.jpg")[..],
+            &include_bytes!("../../tests/inputs/sampling_mixed.libjpeg.rgb")[..],
+        ),
+    ] {
+        let options = DecoderOptions::default().jpeg_set_exact(true);
+        let pixels = JpegDecoder::new_with_options(data, options).decode().unwrap();
+        assert!(pixels == expected);
+
+        // the fast path stays close
+        let pixels = JpegDecoder::new(data).decode().unwrap();
+        assert!(pixels.iter().zip(expected).all(|(a, b)| a.abs_diff(*b) <= 8));
+    }
+}
+
+#[test]
+fn color_matrix_and_range() {
+    use crate::zune_core::options::{JpegColorMatrix, JpegColorRange};
+
+    let data = include_bytes!("../../tests/inputs/sampling_411.jpg");
+    let decode = |options: DecoderOptions| JpegDecoder::new_with_options(data, options).decode();
+
+    let exact = DecoderOptions::default().jpeg_set_exact(true);
+    let bt601 = decode(exact).unwrap();
+    let bt709 = decode(exact.jpeg_set_color_matrix(JpegColorMatrix::Bt709)).unwrap();
+    assert_eq!(bt601.len(), bt709.len());
+    assert!(bt601 != bt709);
+
+    // the most saturated unclipped pixel, checked against the BT.709 equations
+    let bt709_rgb = |p: &[u8]| {
+        let (y, cb, cr) = (f32::from(p[0]), f32::from(p[1]) - 128.0, f32::from(p[2]) - 128.0);
+        [y + 1.5748 * cr, y - 0.187_324 * cb - 0.468_124 * cr, y + 1.8556 * cb]
+    };
+    let ycbcr = decode(exact.jpeg_set_out_colorspace(ColorSpace::YCbCr)).unwrap();
+    let (pixel, expected) = ycbcr
+        .chunks_exact(3)
+        .map(bt709_rgb)
+        .enumerate()
+        .filter(|(_, rgb)| rgb.iter().all(|c| (0.0..=255.0).contains(c)))
+        .max_by_key(|(i, _)| (ycbcr[i * 3 + 1].abs_diff(128)) + ycbcr[i * 3 + 2].abs_diff(128))
+        .unwrap();
+    assert!(bt601[pixel * 3..][..3] != bt709[pixel * 3..][..3]);
+    for (&out, expected) in bt709[pixel * 3..][..3].iter().zip(expected) {
+        assert!((f32::from(out) - expected.round()).abs() <= 1.0);
+    }
+
+    // studio range stretches 16..=235 luma to 0..=255
+    let options = DecoderOptions::default().jpeg_set_out_colorspace(ColorSpace::Luma);
+    let full = decode(options).unwrap();
+    let studio = decode(options.jpeg_set_color_range(JpegColorRange::Studio)).unwrap();
+
+    for (full, studio) in full.iter().zip(&studio) {
+        let expected = (i32::from(*full) - 16) * 255 / 219;
+        assert!((i32::from(*studio) - expected.clamp(0, 255)).abs() <= 1);
+    }
+}
+
+#[test]
+fn strided_output() {
+    use crate::zune_core::bit_depth::BitDepth;
+    use crate::zune_core::options::{EncoderOptions, JpegColorRange};
+
+    use super::JpegEncoder;
+
+    let baseline = include_bytes!("../../tests/inputs/restart_420.jpg");
+    let pixels = JpegDecoder::new(&baseline[..]).decode().unwrap();
+    let options = EncoderOptions::new(96, 64, ColorSpace::RGB, BitDepth::Eight)
+        .set_jpeg_encode_progressive(true);
+    let progressive = JpegEncoder::new(&pixels, options).encode().unwrap();
+
+    let images = [
+        &baseline[..],
+        &progressive,
+        include_bytes!("../../tests/inputs/restart_422_partial.jpg"),
+        include_bytes!("../../tests/inputs/sampling_440.jpg"),
+        include_bytes!("../../tests/inputs/sampling_mixed.jpg"),
+        include_bytes!("../../tests/inputs/dnl_420.jpg"),
+    ];
+    let options = [
+        DecoderOptions::default(),
+        DecoderOptions::default().jpeg_set_out_colorspace(ColorSpace::RGBA),
+        DecoderOptions::default()
+            .jpeg_set_out_colorspace(ColorSpace::Luma)
+            .jpeg_set_color_range(JpegColorRange::Studio),
+        DecoderOptions::default().set_num_threads(4),
+    ];
+
+    for data in images {
+        for options in options {
+            let expected = JpegDecoder::new_with_options(data, options).decode().unwrap();
+
+            let mut decoder = JpegDecoder::new_with_options(data, options);
+            decoder.decode_headers().unwrap();
+            let components = options.jpeg_get_out_colorspace().num_components();
+            let row_len = usize::from(decoder.width()) * components;
+            let height = expected.len() / row_len;
+
+            // decode into a canvas with 3 columns and 2 rows of border
+            let stride = row_len + 5 * components + 3;
+            let mut canvas = vec![0xAA; stride * (height + 4)];
+            let start = 2 * stride + 3 * components;
+
+            decoder
+                .decode_into_strided(&mut canvas, stride, Some((3, 2)))
+                .unwrap();
+
+            for (y, row) in canvas.chunks_exact(stride).enumerate() {
+                if (2..height + 2).contains(&y) {
+                    let image_row = &expected[(y - 2) * row_len..(y - 1) * row_len];
+
+                    assert!(row[3 * components..][..row_len] == *image_row, "{options:?}");
+                    assert!(row[..3 * components].iter().all(|x| *x == 0xAA));
+                    assert!(row[3 * components + row_len..].iter().all(|x| *x == 0xAA));
+                } else {
+                    assert!(row.iter().all(|x| *x == 0xAA));
+                }
+            }
+            // the last row doesn't need its padding
+            let end = start + (height - 1) * stride + row_len;
+            let mut decoder = JpegDecoder::new_with_options(data, options);
+            assert!(decoder
+                .decode_into_strided(&mut canvas[..end], stride, Some((3, 2)))
+                .is_ok());
+
+            let mut decoder = JpegDecoder::new_with_options(data, options);
+            let result = decoder.decode_into_strided(&mut canvas[..end - 1], stride, Some((3, 2)));
+            assert!(matches!(
+                result,
+                Err(DecodeErrors::TooSmallStridedOutput(e, f, s))
+                    if e == end && f == end - 1 && s == stride
+            ));
+            // rows have to fit in the stride
+            let stride = row_len + 3 * components - 1;
+            let mut decoder = JpegDecoder::new_with_options(data, options);
+            let result = decoder.decode_into_strided(&mut canvas, stride, Some((3, 0)));
+            assert!(matches!(
+                result,
+                Err(DecodeErrors::TooSmallStride(s, r)) if s == stride && r == stride + 1
+            ));
+        }
+    }
+    // offsets past the end of the output don't overflow
+    let mut decoder = JpegDecoder::new(&baseline[..]);
+    let mut out = vec![0; 96 * 64 * 3];
+    let result = decoder.decode_into_strided(&mut out, 96 * 3, Some((0, usize::MAX)));
+    assert!(matches!(
+        result,
+        Err(DecodeErrors::TooSmallStridedOutput(usize::MAX, len, _)) if len == out.len()
+    ));
+}
@@ -0,0 +1,786 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+//! A baseline and progressive JPEG encoder
+//!
+//! Pixels are converted to YCbCr, chroma is box filtered down to the
+//! requested subsampling and blocks go through an integer forward DCT
+//! (the `islow` one from libjpeg) before being quantized by the tables from
+//! Annex K of the spec, scaled to the requested quality like libjpeg does.
+//!
+//! Partial MCUs on the right and bottom edges are padded by repeating the
+//! last column and row, which keeps the padding cheap to code.
+//!
+//! Progressive images send the same coefficients over the scans of a
+//! [`JpegScan`] script, libjpeg's default one unless another is given.
+#![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+
+use crate::zune_core::bit_depth::BitDepth;
+use crate::zune_core::bytestream::{ZByteWriter, ZWriterTrait};
+use crate::zune_core::colorspace::ColorSpace;
+use crate::zune_core::options::{EncoderOptions, JpegSubsampling};
+
+use super::errors::EncodeErrors;
+use super::idct::scalar::{
+    FIX_0_298631336, FIX_0_390180644, FIX_0_541196100, FIX_0_765366865, FIX_0_899976223,
+    FIX_1_175875602, FIX_1_501321110, FIX_1_847759065, FIX_1_961570560, FIX_2_053119869,
+    FIX_2_562915447, FIX_3_072711026,
+};
+use super::writer::{Frame, FrameComponent};
+
+/// Luminance quantization table suggested in K.1 of the spec, natural order
+#[rustfmt::skip]
+pub(crate) const STD_LUMA_QT: [i32; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61,
+    12, 12, 14, 19, 26, 58, 60, 55,
+    14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62,
+    18, 22, 37, 56, 68, 109, 103, 77,
+    24, 35, 55, 64, 81, 104, 113, 92,
+    49, 64, 78, 87, 103, 121, 120, 101,
+    72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+/// Chrominance quantization table suggested in K.1 of the spec, natural order
+#[rustfmt::skip]
+pub(crate) const STD_CHROMA_QT: [i32; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99,
+    18, 21, 26, 66, 99, 99, 99, 99,
+    24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+/// Scale a suggested table to `quality`, the way libjpeg does
+///
+/// Quality 50 keeps the table, 100 turns it into all ones.
+pub(crate) fn scale_table(table: &[i32; 64], quality: u8) -> [i32; 64] {
+    let quality = i32::from(quality.clamp(1, 100));
+    let scale = if quality < 50 {
+        5000 / quality
+    } else {
+        200 - quality * 2
+    };
+    table.map(|x| ((x * scale + 50) / 100).clamp(1, 255))
+}
+
+/// Convert a pixel to YCbCr with the fixed point coefficients of libjpeg
+fn rgb_to_ycbcr(r: u8, g: u8, b: u8) -> [u8; 3] {
+    let (r, g, b) = (i32::from(r), i32::from(g), i32::from(b));
+
+    let y = (19595 * r + 38470 * g + 7471 * b + 32768) >> 16;
+    let cb = (-11059 * r - 21709 * g + 32768 * b + (128 << 16) + 32767) >> 16;
+    let cr = (32768 * r - 27439 * g - 5329 * b + (128 << 16) + 32767) >> 16;
+
+    [y as u8, cb as u8, cr as u8]
+}
+
+/// Forward DCT of a block of level shifted samples, in place
+///
+/// This is `jpeg_fdct_islow` from libjpeg, outputs are scaled up by 8.
+#[allow(clippy::erasing_op, clippy::identity_op)]
+fn fdct(block: &mut [i32; 64]) {
+    const CONST_BITS: i32 = 13;
+    const PASS1_BITS: i32 = 2;
+
+    const fn descale(x: i32, n: i32) -> i32 {
+        (x + (1 << (n - 1))) >> n
+    }
+
+    // rows in the first pass, columns in the second
+    for pass in 0..2 {
+        for i in 0..8 {
+            let (base, step) = if pass == 0 { (i * 8, 1) } else { (i, 8) };
+            let at = |k: usize| base + k * step;
+            let d = |k: usize| block[at(k)];
+
+            let tmp0 = d(0) + d(7);
+            let tmp7 = d(0) - d(7);
+            let tmp1 = d(1) + d(6);
+            let tmp6 = d(1) - d(6);
+            let tmp2 = d(2) + d(5);
+            let tmp5 = d(2) - d(5);
+            let tmp3 = d(3) + d(4);
+            let tmp4 = d(3) - d(4);
+
+            let tmp10 = tmp0 + tmp3;
+            let tmp13 = tmp0 - tmp3;
+            let tmp11 = tmp1 + tmp2;
+            let tmp12 = tmp1 - tmp2;
+
+            // even part is kept at PASS1_BITS of extra precision after
+            // the first pass, and brought back after the second
+            let (even_shift, odd_shift) = if pass == 0 {
+                (0, CONST_BITS - PASS1_BITS)
+            } else {
+                (PASS1_BITS, CONST_BITS + PASS1_BITS)
+            };
+            let mut out = [0; 8];
+
+            if pass == 0 {
+                out[0] = (tmp10 + tmp11) << PASS1_BITS;
+                out[4] = (tmp10 - tmp11) << PASS1_BITS;
+            } else {
+                out[0] = descale(tmp10 + tmp11, even_shift);
+                out[4] = descale(tmp10 - tmp11, even_shift);
+            }
+            let z1 = (tmp12 + tmp13) * FIX_0_541196100;
+
+            out[2] = descale(z1 + tmp13 * FIX_0_765366865, odd_shift);
+            out[6] = descale(z1 - tmp12 * FIX_1_847759065, odd_shift);
+
+            // odd part, figure 8 of the Loeffler, Ligtenberg and Moschytz paper
+            let z1 = tmp4 + tmp7;
+            let z2 = tmp5 + tmp6;
+            let z3 = tmp4 + tmp6;
+            let z4 = tmp5 + tmp7;
+            let z5 = (z3 + z4) * FIX_1_175875602;
+
+            let tmp4 = tmp4 * FIX_0_298631336;
+            let tmp5 = tmp5 * FIX_2_053119869;
+            let tmp6 = tmp6 * FIX_3_072711026;
+            let tmp7 = tmp7 * FIX_1_501321110;
+            let z1 = -z1 * FIX_0_899976223;
+            let z2 = -z2 * FIX_2_562915447;
+            let z3 = -z3 * FIX_1_961570560 + z5;
+            let z4 = -z4 * FIX_0_390180644 + z5;
+
+            out[7] = descale(tmp4 + z1 + z3, odd_shift);
+            out[5] = descale(tmp5 + z2 + z4, odd_shift);
+            out[3] = descale(tmp6 + z2 + z3, odd_shift);
+            out[1] = descale(tmp7 + z1 + z4, odd_shift);
+
+            for (k, value) in out.iter().enumerate() {
+                block[at(k)] = *value;
+            }
+        }
+    }
+}
+
+/// Quantize a block from [`fdct`], rounding to the nearest value
+fn quantize(block: &[i32; 64], table: &[i32; 64], out: &mut [i16]) {
+    for ((out, coeff), q) in out.iter_mut().zip(block).zip(table) {
+        // undo the scaling of the DCT along with quantizing
+        let q = q * 8;
+        let value = (coeff.abs() + q / 2) / q;
+
+        *out = (if *coeff < 0 { -value } else { value }) as i16;
+    }
+}
+
+/// Box filter `input` down by `factor_x` and `factor_y`
+fn downsample(
+    input: &[u8],
+    input_width: usize,
+    output: &mut [u8],
+    output_width: usize,
+    factor_x: usize,
+    factor_y: usize,
+) {
+    let count = factor_x * factor_y;
+
+    for (y, row) in output.chunks_exact_mut(output_width).enumerate() {
+        for (x, out) in row.iter_mut().enumerate() {
+            let mut sum = 0;
+
+            for sy in 0..factor_y {
+                let start = (y * factor_y + sy) * input_width + x * factor_x;
+
+                sum += input[start..start + factor_x]
+                    .iter()
+                    .map(|x| usize::from(*x))
+                    .sum::<usize>();
+            }
+            *out = ((sum + count / 2) / count) as u8;
+        }
+    }
+}
+
+/// A scan of a progressive JPEG
+///
+/// Scans either carry the DC coefficients of one or more components, or
+/// a band of AC coefficients of a single component. The first scan of a
+/// coefficient sends it shifted right by `successive_low` bits, later ones
+/// refine it one bit at a time, with `successive_high` set to the
+/// `successive_low` of the previous scan, see G.1 in the spec.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JpegScan {
+    /// Components in the scan, as indices into the frame's components in
+    /// ascending order, 0 is luma and 1 and 2 are Cb and Cr
+    pub components: Vec<usize>,
+    /// First coefficient in the scan, in zigzag order
+    pub spectral_start: u8,
+    /// Last coefficient in the scan, in zigzag order
+    pub spectral_end: u8,
+    /// Bit position of the previous scan of these coefficients,
+    /// zero on their first scan
+    pub successive_high: u8,
+    /// Bit position of this scan
+    pub successive_low: u8,
+}
+
+impl JpegScan {
+    const fn new(components: Vec<usize>, start: u8, end: u8, high: u8, low: u8) -> JpegScan {
+        JpegScan {
+            components,
+            spectral_start: start,
+            spectral_end: end,
+            successive_high: high,
+            successive_low: low,
+        }
+    }
+
+    /// The scan script libjpeg uses by default for an image of
+    /// `num_components` components
+    ///
+    /// DC goes first at reduced precision, then luma and chroma AC bands,
+    /// then the refinements.
+    #[must_use]
+    pub fn default_script(num_components: usize) -> Vec<JpegScan> {
+        let all: Vec<usize> = (0..num_components).collect();
+
+        if num_components == 3 {
+            vec![
+                JpegScan::new(all.clone(), 0, 0, 0, 1),
+                JpegScan::new(vec![0], 1, 5, 0, 2),
+                JpegScan::new(vec![2], 1, 63, 0, 1),
+                JpegScan::new(vec![1], 1, 63, 0, 1),
+                JpegScan::new(vec![0], 6, 63, 0, 2),
+                JpegScan::new(vec![0], 1, 63, 2, 1),
+                JpegScan::new(all, 0, 0, 1, 0),
+                JpegScan::new(vec![2], 1, 63, 1, 0),
+                JpegScan::new(vec![1], 1, 63, 1, 0),
+                JpegScan::new(vec![0], 1, 63, 1, 0),
+            ]
+        } else {
+            let mut script = vec![JpegScan::new(all.clone(), 0, 0, 0, 1)];
+
+            for c in 0..num_components {
+                script.push(JpegScan::new(vec![c], 1, 5, 0, 2));
+            }
+            for c in 0..num_components {
+                script.push(JpegScan::new(vec![c], 6, 63, 0, 2));
+            }
+            for c in 0..num_components {
+                script.push(JpegScan::new(vec![c], 1, 63, 2, 1));
+            }
+            script.push(JpegScan::new(all, 0, 0, 1, 0));
+
+            for c in 0..num_components {
+                script.push(JpegScan::new(vec![c], 1, 63, 1, 0));
+            }
+            script
+        }
+    }
+}
+
+/// Check that `script` is a valid progressive scan script for an image
+/// with `num_components` components, following G.1.1.1 in the spec
+///
+/// Like libjpeg, AC coefficients may be left out or left at reduced
+/// precision, but every DC coefficient has to be sent.
+fn validate_script(script: &[JpegScan], num_components: usize) -> Result<(), EncodeErrors> {
+    let error = |index: usize, reason: &str| {
+        Err(EncodeErrors::BadScanScript(format!("scan {index}: {reason}")))
+    };
+    // bit position each coefficient has been sent up to, -1 if not sent yet
+    let mut sent = vec![[-1_i32; 64]; num_components];
+
+    if script.is_empty() {
+        return Err(EncodeErrors::BadScanScript("script is empty".to_string()));
+    }
+    for (index, scan) in script.iter().enumerate() {
+        let (start, end) = (
+            usize::from(scan.spectral_start),
+            usize::from(scan.spectral_end),
+        );
+        let (high, low) = (scan.successive_high, scan.successive_low);
+
+        if scan.components.is_empty() || scan.components.len() > 4 {
+            return error(index, "scans must have between 1 and 4 components");
+        }
+        if scan.components.windows(2).any(|x| x[0] >= x[1]) {
+            return error(index, "components must be in ascending order");
+        }
+        if scan.components.iter().any(|c| *c >= num_components) {
+            return error(index, "component out of range");
+        }
+        if start > end || end > 63 {
+            return error(index, "bad spectral selection");
+        }
+        if start == 0 && end != 0 {
+            return error(index, "DC and AC coefficients can't share a scan");
+        }
+        if start != 0 && scan.components.len() != 1 {
+            return error(index, "AC scans must have a single component");
+        }
+        if low > 13 || (high != 0 && high != low + 1) {
+            return error(index, "bad successive approximation");
+        }
+        for c in &scan.components {
+            if start != 0 && sent[*c][0] < 0 {
+                return error(index, "AC coefficients sent before DC");
+            }
+            for coeff in &mut sent[*c][start..=end] {
+                let expected = if high == 0 { -1 } else { i32::from(high) };
+
+                if *coeff != expected {
+                    return error(index, "coefficients sent out of order");
+                }
+                *coeff = i32::from(low);
+            }
+        }
+    }
+    if sent.iter().any(|c| c[0] != 0) {
+        return Err(EncodeErrors::BadScanScript(
+            "script doesn't send every DC coefficient".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// A JPEG encoder
+///
+/// Options are taken from [`EncoderOptions`], the ones respected are
+/// - width, height and colorspace of the input
+/// - quality
+/// - [`jpeg_subsampling`](EncoderOptions::jpeg_subsampling) for color images
+/// - [`jpeg_optimized_huffman_tables`](EncoderOptions::jpeg_optimized_huffman_tables)
+/// - [`jpeg_encode_progressive`](EncoderOptions::jpeg_encode_progressive), using
+///   [`JpegScan::default_script`] unless a script is set with
+///   [`set_scan_script`](JpegEncoder::set_scan_script)
+///
+/// RGB, RGBA, BGR, BGRA and YCbCr input is stored as YCbCr, alpha is
+/// dropped. Luma input is stored as a single component image.
+///
+/// # Example
+/// ```no_run
+/// use zune_core::bit_depth::BitDepth;
+/// use zune_core::colorspace::ColorSpace;
+/// use zune_core::options::EncoderOptions;
+/// use zune_jpeg::JpegEncoder;
+///
+/// let pixels = vec![0_u8; 100 * 100 * 3];
+/// let options = EncoderOptions::new(100, 100, ColorSpace::RGB, BitDepth::Eight);
+/// let jpeg = JpegEncoder::new(&pixels, options).encode().unwrap();
+/// ```
+pub struct JpegEncoder<'a> {
+    data: &'a [u8],
+    options: EncoderOptions,
+    scan_script: Option<Vec<JpegScan>>,
+}
+
+impl<'a> JpegEncoder<'a> {
+    /// Create an encoder for interleaved 8 bit pixels described by `options`
+    #[must_use]
+    pub fn new(data: &'a [u8], options: EncoderOptions) -> JpegEncoder<'a> {
+        JpegEncoder {
+            data,
+            options,
+            scan_script: None,
+        }
+    }
+
+    /// Use `script` for progressive images instead of the default one
+    ///
+    /// Components are numbered as in the encoded image, luma only input
+    /// has a single component. The script is ignored for baseline images.
+    pub fn set_scan_script(&mut self, script: Vec<JpegScan>) {
+        self.scan_script = Some(script);
+    }
+
+    /// Encode the image, returning the JPEG file
+    ///
+    /// # Errors
+    /// If the dimensions are zero or above 65535, the colorspace or depth
+    /// can't be encoded, the input length doesn't match the dimensions or
+    /// the scan script is invalid
+    pub fn encode(&self) -> Result<Vec<u8>, EncodeErrors> {
+        let mut out = Vec::new();
+
+        self.encode_to(&mut out)?;
+        Ok(out)
+    }
+
+    /// Encode the image to `sink`, returning the number of bytes written
+    ///
+    /// The file is passed to the sink a few kilobytes at a time as it is
+    /// written, so it is never held in memory whole, e.g when sent as an
+    /// HTTP response through a [`ZSvcWriter`](crate::zune_core::bytestream::ZSvcWriter).
+    ///
+    /// # Errors
+    /// Like [`encode`](JpegEncoder::encode), or if the sink fails, in which
+    /// case part of the file may have been written
+    pub fn encode_to<W: ZWriterTrait>(&self, sink: W) -> Result<usize, EncodeErrors> {
+        let frame = self.encode_frame()?;
+        let optimize = self.options.jpeg_optimized_huffman_tables();
+        let mut sink = ZByteWriter::new(sink);
+
+        if !self.options.jpeg_encode_progressive() {
+            frame.write_baseline(&mut sink, optimize)?;
+            sink.flush()?;
+
+            return Ok(sink.position());
+        }
+        let num_components = frame.components.len();
+        let default_script;
+
+        let script = if let Some(script) = &self.scan_script {
+            script
+        } else {
+            default_script = JpegScan::default_script(num_components);
+            &default_script
+        };
+        validate_script(script, num_components)?;
+
+        frame.write_progressive(&mut sink, script, optimize)?;
+        sink.flush()?;
+
+        Ok(sink.position())
+    }
+
+    /// Transform and quantize the input into a frame
+    #[allow(clippy::too_many_lines)]
+    pub(crate) fn encode_frame(&self) -> Result<Frame, EncodeErrors> {
+        let options = &self.options;
+        let (width, height) = (options.get_width(), options.get_height());
+        let colorspace = options.get_colorspace();
+
+        let (Ok(out_width), Ok(out_height)) = (u16::try_from(width), u16::try_from(height)) else {
+            return Err(EncodeErrors::BadDimensions(width, height));
+        };
+        if width == 0 || height == 0 {
+            return Err(EncodeErrors::BadDimensions(width, height));
+        }
+        if options.get_depth() != BitDepth::Eight {
+            return Err(EncodeErrors::UnsupportedDepth(options.get_depth()));
+        }
+        if !matches!(
+            colorspace,
+            ColorSpace::RGB
+                | ColorSpace::RGBA
+                | ColorSpace::BGR
+                | ColorSpace::BGRA
+                | ColorSpace::YCbCr
+                | ColorSpace::Luma
+        ) {
+            return Err(EncodeErrors::UnsupportedColorspace(colorspace));
+        }
+        let channels = colorspace.num_components();
+        // 65535x65535 images overflow a usize on 32 bit targets
+        let expected = width
+            .checked_mul(height)
+            .and_then(|x| x.checked_mul(channels))
+            .ok_or(EncodeErrors::TooLarge(width, height))?;
+
+        if self.data.len() != expected {
+            return Err(EncodeErrors::BadInputLength(expected, self.data.len()));
+        }
+
+        let gray = colorspace == ColorSpace::Luma;
+        let (h_max, v_max) = match options.jpeg_subsampling() {
+            _ if gray => (1, 1),
+            JpegSubsampling::S444 => (1, 1),
+            JpegSubsampling::S422 => (2, 1),
+            JpegSubsampling::S420 => (2, 2),
+        };
+        let quality = options.get_quality();
+        let luma_table = scale_table(&STD_LUMA_QT, quality);
+        let chroma_table = scale_table(&STD_CHROMA_QT, quality);
+
+        let mcu_width = 8 * h_max;
+        let mcu_height = 8 * v_max;
+        let mcu_x = width.div_ceil(mcu_width);
+        let mcu_y = height.div_ceil(mcu_height);
+        let padded_width = mcu_x * mcu_width;
+
+        let mut components: Vec<FrameComponent> = (0..if gray { 1 } else { 3 })
+            .map(|pos| {
+                let (h, v) = if pos == 0 { (h_max, v_max) } else { (1, 1) };
+
+                FrameComponent {
+                    id: pos as u8 + 1,
+                    horizontal_sample: h,
+                    vertical_sample: v,
+                    quantization_table: u8::from(pos != 0),
+                    stride: mcu_x * h,
+                    blocks: vec![0; mcu_x * h * mcu_y * v * 64],
+                }
+            })
+            .collect();
+
+        // full resolution samples of one MCU row, per component
+        let mut planes = vec![vec![0_u8; padded_width * mcu_height]; components.len()];
+        let mut downsampled = vec![0_u8; padded_width * mcu_height];
+        let mut block = [0_i32; 64];
+
+        for my in 0..mcu_y {
+            for py in 0..mcu_height {
+                // repeat the last row and column into padding
+                let y = (my * mcu_height + py).min(height - 1);
+                let row = &self.data[y * width * channels..(y + 1) * width * channels];
+
+                for px in 0..padded_width {
+                    let pixel = &row[px.min(width - 1) * channels..][..channels];
+                    let out = py * padded_width + px;
+
+                    let samples = match colorspace {
+                        ColorSpace::Luma => [pixel[0], 0, 0],
+                        ColorSpace::YCbCr => [pixel[0], pixel[1], pixel[2]],
+                        ColorSpace::BGR | ColorSpace::BGRA => {
+                            rgb_to_ycbcr(pixel[2], pixel[1], pixel[0])
+                        }
+                        _ => rgb_to_ycbcr(pixel[0], pixel[1], pixel[2]),
+                    };
+                    for (plane, sample) in planes.iter_mut().zip(samples) {
+                        plane[out] = sample;
+                    }
+                }
+            }
+
+            for (component, plane) in components.iter_mut().zip(&planes) {
+                let (h, v) = (component.horizontal_sample, component.vertical_sample);
+                let plane_width = mcu_x * h * 8;
+
+                let samples = if h == h_max && v == v_max {
+                    &plane[..]
+                } else {
+                    downsample(
+                        plane,
+                        padded_width,
+                        &mut downsampled[..plane_width * v * 8],
+                        plane_width,
+                        h_max / h,
+                        v_max / v,
+                    );
+                    &downsampled[..]
+                };
+                let table = if component.quantization_table == 0 {
+                    &luma_table
+                } else {
+                    &chroma_table
+                };
+
+                for by in 0..v {
+                    for bx in 0..component.stride {
+                        for (i, value) in block.iter_mut().enumerate() {
+                            let sample = samples[(by * 8 + i / 8) * plane_width + bx * 8 + i % 8];
+
+                            *value = i32::from(sample) - 128;
+                        }
+                        fdct(&mut block);
+
+                        let index = (my * v + by) * component.stride + bx;
+
+                        quantize(
+                            &block,
+                            table,
+                            &mut component.blocks[index * 64..index * 64 + 64],
+                        );
+                    }
+                }
+            }
+        }
+
+        let mut quantization_tables = vec![(0, luma_table)];
+
+        if !gray {
+            quantization_tables.push((1, chroma_table));
+        }
+        Ok(Frame {
+            width: out_width,
+            height: out_height,
+            colorspace: if gray {
+                ColorSpace::Luma
+            } else {
+                ColorSpace::YCbCr
+            },
+            quantization_tables,
+            components,
+        })
+    }
+}
+
+#[test]
+#[allow(clippy::cast_precision_loss)]
+fn encode_round_trip() {
+    use super::JpegDecoder;
+
+    // smooth gradients, sized to leave partial MCUs
+    let (width, height) = (61, 43);
+    let mut rgb = Vec::with_capacity(width * height * 3);
+
+    for y in 0..height {
+        for x in 0..width {
+            rgb.extend_from_slice(&[
+                (x * 4) as u8,
+                (y * 5) as u8,
+                (128 + x + y) as u8,
+            ]);
+        }
+    }
+    let rgba: Vec<u8> = rgb
+        .chunks_exact(3)
+        .flat_map(|x| [x[0], x[1], x[2], 255])
+        .collect();
+    let luma: Vec<u8> = rgb.chunks_exact(3).map(|x| x[1]).collect();
+
+    let encode = |data: &[u8], colorspace, subsampling, optimize| {
+        let options = EncoderOptions::new(width, height, colorspace, BitDepth::Eight)
+            .set_quality(95)
+            .set_jpeg_subsampling(subsampling)
+            .set_jpeg_optimized_huffman_tables(optimize);
+        JpegEncoder::new(data, options).encode().unwrap()
+    };
+    // mean absolute difference, per sample
+    let difference = |jpeg: &[u8], expected: &[u8], colorspace| {
+        let options = crate::zune_core::options::DecoderOptions::default()
+            .jpeg_set_out_colorspace(colorspace);
+        let mut decoder = JpegDecoder::new_with_options(jpeg, options);
+        let pixels = decoder.decode().unwrap();
+
+        assert_eq!(decoder.dimensions(), Some((width as u16, height as u16)));
+        assert_eq!(pixels.len(), expected.len());
+
+        let sum: usize = pixels
+            .iter()
+            .zip(expected)
+            .map(|(a, b)| usize::from(a.abs_diff(*b)))
+            .sum();
+        sum as f64 / expected.len() as f64
+    };
+
+    for subsampling in [JpegSubsampling::S444, JpegSubsampling::S422, JpegSubsampling::S420] {
+        let plain = encode(&rgb, ColorSpace::RGB, subsampling, false);
+        let optimized = encode(&rgb, ColorSpace::RGB, subsampling, true);
+
+        assert!(difference(&plain, &rgb, ColorSpace::RGB) < 1.0);
+        // only the Huffman tables differ
+        assert!(optimized.len() <= plain.len());
+        assert_eq!(
+            JpegDecoder::new(&plain).decode().unwrap(),
+            JpegDecoder::new(&optimized).decode().unwrap()
+        );
+
+        let from_rgba = encode(&rgba, ColorSpace::RGBA, subsampling, false);
+        assert_eq!(from_rgba, plain);
+    }
+
+    let gray = encode(&luma, ColorSpace::Luma, JpegSubsampling::S420, false);
+    assert!(difference(&gray, &luma, ColorSpace::Luma) < 1.0);
+
+    let options = EncoderOptions::new(width, height, ColorSpace::CMYK, BitDepth::Eight);
+    assert!(JpegEncoder::new(&rgb, options).encode().is_err());
+
+    let options = EncoderOptions::new(width, height + 1, ColorSpace::RGB, BitDepth::Eight);
+    assert!(JpegEncoder::new(&rgb, options).encode().is_err());
+}
+
+#[test]
+fn progressive_round_trip() {
+    use super::JpegDecoder;
+
+    let (width, height) = (75, 50);
+    let mut rgb = Vec::with_capacity(width * height * 3);
+
+    for y in 0..height {
+        for x in 0..width {
+            rgb.extend_from_slice(&[(x * 3) as u8, (y * 5) as u8, ((x * y) % 251) as u8]);
+        }
+    }
+    let options = EncoderOptions::new(width, height, ColorSpace::RGB, BitDepth::Eight);
+    let baseline = JpegEncoder::new(&rgb, options).encode().unwrap();
+    let expected = JpegDecoder::new(&baseline).decode().unwrap();
+
+    let progressive = options.set_jpeg_encode_progressive(true);
+
+    // the same coefficients are sent, only split over scans
+    for optimize in [false, true] {
+        let options = progressive.set_jpeg_optimized_huffman_tables(optimize);
+        let jpeg = JpegEncoder::new(&rgb, options).encode().unwrap();
+        let mut decoder = JpegDecoder::new(&jpeg);
+
+        assert_eq!(decoder.decode().unwrap(), expected);
+        assert!(decoder.is_progressive);
+    }
+
+    // spectral selection only, chroma first
+    let script = vec![
+        JpegScan::new(vec![0, 1, 2], 0, 0, 0, 0),
+        JpegScan::new(vec![1], 1, 63, 0, 0),
+        JpegScan::new(vec![2], 1, 63, 0, 0),
+        JpegScan::new(vec![0], 1, 9, 0, 0),
+        JpegScan::new(vec![0], 10, 63, 0, 0),
+    ];
+    let mut encoder = JpegEncoder::new(&rgb, progressive);
+    encoder.set_scan_script(script);
+    assert_eq!(
+        JpegDecoder::new(&encoder.encode().unwrap()).decode().unwrap(),
+        expected
+    );
+
+    for script in [
+        // AC before DC
+        vec![
+            JpegScan::new(vec![0], 1, 63, 0, 0),
+            JpegScan::new(vec![0, 1, 2], 0, 0, 0, 0),
+        ],
+        // interleaved AC
+        vec![
+            JpegScan::new(vec![0, 1, 2], 0, 0, 0, 0),
+            JpegScan::new(vec![0, 1], 1, 63, 0, 0),
+        ],
+        // DC left at reduced precision
+        vec![JpegScan::new(vec![0, 1, 2], 0, 0, 0, 1)],
+        // refinement of more than one bit
+        vec![
+            JpegScan::new(vec![0, 1, 2], 0, 0, 0, 2),
+            JpegScan::new(vec![0, 1, 2], 0, 0, 2, 0),
+        ],
+    ] {
+        let mut encoder = JpegEncoder::new(&rgb, progressive);
+        encoder.set_scan_script(script);
+        assert!(matches!(encoder.encode(), Err(EncodeErrors::BadScanScript(_))));
+    }
+}
+
+#[test]
+fn encode_to_sink() {
+    use crate::zune_core::bytestream::ZIoWriter;
+
+    // noise, so the file spans several staged chunks
+    let (width, height) = (128, 96);
+    let rgb: Vec<u8> = (0..width * height * 3).map(|i| (i * i / 7 % 251) as u8).collect();
+    let options = EncoderOptions::new(width, height, ColorSpace::RGB, BitDepth::Eight);
+
+    for options in [options, options.set_jpeg_encode_progressive(true)] {
+        let encoder = JpegEncoder::new(&rgb, options);
+        let expected = encoder.encode().unwrap();
+        assert!(expected.len() > 4096);
+
+        // io sinks see the file in chunks
+        let mut sink = std::io::Cursor::new(Vec::new());
+        let written = encoder.encode_to(ZIoWriter::new(&mut sink)).unwrap();
+        assert_eq!(written, expected.len());
+        assert_eq!(sink.into_inner(), expected);
+
+        // growable buffers are appended to
+        let mut out = vec![1, 2];
+        encoder.encode_to(&mut out).unwrap();
+        assert_eq!(out[2..], expected[..]);
+
+        let mut small = vec![0; 1000];
+        assert!(matches!(
+            encoder.encode_to(&mut small[..]),
+            Err(EncodeErrors::WriteFailed(_))
+        ));
+    }
+}
@@ -1,9 +1,14 @@
 //! Contains most common errors that may be encountered in decoding a Decoder
-//! image
+//! image, and in encoding one
 
 use core::fmt::{Debug, Display, Formatter};
 
+use crate::zune_core::bit_depth::BitDepth;
+use crate::zune_core::bytestream::ZByteIoError;
+use crate::zune_core::colorspace::ColorSpace;
+
 use super::decoder::MAX_DIMENSIONS;
+use super::marker::Marker;
 use super::misc::{
     START_OF_FRAME_EXT_AR, START_OF_FRAME_EXT_SEQ, START_OF_FRAME_LOS_SEQ,
     START_OF_FRAME_LOS_SEQ_AR, START_OF_FRAME_PROG_DCT_AR,
@@ -40,10 +45,171 @@ pub enum DecodeErrors {
     LargeDimensions(usize),
     /// Too small output for size
     TooSmallOutput(usize, usize),
+    /// Too small output for size, with rows the given number of bytes apart
+    TooSmallStridedOutput(usize, usize, usize),
+    /// Rows of the image don't fit in the stride of the output, contains
+    /// the stride and the bytes a row needs from its start
+    TooSmallStride(usize, usize),
+    /// Decoding needs more memory than allowed by the decoder options,
+    /// contains the required and allowed number of bytes
+    MemoryLimitExceeded(usize, usize),
+    /// Scratch memory lent to the decoder is too small, contains the
+    /// required and found number of samples
+    TooSmallScratch(usize, usize),
+    /// An error while parsing a marker segment
+    Segment {
+        /// Marker starting the segment
+        marker: Marker,
+        /// Byte offset of the marker in the stream
+        offset: usize,
+        /// The underlying error
+        error: Box<DecodeErrors>,
+    },
+    /// An error while decoding entropy coded data
+    Entropy {
+        /// MCU row being decoded
+        row: usize,
+        /// MCU column being decoded
+        column: usize,
+        /// Byte offset the stream had been read up to.
+        ///
+        /// The bit reader reads ahead, so this may be a few bytes
+        /// past the corrupt data.
+        offset: usize,
+        /// The underlying error
+        error: Box<DecodeErrors>,
+    },
+}
+
+/// The kind of a [`DecodeErrors`], with positional context stripped
+///
+/// These are stable and cheap to copy around, so they are suited for
+/// counting errors, e.g in metrics.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DecodeErrorKind {
+    /// Malformed or unexpected data not covered by other kinds
+    Format,
+    /// Stream doesn't start with a jpeg SOI marker
+    IllegalMagicBytes,
+    /// Problems with huffman tables or huffman coded data
+    Huffman,
+    /// Image width or height is zero
+    ZeroDimensions,
+    /// Malformed quantization tables
+    Dqt,
+    /// Malformed start of scan segment
+    Sos,
+    /// Malformed start of frame segment
+    Sof,
+    /// Image uses an unsupported encoding scheme
+    Unsupported,
+    /// Errors decoding an MCU
+    Mcu,
+    /// Stream ended too early
+    ExhaustedData,
+    /// Image dimensions are too large
+    LargeDimensions,
+    /// Output buffer is too small
+    TooSmallOutput,
+    /// Decoding needs more memory than allowed
+    MemoryLimit,
+    /// Scratch memory is too small
+    TooSmallScratch,
+}
+
+impl DecodeErrorKind {
+    /// A stable name for this error kind
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Format => "format",
+            Self::IllegalMagicBytes => "illegal_magic_bytes",
+            Self::Huffman => "huffman",
+            Self::ZeroDimensions => "zero_dimensions",
+            Self::Dqt => "dqt",
+            Self::Sos => "sos",
+            Self::Sof => "sof",
+            Self::Unsupported => "unsupported",
+            Self::Mcu => "mcu",
+            Self::ExhaustedData => "exhausted_data",
+            Self::LargeDimensions => "large_dimensions",
+            Self::TooSmallOutput => "too_small_output",
+            Self::MemoryLimit => "memory_limit",
+            Self::TooSmallScratch => "too_small_scratch",
+        }
+    }
 }
 
-#[cfg(feature = "std")]
-impl std::error::Error for DecodeErrors {}
+impl DecodeErrors {
+    /// Return the kind of this error
+    ///
+    /// For positional errors this is the kind of the underlying error.
+    #[must_use]
+    pub fn kind(&self) -> DecodeErrorKind {
+        match self {
+            Self::Format(_) | Self::FormatStatic(_) => DecodeErrorKind::Format,
+            Self::IllegalMagicBytes(_) => DecodeErrorKind::IllegalMagicBytes,
+            Self::HuffmanDecode(_) => DecodeErrorKind::Huffman,
+            Self::ZeroError => DecodeErrorKind::ZeroDimensions,
+            Self::DqtError(_) => DecodeErrorKind::Dqt,
+            Self::SosError(_) => DecodeErrorKind::Sos,
+            Self::SofError(_) => DecodeErrorKind::Sof,
+            Self::Unsupported(_) => DecodeErrorKind::Unsupported,
+            Self::MCUError(_) => DecodeErrorKind::Mcu,
+            Self::ExhaustedData => DecodeErrorKind::ExhaustedData,
+            Self::LargeDimensions(_) => DecodeErrorKind::LargeDimensions,
+            Self::TooSmallOutput(_, _)
+            | Self::TooSmallStridedOutput(_, _, _)
+            | Self::TooSmallStride(_, _) => DecodeErrorKind::TooSmallOutput,
+            Self::MemoryLimitExceeded(_, _) => DecodeErrorKind::MemoryLimit,
+            Self::TooSmallScratch(_, _) => DecodeErrorKind::TooSmallScratch,
+            Self::Segment { error, .. } | Self::Entropy { error, .. } => error.kind(),
+        }
+    }
+    /// Return the byte offset in the stream where this error occurred,
+    /// if known
+    #[must_use]
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            Self::Segment { offset, .. } | Self::Entropy { offset, .. } => Some(*offset),
+            _ => None,
+        }
+    }
+    /// Attach the segment this error occurred in, unless the error
+    /// already carries a position
+    pub(crate) fn in_segment(self, marker: Marker, offset: usize) -> DecodeErrors {
+        match self {
+            Self::Segment { .. } | Self::Entropy { .. } => self,
+            error => Self::Segment {
+                marker,
+                offset,
+                error: Box::new(error),
+            },
+        }
+    }
+    /// Attach the MCU position this error occurred in, unless the error
+    /// already carries a position
+    pub(crate) fn in_mcu(self, row: usize, column: usize, offset: usize) -> DecodeErrors {
+        match self {
+            Self::Segment { .. } | Self::Entropy { .. } => self,
+            error => Self::Entropy {
+                row,
+                column,
+                offset,
+                error: Box::new(error),
+            },
+        }
+    }
+}
+
+impl std::error::Error for DecodeErrors {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Segment { error, .. } | Self::Entropy { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 impl From<&'static str> for DecodeErrors {
     fn from(data: &'static str) -> Self {
@@ -79,7 +245,13 @@ impl Debug for DecodeErrors {
                 f,
                 "Too large dimensions {dimensions},library supports up to {MAX_DIMENSIONS}"
             ),
-            Self::TooSmallOutput(expected, found) => write!(f, "Too small output, expected buffer with at least {expected} bytes but got one with {found} bytes")
+            Self::TooSmallOutput(expected, found) => write!(f, "Too small output, expected buffer with at least {expected} bytes but got one with {found} bytes"),
+            Self::TooSmallStridedOutput(expected, found, stride) => write!(f, "Too small output, expected buffer with at least {expected} bytes for rows {stride} bytes apart but got one with {found} bytes"),
+            Self::TooSmallStride(stride, required) => write!(f, "Too small output stride of {stride} bytes, rows need {required} bytes"),
+            Self::MemoryLimitExceeded(required, allowed) => write!(f, "Memory limit exceeded, decoding requires {required} bytes but only {allowed} bytes are allowed"),
+            Self::TooSmallScratch(expected, found) => write!(f, "Too small scratch memory, expected at least {expected} samples but got {found} samples"),
+            Self::Segment { marker, offset, error } => write!(f, "{error:?} (in {marker:?} segment at byte {offset})"),
+            Self::Entropy { row, column, offset, error } => write!(f, "{error:?} (at MCU row {row}, column {column}, near byte {offset})")
         }
     }
 }
@@ -90,6 +262,71 @@ impl Display for DecodeErrors {
     }
 }
 
+/// Common Encode errors
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone)]
+pub enum EncodeErrors {
+    /// Width or height is zero or larger than a jpeg can store,
+    /// contains the width and height
+    BadDimensions(usize, usize),
+    /// The image is larger than the address space of the target,
+    /// contains the width and height
+    TooLarge(usize, usize),
+    /// Input length doesn't match the dimensions and colorspace,
+    /// contains the expected and found lengths
+    BadInputLength(usize, usize),
+    /// The input colorspace can't be encoded
+    UnsupportedColorspace(ColorSpace),
+    /// The input bit depth can't be encoded
+    UnsupportedDepth(BitDepth),
+    /// A progressive scan script is invalid, contains the reason
+    BadScanScript(String),
+    /// The sink the image was written to failed, contains the error
+    /// formatted, as sink errors can't be cloned
+    WriteFailed(String),
+}
+
+impl std::error::Error for EncodeErrors {}
+
+impl Debug for EncodeErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::BadDimensions(width, height) => write!(
+                f,
+                "Cannot encode a {width}x{height} image, dimensions must be between 1 and 65535"
+            ),
+            Self::TooLarge(width, height) => write!(
+                f,
+                "Cannot encode a {width}x{height} image, it is too large for this target"
+            ),
+            Self::BadInputLength(expected, found) => write!(
+                f,
+                "Input length mismatch, expected {expected} bytes but found {found} bytes"
+            ),
+            Self::UnsupportedColorspace(colorspace) => {
+                write!(f, "Cannot encode images in the {colorspace:?} colorspace")
+            }
+            Self::UnsupportedDepth(depth) => {
+                write!(f, "Cannot encode images with a {depth:?} bit depth")
+            }
+            Self::BadScanScript(reason) => write!(f, "Invalid scan script: {reason}"),
+            Self::WriteFailed(error) => write!(f, "Could not write the image: {error}"),
+        }
+    }
+}
+
+impl Display for EncodeErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl From<ZByteIoError> for EncodeErrors {
+    fn from(error: ZByteIoError) -> Self {
+        EncodeErrors::WriteFailed(format!("{error:?}"))
+    }
+}
+
 /// Contains Unsupported/Yet-to-be supported Decoder image encoding types.
 #[derive(Eq, PartialEq, Copy, Clone)]
 
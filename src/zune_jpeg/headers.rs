@@ -222,9 +222,13 @@ pub(crate) fn parse_start_of_frame<T: ZReaderTrait>(
     }
 
     // Check image width or height is zero
-    if img_width == 0 || img_height == 0 {
+    //
+    // Sequential images may leave the height out, it's then defined by a DNL
+    // segment after the first scan
+    if img_width == 0 || (img_height == 0 && sof.is_progressive()) {
         return Err(DecodeErrors::ZeroError);
     }
+    img.awaiting_dnl = img_height == 0;
 
     // Number of components for the image.
     let num_components = img.stream.get_u8_err()?;
@@ -279,6 +283,38 @@ pub(crate) fn parse_start_of_frame<T: ZReaderTrait>(
     Ok(())
 }
 
+/// Parse a define number of lines segment
+///
+/// Section: `B.2.5 Define number of lines syntax`
+pub(crate) fn parse_dnl<T: ZReaderTrait>(img: &mut JpegDecoder<T>) -> Result<(), DecodeErrors> {
+    let length = img.stream.get_u16_be_err()?;
+
+    if length != 4 {
+        return Err(DecodeErrors::Format(format!(
+            "Bad DNL length {length}, corrupt jpeg"
+        )));
+    }
+    let img_height = img.stream.get_u16_be_err()?;
+
+    if img_height == 0 {
+        return Err(DecodeErrors::ZeroError);
+    }
+    if usize::from(img_height) > img.options.get_max_height() {
+        return Err(DecodeErrors::Format(format!("Image height {} greater than height limit {}. If use `set_limits` if you want to support huge images", img_height, img.options.get_max_height())));
+    }
+    if !img.awaiting_dnl && img_height != img.info.height {
+        return Err(DecodeErrors::Format(format!(
+            "DNL height {img_height} differs from frame height {}",
+            img.info.height
+        )));
+    }
+    // log::info!("Image height from DNL :{}", img_height);
+    img.info.set_height(img_height);
+    img.awaiting_dnl = false;
+
+    Ok(())
+}
+
 /// Parse a start of scan data
 pub(crate) fn parse_sos<T: ZReaderTrait>(image: &mut JpegDecoder<T>) -> Result<(), DecodeErrors> {
     // Scan header length
@@ -421,7 +457,7 @@ pub(crate) fn parse_app14<T: ZReaderTrait>(
             "Too short of a length for App14 segment",
         ));
     }
-    if decoder.stream.peek_at(0, 5) == Ok(b"Adobe") {
+    if decoder.stream.peek_fixed_bytes::<5>(0) == Ok(*b"Adobe") {
         // move stream 6 bytes to remove adobe id
         decoder.stream.skip(6);
         // skip version, flags0 and flags1
@@ -471,13 +507,14 @@ pub(crate) fn parse_app1<T: ZReaderTrait>(
     // length bytes
     length -= 2;
 
-    if length > 6 && decoder.stream.peek_at(0, 6).unwrap() == b"Exif\x00\x00" {
+    if length > 6 && decoder.stream.peek_fixed_bytes::<6>(0) == Ok(*b"Exif\x00\x00") {
         // log::info!("Exif segment present");
         // skip bytes we read above
         decoder.stream.skip(6);
         length -= 6;
 
-        let exif_bytes = decoder.stream.peek_at(0, length).unwrap().to_vec();
+        let mut exif_bytes = vec![0; length];
+        decoder.stream.peek_into(0, &mut exif_bytes).unwrap();
 
         decoder.exif_data = Some(exif_bytes);
     } else {
@@ -499,7 +536,7 @@ pub(crate) fn parse_app2<T: ZReaderTrait>(
     // length bytes
     length -= 2;
 
-    if length > 14 && decoder.stream.peek_at(0, 12).unwrap() == *b"ICC_PROFILE\0" {
+    if length > 14 && decoder.stream.peek_fixed_bytes::<12>(0) == Ok(*b"ICC_PROFILE\0") {
         // log::info!("ICC Profile present");
         // skip 12 bytes which indicate ICC profile
         length -= 12;
@@ -509,7 +546,8 @@ pub(crate) fn parse_app2<T: ZReaderTrait>(
         // deduct the two bytes we read above
         length -= 2;
 
-        let data = decoder.stream.peek_at(0, length).unwrap().to_vec();
+        let mut data = vec![0; length];
+        decoder.stream.peek_into(0, &mut data).unwrap();
 
         let icc_chunk = ICCChunk {
             seq_no,
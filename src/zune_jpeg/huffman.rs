@@ -5,6 +5,10 @@
 use super::errors::DecodeErrors;
 
 /// Determines how many bits of lookahead we have for our bitstream decoder.
+///
+/// Lookup tables have `1 << HUFF_LOOKAHEAD` entries, 9 bits keeps them small
+/// on targets with little memory, and within the 16 bits a refill of the
+/// 32 bit accumulator leaves.
 
 pub const HUFF_LOOKAHEAD: u8 = 9;
 
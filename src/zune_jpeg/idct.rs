@@ -1,21 +1,10 @@
 //! Routines for IDCT
 //!
-//! Essentially we provide 2 routines for IDCT, a scalar implementation and a not super optimized
-//! AVX2 one, i'll talk about them here.
+//! The integer IDCT takes shortcuts for blocks with only a DC coefficient, and for columns
+//! whose AC coefficients are zero, which is most of them in low quality images. It only uses
+//! 32 bit arithmetic.
 //!
-//! There are 2 reasons why we have the avx one
-//! 1. No one compiles with -C target-features=avx2 hence binaries won't probably take advantage(even
-//! if it exists).
-//! 2. AVX employs zero short circuit in a way the scalar code cannot employ it.
-//!     - AVX does this by checking for MCU's whose 63 AC coefficients are zero and if true, it writes
-//!        values directly, if false, it goes the long way of calculating.
-//!     -   Although this can be trivially implemented in the scalar version, it  generates code
-//!         I'm not happy width(scalar version that basically loops and that is too many branches for me)
-//!         The avx one does a better job of using bitwise or's with (`_mm256_or_si256`) which is magnitudes of faster
-//!         than anything I could come up with
-//!
-//! The AVX code also has some cool transpose_u16 instructions which look so complicated to be cool
-//! (spoiler alert, i barely understand how it works, that's why I credited the owner).
+//! Exact mode uses libjpeg's accurate integer IDCT instead, so output matches it byte for byte.
 //!
 #![allow(
     clippy::excessive_precision,
@@ -28,24 +17,17 @@
 use crate::zune_core::options::DecoderOptions;
 
 use super::decoder::IDCTPtr;
-pub use super::idct::scalar::idct_int;
-
-#[cfg(feature = "x86")]
-mod avx2;
+pub use super::idct::scalar::{idct_int, idct_islow};
+#[cfg(test)]
+use super::idct::scalar::idct_int_reference;
 
-mod scalar;
+pub(crate) mod scalar;
 
 /// Choose an appropriate IDCT function
 #[allow(unused_variables)]
 pub fn choose_idct_func(options: &DecoderOptions) -> IDCTPtr {
-    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-    #[cfg(feature = "x86")]
-    {
-        if options.use_avx2() {
-            debug!("Using vector integer IDCT");
-            // use avx one
-            return crate::idct::avx2::idct_avx2;
-        }
+    if options.jpeg_get_exact() {
+        return idct_islow;
     }
     // log::debug!("Using scalar integer IDCT");
     // use generic one
@@ -53,52 +35,89 @@ pub fn choose_idct_func(options: &DecoderOptions) -> IDCTPtr {
 }
 
 #[test]
-#[cfg(feature = "x86")]
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-fn idct_test0() {
-    use crate::idct::avx2::idct_avx2;
-    use crate::idct::scalar::idct_int;
+fn idct_islow_flat_block() {
+    use crate::zune_jpeg::idct::scalar::idct_islow;
 
-    let stride = 8;
-    let mut coeff = [10; 64];
-    let mut coeff2 = [10; 64];
-    let mut output_scalar = [0; 64];
-    let mut output_vector = [0; 64];
-    idct_avx2(&mut coeff, &mut output_vector, stride);
-    idct_int(&mut coeff2, &mut output_scalar, stride);
-    assert_eq!(output_scalar, output_vector, "AVX and scalar do not match");
+    for (dc, expected) in [(0, 128), (80, 138), (-1100, 0), (1100, 255)] {
+        let mut coeff = [0; 64];
+        let mut output = [0; 64];
+
+        coeff[0] = dc;
+        idct_islow(&mut coeff, &mut output, 8);
+        assert!(output.iter().all(|x| *x == expected), "{dc}: {output:?}");
+    }
 }
 
 #[test]
-#[cfg(feature = "x86")]
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-fn idct_test1() {
-    use crate::idct::avx2::idct_avx2;
-    use crate::idct::scalar::idct_int;
+fn idct_int_matches_reference() {
+    let check = |coeff: &[i32; 64]| {
+        let mut output = [0; 64];
+        let mut expected = [0; 64];
 
-    let stride = 8;
-    let mut coeff = [14; 64];
-    let mut coeff2 = [14; 64];
-    let mut output_scalar = [0; 64];
-    let mut output_vector = [0; 64];
-    idct_avx2(&mut coeff, &mut output_vector, stride);
-    idct_int(&mut coeff2, &mut output_scalar, stride);
-    assert_eq!(output_scalar, output_vector, "AVX and scalar do not match");
-}
+        idct_int(&mut coeff.clone(), &mut output, 8);
+        idct_int_reference(&mut coeff.clone(), &mut expected, 8);
+        assert_eq!(output, expected, "{coeff:?}");
+    };
+    // only a DC coefficient
+    for dc in -32768..32768 {
+        let mut coeff = [0; 64];
+        coeff[0] = dc;
+        check(&coeff);
+    }
+    // a single AC coefficient
+    for pos in 1..64 {
+        for value in -2048..2048 {
+            let mut coeff = [0; 64];
+            coeff[0] = 517;
+            coeff[pos] = value;
+            check(&coeff);
+        }
+    }
+    // two coefficients
+    for a in 0..64 {
+        for b in a + 1..64 {
+            for (x, y) in [(1, -1), (-37, 255), (1023, 1023), (-2048, 7), (4095, -4096)] {
+                let mut coeff = [0; 64];
+                coeff[a] = x;
+                coeff[b] = y;
+                check(&coeff);
+            }
+        }
+    }
+    // random blocks, from dense to sparse rows and columns
+    let mut state = 0x2545_f491_u32;
+    let mut next = || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    };
+    for _ in 0..100_000 {
+        let (rows, cols) = (next() & 0xFF, next() & 0xFF);
+        let density = next() % 64;
+        let mut coeff = [0; 64];
 
-#[test]
-#[cfg(feature = "x86")]
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-fn idct_zeroes() {
-    use crate::idct::avx2::idct_avx2;
-    use crate::idct::scalar::idct_int;
+        for (i, c) in coeff.iter_mut().enumerate() {
+            let (row, col) = (i / 8, i % 8);
 
-    let stride = 8;
+            if (rows >> row) & 1 == 1 && (cols >> col) & 1 == 1 && next() % 64 <= density {
+                *c = i32::try_from(next() % 8192).unwrap() - 4096;
+            }
+        }
+        check(&coeff);
+    }
+    // rows are `stride` apart, and nothing between them is written
     let mut coeff = [0; 64];
-    let mut coeff2 = [0; 64];
-    let mut output_scalar = [0; 64];
-    let mut output_vector = [0; 64];
-    idct_avx2(&mut coeff, &mut output_vector, stride);
-    idct_int(&mut coeff2, &mut output_scalar, stride);
-    assert_eq!(output_scalar, output_vector, "AVX and scalar do not match");
+    coeff[0] = 240;
+    coeff[9] = -75;
+    let mut output = [-1; 8 * 12];
+    let mut expected = [0; 64];
+
+    idct_int(&mut coeff.clone(), &mut output, 12);
+    idct_int_reference(&mut coeff, &mut expected, 8);
+
+    for (row, expected) in output.chunks_exact(12).zip(expected.chunks_exact(8)) {
+        assert_eq!(&row[..8], expected);
+        assert!(row[8..].iter().all(|x| *x == -1));
+    }
 }
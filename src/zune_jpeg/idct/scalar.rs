@@ -1,16 +1,328 @@
 //! Platform independent IDCT algorithm
 //!
-//! Not as fast as AVX one.
+//! [`idct_islow`] is a port of libjpeg's `jpeg_idct_islow`, for callers who
+//! need libjpeg's output bit for bit.
+
+use core::num::Wrapping;
 
 const SCALE_BITS: i32 = 512 + 65536 + (128 << 17);
 
+/// One dimensional pass of [`idct_int`], with constants scaled by 1 << 12
+///
+/// `bias` is added before descaling by `shift` bits. Arithmetic is on 32 bit
+/// integers and wraps.
+#[inline(always)]
+fn idct_int_1d(x: [i32; 8], bias: i32, shift: u32) -> [i32; 8]
+{
+    // even part
+    let p1 = x[2].wrapping_add(x[6]).wrapping_mul(2217);
+    let t2 = p1.wrapping_add(x[6].wrapping_mul(-7567));
+    let t3 = p1.wrapping_add(x[2].wrapping_mul(3135));
+
+    let t0 = fsh(x[0].wrapping_add(x[4]));
+    let t1 = fsh(x[0].wrapping_sub(x[4]));
+
+    let x0 = t0.wrapping_add(t3).wrapping_add(bias);
+    let x3 = t0.wrapping_sub(t3).wrapping_add(bias);
+    let x1 = t1.wrapping_add(t2).wrapping_add(bias);
+    let x2 = t1.wrapping_sub(t2).wrapping_add(bias);
+
+    // odd part
+    let p3 = x[7].wrapping_add(x[3]);
+    let p4 = x[5].wrapping_add(x[1]);
+    let p1 = x[7].wrapping_add(x[1]);
+    let p2 = x[5].wrapping_add(x[3]);
+    let p5 = p3.wrapping_add(p4).wrapping_mul(4816);
+
+    let p1 = p5.wrapping_add(p1.wrapping_mul(-3685));
+    let p2 = p5.wrapping_add(p2.wrapping_mul(-10497));
+    let p3 = p3.wrapping_mul(-8034);
+    let p4 = p4.wrapping_mul(-1597);
+
+    let t0 = x[7].wrapping_mul(1223).wrapping_add(p1).wrapping_add(p3);
+    let t1 = x[5].wrapping_mul(8410).wrapping_add(p2).wrapping_add(p4);
+    let t2 = x[3].wrapping_mul(12586).wrapping_add(p2).wrapping_add(p3);
+    let t3 = x[1].wrapping_mul(6149).wrapping_add(p1).wrapping_add(p4);
+
+    [
+        x0.wrapping_add(t3) >> shift,
+        x1.wrapping_add(t2) >> shift,
+        x2.wrapping_add(t1) >> shift,
+        x3.wrapping_add(t0) >> shift,
+        x3.wrapping_sub(t0) >> shift,
+        x2.wrapping_sub(t1) >> shift,
+        x1.wrapping_sub(t2) >> shift,
+        x0.wrapping_sub(t3) >> shift
+    ]
+}
+
+/// Integer IDCT
+///
+/// Low quality images are mostly made of blocks with a few coefficients, so
+/// this skips work the full transform would do on zeroes
+/// - Blocks whose 63 AC coefficients are zero are filled with a single value.
+/// - Columns whose AC coefficients are zero are a scaled copy of their DC
+///   coefficient after the first pass.
+/// - When only the first column has coefficients, rows are a single value
+///   after the first pass.
+///
+/// The shortcuts give the same output as the full transform would. All
+/// arithmetic is on 32 bit integers, with no 64 bit multiplies, so this
+/// stays cheap on 32 bit targets.
+#[allow(clippy::cast_possible_truncation)]
+pub fn idct_int(in_vector: &mut [i32; 64], out_vector: &mut [i16], stride: usize)
+{
+    if in_vector[1..] == [0; 63]
+    {
+        let value = clamp((in_vector[0] >> 3) + 128);
+
+        for row in 0..8
+        {
+            out_vector[row * stride..row * stride + 8].fill(value);
+        }
+        return;
+    }
+    // AC coefficients of each column, or-ed together
+    let mut column_ac = [0; 8];
+
+    for row in in_vector[8..].chunks_exact(8)
+    {
+        for (ac, x) in column_ac.iter_mut().zip(row)
+        {
+            *ac |= x;
+        }
+    }
+    // a column with no coefficients stays zero after the first pass, so rows
+    // only lose their AC terms together, when just the first column has
+    // coefficients
+    let first_column_only = in_vector[1..8] == [0; 7] && column_ac[1..] == [0; 7];
+
+    // columns, in place
+    //
+    // constants scaled things up by 1<<12; let's bring them back
+    // down, but keep 2 extra bits of precision
+    if column_ac.contains(&0)
+    {
+        for col in 0..8
+        {
+            if column_ac[col] == 0
+            {
+                // a column with only a DC coefficient is a copy of it
+                let value = fsh(in_vector[col]).wrapping_add(512) >> 10;
+
+                for row in 0..8
+                {
+                    in_vector[row * 8 + col] = value;
+                }
+                continue;
+            }
+            let x: [i32; 8] = core::array::from_fn(|row| in_vector[row * 8 + col]);
+
+            for (row, value) in idct_int_1d(x, 512, 10).into_iter().enumerate()
+            {
+                in_vector[row * 8 + col] = value;
+            }
+        }
+    }
+    else
+    {
+        // kept free of branches so it can be vectorized
+        for col in 0..8
+        {
+            let x: [i32; 8] = core::array::from_fn(|row| in_vector[row * 8 + col]);
+
+            for (row, value) in idct_int_1d(x, 512, 10).into_iter().enumerate()
+            {
+                in_vector[row * 8 + col] = value;
+            }
+        }
+    }
+    // rows
+    if first_column_only
+    {
+        for (row, x) in in_vector.chunks_exact(8).enumerate()
+        {
+            let value = clamp(fsh(x[0]).wrapping_add(SCALE_BITS) >> 17);
+
+            out_vector[row * stride..row * stride + 8].fill(value);
+        }
+        return;
+    }
+    for (row, x) in in_vector.chunks_exact(8).enumerate()
+    {
+        let x: [i32; 8] = x.try_into().unwrap();
+        let out = &mut out_vector[row * stride..row * stride + 8];
+
+        // constants scaled things up by 1<<12, plus we had 1<<2 from first
+        // loop, plus horizontal and vertical each scale by sqrt(8) so together
+        // we've got an extra 1<<3, so 1<<17 total we need to remove.
+        // so we want to round that, which means adding 0.5 * 1<<17,
+        // aka 65536. Also, we'll end up with -128 to 127 that we want
+        // to encode as 0..255 by adding 128, so we'll add that before the shift
+        for (out, value) in out.iter_mut().zip(idct_int_1d(x, SCALE_BITS, 17))
+        {
+            *out = clamp(value);
+        }
+    }
+}
+
+// libjpeg constants, scaled by 1 << 13
+pub(crate) const FIX_0_298631336: i32 = 2446;
+pub(crate) const FIX_0_390180644: i32 = 3196;
+pub(crate) const FIX_0_541196100: i32 = 4433;
+pub(crate) const FIX_0_765366865: i32 = 6270;
+pub(crate) const FIX_0_899976223: i32 = 7373;
+pub(crate) const FIX_1_175875602: i32 = 9633;
+pub(crate) const FIX_1_501321110: i32 = 12299;
+pub(crate) const FIX_1_847759065: i32 = 15137;
+pub(crate) const FIX_1_961570560: i32 = 16069;
+pub(crate) const FIX_2_053119869: i32 = 16819;
+pub(crate) const FIX_2_562915447: i32 = 20995;
+pub(crate) const FIX_3_072711026: i32 = 25172;
+
+const CONST_BITS: usize = 13;
+const PASS1_BITS: usize = 2;
+
+/// One dimensional pass of `jpeg_idct_islow`, descaling by `shift` bits
+///
+/// Arithmetic wraps, like libjpeg's 32 bit `JLONG` does on corrupt data.
+#[inline(always)]
+fn islow_1d(x: [Wrapping<i32>; 8], shift: usize) -> [Wrapping<i32>; 8]
+{
+    let fix = Wrapping;
+
+    // Even part
+    let z1 = (x[2] + x[6]) * fix(FIX_0_541196100);
+    let tmp2 = z1 - x[6] * fix(FIX_1_847759065);
+    let tmp3 = z1 + x[2] * fix(FIX_0_765366865);
+
+    let tmp0 = (x[0] + x[4]) << CONST_BITS;
+    let tmp1 = (x[0] - x[4]) << CONST_BITS;
+
+    let tmp10 = tmp0 + tmp3;
+    let tmp13 = tmp0 - tmp3;
+    let tmp11 = tmp1 + tmp2;
+    let tmp12 = tmp1 - tmp2;
+
+    // Odd part
+    let z1 = x[7] + x[1];
+    let z2 = x[5] + x[3];
+    let z3 = x[7] + x[3];
+    let z4 = x[5] + x[1];
+    let z5 = (z3 + z4) * fix(FIX_1_175875602);
+
+    let z1 = z1 * fix(-FIX_0_899976223);
+    let z2 = z2 * fix(-FIX_2_562915447);
+    let z3 = z3 * fix(-FIX_1_961570560) + z5;
+    let z4 = z4 * fix(-FIX_0_390180644) + z5;
+
+    let tmp0 = x[7] * fix(FIX_0_298631336) + z1 + z3;
+    let tmp1 = x[5] * fix(FIX_2_053119869) + z2 + z4;
+    let tmp2 = x[3] * fix(FIX_3_072711026) + z2 + z3;
+    let tmp3 = x[1] * fix(FIX_1_501321110) + z1 + z4;
+
+    let round = fix(1 << (shift - 1));
+
+    [
+        (tmp10 + tmp3 + round) >> shift,
+        (tmp11 + tmp2 + round) >> shift,
+        (tmp12 + tmp1 + round) >> shift,
+        (tmp13 + tmp0 + round) >> shift,
+        (tmp13 - tmp0 + round) >> shift,
+        (tmp12 - tmp1 + round) >> shift,
+        (tmp11 - tmp2 + round) >> shift,
+        (tmp10 - tmp3 + round) >> shift
+    ]
+}
+
+/// libjpeg's range limiting of IDCT output
+///
+/// libjpeg masks values to 10 bits before looking them up in a table, so
+/// values far out of range wrap around instead of saturating.
+#[inline(always)]
+#[allow(clippy::cast_possible_truncation)]
+fn islow_range_limit(x: Wrapping<i32>) -> i16
+{
+    let x = x.0 & 1023;
+
+    (match x
+    {
+        0..=127 => x + 128,
+        128..=511 => 255,
+        512..=895 => 0,
+        _ => x - 896
+    }) as i16
+}
+
+/// Accurate integer IDCT, matching libjpeg's `jpeg_idct_islow` bit for bit
+///
+/// This is slower than [`idct_int`], which uses a different rounding.
+pub fn idct_islow(in_vector: &mut [i32; 64], out_vector: &mut [i16], stride: usize)
+{
+    let mut workspace = [[Wrapping(0_i32); 8]; 8];
+
+    // columns, into the transposed workspace
+    for (col, ws) in workspace.iter_mut().enumerate()
+    {
+        let x: [Wrapping<i32>; 8] = core::array::from_fn(|row| Wrapping(in_vector[row * 8 + col]));
+
+        if x[1..].iter().all(|v| v.0 == 0)
+        {
+            // the same as the full transform, only faster
+            *ws = [x[0] << PASS1_BITS; 8];
+            continue;
+        }
+        *ws = islow_1d(x, CONST_BITS - PASS1_BITS);
+    }
+    // rows
+    for row in 0..8
+    {
+        let x = core::array::from_fn(|col| workspace[col][row]);
+        let out = &mut out_vector[row * stride..row * stride + 8];
+
+        for (out, value) in out
+            .iter_mut()
+            .zip(islow_1d(x, CONST_BITS + PASS1_BITS + 3))
+        {
+            *out = islow_range_limit(value);
+        }
+    }
+}
+
+#[cfg(test)]
+#[inline]
+#[allow(clippy::cast_possible_truncation)]
+/// Multiply a number by 4096
+fn f2f(x: f32) -> i32
+{
+    (x * 4096.0 + 0.5) as i32
+}
+
+#[inline]
+/// Multiply a number by 4096
+fn fsh(x: i32) -> i32
+{
+    x << 12
+}
+
+/// Clamp values between 0 and 255
+#[inline]
+#[allow(clippy::cast_possible_truncation)]
+fn clamp(a: i32) -> i16
+{
+    a.clamp(0, 255) as i16
+}
+
+/// The full transform [`idct_int`] used to do, to check its shortcuts
+/// give the same output
+#[cfg(test)]
 #[allow(unused_assignments)]
 #[allow(
     clippy::too_many_lines,
     clippy::op_ref,
     clippy::cast_possible_truncation
 )]
-pub fn idct_int(in_vector: &mut [i32; 64], out_vector: &mut [i16], stride: usize)
+pub fn idct_int_reference(in_vector: &mut [i32; 64], out_vector: &mut [i16], stride: usize)
 {
     // Temporary variables.
 
@@ -188,26 +500,3 @@ pub fn idct_int(in_vector: &mut [i32; 64], out_vector: &mut [i16], stride: usize
         }
     }
 }
-
-#[inline]
-#[allow(clippy::cast_possible_truncation)]
-/// Multiply a number by 4096
-fn f2f(x: f32) -> i32
-{
-    (x * 4096.0 + 0.5) as i32
-}
-
-#[inline]
-/// Multiply a number by 4096
-fn fsh(x: i32) -> i32
-{
-    x << 12
-}
-
-/// Clamp values between 0 and 255
-#[inline]
-#[allow(clippy::cast_possible_truncation)]
-fn clamp(a: i32) -> i16
-{
-    a.clamp(0, 255) as i16
-}
@@ -6,19 +6,23 @@
  * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
  */
 
-use std::cmp::min;
-
 use crate::zune_core::bytestream::ZReaderTrait;
 use crate::zune_core::colorspace::ColorSpace;
+use crate::zune_core::options::{JpegColorRange, JpegConcealment};
 
 use super::bitstream::BitStream;
+use super::color_convert::expand_studio_luma;
 use super::components::SampleRatios;
 use super::decoder::MAX_COMPONENTS;
 use super::errors::DecodeErrors;
 use super::marker::Marker;
+use super::headers::parse_dnl;
 use super::misc::{calculate_padded_width, setup_component_params};
+use super::report::{DecodeReport, DecodeWarning};
+use super::scratch::ScratchRegion;
 use super::worker::{
-    color_convert_no_sampling, upsample_and_color_convert_h, upsample_and_color_convert_v,
+    color_convert_no_sampling, output_rows, upsample_and_color_convert_generic,
+    upsample_and_color_convert_h, upsample_and_color_convert_v,
 };
 use super::JpegDecoder;
 
@@ -86,42 +90,10 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
         &mut self,
         pixels: &mut [u8],
     ) -> Result<(), DecodeErrors> {
-        setup_component_params(self)?;
-
-        // check dc and AC tables
-        self.check_tables()?;
-
-        let (mut mcu_width, mut mcu_height);
-
-        if self.is_interleaved {
-            // set upsampling functions
-            self.set_upsampling()?;
+        let (mcu_width, mcu_height) = self.setup_baseline()?;
 
-            mcu_width = self.mcu_x;
-            mcu_height = self.mcu_y;
-        } else {
-            // For non-interleaved images( (1*1) subsampling)
-            // number of MCU's are the widths (+7 to account for paddings) divided bu 8.
-            mcu_width = ((self.info.width + 7) / 8) as usize;
-            mcu_height = ((self.info.height + 7) / 8) as usize;
-        }
-
-        if self.input_colorspace.num_components() > self.components.len() {
-            let msg = format!(
-                " Expected {} number of components but found {}",
-                self.input_colorspace.num_components(),
-                self.components.len()
-            );
-            return Err(DecodeErrors::Format(msg));
-        }
-
-        if self.input_colorspace == ColorSpace::Luma && self.is_interleaved {
-            // log::warn!("Grayscale image with down-sampled component, resetting component details");
-
-            self.reset_params();
-
-            mcu_width = ((self.info.width + 7) / 8) as usize;
-            mcu_height = ((self.info.height + 7) / 8) as usize;
+        if self.decode_mcu_ycbcr_parallel(pixels, mcu_width, mcu_height)? {
+            return Ok(());
         }
         let width = usize::from(self.info.width);
 
@@ -130,53 +102,39 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
         let mut stream = BitStream::new();
         let mut tmp = [0_i32; DCT_BLOCK];
 
-        for (pos, comp) in self.components.iter_mut().enumerate() {
-            // Allocate only needed components.
-            //
-            // For special colorspaces i.e YCCK and CMYK, just allocate all of the needed
-            // components.
-            if min(
-                self.options.jpeg_get_out_colorspace().num_components() - 1,
-                pos,
-            ) == pos
-                || self.input_colorspace == ColorSpace::YCCK
-                || self.input_colorspace == ColorSpace::CMYK
-            {
-                // allocate enough space to hold a whole MCU width
-                // this means we should take into account sampling ratios
-                // `*8` is because each MCU spans 8 widths.
-                let len = comp.width_stride * comp.vertical_sample * 8;
-
-                comp.needed = true;
-                comp.raw_coeff = vec![0; len];
-            } else {
-                comp.needed = false;
-            }
-        }
-
         let mut pixels_written = 0;
 
-        let is_hv = usize::from(self.sub_sample_ratio == SampleRatios::HV);
-        let upsampler_scratch_size = is_hv * self.components[0].width_stride;
-        let mut upsampler_scratch_space = vec![0; upsampler_scratch_size];
+        let scratch_size = self.upsampler_scratch_size();
+        let mut upsampler_scratch_space = self.arena.take(ScratchRegion::Hot, scratch_size, 0)?;
 
         for i in 0..mcu_height {
             // Report if we have no more bytes
             // This may generate false negatives since we over-read bytes
             // hence that why 37 is chosen(we assume if we over-read more than 37 bytes, we have a problem)
-            if stream.overread_by > 37
-            // favourite number :)
+            //
+            // Images cut short may also end with an EOI marker before all rows are decoded
+            if self.conceal_left == 0
+                && (stream.overread() > 37 // favourite number :)
+                    || stream.data_exhausted())
             {
-                if self.options.get_strict_mode() {
+                if self.options.get_strict_mode() && !self.can_conceal() {
                     return Err(DecodeErrors::FormatStatic("Premature end of buffer"));
-                };
+                }
 
-                log::error!("Premature end of buffer");
-                break;
+                self.report.warn(DecodeWarning::Truncated {
+                    row: i,
+                    offset: self.stream.get_position(),
+                });
+                if !self.can_conceal() {
+                    break;
+                }
+                // conceal the rest of the image
+                self.start_concealment(i, 0, mcu_width, &mut stream);
             }
             // decode a whole MCU width,
             // this takes into account interleaved components.
-            self.decode_mcu_width(mcu_width, &mut tmp, &mut stream)?;
+            self.decode_mcu_width(i, mcu_width, &mut tmp, &mut stream)?;
+            self.report_mcu_row(&mut stream, i);
             // process that width up until it's impossible
             self.post_process(
                 pixels,
@@ -187,66 +145,375 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
                 &mut pixels_written,
                 &mut upsampler_scratch_space,
             )?;
+            self.call_yield_hook(i + 1, mcu_height);
         }
+        self.report_stream_end(&mut stream);
 
         // log::info!("Finished decoding image");
 
         Ok(())
     }
+
+    /// Decode the quantized coefficients of a baseline image
+    ///
+    /// Coefficients are laid out like those of progressive images, see
+    /// [`decode_progressive_coefficients`](Self::decode_progressive_coefficients)
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn decode_baseline_coefficients(
+        &mut self,
+    ) -> Result<[Vec<i16>; MAX_COMPONENTS], DecodeErrors> {
+        let (mcu_width, mcu_height) = self.setup_baseline_blocks()?;
+
+        let mut block: [Vec<i16>; MAX_COMPONENTS] = [vec![], vec![], vec![], vec![]];
+        let mut strides = [0; MAX_COMPONENTS];
+
+        for (pos, comp) in self.components.iter().enumerate() {
+            block[pos] = vec![0; comp.width_stride * comp.vertical_sample * 8 * mcu_height];
+            strides[pos] = comp.width_stride / 8;
+        }
+
+        self.decode_baseline_blocks(mcu_width, mcu_height, |pos, x, y, coeffs| {
+            let position = 64 * (x + y * strides[pos]);
+
+            for (out, coeff) in block[pos][position..position + 64].iter_mut().zip(coeffs) {
+                *out = *coeff as i16;
+            }
+        })?;
+
+        Ok(block)
+    }
+
+    /// Set up decoding of a baseline image block by block, returning the
+    /// number of MCUs in a row and a column
+    pub(crate) fn setup_baseline_blocks(&mut self) -> Result<(usize, usize), DecodeErrors> {
+        if self.awaiting_dnl {
+            return Err(DecodeErrors::FormatStatic(
+                "Images with a DNL segment have no coefficients to read",
+            ));
+        }
+        self.setup_baseline()
+    }
+
+    /// Entropy decode a baseline image, passing every block to `f`
+    ///
+    /// `f` receives the component index, the block column and row within
+    /// the component, and the quantized coefficients in natural order. Blocks
+    /// are passed in scan order and none of them is kept around, so this needs
+    /// no memory proportional to the image.
+    ///
+    /// [`setup_baseline_blocks`](Self::setup_baseline_blocks) gives the
+    /// MCU counts to pass.
+    pub(crate) fn decode_baseline_blocks<F>(
+        &mut self,
+        mcu_width: usize,
+        mcu_height: usize,
+        mut f: F,
+    ) -> Result<(), DecodeErrors>
+    where
+        F: FnMut(usize, usize, usize, &[i32; DCT_BLOCK]),
+    {
+        let mut stream = BitStream::new();
+        let mut tmp = [0_i32; DCT_BLOCK];
+        // keep coefficients quantized
+        let ones = [1; DCT_BLOCK];
+
+        for i in 0..mcu_height {
+            if self.check_truncated_scan(&stream, i)? {
+                break;
+            }
+            for j in 0..mcu_width {
+                for (pos, component) in self.components.iter_mut().enumerate() {
+                    let dc_table = self.dc_huffman_tables[component.dc_huff_table % MAX_COMPONENTS]
+                        .as_ref()
+                        .unwrap();
+                    let ac_table = self.ac_huffman_tables[component.ac_huff_table % MAX_COMPONENTS]
+                        .as_ref()
+                        .unwrap();
+
+                    for v_samp in 0..component.vertical_sample {
+                        for h_samp in 0..component.horizontal_sample {
+                            tmp.fill(0);
+
+                            stream
+                                .decode_mcu_block(
+                                    &mut self.stream,
+                                    dc_table,
+                                    ac_table,
+                                    &ones,
+                                    &mut tmp,
+                                    &mut component.dc_pred,
+                                )
+                                .map_err(|e| e.in_mcu(i, j, self.stream.get_position()))?;
+
+                            let x2 = j * component.horizontal_sample + h_samp;
+                            let y2 = i * component.vertical_sample + v_samp;
+
+                            f(pos, x2, y2, &tmp);
+                        }
+                    }
+                }
+                self.todo = self.todo.wrapping_sub(1);
+
+                if self.todo == 0 {
+                    self.handle_rst(&mut stream, i, j)
+                        .map_err(|e| e.in_mcu(i, j, self.stream.get_position()))?;
+                }
+            }
+            self.report.mcus_decoded += mcu_width;
+            self.report_mcu_row(&mut stream, i);
+            self.call_yield_hook(i + 1, mcu_height);
+        }
+        self.report_stream_end(&mut stream);
+
+        Ok(())
+    }
+
+    /// Decode an image whose height is defined by a DNL segment after the scan
+    ///
+    /// We don't know how many MCU rows the scan has, so the output grows an MCU row
+    /// at a time until we find the DNL marker, up to the height limit set in the
+    /// decoder options. The output is then truncated to the height in the DNL segment.
+    #[inline(never)]
+    pub(crate) fn decode_mcu_ycbcr_baseline_dnl(&mut self) -> Result<Vec<u8>, DecodeErrors> {
+        // output isn't known yet, it's accounted for as it grows
+        let memory_required = self.memory_required();
+        self.check_memory_limit(0)?;
+
+        let (mcu_width, _) = self.setup_baseline()?;
+
+        let width = usize::from(self.info.width);
+
+        let padded_width = calculate_padded_width(width, self.sub_sample_ratio);
+
+        let out_colorspace_components = self.options.jpeg_get_out_colorspace().num_components();
+        // number of pixel rows in an MCU row
+        let mcu_rows = if self.is_interleaved { self.v_max * 8 } else { 8 };
+        let mcu_row_size = width * out_colorspace_components * mcu_rows;
+        // maximum number of MCU rows an image within our limits can have
+        let max_mcu_height = self.options.get_max_height().div_ceil(mcu_rows);
+
+        let mut stream = BitStream::new();
+        let mut tmp = [0_i32; DCT_BLOCK];
+
+        let mut pixels = Vec::new();
+        let mut pixels_written = 0;
+
+        let scratch_size = self.upsampler_scratch_size();
+        let mut upsampler_scratch_space = self.arena.take(ScratchRegion::Hot, scratch_size, 0)?;
+
+        for i in 0..max_mcu_height {
+            if stream.overread() > 37 || stream.data_exhausted() {
+                return Err(DecodeErrors::FormatStatic(
+                    "Premature end of buffer, no DNL marker found",
+                ));
+            }
+            self.decode_mcu_width(i, mcu_width, &mut tmp, &mut stream)?;
+            self.report_mcu_row(&mut stream, i);
+
+            let found_dnl = stream.segment_end_marker(&mut self.stream)? == Some(Marker::DNL);
+            // post processing holds back rows that need the row below for up-sampling
+            // until it sees the last MCU row, which we only know once we find DNL
+            let mcu_height = if found_dnl { i + 1 } else { usize::MAX };
+
+            let required = memory_required.saturating_add(pixels.len() + mcu_row_size);
+
+            if required > self.options.get_max_memory() {
+                return Err(DecodeErrors::MemoryLimitExceeded(
+                    required,
+                    self.options.get_max_memory(),
+                ));
+            }
+            // growing geometrically could allocate up to twice the limit
+            pixels.reserve_exact(mcu_row_size);
+            pixels.resize(pixels.len() + mcu_row_size, 0);
+
+            self.post_process(
+                &mut pixels,
+                i,
+                mcu_height,
+                width,
+                padded_width,
+                &mut pixels_written,
+                &mut upsampler_scratch_space,
+            )?;
+            // we don't know the number of rows until we find DNL
+            self.call_yield_hook(i + 1, 0);
+
+            if found_dnl {
+                stream.marker.take();
+
+                let offset = self.stream.get_position().saturating_sub(2);
+                parse_dnl(self).map_err(|e| e.in_segment(Marker::DNL, offset))?;
+
+                let height = usize::from(self.info.height);
+
+                if height > mcu_height * mcu_rows {
+                    return Err(DecodeErrors::Format(format!(
+                        "DNL height {height} is larger than the {} decoded rows",
+                        mcu_height * mcu_rows
+                    )));
+                }
+                pixels.truncate(width * height * out_colorspace_components);
+
+                self.report.mcus_total = self.report.mcus_decoded;
+
+                if stream.overread() > 0 {
+                    self.report.warn(DecodeWarning::Overread {
+                        bytes: stream.overread(),
+                    });
+                }
+                if self.stream.peek_fixed_bytes::<2>(0) != Ok([0xFF, 0xD9]) {
+                    self.report.warn(DecodeWarning::MissingEoi {
+                        offset: self.stream.get_position(),
+                    });
+                }
+                return Ok(pixels);
+            }
+        }
+
+        Err(DecodeErrors::Format(format!(
+            "No DNL marker found within height limit {}",
+            self.options.get_max_height()
+        )))
+    }
+
+    /// Set up component parameters, up-samplers and coefficient buffers
+    /// for baseline decoding
+    ///
+    /// Returns the number of MCU's in the x and y plane
+    fn setup_baseline(&mut self) -> Result<(usize, usize), DecodeErrors> {
+        self.report = DecodeReport::default();
+        self.next_restart = 0;
+        self.conceal_left = 0;
+        self.pass = 0;
+
+        setup_component_params(self)?;
+
+        // check dc and AC tables
+        self.check_tables()?;
+
+        if self.input_colorspace == ColorSpace::Luma && self.is_interleaved {
+            // log::warn!("Grayscale image with down-sampled component, resetting component details");
+
+            self.reset_params();
+        }
+        let (mcu_width, mcu_height);
+
+        if self.is_interleaved {
+            // set upsampling functions
+            self.set_upsampling()?;
+
+            mcu_width = self.mcu_x;
+            mcu_height = self.mcu_y;
+        } else {
+            // For non-interleaved images( (1*1) subsampling)
+            // number of MCU's are the widths (+7 to account for paddings) divided bu 8.
+            mcu_width = ((self.info.width + 7) / 8) as usize;
+            mcu_height = ((self.info.height + 7) / 8) as usize;
+        }
+
+        if self.input_colorspace.num_components() > self.components.len() {
+            let msg = format!(
+                " Expected {} number of components but found {}",
+                self.input_colorspace.num_components(),
+                self.components.len()
+            );
+            return Err(DecodeErrors::Format(msg));
+        }
+        self.setup_component_rows()?;
+
+        self.report.mcus_total = mcu_width * mcu_height;
+
+        Ok((mcu_width, mcu_height))
+    }
+    /// Allocate MCU row buffers of components needed for the output
+    /// colorspace, and the rows kept from the previous MCU row for
+    /// concealment and deblocking
+    pub(crate) fn setup_component_rows(&mut self) -> Result<(), DecodeErrors> {
+        let copy_above = !self.is_progressive
+            && self.can_conceal()
+            && self.options.jpeg_get_concealment() == JpegConcealment::CopyAbove;
+        let deblocking = self.options.jpeg_get_deblocking() != 0;
+
+        for pos in 0..self.components.len() {
+            let needed = self.component_needed(pos);
+            let comp = &mut self.components[pos];
+
+            // Allocate only needed components.
+            comp.needed = needed;
+
+            if !needed {
+                continue;
+            }
+            // allocate enough space to hold a whole MCU width
+            // this means we should take into account sampling ratios
+            // `*8` is because each MCU spans 8 widths.
+            let len = comp.width_stride * comp.vertical_sample * 8;
+
+            comp.raw_coeff = self.arena.take(ScratchRegion::Hot, len, 0)?;
+
+            if deblocking {
+                comp.deblock_rows = self.arena.take(ScratchRegion::Hot, 4 * comp.width_stride, 0)?;
+            }
+            if copy_above {
+                comp.conceal_row = self.arena.take(ScratchRegion::Hot, comp.width_stride, 0)?;
+            }
+        }
+        Ok(())
+    }
+    /// Report coefficients out of range in the MCU row just decoded
+    pub(crate) fn report_mcu_row(&mut self, stream: &mut BitStream, row: usize) {
+        if stream.out_of_range != 0 {
+            self.report.warn(DecodeWarning::CoefficientOutOfRange {
+                row,
+                count: stream.out_of_range,
+                offset: self.stream.get_position(),
+            });
+            stream.out_of_range = 0;
+        }
+    }
+    /// Report over-reads and a missing EOI marker once all entropy
+    /// coded data was decoded
+    pub(crate) fn report_stream_end(&mut self, stream: &mut BitStream) {
+        if stream.overread() > 0 {
+            self.report.warn(DecodeWarning::Overread {
+                bytes: stream.overread(),
+            });
+        }
+        let found_eoi = stream.marker == Some(Marker::EOI)
+            || matches!(
+                stream.segment_end_marker(&mut self.stream),
+                Ok(Some(Marker::EOI))
+            );
+
+        if !found_eoi {
+            self.report.warn(DecodeWarning::MissingEoi {
+                offset: self.stream.get_position(),
+            });
+        }
+    }
     fn decode_mcu_width(
         &mut self,
+        row: usize,
         mcu_width: usize,
         tmp: &mut [i32; 64],
         stream: &mut BitStream,
     ) -> Result<(), DecodeErrors> {
+        let concealed = self.report.mcus_concealed;
+
         for j in 0..mcu_width {
-            // iterate over components
-            for component in &mut self.components {
-                let dc_table = self.dc_huffman_tables[component.dc_huff_table % MAX_COMPONENTS]
-                    .as_ref()
-                    .unwrap();
-
-                let ac_table = self.ac_huffman_tables[component.ac_huff_table % MAX_COMPONENTS]
-                    .as_ref()
-                    .unwrap();
-
-                let qt_table = &component.quantization_table;
-                let channel = &mut component.raw_coeff;
-
-                // If image is interleaved iterate over scan components,
-                // otherwise if it-s non-interleaved, these routines iterate in
-                // trivial scanline order(Y,Cb,Cr)
-                for v_samp in 0..component.vertical_sample {
-                    for h_samp in 0..component.horizontal_sample {
-                        // Fill the array with zeroes, decode_mcu_block expects
-                        // a zero based array.
-                        tmp.fill(0);
-
-                        stream.decode_mcu_block(
-                            &mut self.stream,
-                            dc_table,
-                            ac_table,
-                            qt_table,
-                            tmp,
-                            &mut component.dc_pred,
-                        )?;
-
-                        if component.needed {
-                            let idct_position = {
-                                // derived from stb and rewritten for my tastes
-                                let c2 = v_samp * 8;
-                                let c3 = ((j * component.horizontal_sample) + h_samp) * 8;
-
-                                component.width_stride * c2 + c3
-                            };
-
-                            let idct_pos = channel.get_mut(idct_position..).unwrap();
-                            //  call idct.
-                            (self.idct_func)(tmp, idct_pos, component.width_stride);
-                        }
+            if self.conceal_left == 0 {
+                if let Err(e) = self.decode_mcu(j, tmp, stream) {
+                    if !self.can_conceal() {
+                        return Err(e.in_mcu(row, j, self.stream.get_position()));
                     }
+                    self.start_concealment(row, j, mcu_width, stream);
                 }
             }
+            if self.conceal_left != 0 {
+                self.conceal_mcu(row, j);
+                continue;
+            }
             self.todo = self.todo.saturating_sub(1);
             // After all interleaved components, that's an MCU
             // handle stream markers
@@ -260,25 +527,107 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
             // allow it because of some weird reason.
             if let Some(m) = stream.marker {
                 if m == Marker::EOI {
-                    // acknowledge and ignore EOI marker.
-                    stream.marker.take();
+                    // acknowledge and ignore EOI marker, keeping it so we
+                    // don't read past the end of the image.
                     // log::info!("Found EOI marker");
-                } else if let Marker::RST(_) = m {
-                    if self.todo == 0 {
-                        self.handle_rst(stream)?;
+                    if self.can_conceal() && stream.bits_left == 0 {
+                        // the image may end early, conceal what's missing
+                        self.start_concealment(row, j + 1, mcu_width, stream);
                     }
+                } else if let Marker::RST(n) = m {
+                    if self.can_conceal()
+                        && ((self.todo == 0 && n != self.next_restart)
+                            || (self.todo != 0 && stream.bits_left == 0))
+                    {
+                        // restart intervals are missing, or this one ended early,
+                        // conceal the MCUs lost up to the marker
+                        self.start_concealment(row, j + 1, mcu_width, stream);
+                    } else if self.todo == 0 {
+                        self.handle_rst(stream, row, j)
+                            .map_err(|e| e.in_mcu(row, j, self.stream.get_position()))?;
+                    }
+                } else if m == Marker::DNL {
+                    // the scan ends here, the caller handles it after
+                    // finishing this MCU row
                 } else {
+                    // the bit reader has consumed the marker
+                    let offset = self.stream.get_position().saturating_sub(2);
+
                     if self.options.get_strict_mode() {
                         return Err(DecodeErrors::Format(format!(
                             "Marker {m:?} found where not expected"
-                        )));
+                        ))
+                        .in_mcu(row, j, offset));
                     }
-                    log::error!(
-                        "Marker `{:?}` Found within Huffman Stream, possibly corrupt jpeg",
-                        m
-                    );
+                    self.report.warn(DecodeWarning::UnexpectedMarker {
+                        marker: m,
+                        row,
+                        column: j,
+                        offset,
+                    });
+
+                    self.parse_marker_inner(m)
+                        .map_err(|e| e.in_segment(m, offset))?;
+                }
+            }
+        }
+        self.save_conceal_rows();
+        self.report.mcus_decoded += mcu_width - (self.report.mcus_concealed - concealed);
+
+        Ok(())
+    }
+    /// Decode the MCU at `column` of the current MCU row
+    #[inline(always)]
+    fn decode_mcu(
+        &mut self,
+        column: usize,
+        tmp: &mut [i32; 64],
+        stream: &mut BitStream,
+    ) -> Result<(), DecodeErrors> {
+        // iterate over components
+        for component in &mut self.components {
+            let dc_table = self.dc_huffman_tables[component.dc_huff_table % MAX_COMPONENTS]
+                .as_ref()
+                .unwrap();
 
-                    self.parse_marker_inner(m)?;
+            let ac_table = self.ac_huffman_tables[component.ac_huff_table % MAX_COMPONENTS]
+                .as_ref()
+                .unwrap();
+
+            let qt_table = &component.quantization_table;
+            let channel = &mut component.raw_coeff;
+
+            // If image is interleaved iterate over scan components,
+            // otherwise if it-s non-interleaved, these routines iterate in
+            // trivial scanline order(Y,Cb,Cr)
+            for v_samp in 0..component.vertical_sample {
+                for h_samp in 0..component.horizontal_sample {
+                    // Fill the array with zeroes, decode_mcu_block expects
+                    // a zero based array.
+                    tmp.fill(0);
+
+                    stream.decode_mcu_block(
+                        &mut self.stream,
+                        dc_table,
+                        ac_table,
+                        qt_table,
+                        tmp,
+                        &mut component.dc_pred,
+                    )?;
+
+                    if component.needed {
+                        let idct_position = {
+                            // derived from stb and rewritten for my tastes
+                            let c2 = v_samp * 8;
+                            let c3 = ((column * component.horizontal_sample) + h_samp) * 8;
+
+                            component.width_stride * c2 + c3
+                        };
+
+                        let idct_pos = channel.get_mut(idct_position..).unwrap();
+                        //  call idct.
+                        (self.idct_func)(tmp, idct_pos, component.width_stride);
+                    }
                 }
             }
         }
@@ -288,14 +637,30 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
     // No-op if not using restarts
     // this routine is shared with mcu_prog
     #[cold]
-    pub(crate) fn handle_rst(&mut self, stream: &mut BitStream) -> Result<(), DecodeErrors> {
+    pub(crate) fn handle_rst(
+        &mut self,
+        stream: &mut BitStream,
+        row: usize,
+        column: usize,
+    ) -> Result<(), DecodeErrors> {
         self.todo = self.restart_interval;
 
         if let Some(marker) = stream.marker {
             // Found a marker
             // Read stream and see what marker is stored there
             match marker {
-                Marker::RST(_) => {
+                Marker::RST(n) => {
+                    if n != self.next_restart {
+                        self.report.warn(DecodeWarning::BadRestartMarker {
+                            expected: self.next_restart,
+                            found: n,
+                            row,
+                            column,
+                            offset: self.stream.get_position().saturating_sub(2),
+                        });
+                    }
+                    // continue the sequence from the marker we found
+                    self.next_restart = (n + 1) & 7;
                     // reset stream
                     stream.reset();
                     // Initialize dc predictions to zero for all components
@@ -314,6 +679,39 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
         }
         Ok(())
     }
+    /// Replicate the last column, and in the last MCU row the last row, of
+    /// sub-sampled components into their padding
+    ///
+    /// libjpeg up-samples as if samples past the edges of the image were
+    /// the edge samples, instead of using the padding the encoder stored.
+    fn replicate_edges(&mut self, last_mcu_row: bool, i: usize) {
+        let (width, height) = (usize::from(self.info.width), usize::from(self.info.height));
+
+        for c in &mut self.components {
+            if !c.needed || (c.h_ratio == 1 && c.v_ratio == 1) {
+                continue;
+            }
+            let stride = c.width_stride;
+            let real_width = (width * c.horizontal_sample).div_ceil(self.h_max);
+
+            if real_width < stride {
+                for row in c.raw_coeff.chunks_exact_mut(stride) {
+                    let last = row[real_width - 1];
+                    row[real_width..].fill(last);
+                }
+            }
+            if last_mcu_row && height != 0 {
+                let real_height = (height * c.vertical_sample).div_ceil(self.v_max);
+                let real_rows = real_height - i * 8 * c.vertical_sample;
+                let (rows, padding) = c.raw_coeff.split_at_mut(real_rows * stride);
+                let last = &rows[rows.len() - stride..];
+
+                for row in padding.chunks_exact_mut(stride) {
+                    row.copy_from_slice(last);
+                }
+            }
+        }
+    }
     #[allow(clippy::too_many_lines, clippy::too_many_arguments)]
     pub(crate) fn post_process(
         &mut self,
@@ -325,39 +723,67 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
         pixels_written: &mut usize,
         upsampler_scratch_space: &mut [i16],
     ) -> Result<(), DecodeErrors> {
-        let out_colorspace_components = self.options.jpeg_get_out_colorspace().num_components();
+        let output_stride = self.output_row_stride(width);
+
+        if self.options.jpeg_get_deblocking() != 0 {
+            self.deblock_mcu_row(i);
+        }
+        if self.options.jpeg_get_exact() && self.is_interleaved {
+            self.replicate_edges(i == mcu_height.saturating_sub(1), i);
+        }
 
         if self.is_interleaved && self.options.jpeg_get_out_colorspace() != ColorSpace::Luma {
-            if self.sub_sample_ratio == SampleRatios::H {
-                // H sample has it easy since it doesn't require the rows below or above
-
-                upsample_and_color_convert_h(
-                    &mut self.components,
-                    self.color_convert_16,
-                    self.input_colorspace,
-                    self.options.jpeg_get_out_colorspace(),
-                    &mut pixels[*pixels_written..],
-                    width,
-                    padded_width,
-                )?;
-
-                // increment pointer to number of pixels written
-                *pixels_written += width * out_colorspace_components * 8;
-            } else {
-                // an abomination this one ...
-                upsample_and_color_convert_v(
-                    &mut self.components,
-                    self.color_convert_16,
-                    self.input_colorspace,
-                    self.options.jpeg_get_out_colorspace(),
-                    pixels,
-                    width,
-                    padded_width,
-                    pixels_written,
-                    upsampler_scratch_space,
-                    i,
-                    mcu_height,
-                )?;
+            match self.sub_sample_ratio {
+                SampleRatios::H => {
+                    // H sample has it easy since it doesn't require the rows below or above
+
+                    upsample_and_color_convert_h(
+                        &mut self.components,
+                        self.color_convert_16,
+                        self.input_colorspace,
+                        self.options.jpeg_get_out_colorspace(),
+                        &mut pixels[*pixels_written..],
+                        width,
+                        padded_width,
+                        output_stride,
+                    )?;
+
+                    // increment pointer to number of pixels written
+                    *pixels_written += output_stride * 8;
+                }
+                SampleRatios::Generic(_, _) => {
+                    upsample_and_color_convert_generic(
+                        &mut self.components,
+                        self.color_convert_16,
+                        self.input_colorspace,
+                        self.options.jpeg_get_out_colorspace(),
+                        pixels,
+                        width,
+                        padded_width,
+                        output_stride,
+                        pixels_written,
+                        upsampler_scratch_space,
+                        i,
+                        mcu_height,
+                    )?;
+                }
+                _ => {
+                    // an abomination this one ...
+                    upsample_and_color_convert_v(
+                        &mut self.components,
+                        self.color_convert_16,
+                        self.input_colorspace,
+                        self.options.jpeg_get_out_colorspace(),
+                        pixels,
+                        width,
+                        padded_width,
+                        output_stride,
+                        pixels_written,
+                        upsampler_scratch_space,
+                        i,
+                        mcu_height,
+                    )?;
+                }
             }
         } else {
             let mut channels_ref: [&[i16]; MAX_COMPONENTS] = [&[]; MAX_COMPONENTS];
@@ -375,12 +801,148 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
                 &mut pixels[*pixels_written..],
                 width,
                 padded_width,
+                output_stride,
             )?;
 
+            if self.options.jpeg_get_color_range() == JpegColorRange::Studio
+                && self.options.jpeg_get_out_colorspace() == ColorSpace::Luma
+                && matches!(self.input_colorspace, ColorSpace::YCbCr | ColorSpace::Luma)
+            {
+                // the same rows color_convert_no_sampling wrote
+                let rows = channels_ref[0].len() / padded_width;
+                let output = &mut pixels[*pixels_written..];
+
+                for row in output_rows(output, width, output_stride).take(rows) {
+                    expand_studio_luma(row);
+                }
+            }
+            // an interleaved MCU row has 8 pixel rows per vertical sample
+            let mcu_rows = if self.is_interleaved { self.v_max * 8 } else { 8 };
             // increment pointer to number of pixels written
-            *pixels_written += width * out_colorspace_components * 8;
+            *pixels_written += output_stride * mcu_rows;
         }
 
         Ok(())
     }
 }
+
+#[test]
+fn decode_dnl_height() {
+    use crate::zune_jpeg::JpegDecoder;
+
+    for (data, (width, height), components) in [
+        (&include_bytes!("../../tests/inputs/dnl_420.jpg")[..], (50, 37), 3),
+        (&include_bytes!("../../tests/inputs/dnl_422_rst.jpg")[..], (50, 37), 3),
+        (&include_bytes!("../../tests/inputs/dnl_gray.jpg")[..], (33, 20), 1),
+    ] {
+        let mut decoder = JpegDecoder::new(data);
+        decoder.decode_headers().unwrap();
+        // height isn't known until we reach the DNL segment
+        assert_eq!(decoder.output_buffer_size(), None);
+
+        let pixels = decoder.decode().unwrap();
+
+        assert_eq!(decoder.dimensions(), Some((width, height)));
+        assert_eq!(
+            pixels.len(),
+            usize::from(width) * usize::from(height) * components
+        );
+        assert_eq!(decoder.output_buffer_size(), Some(pixels.len()));
+    }
+}
+
+#[test]
+fn decode_dnl_height_limit() {
+    use crate::zune_core::options::DecoderOptions;
+    use crate::zune_jpeg::JpegDecoder;
+
+    let data = include_bytes!("../../tests/inputs/dnl_420.jpg");
+
+    let options = DecoderOptions::default().set_max_height(16);
+    let mut decoder = JpegDecoder::new_with_options(data, options);
+    assert!(decoder.decode().is_err());
+
+    let mut decoder = JpegDecoder::new(data);
+    let mut out = vec![0; 100];
+    assert!(matches!(
+        decoder.decode_into(&mut out),
+        Err(DecodeErrors::TooSmallOutput(5550, 100))
+    ));
+}
+
+#[test]
+fn decode_report() {
+    use crate::zune_core::options::DecoderOptions;
+    use crate::zune_jpeg::JpegDecoder;
+
+    let options = DecoderOptions::default().set_strict_mode(false);
+    let data = include_bytes!("../../tests/inputs/sampling_mixed.jpg");
+
+    let mut decoder = JpegDecoder::new_with_options(&data[..], options);
+    decoder.decode().unwrap();
+    assert!(decoder.report().is_clean());
+    assert!((decoder.report().decoded_fraction() - 1.0).abs() < f32::EPSILON);
+
+    // no EOI marker
+    let mut decoder = JpegDecoder::new_with_options(&data[..data.len() - 2], options);
+    decoder.decode().unwrap();
+    assert!(decoder
+        .report()
+        .warnings
+        .iter()
+        .any(|w| matches!(w, DecodeWarning::MissingEoi { .. })));
+
+    // image cut short, ending with an EOI marker
+    let mut truncated = data[..data.len() / 2].to_vec();
+    truncated.extend_from_slice(&[0xFF, 0xD9]);
+
+    let mut decoder = JpegDecoder::new_with_options(&truncated[..], options);
+    decoder.decode().unwrap();
+    assert!(matches!(
+        decoder.report().warnings[..],
+        [DecodeWarning::Truncated { row: 1, .. }]
+    ));
+    assert!(decoder.report().decoded_fraction() < 0.5);
+
+    // strict mode turns truncation into an error
+    let mut decoder = JpegDecoder::new(&truncated[..]);
+    assert!(decoder.decode().is_err());
+
+    // restart markers out of sequence
+    let mut data = include_bytes!("../../tests/inputs/dnl_422_rst.jpg").to_vec();
+    let pos = data.windows(2).position(|w| w == [0xFF, 0xD1]).unwrap();
+    data[pos + 1] = 0xD3;
+
+    let mut decoder = JpegDecoder::new_with_options(&data[..], options);
+    decoder.decode().unwrap();
+    assert!(matches!(
+        decoder.report().warnings[0],
+        DecodeWarning::BadRestartMarker {
+            expected: 1,
+            found: 3,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn decode_luma_vertical_sampling() {
+    use crate::zune_core::options::DecoderOptions;
+    use crate::zune_jpeg::JpegDecoder;
+
+    // Y 2x2, so an MCU row is 16 pixel rows
+    let data = include_bytes!("../../tests/inputs/restart_420.jpg");
+
+    let rgb = JpegDecoder::new(data).decode().unwrap();
+    let options = DecoderOptions::default().jpeg_set_out_colorspace(ColorSpace::Luma);
+    let luma = JpegDecoder::new_with_options(data, options).decode().unwrap();
+
+    assert_eq!(rgb.len(), luma.len() * 3);
+
+    for (pix, y) in rgb.chunks_exact(3).zip(&luma) {
+        let [r, g, b] = [pix[0], pix[1], pix[2]].map(i32::from);
+        let expected = (r * 299 + g * 587 + b * 114 + 500) / 1000;
+
+        assert!((expected - i32::from(*y)).abs() <= 2);
+    }
+}
@@ -19,13 +19,12 @@ Half of the complexity comes from the jpeg spec, because progressive decoding,
 is one hell of a ride.
 
 */
-use std::cmp::min;
 
 use crate::zune_core::bytestream::{ZByteReader, ZReaderTrait};
 use crate::zune_core::colorspace::ColorSpace;
 
 use super::bitstream::BitStream;
-use super::components::{ComponentID, SampleRatios};
+use super::components::SampleRatios;
 use super::decoder::{JpegDecoder, MAX_COMPONENTS};
 use super::errors::DecodeErrors;
 use super::errors::DecodeErrors::Format;
@@ -33,28 +32,46 @@ use super::headers::{parse_huffman, parse_sos};
 use super::marker::Marker;
 use super::mcu::DCT_BLOCK;
 use super::misc::{calculate_padded_width, setup_component_params};
+use super::report::{DecodeReport, DecodeWarning};
+use super::scratch::{ScratchBuf, ScratchRegion};
 
 impl<T: ZReaderTrait> JpegDecoder<T> {
     /// Decode a progressive image
     ///
     /// This routine decodes a progressive image, stopping if it finds any error.
+    #[inline(never)]
+    pub(crate) fn decode_mcu_ycbcr_progressive(
+        &mut self,
+        pixels: &mut [u8],
+    ) -> Result<(), DecodeErrors> {
+        let block = self.decode_progressive_coefficients()?;
+        let block: [&[i16]; MAX_COMPONENTS] = [&block[0], &block[1], &block[2], &block[3]];
+
+        self.finish_progressive_decoding(&block, pixels)
+    }
+
+    /// Decode the quantized coefficients of all scans of a progressive image
+    ///
+    /// Coefficients of every component are stored block after block in natural
+    /// order, with `width_stride / 8` blocks per block row.
     #[allow(
         clippy::needless_range_loop,
         clippy::cast_sign_loss,
         clippy::redundant_else,
         clippy::too_many_lines
     )]
-    #[inline(never)]
-    pub(crate) fn decode_mcu_ycbcr_progressive(
+    pub(crate) fn decode_progressive_coefficients(
         &mut self,
-        pixels: &mut [u8],
-    ) -> Result<(), DecodeErrors> {
+    ) -> Result<[ScratchBuf; MAX_COMPONENTS], DecodeErrors> {
+        self.report = DecodeReport::default();
+        self.pass = 0;
+
         setup_component_params(self)?;
 
         let mcu_height;
 
         // memory location for decoded pixels for components
-        let mut block: [Vec<i16>; MAX_COMPONENTS] = [vec![], vec![], vec![], vec![]];
+        let mut block: [ScratchBuf; MAX_COMPONENTS] = Default::default();
         let mut mcu_width;
 
         let mut seen_scans = 1;
@@ -76,6 +93,8 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
             mcu_height = (self.info.height as usize + 7) / 8;
         }
 
+        self.report.mcus_total = mcu_width * mcu_height;
+
         mcu_width *= 64;
 
         if self.input_colorspace.num_components() > self.components.len() {
@@ -90,7 +109,7 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
             let comp = &self.components[i];
             let len = mcu_width * comp.vertical_sample * comp.horizontal_sample * mcu_height;
 
-            block[i] = vec![0; len];
+            block[i] = self.arena.take(ScratchRegion::Large, len, 0)?;
         }
 
         let mut stream = BitStream::new_progressive(
@@ -102,6 +121,7 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
 
         // there are multiple scans in the stream, this should resolve the first scan
         self.parse_entropy_coded_data(&mut stream, &mut block)?;
+        self.pass += 1;
 
         // extract marker
         let mut marker = stream
@@ -117,10 +137,12 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
         'eoi: while marker != Marker::EOI {
             match marker {
                 Marker::DHT => {
-                    parse_huffman(self)?;
+                    let offset = self.stream.get_position().saturating_sub(2);
+                    parse_huffman(self).map_err(|e| e.in_segment(marker, offset))?;
                 }
                 Marker::SOS => {
-                    parse_sos(self)?;
+                    let offset = self.stream.get_position().saturating_sub(2);
+                    parse_sos(self).map_err(|e| e.in_segment(marker, offset))?;
 
                     stream.update_progressive_params(
                         self.succ_high,
@@ -131,6 +153,7 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
 
                     // after every SOS, marker, parse data for that scan.
                     self.parse_entropy_coded_data(&mut stream, &mut block)?;
+                    self.pass += 1;
                     // extract marker, might either indicate end of image or we continue
                     // scanning(hence the continue statement to determine).
                     match get_marker(&mut self.stream, &mut stream) {
@@ -151,7 +174,9 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
                             if self.options.get_strict_mode() {
                                 return Err(msg);
                             }
-                            log::error!("{:?}", msg);
+                            self.report.warn(DecodeWarning::MissingEoi {
+                                offset: self.stream.get_position(),
+                            });
                             break 'eoi;
                         }
                     }
@@ -169,22 +194,42 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
                     if self.options.get_strict_mode() {
                         return Err(e);
                     }
-                    log::error!("{}", e);
+                    self.report.warn(DecodeWarning::MissingEoi {
+                        offset: self.stream.get_position(),
+                    });
+                    break 'eoi;
                 }
             }
         }
+        if stream.overread() > 0 {
+            self.report.warn(DecodeWarning::Overread {
+                bytes: stream.overread(),
+            });
+        }
+        // every scan refines the whole image, so a truncated scan
+        // leaves no MCU fully decoded
+        let truncated = self
+            .report
+            .warnings
+            .iter()
+            .any(|w| matches!(w, DecodeWarning::Truncated { .. }));
+
+        if !truncated {
+            self.report.mcus_decoded = self.report.mcus_total;
+        }
 
-        self.finish_progressive_decoding(&block, mcu_width, pixels)
+        Ok(block)
     }
 
     #[allow(clippy::too_many_lines, clippy::cast_sign_loss)]
     fn parse_entropy_coded_data(
         &mut self,
         stream: &mut BitStream,
-        buffer: &mut [Vec<i16>; MAX_COMPONENTS],
+        buffer: &mut [ScratchBuf; MAX_COMPONENTS],
     ) -> Result<(), DecodeErrors> {
         stream.reset();
         self.components.iter_mut().for_each(|x| x.dc_pred = 0);
+        self.next_restart = 0;
 
         if usize::from(self.num_scans) > self.input_colorspace.num_components() {
             return Err(Format(format!(
@@ -211,25 +256,23 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
                 )));
             }
 
-            let (mcu_width, mcu_height);
-
-            if self.components[k].component_id == ComponentID::Y
-                && (self.components[k].vertical_sample != 1
-                    || self.components[k].horizontal_sample != 1)
-                || !self.is_interleaved
-            {
-                // For Y channel  or non interleaved scans ,
-                // mcu's is the image dimensions divided by 8
-                mcu_width = ((self.info.width + 7) / 8) as usize;
-                mcu_height = ((self.info.height + 7) / 8) as usize;
-            } else {
-                // For other channels, in an interleaved mcu, number of MCU's
-                // are determined by some weird maths done in headers.rs->parse_sos()
-                mcu_width = self.mcu_x;
-                mcu_height = self.mcu_y;
-            }
+            // Non interleaved scans contain just enough blocks to cover the component
+            // dimensions, which are the image dimensions scaled by the component's
+            // sampling factors (see A.2.2 in the spec)
+            let (h_samp, v_samp) = (
+                self.components[k].horizontal_sample,
+                self.components[k].vertical_sample,
+            );
+            let comp_width = (usize::from(self.info.width) * h_samp).div_ceil(self.h_max);
+            let comp_height = (usize::from(self.info.height) * v_samp).div_ceil(self.v_max);
+
+            let mcu_width = comp_width.div_ceil(8);
+            let mcu_height = comp_height.div_ceil(8);
 
             for i in 0..mcu_height {
+                if self.check_truncated_scan(stream, i)? {
+                    return Ok(());
+                }
                 for j in 0..mcu_width {
                     if self.spec_start != 0 && self.succ_high == 0 && stream.eob_run > 0 {
                         // handle EOB runs here.
@@ -263,15 +306,19 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
 
                         if self.succ_high == 0 {
                             // first scan for this mcu
-                            stream.decode_prog_dc_first(
-                                &mut self.stream,
-                                dc_table,
-                                &mut data[0],
-                                dc_pred,
-                            )?;
+                            stream
+                                .decode_prog_dc_first(
+                                    &mut self.stream,
+                                    dc_table,
+                                    &mut data[0],
+                                    dc_pred,
+                                )
+                                .map_err(|e| e.in_mcu(i, j, self.stream.get_position()))?;
                         } else {
                             // refining scans for this MCU
-                            stream.decode_prog_dc_refine(&mut self.stream, &mut data[0])?;
+                            stream
+                                .decode_prog_dc_refine(&mut self.stream, &mut data[0])
+                                .map_err(|e| e.in_mcu(i, j, self.stream.get_position()))?;
                         }
                     } else {
                         let pos = self.components[k].ac_huff_table;
@@ -293,19 +340,26 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
                         if self.succ_high == 0 {
                             debug_assert!(stream.eob_run == 0, "EOB run is not zero");
 
-                            stream.decode_mcu_ac_first(&mut self.stream, ac_table, data)?;
+                            stream
+                                .decode_mcu_ac_first(&mut self.stream, ac_table, data)
+                                .map_err(|e| e.in_mcu(i, j, self.stream.get_position()))?;
                         } else {
                             // refinement scan
-                            stream.decode_mcu_ac_refine(&mut self.stream, ac_table, data)?;
+                            stream
+                                .decode_mcu_ac_refine(&mut self.stream, ac_table, data)
+                                .map_err(|e| e.in_mcu(i, j, self.stream.get_position()))?;
                         }
                     }
                     // + EOB and investigate effect.
                     self.todo -= 1;
 
                     if self.todo == 0 {
-                        self.handle_rst(stream)?;
+                        self.handle_rst(stream, i, j)
+                            .map_err(|e| e.in_mcu(i, j, self.stream.get_position()))?;
                     }
                 }
+                self.report_mcu_row(stream, i);
+                self.call_yield_hook(i + 1, mcu_height);
             }
         } else {
             if self.spec_end != 0 {
@@ -349,6 +403,9 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
             // Components shall not be interleaved in progressive mode, except for
             // the DC coefficients in the first scan for each component of a progressive frame.
             for i in 0..self.mcu_y {
+                if self.check_truncated_scan(stream, i)? {
+                    return Ok(());
+                }
                 for j in 0..self.mcu_x {
                     // process scan n elements in order
                     for k in 0..self.num_scans {
@@ -372,14 +429,18 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
                                 let data = &mut buffer[n][position];
 
                                 if self.succ_high == 0 {
-                                    stream.decode_prog_dc_first(
-                                        &mut self.stream,
-                                        huff_table,
-                                        data,
-                                        &mut component.dc_pred,
-                                    )?;
+                                    stream
+                                        .decode_prog_dc_first(
+                                            &mut self.stream,
+                                            huff_table,
+                                            data,
+                                            &mut component.dc_pred,
+                                        )
+                                        .map_err(|e| e.in_mcu(i, j, self.stream.get_position()))?;
                                 } else {
-                                    stream.decode_prog_dc_refine(&mut self.stream, data)?;
+                                    stream
+                                        .decode_prog_dc_refine(&mut self.stream, data)
+                                        .map_err(|e| e.in_mcu(i, j, self.stream.get_position()))?;
                                 }
                             }
                         }
@@ -389,20 +450,46 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
                     self.todo = self.todo.wrapping_sub(1);
                     // after every scan that's a mcu, count down restart markers.
                     if self.todo == 0 {
-                        self.handle_rst(stream)?;
+                        self.handle_rst(stream, i, j)
+                            .map_err(|e| e.in_mcu(i, j, self.stream.get_position()))?;
                     }
                 }
+                self.report_mcu_row(stream, i);
+                self.call_yield_hook(i + 1, self.mcu_y);
             }
         }
         return Ok(());
     }
 
+    /// Check if the scan's data ran out before MCU row `row`
+    ///
+    /// Returns true if the rest of the scan should be skipped
+    pub(crate) fn check_truncated_scan(
+        &mut self,
+        stream: &BitStream,
+        row: usize,
+    ) -> Result<bool, DecodeErrors> {
+        if stream.overread() > 37 || stream.data_exhausted() {
+            if self.options.get_strict_mode() {
+                return Err(DecodeErrors::FormatStatic("Premature end of buffer"));
+            }
+            self.report.warn(DecodeWarning::Truncated {
+                row,
+                offset: self.stream.get_position(),
+            });
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
     #[allow(clippy::too_many_lines)]
     #[allow(clippy::needless_range_loop, clippy::cast_sign_loss)]
-    fn finish_progressive_decoding(
+    /// Dequantize, IDCT and post-process coefficients laid out like
+    /// [`decode_progressive_coefficients`](Self::decode_progressive_coefficients)
+    /// returns them
+    pub(crate) fn finish_progressive_decoding(
         &mut self,
-        block: &[Vec<i16>; MAX_COMPONENTS],
-        _mcu_width: usize,
+        block: &[&[i16]],
         pixels: &mut [u8],
     ) -> Result<(), DecodeErrors> {
         // This function is complicated because we need to replicate
@@ -433,38 +520,16 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
         };
 
         // Size of our output image(width*height)
-        let is_hv = usize::from(self.sub_sample_ratio == SampleRatios::HV);
-        let upsampler_scratch_size = is_hv * self.components[0].width_stride;
+        let upsampler_scratch_size = self.upsampler_scratch_size();
         let width = usize::from(self.info.width);
         let padded_width = calculate_padded_width(width, self.sub_sample_ratio);
 
         //let mut pixels = vec![0; capacity * out_colorspace_components];
-        let mut upsampler_scratch_space = vec![0; upsampler_scratch_size];
+        let mut upsampler_scratch_space =
+            self.arena.take(ScratchRegion::Hot, upsampler_scratch_size, 0)?;
         let mut tmp = [0_i32; DCT_BLOCK];
 
-        for (pos, comp) in self.components.iter_mut().enumerate() {
-            // Allocate only needed components.
-            //
-            // For special colorspaces i.e YCCK and CMYK, just allocate all of the needed
-            // components.
-            if min(
-                self.options.jpeg_get_out_colorspace().num_components() - 1,
-                pos,
-            ) == pos
-                || self.input_colorspace == ColorSpace::YCCK
-                || self.input_colorspace == ColorSpace::CMYK
-            {
-                // allocate enough space to hold a whole MCU width
-                // this means we should take into account sampling ratios
-                // `*8` is because each MCU spans 8 widths.
-                let len = comp.width_stride * comp.vertical_sample * 8;
-
-                comp.needed = true;
-                comp.raw_coeff = vec![0; len];
-            } else {
-                comp.needed = false;
-            }
-        }
+        self.setup_component_rows()?;
 
         let mut pixels_written = 0;
 
@@ -541,6 +606,7 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
                 &mut pixels_written,
                 &mut upsampler_scratch_space,
             )?;
+            self.call_yield_hook(i + 1, mcu_height);
         }
 
         // log::debug!("Finished decoding image");
@@ -258,22 +258,17 @@ pub(crate) fn setup_component_params<T: ZReaderTrait>(
         // probably not needed. :)
         component.y = y;
         component.quantization_table = qt_table;
-        // initially stride contains its horizontal sub-sampling
-        component.width_stride *= img.mcu_x * 8;
+        component.width_stride = component.horizontal_sample * img.mcu_x * 8;
     }
     if img.is_interleaved
-        && img.components[0].horizontal_sample == 1
-        && img.components[0].vertical_sample == 1
+        && (img.components[0].horizontal_sample != img.h_max
+            || img.components[0].vertical_sample != img.v_max)
     {
         return Err(DecodeErrors::FormatStatic(
-            "Unsupported unsampled Y component with sampled Cb / Cr components",
+            "Unsupported Y component sampled lower than Cb / Cr components",
         ));
     }
 
-    // delete quantization tables, we'll extract them from the components when
-    // needed
-    img.qt_tables = [None, None, None, None];
-
     Ok(())
 }
 
@@ -300,5 +295,10 @@ pub fn calculate_padded_width(actual_width: usize, sub_sample: SampleRatios) ->
             // sends two rows, width can be expanded by up to 15 more bytes
             ((actual_width + 15) / 16) * 16
         }
+        SampleRatios::Generic(h_max, _) => {
+            // sends h_max blocks per MCU
+            let mcu_width = 8 * h_max;
+            actual_width.div_ceil(mcu_width) * mcu_width
+        }
     }
 }
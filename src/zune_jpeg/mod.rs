@@ -4,7 +4,6 @@
 //!
 //!
 //! # Features
-//!  - SSE and AVX accelerated functions to speed up certain decoding operations
 //!  - FAST and accurate 32 bit IDCT algorithm
 //!  - Fast color convert functions
 //!  - RGBA and RGBX (4-Channel) color conversion functions
@@ -57,18 +56,12 @@
 //! enough to handle the complexities arising from parsing different types
 //! of jpeg images.
 //!
-//! Safety is a top concern that is why we provide both static ways to disable unsafe code,
-//! disabling x86 feature, and dynamic ,by using [`DecoderOptions::set_use_unsafe(false)`],
-//! both of these disable platform specific optimizations, which reduce the speed of decompression.
-//!
-//! Please do note that careful consideration has been taken to ensure that the unsafe paths
-//! are only unsafe because they depend on platform specific intrinsics, hence no need to disable them
+//! Safety is a top concern, decoding has no platform specific intrinsics, and the only unsafe
+//! code lends caller provided scratch memory to the decoder.
 //!
 //! The crate tries to decode as many images as possible, as a best effort, even those violating the standard
 //! , this means a lot of images may  get silent warnings and wrong output, but if you are sure you will be handling
 //! images that follow the spec, set `ZuneJpegOptions::set_strict` to true.
-//!
-//![`DecoderOptions::set_use_unsafe(false)`]:  https://docs.rs/zune-core/0.2.1/zune_core/options/struct.DecoderOptions.html#method.set_use_unsafe
 
 #![warn(
     clippy::correctness,
@@ -91,12 +84,26 @@
 #![allow(unused)]
 
 use crate::zune_core;
-pub use decoder::{ImageInfo, JpegDecoder};
+pub use coefficients::{ComponentCoefficients, JpegCoefficients};
+pub use dc::{DcThumbnail, HashKind, PerceptualHash};
+pub use decoder::{DecodeProgress, ImageInfo, JpegDecoder, YieldHook};
+pub use encoder::{JpegEncoder, JpegScan};
+pub use marker::Marker;
+pub use motion::{MotionBox, MotionDetector, MotionReport};
+pub use quality::{estimate_quality, ChromaSubsampling, EncodingFingerprint, QualityEstimate};
+pub use scratch::ScratchRequirements;
+pub use split::{JpegFrameSplitter, SplitEvent};
+pub use transform::{CropRegion, JpegTransform};
 
 mod bitstream;
+mod coefficients;
 mod color_convert;
 mod components;
+mod conceal;
+mod dc;
+mod deblock;
 mod decoder;
+mod encoder;
 pub mod errors;
 mod headers;
 mod huffman;
@@ -105,6 +112,14 @@ mod marker;
 mod mcu;
 mod mcu_prog;
 mod misc;
-mod unsafe_utils;
+mod motion;
+mod parallel;
+mod quality;
+pub mod report;
+mod scratch;
+mod split;
+mod transform;
 mod upsampler;
 mod worker;
+mod writer;
+mod writer_prog;
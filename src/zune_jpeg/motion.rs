@@ -0,0 +1,455 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+//! Motion detection on compressed frames
+//!
+//! Frames are compared block by block on their luma DC coefficient, the
+//! block average, and optionally their lowest AC coefficients, which catch
+//! changes in texture that keep the average. These only take entropy
+//! decoding, so frames are never decoded to pixels.
+//!
+//! Blocks are compared against a background model that follows the scene
+//! slowly, so gradual lighting changes and objects that stop moving fade
+//! into the background.
+#![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+
+use crate::zune_core::bytestream::ZReaderTrait;
+
+use super::decoder::JpegDecoder;
+use super::errors::DecodeErrors;
+
+/// A rectangle of pixels
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MotionBox {
+    /// Left edge
+    pub x: usize,
+    /// Top edge
+    pub y: usize,
+    /// Width in pixels
+    pub width: usize,
+    /// Height in pixels
+    pub height: usize,
+    /// Number of changed blocks in the box
+    pub blocks: usize,
+}
+
+/// What changed in a frame
+#[derive(Clone, Debug, PartialEq)]
+pub struct MotionReport {
+    /// Width of the frame in blocks
+    pub width: usize,
+    /// Height of the frame in blocks
+    pub height: usize,
+    /// Whether each block changed, row by row
+    ///
+    /// Groups of fewer touching blocks than the detector's minimum are
+    /// noise and aren't marked.
+    pub changed: Vec<bool>,
+    /// Number of changed blocks
+    pub changed_blocks: usize,
+    /// Boxes around groups of touching changed blocks, largest first
+    pub boxes: Vec<MotionBox>,
+    /// Fraction of the blocks in the region that changed, from 0 to 1
+    pub score: f32,
+}
+
+impl MotionReport {
+    /// Whether anything moved
+    #[must_use]
+    pub fn has_motion(&self) -> bool {
+        !self.boxes.is_empty()
+    }
+}
+
+/// Detects motion between frames of the same size
+///
+/// ```no_run
+/// use zune_jpeg::{JpegDecoder, MotionDetector};
+///
+/// let mut detector = MotionDetector::new().set_threshold(10).set_min_blocks(2);
+/// # let frames: Vec<Vec<u8>> = vec![];
+///
+/// for frame in &frames {
+///     let report = detector.process(&mut JpegDecoder::new(frame)).unwrap();
+///
+///     if report.has_motion() {
+///         println!("motion at {:?}", report.boxes[0]);
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct MotionDetector {
+    threshold: u32,
+    ac_coefficients: usize,
+    adaptation: u8,
+    min_blocks: usize,
+    region: Option<Vec<bool>>,
+    /// Background per block and coefficient, in 1/16ths
+    background: Vec<i32>,
+    width: usize,
+    height: usize,
+}
+
+impl Default for MotionDetector {
+    fn default() -> Self {
+        MotionDetector {
+            threshold: 12,
+            ac_coefficients: 0,
+            adaptation: 3,
+            min_blocks: 1,
+            region: None,
+            background: Vec::new(),
+            width: 0,
+            height: 0,
+        }
+    }
+}
+
+impl MotionDetector {
+    /// Create a detector with default settings
+    #[must_use]
+    pub fn new() -> MotionDetector {
+        MotionDetector::default()
+    }
+
+    /// Set how much a block has to change to count as motion
+    ///
+    /// This is in luma levels of the block average, plus the change of the
+    /// AC coefficients when they are compared. Lower is more sensitive.
+    ///
+    /// - Default value: 12
+    #[must_use]
+    pub fn set_threshold(mut self, threshold: u32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Set how many of the lowest frequency AC coefficients of a block are
+    /// compared along with its average
+    ///
+    /// These catch changes that keep the block average, e.g something moving
+    /// over a background of the same brightness. The first two are the
+    /// horizontal and vertical gradients of the block.
+    ///
+    /// - Default value: 0, clamped to 63
+    #[must_use]
+    pub fn set_ac_coefficients(mut self, coefficients: usize) -> Self {
+        self.ac_coefficients = coefficients.min(63);
+        self.background.clear();
+        self
+    }
+
+    /// Set how fast the background follows the scene
+    ///
+    /// Every frame the background moves `1 / 2^adaptation` of the way to the
+    /// frame. Zero compares every frame to the previous one.
+    ///
+    /// - Default value: 3, clamped to 8
+    #[must_use]
+    pub fn set_adaptation(mut self, adaptation: u8) -> Self {
+        self.adaptation = adaptation.min(8);
+        self
+    }
+
+    /// Set the smallest number of touching changed blocks reported as a box,
+    /// smaller groups are treated as noise
+    ///
+    /// - Default value: 1
+    #[must_use]
+    pub fn set_min_blocks(mut self, blocks: usize) -> Self {
+        self.min_blocks = blocks.max(1);
+        self
+    }
+
+    /// Only look for motion in blocks where `mask` is true
+    ///
+    /// The mask has a value per 8x8 block, row by row, for frames
+    /// `(width + 7) / 8` blocks wide and `(height + 7) / 8` blocks high.
+    #[must_use]
+    pub fn set_region_mask(mut self, mask: Vec<bool>) -> Self {
+        self.region = Some(mask);
+        self
+    }
+
+    /// Forget the background, the next frame starts a new one
+    pub fn reset(&mut self) {
+        self.background.clear();
+    }
+
+    /// Compare the frame in `decoder` with the background, then update
+    /// the background
+    ///
+    /// The first frame, and the first one after the frame size changes,
+    /// only sets up the background and reports no motion.
+    ///
+    /// # Errors
+    /// If the frame can't be decoded or the region mask doesn't match the
+    /// frame size
+    pub fn process<T: ZReaderTrait>(
+        &mut self,
+        decoder: &mut JpegDecoder<T>,
+    ) -> Result<MotionReport, DecodeErrors> {
+        let coefficients = self.ac_coefficients + 1;
+        let blocks = decoder.decode_luma_blocks(coefficients)?;
+        let (width, height) = (blocks.width, blocks.height);
+        let count = width * height;
+
+        if let Some(region) = &self.region {
+            if region.len() != count {
+                return Err(DecodeErrors::Format(format!(
+                    "Region mask has {} blocks but frames have {width}x{height} blocks",
+                    region.len()
+                )));
+            }
+        }
+        let mut report = MotionReport {
+            width,
+            height,
+            changed: vec![false; count],
+            changed_blocks: 0,
+            boxes: Vec::new(),
+            score: 0.0,
+        };
+        if self.background.len() != blocks.values.len() || self.width != width {
+            self.background = blocks.values.iter().map(|x| x * 16).collect();
+            self.width = width;
+            self.height = height;
+
+            return Ok(report);
+        }
+
+        for (index, (values, background)) in blocks
+            .values
+            .chunks_exact(coefficients)
+            .zip(self.background.chunks_exact_mut(coefficients))
+            .enumerate()
+        {
+            // coefficients are eight times the levels they stand for
+            let difference: u32 = values
+                .iter()
+                .zip(background.iter())
+                .map(|(value, background)| (value * 16 - background).unsigned_abs())
+                .sum::<u32>()
+                / (16 * 8);
+
+            if self.region.iter().all(|region| region[index])
+                && difference > self.threshold
+            {
+                report.changed[index] = true;
+            }
+            for (value, background) in values.iter().zip(background.iter_mut()) {
+                *background += (value * 16 - *background) >> self.adaptation;
+            }
+        }
+
+        report.boxes = self.find_boxes(&mut report.changed, decoder);
+        report.changed_blocks = report.boxes.iter().map(|b| b.blocks).sum();
+
+        let considered = self
+            .region
+            .as_ref()
+            .map_or(count, |region| region.iter().filter(|x| **x).count());
+
+        if considered > 0 {
+            report.score = report.changed_blocks as f32 / considered as f32;
+        }
+
+        Ok(report)
+    }
+
+    /// Group touching changed blocks into boxes, clamped to the frame
+    ///
+    /// Groups smaller than `min_blocks` are cleared from `changed`.
+    fn find_boxes<T: ZReaderTrait>(
+        &self,
+        changed: &mut [bool],
+        decoder: &JpegDecoder<T>,
+    ) -> Vec<MotionBox> {
+        let (width, height) = (self.width, self.height);
+        let (pixel_width, pixel_height) = (
+            usize::from(decoder.info.width),
+            usize::from(decoder.info.height),
+        );
+        let mut seen = vec![false; changed.len()];
+        let mut boxes = Vec::new();
+        let mut stack = Vec::new();
+        let mut group = Vec::new();
+
+        for start in 0..changed.len() {
+            if !changed[start] || seen[start] {
+                continue;
+            }
+            let (mut x0, mut y0, mut x1, mut y1) = (width, height, 0, 0);
+            group.clear();
+
+            seen[start] = true;
+            stack.push(start);
+
+            while let Some(index) = stack.pop() {
+                let (x, y) = (index % width, index / width);
+
+                x0 = x0.min(x);
+                y0 = y0.min(y);
+                x1 = x1.max(x);
+                y1 = y1.max(y);
+                group.push(index);
+
+                let neighbours = [
+                    (x > 0).then(|| index - 1),
+                    (x + 1 < width).then(|| index + 1),
+                    (y > 0).then(|| index - width),
+                    (y + 1 < height).then(|| index + width),
+                ];
+                for next in neighbours.into_iter().flatten() {
+                    if changed[next] && !seen[next] {
+                        seen[next] = true;
+                        stack.push(next);
+                    }
+                }
+            }
+            if group.len() >= self.min_blocks {
+                boxes.push(MotionBox {
+                    x: x0 * 8,
+                    y: y0 * 8,
+                    width: ((x1 + 1) * 8).min(pixel_width) - x0 * 8,
+                    height: ((y1 + 1) * 8).min(pixel_height) - y0 * 8,
+                    blocks: group.len(),
+                });
+            } else {
+                for &index in &group {
+                    changed[index] = false;
+                }
+            }
+        }
+        boxes.sort_by_key(|b| core::cmp::Reverse(b.blocks));
+        boxes
+    }
+}
+
+#[test]
+fn motion_detection() {
+    use crate::zune_core::bit_depth::BitDepth;
+    use crate::zune_core::colorspace::ColorSpace;
+    use crate::zune_core::options::EncoderOptions;
+
+    use super::JpegEncoder;
+
+    let (width, height) = (100, 60);
+    // a gradient with a square at (x, y), either bright or a checkerboard
+    // with the average of the background
+    let frame = |square: Option<(usize, usize)>, checkered: bool| {
+        let mut pixels = Vec::with_capacity(width * height);
+
+        for y in 0..height {
+            for x in 0..width {
+                let background = 100 + (x / 4) as u8;
+                let value = match square {
+                    Some((sx, sy)) if (sx..sx + 16).contains(&x) && (sy..sy + 16).contains(&y) => {
+                        if !checkered {
+                            230
+                        } else if (x / 4 + y / 4) % 2 == 0 {
+                            background - 60
+                        } else {
+                            background + 60
+                        }
+                    }
+                    _ => background,
+                };
+                pixels.push(value);
+            }
+        }
+        let options = EncoderOptions::new(width, height, ColorSpace::Luma, BitDepth::Eight)
+            .set_quality(85);
+        JpegEncoder::new(&pixels, options).encode().unwrap()
+    };
+    let empty = frame(None, false);
+
+    let mut detector = MotionDetector::new();
+    let mut process = |detector: &mut MotionDetector, jpeg: &[u8]| {
+        detector.process(&mut JpegDecoder::new(jpeg)).unwrap()
+    };
+
+    // the first frame sets the background up
+    assert!(!process(&mut detector, &empty).has_motion());
+    assert!(!process(&mut detector, &empty).has_motion());
+
+    let report = process(&mut detector, &frame(Some((32, 24)), false));
+    assert_eq!((report.width, report.height), (13, 8));
+    assert_eq!(report.changed_blocks, 4);
+    assert_eq!(
+        report.boxes,
+        vec![MotionBox {
+            x: 32,
+            y: 24,
+            width: 16,
+            height: 16,
+            blocks: 4
+        }]
+    );
+    assert!((report.score - 4.0 / 104.0).abs() < 1e-6);
+
+    // the square leaving its place is motion too, and boxes are clamped
+    // to the frame
+    let report = process(&mut detector, &frame(Some((96, 56)), false));
+    assert_eq!(report.boxes.len(), 2);
+    assert_eq!((report.boxes[0].x, report.boxes[0].y), (32, 24));
+    assert_eq!(
+        report.boxes[1],
+        MotionBox {
+            x: 96,
+            y: 56,
+            width: 4,
+            height: 4,
+            blocks: 1
+        }
+    );
+
+    // a square whose blocks keep their average only shows in AC coefficients
+    let mut detector = MotionDetector::new();
+    process(&mut detector, &empty);
+    assert!(!process(&mut detector, &frame(Some((32, 24)), true)).has_motion());
+
+    let mut detector = MotionDetector::new().set_ac_coefficients(5);
+    process(&mut detector, &empty);
+    assert_eq!(
+        process(&mut detector, &frame(Some((32, 24)), true)).changed_blocks,
+        4
+    );
+
+    // blocks outside the region are ignored
+    let mut region = vec![true; 13 * 8];
+    for y in 3..5 {
+        for x in 4..6 {
+            region[y * 13 + x] = false;
+        }
+    }
+    let mut detector = MotionDetector::new().set_region_mask(region);
+    process(&mut detector, &empty);
+    assert!(!process(&mut detector, &frame(Some((32, 24)), false)).has_motion());
+
+    // groups smaller than the minimum are noise, and don't count at all
+    let mut detector = MotionDetector::new().set_min_blocks(5);
+    process(&mut detector, &empty);
+    let report = process(&mut detector, &frame(Some((32, 24)), false));
+    assert!(!report.has_motion());
+    assert_eq!(report.changed_blocks, 0);
+    assert!(report.changed.iter().all(|x| !x));
+    assert!(report.score.abs() < f32::EPSILON);
+
+    let mut detector = MotionDetector::new().set_region_mask(vec![true; 3]);
+    assert!(detector.process(&mut JpegDecoder::new(&empty)).is_err());
+
+    // a square that stays fades into the background
+    let mut detector = MotionDetector::new();
+    let still = frame(Some((32, 24)), false);
+    process(&mut detector, &empty);
+
+    let reports: Vec<_> = (0..30).map(|_| process(&mut detector, &still)).collect();
+    assert!(reports[0].has_motion());
+    assert!(!reports[29].has_motion());
+}
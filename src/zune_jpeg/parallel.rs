@@ -0,0 +1,395 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+//! Decoding restart intervals on multiple threads
+//!
+//! Restart markers split the entropy coded data into intervals that can be
+//! decoded independently, so we index them, split the image into groups of MCU
+//! rows starting at restart intervals and entropy decode and idct each group on
+//! its own thread.
+//!
+//! Up-sampling needs the rows above and below, so it and color conversion run on
+//! the calling thread afterwards, going through the same routines as the serial
+//! decoder, so output is identical.
+//!
+//! Anything out of the ordinary (corrupt data, missing markers, a memory limit
+//! too small for the extra buffers) makes us fall back to the serial decoder, which
+//! handles and reports it.
+use std::thread;
+
+use crate::zune_core::bytestream::{ZByteReader, ZReaderTrait};
+
+use super::bitstream::BitStream;
+use super::components::Components;
+use super::decoder::{IDCTPtr, JpegDecoder, MAX_COMPONENTS};
+use super::errors::DecodeErrors;
+use super::huffman::HuffmanTable;
+use super::marker::Marker;
+use super::mcu::DCT_BLOCK;
+use super::misc::calculate_padded_width;
+use super::report::DecodeWarning;
+
+/// Stack size of worker threads, decoding needs very little stack
+const WORKER_STACK_SIZE: usize = 16 * 1024;
+
+/// Everything a worker thread needs to decode a group of MCU rows
+struct RowGroupDecoder<'a> {
+    components: &'a [Components],
+    dc_huffman_tables: &'a [Option<HuffmanTable>; MAX_COMPONENTS],
+    ac_huffman_tables: &'a [Option<HuffmanTable>; MAX_COMPONENTS],
+    idct_func: IDCTPtr,
+    mcu_width: usize,
+    restart_interval: usize,
+}
+
+impl RowGroupDecoder<'_> {
+    /// Decode `rows` MCU rows from `data`, which starts at a restart interval,
+    /// writing samples of every component to `planes`
+    ///
+    /// Returns the number of out of range coefficients in every row
+    fn decode(
+        &self,
+        data: &[u8],
+        rows: usize,
+        planes: &mut [&mut [i16]],
+    ) -> Result<Vec<usize>, DecodeErrors> {
+        let mut reader = ZByteReader::new(data);
+        let mut stream: BitStream = BitStream::new();
+        let mut tmp = [0_i32; DCT_BLOCK];
+        let mut dc_pred = [0_i32; MAX_COMPONENTS];
+        let mut todo = self.restart_interval;
+        let mut out_of_range = vec![0; rows];
+
+        for (row, row_out_of_range) in out_of_range.iter_mut().enumerate() {
+            for j in 0..self.mcu_width {
+                for (pos, component) in self.components.iter().enumerate() {
+                    let dc_table = self.dc_huffman_tables[component.dc_huff_table % MAX_COMPONENTS]
+                        .as_ref()
+                        .unwrap();
+                    let ac_table = self.ac_huffman_tables[component.ac_huff_table % MAX_COMPONENTS]
+                        .as_ref()
+                        .unwrap();
+
+                    let stride = component.width_stride;
+                    let row_start = row * stride * component.vertical_sample * 8;
+
+                    for v_samp in 0..component.vertical_sample {
+                        for h_samp in 0..component.horizontal_sample {
+                            tmp.fill(0);
+
+                            stream.decode_mcu_block(
+                                &mut reader,
+                                dc_table,
+                                ac_table,
+                                &component.quantization_table,
+                                &mut tmp,
+                                &mut dc_pred[pos],
+                            )?;
+
+                            if component.needed {
+                                let c2 = v_samp * 8;
+                                let c3 = ((j * component.horizontal_sample) + h_samp) * 8;
+                                let idct_pos = &mut planes[pos][row_start + stride * c2 + c3..];
+
+                                (self.idct_func)(&mut tmp, idct_pos, stride);
+                            }
+                        }
+                    }
+                }
+                todo -= 1;
+
+                if todo == 0 {
+                    // intervals end exactly at their restart marker, anything
+                    // else goes to the serial decoder
+                    match stream.marker {
+                        Some(Marker::RST(_)) => {
+                            stream.reset();
+                            dc_pred = [0; MAX_COMPONENTS];
+                            todo = self.restart_interval;
+                        }
+                        Some(Marker::EOI) => {}
+                        _ => return Err(DecodeErrors::FormatStatic("Restart marker missing")),
+                    }
+                } else if stream.marker.is_some() && stream.bits_left == 0 {
+                    return Err(DecodeErrors::FormatStatic("Restart interval ended early"));
+                }
+            }
+            if stream.overread_by != 0 {
+                return Err(DecodeErrors::FormatStatic("Restart interval ended early"));
+            }
+            *row_out_of_range = stream.out_of_range;
+            stream.out_of_range = 0;
+        }
+        Ok(out_of_range)
+    }
+}
+
+/// Find where restart intervals start in entropy coded data
+///
+/// Returns the start of every interval and the position of the EOI marker,
+/// or `None` if restart markers are out of order or other markers are found.
+fn index_restart_intervals(data: &[u8]) -> Option<(Vec<usize>, usize)> {
+    let mut starts = vec![0];
+    let mut i = 0;
+
+    while i + 1 < data.len() {
+        if data[i] != 0xFF {
+            i += 1;
+            continue;
+        }
+        match data[i + 1] {
+            // stuffed byte, or fill bytes before a marker
+            0x00 => i += 2,
+            0xFF => i += 1,
+            m @ 0xD0..=0xD7 => {
+                if usize::from(m - 0xD0) != (starts.len() - 1) & 7 {
+                    return None;
+                }
+                i += 2;
+                starts.push(i);
+            }
+            0xD9 => return Some((starts, i)),
+            _ => return None,
+        }
+    }
+    None
+}
+
+const fn gcd(mut a: usize, mut b: usize) -> usize {
+    while b != 0 {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+    a
+}
+
+impl<T: ZReaderTrait> JpegDecoder<T> {
+    /// Decode a baseline image on multiple threads
+    ///
+    /// Returns false if the image can't be decoded in parallel, e.g if the
+    /// source isn't contiguous in memory, the caller should then decode it
+    /// serially. Nothing is consumed from the stream in that case.
+    #[allow(clippy::too_many_lines)]
+    pub(crate) fn decode_mcu_ycbcr_parallel(
+        &mut self,
+        pixels: &mut [u8],
+        mcu_width: usize,
+        mcu_height: usize,
+    ) -> Result<bool, DecodeErrors> {
+        let threads = usize::from(self.options.get_num_threads());
+
+        // workers allocate their own buffers, which scratch memory can't provide
+        if threads < 2 || self.restart_interval == 0 || self.arena.is_lent() {
+            return Ok(false);
+        }
+        let scan_start = self.stream.get_position();
+
+        // workers need the scan as a single slice
+        let Ok(data) = self.stream.peek_at(0, self.stream.remaining()) else {
+            return Ok(false);
+        };
+        let Some((starts, eoi)) = index_restart_intervals(data) else {
+            return Ok(false);
+        };
+        let restart_interval = self.restart_interval;
+
+        if starts.len() != (mcu_width * mcu_height).div_ceil(restart_interval) {
+            return Ok(false);
+        }
+        // groups have to start at a restart interval, so they are made of units
+        // of MCU rows spanning a whole number of restart intervals
+        let unit = restart_interval / gcd(restart_interval, mcu_width);
+        let units = mcu_height.div_ceil(unit);
+        let threads = threads.min(units);
+
+        if threads < 2 {
+            return Ok(false);
+        }
+        let group_rows = units.div_ceil(threads) * unit;
+
+        let row_sizes: Vec<usize> = self
+            .components
+            .iter()
+            .map(|c| {
+                if c.needed {
+                    c.width_stride * c.vertical_sample * 8
+                } else {
+                    0
+                }
+            })
+            .collect();
+
+        // samples of the whole image, on top of what the serial decoder needs
+        let planes_size = row_sizes.iter().sum::<usize>() * mcu_height;
+
+        if self
+            .memory_required()
+            .saturating_add(planes_size * core::mem::size_of::<i16>())
+            > self.options.get_max_memory()
+        {
+            return Ok(false);
+        }
+        let mut planes: Vec<Vec<i16>> = row_sizes.iter().map(|x| vec![0; x * mcu_height]).collect();
+
+        // split data and planes into row groups
+        let mut groups = Vec::with_capacity(threads);
+        let mut rest: Vec<&mut [i16]> = planes.iter_mut().map(|x| &mut x[..]).collect();
+
+        for first_row in (0..mcu_height).step_by(group_rows) {
+            let rows = group_rows.min(mcu_height - first_row);
+            let first_interval = first_row * mcu_width / restart_interval;
+            let start = starts[first_interval];
+            // include the marker ending the group, the last group
+            // may end with a partial restart interval
+            let end = if first_row + rows == mcu_height {
+                eoi + 2
+            } else {
+                starts[(first_row + rows) * mcu_width / restart_interval]
+            };
+
+            let mut group_planes = Vec::with_capacity(rest.len());
+
+            rest = rest
+                .into_iter()
+                .zip(&row_sizes)
+                .map(|(plane, row_size)| {
+                    let (group, rest) = plane.split_at_mut(row_size * rows);
+                    group_planes.push(group);
+                    rest
+                })
+                .collect();
+
+            groups.push((&data[start..end], rows, group_planes));
+        }
+
+        let decoder = RowGroupDecoder {
+            components: &self.components,
+            dc_huffman_tables: &self.dc_huffman_tables,
+            ac_huffman_tables: &self.ac_huffman_tables,
+            idct_func: self.idct_func,
+            mcu_width,
+            restart_interval,
+        };
+
+        let results: Vec<Result<Vec<usize>, DecodeErrors>> = thread::scope(|s| {
+            let decoder = &decoder;
+            let mut groups = groups.into_iter();
+            // the calling thread decodes the first group
+            let mut first = groups.next().unwrap();
+
+            let handles: Vec<_> = groups
+                .map(|(data, rows, mut planes)| {
+                    thread::Builder::new()
+                        .stack_size(WORKER_STACK_SIZE)
+                        .spawn_scoped(s, move || decoder.decode(data, rows, &mut planes))
+                })
+                .collect();
+
+            let mut results = vec![decoder.decode(first.0, first.1, &mut first.2)];
+
+            for handle in handles {
+                results.push(match handle {
+                    Ok(handle) => handle
+                        .join()
+                        .unwrap_or(Err(DecodeErrors::FormatStatic("Worker thread panicked"))),
+                    Err(_) => Err(DecodeErrors::FormatStatic("Could not spawn worker thread")),
+                });
+            }
+            results
+        });
+
+        let mut out_of_range = Vec::with_capacity(mcu_height);
+
+        for result in results {
+            match result {
+                Ok(rows) => out_of_range.extend(rows),
+                // let the serial decoder handle it
+                Err(_) => return Ok(false),
+            }
+        }
+
+        // up-sample and color convert in order
+        let width = usize::from(self.info.width);
+        let padded_width = calculate_padded_width(width, self.sub_sample_ratio);
+
+        let mut pixels_written = 0;
+        let mut upsampler_scratch_space = vec![0; self.upsampler_scratch_size()];
+
+        for i in 0..mcu_height {
+            for ((component, plane), row_size) in
+                self.components.iter_mut().zip(&planes).zip(&row_sizes)
+            {
+                if component.needed {
+                    component
+                        .raw_coeff
+                        .copy_from_slice(&plane[i * row_size..(i + 1) * row_size]);
+                }
+            }
+            self.post_process(
+                pixels,
+                i,
+                mcu_height,
+                width,
+                padded_width,
+                &mut pixels_written,
+                &mut upsampler_scratch_space,
+            )?;
+
+            if out_of_range[i] != 0 {
+                self.report.warn(DecodeWarning::CoefficientOutOfRange {
+                    row: i,
+                    count: out_of_range[i],
+                    offset: scan_start,
+                });
+            }
+            self.call_yield_hook(i + 1, mcu_height);
+        }
+        self.report.mcus_decoded = self.report.mcus_total;
+        // skip past the EOI marker, like the serial decoder
+        self.stream.set_position(scan_start + eoi + 2);
+
+        Ok(true)
+    }
+}
+
+#[test]
+fn parallel_restart_intervals() {
+    use crate::zune_core::options::{DecoderOptions, JpegConcealment};
+
+    let data = include_bytes!("../../tests/inputs/restart_420.jpg");
+    // 7x9 MCUs with 5 MCUs per restart interval, the last one is partial
+    let partial = include_bytes!("../../tests/inputs/restart_422_partial.jpg");
+
+    for input in [&data[..], &partial[..]] {
+        let serial = JpegDecoder::new(input).decode().unwrap();
+
+        for threads in [2, 3, 4, 8] {
+            let options = DecoderOptions::default().set_num_threads(threads);
+            let mut decoder = JpegDecoder::new_with_options(input, options);
+
+            assert_eq!(decoder.decode().unwrap(), serial);
+            assert!(decoder.report().is_clean());
+            assert_eq!(decoder.report().mcus_decoded, decoder.report().mcus_total);
+        }
+    }
+
+    // corrupt data falls back to the serial decoder, which conceals it
+    let mut corrupt = data.to_vec();
+    let end = corrupt.len() - 8;
+    corrupt[end..end + 4].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0x13]);
+
+    let options = DecoderOptions::default().jpeg_set_concealment(JpegConcealment::Grey);
+    let serial = JpegDecoder::new_with_options(&corrupt[..], options)
+        .decode()
+        .unwrap();
+    let mut decoder = JpegDecoder::new_with_options(&corrupt[..], options.set_num_threads(4));
+
+    assert_eq!(decoder.decode().unwrap(), serial);
+    assert!(decoder.report().mcus_concealed > 0);
+}
@@ -0,0 +1,285 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+//! Estimating the quality an image was encoded at
+//!
+//! Most encoders (libjpeg, camera firmware, browsers) scale the tables from
+//! Annex K of the spec by the IJG quality factor, so finding the factor that
+//! reproduces the DQT tables gives the quality setting of the encoder. Tables
+//! that no factor reproduces exactly come from a custom encoder, for those
+//! the quality is estimated from the average scale of the tables.
+#![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+
+use core::fmt::{Display, Formatter};
+
+use crate::zune_core::bytestream::ZReaderTrait;
+
+use super::decoder::JpegDecoder;
+use super::encoder::{scale_table, STD_CHROMA_QT, STD_LUMA_QT};
+use super::errors::DecodeErrors;
+
+/// Estimated IJG quality of an image
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct QualityEstimate {
+    /// Quality factor, from 1 to 100
+    pub quality: u8,
+    /// Whether the tables are exactly the standard ones scaled to `quality`,
+    /// if not `quality` is an approximation
+    pub standard_tables: bool,
+}
+
+/// Chroma subsampling of an image, from the sampling factors of its components
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChromaSubsampling {
+    /// A single component
+    Grayscale,
+    /// Chroma at full resolution
+    S444,
+    /// Chroma at half the horizontal resolution
+    S422,
+    /// Chroma at half the horizontal and vertical resolution
+    S420,
+    /// Chroma at half the vertical resolution
+    S440,
+    /// Chroma at a quarter of the horizontal resolution
+    S411,
+    /// Any other combination of sampling factors
+    Other,
+}
+
+impl Display for ChromaSubsampling {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let name = match self {
+            Self::Grayscale => "gray",
+            Self::S444 => "4:4:4",
+            Self::S422 => "4:2:2",
+            Self::S420 => "4:2:0",
+            Self::S440 => "4:4:0",
+            Self::S411 => "4:1:1",
+            Self::Other => "other",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// How an image was encoded, as far as its headers tell
+///
+/// Images from the same encoder with the same settings share a fingerprint.
+/// The `Display` implementation gives a compact form, e.g
+/// `q85 4:2:0 baseline`, with custom tables shown as `~q85`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct EncodingFingerprint {
+    /// Estimated quality
+    pub quality: QualityEstimate,
+    /// Chroma subsampling
+    pub subsampling: ChromaSubsampling,
+    /// Whether the image is progressive
+    pub progressive: bool,
+    /// Number of components
+    pub components: u8,
+    /// FNV-1a hash of the quantization tables, in component order,
+    /// telling apart encoders with different custom tables
+    pub tables_hash: u32,
+}
+
+impl Display for EncodingFingerprint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{}q{} {} {}",
+            if self.quality.standard_tables { "" } else { "~" },
+            self.quality.quality,
+            self.subsampling,
+            if self.progressive { "progressive" } else { "baseline" }
+        )
+    }
+}
+
+/// Whether `table` is `expected`, allowing for 16 bit tables that
+/// weren't clamped to 255
+fn matches_table(table: &[i32; 64], expected: &[i32; 64]) -> bool {
+    table
+        .iter()
+        .zip(expected)
+        .all(|(a, e)| a == e || (*e == 255 && *a > 255))
+}
+
+/// Estimate the IJG quality of the luma table and, for color images,
+/// the chroma table
+///
+/// Tables are in natural order.
+#[must_use]
+pub fn estimate_quality(luma: &[i32; 64], chroma: Option<&[i32; 64]>) -> QualityEstimate {
+    for quality in 1..=100 {
+        if matches_table(luma, &scale_table(&STD_LUMA_QT, quality))
+            && chroma
+                .iter()
+                .all(|t| matches_table(t, &scale_table(&STD_CHROMA_QT, quality)))
+        {
+            return QualityEstimate {
+                quality,
+                standard_tables: true,
+            };
+        }
+    }
+    // invert the scaling for the average ratio to the standard tables
+    let mut actual: i64 = luma.iter().map(|x| i64::from(*x)).sum();
+    let mut standard: i64 = STD_LUMA_QT.iter().map(|x| i64::from(*x)).sum();
+
+    if let Some(chroma) = chroma {
+        actual += chroma.iter().map(|x| i64::from(*x)).sum::<i64>();
+        standard += STD_CHROMA_QT.iter().map(|x| i64::from(*x)).sum::<i64>();
+    }
+    let scale = (actual * 100 + standard / 2) / standard;
+    let quality = if scale <= 100 {
+        (200 - scale + 1) / 2
+    } else {
+        (5000 + scale / 2) / scale
+    };
+    QualityEstimate {
+        quality: quality.clamp(1, 100) as u8,
+        standard_tables: false,
+    }
+}
+
+impl<T: ZReaderTrait> JpegDecoder<T> {
+    /// Quantization table of the component at `index`, in natural order
+    fn component_table(&self, index: usize) -> [i32; 64] {
+        let component = &self.components[index];
+
+        self.qt_tables[usize::from(component.quantization_table_number)]
+            .unwrap_or(component.quantization_table)
+    }
+
+    /// Estimate the IJG quality the image was encoded at, from its
+    /// quantization tables
+    ///
+    /// This only needs the headers, which are decoded if they weren't already.
+    ///
+    /// # Errors
+    /// If the headers can't be decoded
+    pub fn estimate_quality(&mut self) -> Result<QualityEstimate, DecodeErrors> {
+        self.decode_headers_internal()?;
+
+        let luma = self.component_table(0);
+        let chroma = (self.components.len() > 1).then(|| self.component_table(1));
+
+        Ok(estimate_quality(&luma, chroma.as_ref()))
+    }
+
+    /// Describe how the image was encoded, see [`EncodingFingerprint`]
+    ///
+    /// This only needs the headers, which are decoded if they weren't already.
+    ///
+    /// # Errors
+    /// If the headers can't be decoded
+    pub fn encoding_fingerprint(&mut self) -> Result<EncodingFingerprint, DecodeErrors> {
+        let quality = self.estimate_quality()?;
+
+        let subsampling = if let [luma, chroma @ ..] = &self.components[..] {
+            let (h, v) = (luma.horizontal_sample, luma.vertical_sample);
+
+            match chroma.first() {
+                None => ChromaSubsampling::Grayscale,
+                Some(c)
+                    if chroma.iter().any(|x| {
+                        (x.horizontal_sample, x.vertical_sample)
+                            != (c.horizontal_sample, c.vertical_sample)
+                    }) =>
+                {
+                    ChromaSubsampling::Other
+                }
+                Some(c) if h % c.horizontal_sample != 0 || v % c.vertical_sample != 0 => {
+                    ChromaSubsampling::Other
+                }
+                Some(c) => match (h / c.horizontal_sample, v / c.vertical_sample) {
+                    (1, 1) => ChromaSubsampling::S444,
+                    (2, 1) => ChromaSubsampling::S422,
+                    (2, 2) => ChromaSubsampling::S420,
+                    (1, 2) => ChromaSubsampling::S440,
+                    (4, 1) => ChromaSubsampling::S411,
+                    _ => ChromaSubsampling::Other,
+                },
+            }
+        } else {
+            ChromaSubsampling::Other
+        };
+
+        let mut tables_hash: u32 = 0x811C_9DC5;
+
+        for index in 0..self.components.len() {
+            for value in self.component_table(index) {
+                for byte in (value as u16).to_be_bytes() {
+                    tables_hash = (tables_hash ^ u32::from(byte)).wrapping_mul(0x0100_0193);
+                }
+            }
+        }
+        Ok(EncodingFingerprint {
+            quality,
+            subsampling,
+            progressive: self.is_progressive,
+            components: self.components.len() as u8,
+            tables_hash,
+        })
+    }
+}
+
+#[test]
+fn quality_fingerprint() {
+    use crate::zune_core::bit_depth::BitDepth;
+    use crate::zune_core::colorspace::ColorSpace;
+    use crate::zune_core::options::{EncoderOptions, JpegSubsampling};
+
+    use super::JpegEncoder;
+
+    let (width, height) = (32, 24);
+    let pixels = vec![100; width * height * 3];
+
+    for quality in [1, 10, 50, 75, 90, 100] {
+        for (subsampling, expected) in [
+            (JpegSubsampling::S444, ChromaSubsampling::S444),
+            (JpegSubsampling::S422, ChromaSubsampling::S422),
+            (JpegSubsampling::S420, ChromaSubsampling::S420),
+        ] {
+            let options = EncoderOptions::new(width, height, ColorSpace::RGB, BitDepth::Eight)
+                .set_quality(quality)
+                .set_jpeg_subsampling(subsampling)
+                .set_jpeg_encode_progressive(quality == 75);
+            let jpeg = JpegEncoder::new(&pixels, options).encode().unwrap();
+            let fingerprint = JpegDecoder::new(&jpeg).encoding_fingerprint().unwrap();
+
+            assert_eq!(fingerprint.quality.quality, quality);
+            assert!(fingerprint.quality.standard_tables);
+            assert_eq!(fingerprint.subsampling, expected);
+            assert_eq!(fingerprint.progressive, quality == 75);
+            assert_eq!(fingerprint.components, 3);
+        }
+    }
+
+    let options = EncoderOptions::new(width, height, ColorSpace::Luma, BitDepth::Eight);
+    let gray = JpegEncoder::new(&pixels[..width * height], options)
+        .encode()
+        .unwrap();
+    let mut decoder = JpegDecoder::new(&gray);
+
+    // tables are still found after decoding
+    decoder.decode().unwrap();
+    let fingerprint = decoder.encoding_fingerprint().unwrap();
+    assert_eq!(fingerprint.to_string(), "q80 gray baseline");
+
+    // a slightly tweaked table is custom, with a close estimate
+    let mut luma = scale_table(&STD_LUMA_QT, 60);
+    luma[63] += 1;
+    let estimate = estimate_quality(&luma, None);
+
+    assert!(!estimate.standard_tables);
+    assert!((58..=62).contains(&estimate.quality));
+
+    let custom = [10; 64];
+    assert_ne!(estimate_quality(&custom, Some(&custom)).quality, 0);
+}
@@ -0,0 +1,118 @@
+//! Non-fatal issues found while decoding an image
+//!
+//! The decoder tolerates some damage (e.g truncated data) and keeps
+//! decoding instead of returning an error. What it had to paper over is
+//! collected in a [`DecodeReport`], available from
+//! [`JpegDecoder::report`](crate::zune_jpeg::JpegDecoder::report) after decoding.
+
+use super::marker::Marker;
+
+/// A non-fatal issue found while decoding
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeWarning {
+    /// Image data ran out, decoding stopped before MCU row `row`
+    Truncated {
+        /// MCU row where decoding stopped
+        row: usize,
+        /// Byte offset the stream had been read up to
+        offset: usize,
+    },
+    /// The bit reader read past the end of the data, and decoded
+    /// zeroes instead
+    Overread {
+        /// Number of bytes read past the end of the data
+        bytes: usize,
+    },
+    /// The stream didn't end with an EOI marker
+    MissingEoi {
+        /// Byte offset where decoding finished
+        offset: usize,
+    },
+    /// A restart marker was out of sequence
+    BadRestartMarker {
+        /// Restart marker number we expected
+        expected: u8,
+        /// Restart marker number found in the stream
+        found: u8,
+        /// MCU row of the restart marker
+        row: usize,
+        /// MCU column of the restart marker
+        column: usize,
+        /// Byte offset of the restart marker
+        offset: usize,
+    },
+    /// MCUs lost to corrupt data were concealed, see
+    /// [`DecoderOptions::jpeg_set_concealment`](crate::zune_core::options::DecoderOptions::jpeg_set_concealment)
+    Concealed {
+        /// Index of the restart interval where the corrupt data starts
+        interval: usize,
+        /// MCU row of the first concealed MCU
+        row: usize,
+        /// MCU column of the first concealed MCU
+        column: usize,
+        /// Number of concealed MCUs, running across restart intervals
+        /// whose markers are missing
+        mcus: usize,
+        /// Byte offset where decoding resumes
+        offset: usize,
+    },
+    /// A marker other than RST was found within entropy coded data
+    UnexpectedMarker {
+        /// The marker found
+        marker: Marker,
+        /// MCU row of the marker
+        row: usize,
+        /// MCU column of the marker
+        column: usize,
+        /// Byte offset of the marker
+        offset: usize,
+    },
+    /// Coefficients outside the range allowed for 8 bit images, or
+    /// past the end of a block
+    CoefficientOutOfRange {
+        /// MCU row with the coefficients
+        row: usize,
+        /// Number of coefficients out of range in this row
+        count: usize,
+        /// Byte offset the stream had been read up to
+        offset: usize,
+    },
+}
+
+/// Report of the non-fatal issues found while decoding an image
+#[derive(Clone, Debug, Default)]
+pub struct DecodeReport {
+    /// Issues found while decoding, in stream order
+    pub warnings: Vec<DecodeWarning>,
+    /// Number of MCUs in the image
+    pub mcus_total: usize,
+    /// Number of MCUs decoded from image data
+    ///
+    /// Progressive images refine the whole image with every scan, so if one
+    /// of their scans is truncated, no MCU counts as decoded.
+    pub mcus_decoded: usize,
+    /// Number of MCUs filled in by concealment, these don't count as decoded
+    pub mcus_concealed: usize,
+}
+
+impl DecodeReport {
+    /// Return true if decoding found no issues
+    #[must_use]
+    pub fn is_clean(&self) -> bool {
+        self.warnings.is_empty()
+    }
+
+    /// Return the fraction of MCUs decoded from image data, between 0 and 1
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn decoded_fraction(&self) -> f32 {
+        if self.mcus_total == 0 {
+            return 1.0;
+        }
+        self.mcus_decoded as f32 / self.mcus_total as f32
+    }
+
+    pub(crate) fn warn(&mut self, warning: DecodeWarning) {
+        self.warnings.push(warning);
+    }
+}
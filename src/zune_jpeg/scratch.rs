@@ -0,0 +1,482 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+//! Decoding into caller provided scratch memory
+//!
+//! Besides the output, the decoder needs buffers for component MCU rows,
+//! up-sampling, and for progressive images the coefficients of the whole image.
+//! These are normally allocated on the heap for every decode.
+//!
+//! [`JpegDecoder::decode_into_with_scratch`] instead carves them out of two
+//! regions lent by the caller, so memory can be placed where it's needed (e.g
+//! small and hot buffers in internal RAM, large ones in external RAM), and
+//! decoding makes no heap allocation once headers are decoded.
+//!
+//! Buffers carved out of lent regions only live as long as the call borrows
+//! them, they are released before it returns, even when unwinding.
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+
+use crate::zune_core::bytestream::ZReaderTrait;
+use crate::zune_core::colorspace::ColorSpace;
+use crate::zune_core::options::JpegConcealment;
+
+use super::components::SampleRatios;
+use super::decoder::JpegDecoder;
+use super::errors::DecodeErrors;
+use super::misc::setup_component_params;
+
+/// Scratch memory the decoder needs to decode an image, besides the
+/// output buffer
+///
+/// Sizes are in samples (`i16`), see
+/// [`JpegDecoder::scratch_requirements`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScratchRequirements {
+    /// Buffers accessed for every pixel, proportional to the width
+    /// of the image: component MCU rows and up-sampling rows
+    pub hot: usize,
+    /// Buffers proportional to the size of the image: coefficients
+    /// of progressive images, zero for baseline images
+    pub large: usize,
+}
+
+/// A buffer of samples, on the heap or in a region lent to the decoder
+pub(crate) enum ScratchBuf {
+    Heap(Vec<i16>),
+    /// Part of a region lent by the caller of `decode_into_with_scratch`,
+    /// only valid during that call
+    Lent(NonNull<i16>, usize),
+}
+
+// SAFETY: a lent buffer is an exclusive borrow, like `&mut [i16]`
+unsafe impl Send for ScratchBuf {}
+unsafe impl Sync for ScratchBuf {}
+
+impl Default for ScratchBuf {
+    fn default() -> Self {
+        ScratchBuf::Heap(Vec::new())
+    }
+}
+
+impl Clone for ScratchBuf {
+    fn clone(&self) -> Self {
+        ScratchBuf::Heap(self.to_vec())
+    }
+}
+
+impl Deref for ScratchBuf {
+    type Target = [i16];
+
+    fn deref(&self) -> &[i16] {
+        match self {
+            ScratchBuf::Heap(vec) => vec,
+            // SAFETY: the region outlives the buffer and parts of it
+            // handed out don't overlap, see ScratchArena::take
+            ScratchBuf::Lent(start, len) => unsafe {
+                core::slice::from_raw_parts(start.as_ptr(), *len)
+            },
+        }
+    }
+}
+
+impl DerefMut for ScratchBuf {
+    fn deref_mut(&mut self) -> &mut [i16] {
+        match self {
+            ScratchBuf::Heap(vec) => vec,
+            // SAFETY: same as above
+            ScratchBuf::Lent(start, len) => unsafe {
+                core::slice::from_raw_parts_mut(start.as_ptr(), *len)
+            },
+        }
+    }
+}
+
+impl ScratchBuf {
+    /// Move the samples into a vector, copying them if they aren't
+    /// on the heap
+    pub(crate) fn into_vec(self) -> Vec<i16> {
+        match self {
+            ScratchBuf::Heap(vec) => vec,
+            lent @ ScratchBuf::Lent(..) => lent.to_vec(),
+        }
+    }
+}
+
+/// Region a scratch buffer is carved out of
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum ScratchRegion {
+    /// See [`ScratchRequirements::hot`]
+    Hot,
+    /// See [`ScratchRequirements::large`]
+    Large,
+}
+
+#[derive(Copy, Clone)]
+struct Region {
+    start: NonNull<i16>,
+    len: usize,
+    used: usize,
+}
+
+/// Where the decoder gets its buffers from
+#[derive(Default)]
+pub(crate) struct ScratchArena {
+    /// Hot and large regions lent by the caller, or `None` to
+    /// allocate on the heap
+    lent: Option<[Region; 2]>,
+}
+
+// SAFETY: see ScratchBuf
+unsafe impl Send for ScratchArena {}
+unsafe impl Sync for ScratchArena {}
+
+impl ScratchArena {
+    /// Carve buffers out of `hot` and `large` until the arena is reset
+    ///
+    /// The regions have to outlive the arena and every buffer taken from it.
+    fn lend(&mut self, hot: &mut [i16], large: &mut [i16]) {
+        let region = |region: &mut [i16]| Region {
+            start: NonNull::new(region.as_mut_ptr()).unwrap(),
+            len: region.len(),
+            used: 0,
+        };
+        self.lent = Some([region(hot), region(large)]);
+    }
+    /// Return true if buffers are carved out of regions lent by the caller
+    pub(crate) const fn is_lent(&self) -> bool {
+        self.lent.is_some()
+    }
+
+    /// Get a buffer of `len` samples set to `value`
+    pub(crate) fn take(
+        &mut self,
+        region: ScratchRegion,
+        len: usize,
+        value: i16,
+    ) -> Result<ScratchBuf, DecodeErrors> {
+        let Some(regions) = &mut self.lent else {
+            return Ok(ScratchBuf::Heap(vec![value; len]));
+        };
+        let region = &mut regions[region as usize];
+
+        if region.len - region.used < len {
+            return Err(DecodeErrors::TooSmallScratch(region.used + len, region.len));
+        }
+        // SAFETY: in bounds of the region, and the part is never handed out again
+        let start = unsafe { NonNull::new_unchecked(region.start.as_ptr().add(region.used)) };
+        region.used += len;
+
+        let mut buffer = ScratchBuf::Lent(start, len);
+        buffer.fill(value);
+
+        Ok(buffer)
+    }
+}
+
+/// Size of the scratch space needed by the up-sampling routines for
+/// components whose rows are `strides` samples
+pub(crate) fn upsampler_scratch_size(
+    layout: SampleRatios,
+    mut strides: impl Iterator<Item = usize>,
+) -> usize {
+    match layout {
+        SampleRatios::HV => strides.next().unwrap_or(0),
+        SampleRatios::Generic(_, _) => strides.max().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// Releases regions lent to a decoder when dropped
+struct LentScratch<'a, T: ZReaderTrait>(&'a mut JpegDecoder<T>);
+
+impl<T: ZReaderTrait> Drop for LentScratch<'_, T> {
+    fn drop(&mut self) {
+        self.0.arena = ScratchArena::default();
+
+        for component in &mut self.0.components {
+            component.release_scratch();
+        }
+    }
+}
+
+impl<T: ZReaderTrait> JpegDecoder<T> {
+    /// Return true if component `pos` is decoded to produce the output colorspace
+    pub(crate) fn component_needed(&self, pos: usize) -> bool {
+        // For special colorspaces i.e YCCK and CMYK, all components are needed.
+        pos < self.options.jpeg_get_out_colorspace().num_components()
+            || self.input_colorspace == ColorSpace::YCCK
+            || self.input_colorspace == ColorSpace::CMYK
+    }
+
+    /// Return the scratch memory needed to decode the image with
+    /// [`decode_into_with_scratch`](Self::decode_into_with_scratch)
+    ///
+    /// This depends on the decoder options, which shouldn't be changed
+    /// afterwards.
+    ///
+    /// # Errors
+    /// If the headers can't be decoded, or the image has a DNL segment,
+    /// whose height is only known after decoding
+    pub fn scratch_requirements(&mut self) -> Result<ScratchRequirements, DecodeErrors> {
+        self.decode_headers_internal()?;
+
+        if self.awaiting_dnl {
+            return Err(DecodeErrors::FormatStatic(
+                "Images with a DNL segment can't be decoded into scratch memory",
+            ));
+        }
+        setup_component_params(self)?;
+
+        if self.input_colorspace == ColorSpace::Luma && self.is_interleaved {
+            self.reset_params();
+        }
+        if self.is_interleaved {
+            self.sub_sample_ratio = self.upsampling_layout()?;
+        }
+        Ok(self.scratch_sizes(self.h_max, self.v_max, self.sub_sample_ratio))
+    }
+
+    /// Samples the decoder takes from each region to decode the image, with
+    /// MCUs of `h_max` by `v_max` blocks up-sampled using `layout`
+    ///
+    /// This only depends on the headers, the memory limit is checked with it
+    /// before components are set up.
+    pub(crate) fn scratch_sizes(
+        &self,
+        h_max: usize,
+        v_max: usize,
+        layout: SampleRatios,
+    ) -> ScratchRequirements {
+        let is_sampled = h_max != 1 || v_max != 1;
+        let mcu_x = usize::from(self.info.width).div_ceil(8 * h_max);
+        let mcu_y = usize::from(self.info.height).div_ceil(8 * v_max);
+
+        let copy_above = !self.is_progressive
+            && self.can_conceal()
+            && self.options.jpeg_get_concealment() == JpegConcealment::CopyAbove;
+        let deblocking = self.options.jpeg_get_deblocking() != 0;
+
+        let strides = self
+            .components
+            .iter()
+            .map(|c| c.horizontal_sample.saturating_mul(mcu_x * 8));
+
+        let mut required = ScratchRequirements {
+            hot: upsampler_scratch_size(layout, strides.clone()),
+            large: 0,
+        };
+        // headers can ask for more than fits in a usize on 32 bit targets
+        let mut add_hot = |samples: usize| required.hot = required.hot.saturating_add(samples);
+
+        for ((pos, c), stride) in self.components.iter().enumerate().zip(strides) {
+            let mcu_row = stride.saturating_mul(c.vertical_sample * 8);
+
+            if self.component_needed(pos) {
+                add_hot(mcu_row);
+
+                if deblocking {
+                    add_hot(stride.saturating_mul(4));
+                }
+                if copy_above {
+                    add_hot(stride);
+                }
+            }
+            if is_sampled {
+                match layout {
+                    SampleRatios::H if pos == 0 => {}
+                    SampleRatios::H | SampleRatios::V | SampleRatios::HV => {
+                        add_hot(stride.saturating_mul(2 * c.vertical_sample));
+                        add_hot(stride.saturating_mul(h_max * v_max));
+                    }
+                    SampleRatios::Generic(h_max, v_max) => {
+                        let ratio = (h_max / c.horizontal_sample) * (v_max / c.vertical_sample);
+
+                        add_hot(stride);
+                        add_hot(mcu_row);
+                        add_hot(mcu_row.saturating_mul(ratio));
+                    }
+                    SampleRatios::None => {}
+                }
+            }
+            if self.is_progressive && pos < self.input_colorspace.num_components() {
+                let coefficients = mcu_row.saturating_mul(mcu_y);
+                required.large = required.large.saturating_add(coefficients);
+            }
+        }
+        required
+    }
+
+    /// Decode the image into `out`, using `hot` and `large` as scratch
+    /// memory instead of allocating on the heap
+    ///
+    /// The regions need at least as many samples as
+    /// [`scratch_requirements`](Self::scratch_requirements) returns. Once headers
+    /// are decoded this makes no heap allocation, except to record warnings
+    /// in the [decode report](Self::report) and errors.
+    ///
+    /// Images are decoded on a single thread, whatever the decoder options say.
+    ///
+    /// # Errors
+    /// If the image can't be decoded or has a DNL segment, `out` is too small
+    /// or the regions are smaller than required
+    pub fn decode_into_with_scratch(
+        &mut self,
+        out: &mut [u8],
+        hot: &mut [i16],
+        large: &mut [i16],
+    ) -> Result<(), DecodeErrors> {
+        let required = self.scratch_requirements()?;
+
+        if hot.len() < required.hot {
+            return Err(DecodeErrors::TooSmallScratch(required.hot, hot.len()));
+        }
+        if large.len() < required.large {
+            return Err(DecodeErrors::TooSmallScratch(required.large, large.len()));
+        }
+        let lent = LentScratch(self);
+        lent.0.arena.lend(hot, large);
+
+        lent.0.decode_into(out)
+    }
+}
+
+#[cfg(test)]
+mod counting {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    std::thread_local! {
+        pub static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// Counts allocations made by each thread
+    pub struct CountingAllocator;
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|x| x.set(x.get() + 1));
+            System.alloc(layout)
+        }
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout);
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+}
+
+#[test]
+fn scratch_decoding_allocates_nothing() {
+    // samples no buffer is set to
+    const UNUSED: i16 = 0x5A5A;
+
+    use crate::zune_core::bit_depth::BitDepth;
+    use crate::zune_core::options::{DecoderOptions, EncoderOptions};
+
+    use super::JpegEncoder;
+
+    let baseline = include_bytes!("../../tests/inputs/restart_420.jpg");
+    let pixels = JpegDecoder::new(&baseline[..]).decode().unwrap();
+    let options = EncoderOptions::new(96, 64, ColorSpace::RGB, BitDepth::Eight)
+        .set_jpeg_encode_progressive(true);
+    let progressive = JpegEncoder::new(&pixels, options).encode().unwrap();
+
+    let images = [
+        &baseline[..],
+        &progressive,
+        include_bytes!("../../tests/inputs/sampling_411.jpg"),
+        include_bytes!("../../tests/inputs/sampling_440.jpg"),
+        include_bytes!("../../tests/inputs/sampling_mixed.jpg"),
+        include_bytes!("../../tests/inputs/restart_422_partial.jpg"),
+    ];
+    let options = [
+        DecoderOptions::default(),
+        DecoderOptions::default().jpeg_set_out_colorspace(ColorSpace::Luma),
+        DecoderOptions::default().jpeg_set_out_colorspace(ColorSpace::RGBA),
+        DecoderOptions::default().jpeg_set_exact(true),
+        DecoderOptions::default().jpeg_set_fancy_upsampling(false),
+        DecoderOptions::default().jpeg_set_deblocking(8),
+        DecoderOptions::default()
+            .jpeg_set_concealment(JpegConcealment::CopyAbove)
+            .set_num_threads(4),
+    ];
+
+    for data in images {
+        for options in options {
+            let expected = JpegDecoder::new_with_options(data, options)
+                .decode()
+                .unwrap();
+
+            let mut decoder = JpegDecoder::new_with_options(data, options);
+            let required = decoder.scratch_requirements().unwrap();
+            let mut out = vec![0; decoder.output_buffer_size().unwrap()];
+            let mut hot = vec![0; required.hot];
+            let mut large = vec![0; required.large];
+
+            let before = counting::ALLOCATIONS.with(std::cell::Cell::get);
+            decoder
+                .decode_into_with_scratch(&mut out, &mut hot, &mut large)
+                .unwrap();
+            let after = counting::ALLOCATIONS.with(std::cell::Cell::get);
+
+            assert_eq!(after, before, "{options:?}");
+            assert!(out == expected);
+
+            // requirements are exact, regions starting anywhere are used to their end
+            let mut hot = vec![UNUSED; required.hot + 1];
+            let mut large = vec![UNUSED; required.large + 1];
+            let mut decoder = JpegDecoder::new_with_options(data, options);
+            decoder
+                .decode_into_with_scratch(&mut out, &mut hot[1..], &mut large[1..])
+                .unwrap();
+            assert!(out == expected);
+            assert_eq!((hot[0], large[0]), (UNUSED, UNUSED));
+            assert!(hot.last() != Some(&UNUSED), "{options:?}");
+            assert!(required.large == 0 || large.last() != Some(&UNUSED));
+
+            let mut decoder = JpegDecoder::new_with_options(data, options);
+            let result = decoder.decode_into_with_scratch(&mut out, &mut hot[2..], &mut large);
+            assert!(matches!(
+                result,
+                Err(DecodeErrors::TooSmallScratch(r, l)) if r == required.hot && l == r - 1
+            ));
+            if required.large != 0 {
+                let mut decoder = JpegDecoder::new_with_options(data, options);
+                let result = decoder.decode_into_with_scratch(&mut out, &mut hot, &mut large[2..]);
+                assert!(matches!(
+                    result,
+                    Err(DecodeErrors::TooSmallScratch(r, l)) if r == required.large && l == r - 1
+                ));
+            }
+        }
+    }
+    // plain decoding allocates
+    let mut out = vec![0; 96 * 64 * 3];
+    let before = counting::ALLOCATIONS.with(std::cell::Cell::get);
+    JpegDecoder::new(&baseline[..])
+        .decode_into(&mut out)
+        .unwrap();
+    assert!(counting::ALLOCATIONS.with(std::cell::Cell::get) > before);
+
+    // decoder buffers don't outlive the call
+    let mut decoder = JpegDecoder::new(&baseline[..]);
+    let required = decoder.scratch_requirements().unwrap();
+    let mut out = vec![0; decoder.output_buffer_size().unwrap()];
+    let mut hot = vec![0; required.hot];
+
+    decoder
+        .decode_into_with_scratch(&mut out, &mut hot, &mut [])
+        .unwrap();
+    assert!(!decoder.arena.is_lent());
+    assert!(decoder.components.iter().all(|c| c.raw_coeff.is_empty()));
+    // the height of DNL images is only known after decoding
+    let dnl = include_bytes!("../../tests/inputs/dnl_420.jpg");
+    assert!(JpegDecoder::new(&dnl[..]).scratch_requirements().is_err());
+}
@@ -0,0 +1,409 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+//! Splitting streams of concatenated JPEG frames
+//!
+//! MJPEG captures, from UART dumps, HTTP multipart streams or AVI payloads,
+//! are JPEG frames one after the other, with anything in between. Frames are
+//! found by walking their markers, so SOI and EOI byte pairs inside segment
+//! payloads (e.g. EXIF thumbnails) or entropy coded data don't split them.
+
+use super::marker::Marker;
+
+/// Something found in a stream of frames
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SplitEvent<'a> {
+    /// A frame, from its SOI marker to its EOI marker
+    Frame(&'a [u8]),
+    /// Bytes outside of frames, e.g multipart headers or noise
+    Junk {
+        /// Offset of the first byte in the stream
+        offset: u64,
+        /// Number of bytes
+        length: u64,
+    },
+    /// A frame that was dropped, because another SOI marker came before its
+    /// EOI marker, its markers are corrupt, the stream ended or it is larger
+    /// than the maximum frame size
+    Incomplete {
+        /// Offset of its SOI marker in the stream
+        offset: u64,
+        /// Number of bytes dropped
+        length: u64,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    /// Looking for an SOI marker
+    Searching,
+    /// In a frame, where a marker is expected
+    Markers,
+    /// In entropy coded data after an SOS segment
+    Entropy,
+}
+
+/// Splits a stream of concatenated JPEG frames into frames
+///
+/// Data is pushed in chunks of any size, and complete frames are returned
+/// once their EOI marker is found. Only the frame being split is kept,
+/// bytes between frames are counted and dropped.
+///
+/// ```no_run
+/// use zune_jpeg::{JpegDecoder, JpegFrameSplitter, SplitEvent};
+///
+/// let mut splitter = JpegFrameSplitter::new().set_max_frame_size(1 << 20);
+/// # let chunks: Vec<Vec<u8>> = vec![];
+///
+/// for chunk in &chunks {
+///     splitter.push(chunk);
+///
+///     while let Some(event) = splitter.next_event() {
+///         match event {
+///             SplitEvent::Frame(frame) => {
+///                 let pixels = JpegDecoder::new(frame).decode();
+///             }
+///             SplitEvent::Junk { offset, length } => {
+///                 println!("{length} bytes of junk at {offset}");
+///             }
+///             SplitEvent::Incomplete { offset, .. } => {
+///                 println!("dropped a frame at {offset}");
+///             }
+///         }
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct JpegFrameSplitter {
+    max_frame_size: usize,
+    /// Bytes of the current frame, or the last bytes searched for one
+    buffer: Vec<u8>,
+    /// Offset of `buffer[0]` in the stream
+    offset: u64,
+    /// Bytes at the start of `buffer` returned in the last event, dropped
+    /// on the next call
+    consumed: usize,
+    /// Where to continue parsing, may be past the end of `buffer` while
+    /// skipping a segment
+    position: usize,
+    state: State,
+    /// Offset and length of junk found since the last frame
+    junk: (u64, u64),
+    finished: bool,
+}
+
+impl Default for JpegFrameSplitter {
+    fn default() -> Self {
+        JpegFrameSplitter {
+            max_frame_size: usize::MAX,
+            buffer: Vec::new(),
+            offset: 0,
+            consumed: 0,
+            position: 0,
+            state: State::Searching,
+            junk: (0, 0),
+            finished: false,
+        }
+    }
+}
+
+impl JpegFrameSplitter {
+    /// Create a splitter with default settings
+    #[must_use]
+    pub fn new() -> JpegFrameSplitter {
+        JpegFrameSplitter::default()
+    }
+
+    /// Set the size of the largest frame to keep
+    ///
+    /// Larger frames are dropped and reported as incomplete, this bounds the
+    /// memory used when EOI markers are lost.
+    ///
+    /// - Default value: unlimited
+    #[must_use]
+    pub fn set_max_frame_size(mut self, bytes: usize) -> Self {
+        self.max_frame_size = bytes;
+        self
+    }
+
+    /// Add the next chunk of the stream
+    pub fn push(&mut self, data: &[u8]) {
+        self.drop_consumed();
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Mark the end of the stream
+    ///
+    /// The next events report what is left of it as junk or an incomplete
+    /// frame.
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    /// Return the next frame, junk or incomplete frame in the data pushed so
+    /// far, or `None` if more data is needed to find one
+    pub fn next_event(&mut self) -> Option<SplitEvent<'_>> {
+        self.drop_consumed();
+
+        loop {
+            // the frame ends, at the earliest, with an EOI marker at `position`
+            if self.state != State::Searching
+                && self.position.saturating_add(2) > self.max_frame_size
+            {
+                return Some(self.drop_frame(self.position.min(self.buffer.len())));
+            }
+            match self.state {
+                State::Searching => {
+                    let start = self.position;
+                    let found = self.buffer[start..]
+                        .windows(2)
+                        .position(|pair| pair == [0xFF, 0xD8]);
+                    // a trailing 0xFF may start an SOI marker in the next chunk
+                    let end = match found {
+                        Some(i) => start + i,
+                        None if self.finished => self.buffer.len(),
+                        None => start + self.buffer[start..].len().saturating_sub(1),
+                    };
+                    self.add_junk(end - start);
+                    self.consumed = end;
+                    self.position = end;
+
+                    if found.is_some() {
+                        // past the SOI marker, `buffer` will start at it
+                        self.state = State::Markers;
+                        self.position = end + 2;
+                    }
+                    if found.is_some() || self.finished {
+                        let (offset, length) = core::mem::take(&mut self.junk);
+
+                        if length != 0 {
+                            return Some(SplitEvent::Junk { offset, length });
+                        }
+                    }
+                    found?;
+                    self.drop_consumed();
+                }
+                State::Markers => {
+                    let Some(&[first, code]) = self.buffer.get(self.position..self.position + 2)
+                    else {
+                        return self.end_of_data();
+                    };
+                    if first != 0xFF {
+                        return Some(self.drop_frame(self.position));
+                    }
+                    match Marker::from_u8(code) {
+                        Some(Marker::SOI) => {
+                            return Some(self.drop_frame(self.position));
+                        }
+                        Some(Marker::EOI) => {
+                            let end = self.position + 2;
+
+                            self.state = State::Searching;
+                            self.consumed = end;
+                            self.position = end;
+
+                            return Some(SplitEvent::Frame(&self.buffer[..end]));
+                        }
+                        Some(Marker::RST(_)) => self.position += 2,
+                        // fill bytes before a marker
+                        _ if code == 0xFF => self.position += 1,
+                        _ if code == 0x00 => {
+                            return Some(self.drop_frame(self.position));
+                        }
+                        marker => {
+                            let Some(&[high, low]) =
+                                self.buffer.get(self.position + 2..self.position + 4)
+                            else {
+                                return self.end_of_data();
+                            };
+                            let length = usize::from(u16::from_be_bytes([high, low]));
+
+                            if length < 2 {
+                                return Some(self.drop_frame(self.position));
+                            }
+                            self.position += 2 + length;
+
+                            if marker == Some(Marker::SOS) {
+                                self.state = State::Entropy;
+                            }
+                        }
+                    }
+                }
+                State::Entropy => {
+                    let start = self.position.min(self.buffer.len());
+                    let Some(i) = self.buffer[start..].iter().position(|x| *x == 0xFF) else {
+                        self.position = self.position.max(self.buffer.len());
+                        return self.end_of_data();
+                    };
+                    self.position = start + i;
+
+                    let Some(&code) = self.buffer.get(self.position + 1) else {
+                        return self.end_of_data();
+                    };
+                    match code {
+                        // stuffed zero, restart marker, or fill byte
+                        0x00 | 0xD0..=0xD7 => self.position += 2,
+                        0xFF => self.position += 1,
+                        // the end of the scan
+                        _ => self.state = State::Markers,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Drop bytes returned in the last event
+    fn drop_consumed(&mut self) {
+        if self.consumed != 0 {
+            self.buffer.drain(..self.consumed);
+            self.offset += self.consumed as u64;
+            self.position -= self.consumed.min(self.position);
+            self.consumed = 0;
+        }
+    }
+
+    fn add_junk(&mut self, length: usize) {
+        if self.junk.1 == 0 {
+            self.junk.0 = self.offset + self.position as u64;
+        }
+        self.junk.1 += length as u64;
+    }
+
+    /// Drop the current frame up to `end`, and look for another one from there
+    fn drop_frame(&mut self, end: usize) -> SplitEvent<'_> {
+        self.state = State::Searching;
+        self.consumed = end;
+        self.position = end;
+
+        SplitEvent::Incomplete {
+            offset: self.offset,
+            length: end as u64,
+        }
+    }
+
+    /// More data is needed to go on with the current frame
+    fn end_of_data(&mut self) -> Option<SplitEvent<'_>> {
+        if self.finished {
+            return Some(self.drop_frame(self.buffer.len()));
+        }
+        None
+    }
+}
+
+#[test]
+#[allow(clippy::cast_possible_truncation)]
+fn split_frames() {
+    use crate::zune_core::bit_depth::BitDepth;
+    use crate::zune_core::colorspace::ColorSpace;
+    use crate::zune_core::options::EncoderOptions;
+
+    use super::JpegEncoder;
+
+    let pixels: Vec<u8> = (0..64 * 48 * 3_u32).map(|i| (i * 7 % 253) as u8).collect();
+    let encode = |progressive| {
+        let options = EncoderOptions::new(64, 48, ColorSpace::RGB, BitDepth::Eight)
+            .set_jpeg_encode_progressive(progressive);
+        JpegEncoder::new(&pixels, options).encode().unwrap()
+    };
+    // an APP segment with markers in its payload, like an EXIF thumbnail
+    let mut with_thumbnail = encode(false);
+    let app = [
+        0xFF, 0xE1, 0x00, 0x0C, 0xFF, 0xD8, 0xFF, 0x00, 0xFF, 0xD9, 0xFF, 0xD9, 0xFF, 0xFF,
+    ];
+    with_thumbnail.splice(2..2, app);
+
+    let frames = [
+        encode(false),
+        encode(true),
+        with_thumbnail,
+        include_bytes!("../../tests/inputs/restart_420.jpg").to_vec(),
+    ];
+    let boundary = b"\r\n--frame\r\nContent-Type: image/jpeg\r\n\r\n\xFF";
+
+    let mut stream = vec![];
+    let mut expected = vec![];
+
+    for (i, frame) in frames.iter().enumerate() {
+        if i != 0 {
+            expected.push(format!("junk {} {}", stream.len(), boundary.len()));
+            stream.extend(boundary);
+        }
+        // cut short in its entropy coded data by the next frame
+        if i == 2 {
+            expected.push(format!("incomplete {} {}", stream.len(), frame.len() - 100));
+            stream.extend(&frame[..frame.len() - 100]);
+        }
+        expected.push(format!("frame {}", frame.len()));
+        stream.extend(frame);
+    }
+    expected.push(format!("incomplete {} 100", stream.len()));
+    stream.extend(&frames[0][..100]);
+
+    for chunk_size in [1, 2, 7, 1000, stream.len()] {
+        let mut splitter = JpegFrameSplitter::new();
+        let mut events = vec![];
+        let mut found_frames = vec![];
+
+        let mut describe = |event: SplitEvent| match event {
+            SplitEvent::Frame(frame) => {
+                found_frames.push(frame.to_vec());
+                format!("frame {}", frame.len())
+            }
+            SplitEvent::Junk { offset, length } => format!("junk {offset} {length}"),
+            SplitEvent::Incomplete { offset, length } => format!("incomplete {offset} {length}"),
+        };
+        for chunk in stream.chunks(chunk_size) {
+            splitter.push(chunk);
+
+            while let Some(event) = splitter.next_event() {
+                events.push(describe(event));
+            }
+        }
+        splitter.finish();
+
+        while let Some(event) = splitter.next_event() {
+            events.push(describe(event));
+        }
+        assert_eq!(events, expected, "chunks of {chunk_size}");
+        assert_eq!(found_frames, frames);
+    }
+
+    // frames that are too large are dropped, up to the next one
+    let mut splitter = JpegFrameSplitter::new().set_max_frame_size(frames[0].len());
+    splitter.push(&frames[2]);
+    splitter.push(&frames[0]);
+
+    let Some(SplitEvent::Incomplete { offset: 0, length }) = splitter.next_event() else {
+        unreachable!("expected an incomplete frame");
+    };
+    assert!(length > frames[0].len() as u64);
+    assert_eq!(
+        splitter.next_event(),
+        Some(SplitEvent::Junk {
+            offset: length,
+            length: frames[2].len() as u64 - length
+        })
+    );
+    assert_eq!(splitter.next_event(), Some(SplitEvent::Frame(&frames[0])));
+    assert_eq!(splitter.next_event(), None);
+
+    // the maximum size includes the EOI marker
+    for (max_frame_size, kept) in [(frames[0].len(), true), (frames[0].len() - 1, false)] {
+        let mut splitter = JpegFrameSplitter::new().set_max_frame_size(max_frame_size);
+        splitter.push(&frames[0]);
+        splitter.finish();
+
+        let event = splitter.next_event();
+        let found = matches!(event, Some(SplitEvent::Frame(frame)) if frame == frames[0]);
+        let dropped = matches!(event, Some(SplitEvent::Incomplete { offset: 0, .. }));
+        assert!(
+            found == kept && dropped != kept,
+            "max frame size {max_frame_size}"
+        );
+    }
+}
@@ -0,0 +1,368 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+//! Lossless rotation, flipping and cropping
+//!
+//! Like `jpegtran`, these work on quantized DCT coefficients, moving whole blocks
+//! around and transposing or negating coefficients within blocks, so no IDCT is
+//! involved and no quality is lost. The result is written as a baseline JPEG with
+//! the quantization tables of the source image and optimal Huffman tables.
+//!
+//! Only whole blocks can be moved, so partial MCUs on an edge that a transform
+//! mirrors to the other side are trimmed away, like `jpegtran -trim`.
+//!
+//! Metadata segments (EXIF, ICC profiles, comments) are not carried over.
+use crate::zune_core::bytestream::{ZByteWriter, ZReaderTrait};
+use crate::zune_core::colorspace::ColorSpace;
+
+use super::decoder::{JpegDecoder, MAX_COMPONENTS};
+use super::errors::DecodeErrors;
+use super::scratch::ScratchBuf;
+use super::writer::{Frame, FrameComponent};
+
+/// A lossless transform of a JPEG image
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum JpegTransform {
+    /// Keep the image as it is, useful to crop only
+    None,
+    /// Mirror the image horizontally
+    FlipHorizontal,
+    /// Mirror the image vertically
+    FlipVertical,
+    /// Mirror the image along its top-left to bottom-right diagonal
+    Transpose,
+    /// Mirror the image along its top-right to bottom-left diagonal
+    Transverse,
+    /// Rotate the image 90 degrees clockwise
+    Rotate90,
+    /// Rotate the image 180 degrees
+    Rotate180,
+    /// Rotate the image 270 degrees clockwise
+    Rotate270,
+}
+
+impl JpegTransform {
+    /// Split the transform into an optional transpose followed by
+    /// horizontal and vertical flips
+    const fn steps(self) -> (bool, bool, bool) {
+        match self {
+            JpegTransform::None => (false, false, false),
+            JpegTransform::FlipHorizontal => (false, true, false),
+            JpegTransform::FlipVertical => (false, false, true),
+            JpegTransform::Transpose => (true, false, false),
+            JpegTransform::Transverse => (true, true, true),
+            JpegTransform::Rotate90 => (true, true, false),
+            JpegTransform::Rotate180 => (false, true, true),
+            JpegTransform::Rotate270 => (true, false, true),
+        }
+    }
+}
+
+/// A region of the transformed image to keep
+///
+/// `x` and `y` must be multiples of the MCU size of the transformed image,
+/// e.g 16 pixels for 4:2:0 images.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CropRegion {
+    /// Left edge of the region
+    pub x: usize,
+    /// Top edge of the region
+    pub y: usize,
+    /// Width of the region
+    pub width: usize,
+    /// Height of the region
+    pub height: usize,
+}
+
+/// Transform a block of coefficients in natural order
+fn transform_block(input: &[i16], output: &mut [i16], steps: (bool, bool, bool)) {
+    let (transpose, flip_x, flip_y) = steps;
+
+    for v in 0..8 {
+        for u in 0..8 {
+            let coeff = if transpose {
+                input[u * 8 + v]
+            } else {
+                input[v * 8 + u]
+            };
+            // mirroring negates odd frequencies along that axis
+            let negate = (flip_x && u % 2 == 1) != (flip_y && v % 2 == 1);
+
+            output[v * 8 + u] = if negate { coeff.wrapping_neg() } else { coeff };
+        }
+    }
+}
+
+impl<T: ZReaderTrait> JpegDecoder<T> {
+    /// Losslessly transform and crop the image, returning it as a baseline JPEG
+    ///
+    /// The crop region is given in coordinates of the transformed image, see
+    /// [`CropRegion`] for alignment requirements. Partial MCUs on edges that a
+    /// transform mirrors are trimmed away.
+    ///
+    /// # Errors
+    /// If the image can't be decoded, the crop region is misaligned or doesn't fit
+    /// in the transformed image, or the result is larger than 65535 pixels on a side
+    #[allow(clippy::too_many_lines, clippy::cast_possible_truncation)]
+    pub fn transform(
+        &mut self,
+        transform: JpegTransform,
+        crop: Option<CropRegion>,
+    ) -> Result<Vec<u8>, DecodeErrors> {
+        self.decode_headers_internal()?;
+
+        let h_max = self.components.iter().map(|c| c.horizontal_sample).max().unwrap_or(1);
+        let v_max = self.components.iter().map(|c| c.vertical_sample).max().unwrap_or(1);
+        let mcu_x = usize::from(self.info.width).div_ceil(8 * h_max);
+        let mcu_y = usize::from(self.info.height).div_ceil(8 * v_max);
+
+        // source and transformed coefficients
+        let coefficients_size: usize = self
+            .components
+            .iter()
+            .map(|c| mcu_x * c.horizontal_sample * mcu_y * c.vertical_sample * 64)
+            .sum();
+        self.check_memory_limit(coefficients_size * 2 * core::mem::size_of::<i16>())?;
+
+        let coefficients = if self.is_progressive {
+            self.decode_progressive_coefficients()?
+                .map(ScratchBuf::into_vec)
+        } else {
+            self.decode_baseline_coefficients()?
+        };
+
+        let steps = transform.steps();
+        let (transpose, flip_x, flip_y) = steps;
+        let single = self.components.len() == 1;
+
+        // sampling factors of the transformed image, single component
+        // images have no MCUs spanning several blocks
+        let sampling = |h: usize, v: usize| {
+            if single {
+                (1, 1)
+            } else if transpose {
+                (v, h)
+            } else {
+                (h, v)
+            }
+        };
+        let (out_h_max, out_v_max) = sampling(self.h_max, self.v_max);
+        let (mcu_width, mcu_height) = (8 * out_h_max, 8 * out_v_max);
+
+        let (mut width, mut height) = (usize::from(self.info.width), usize::from(self.info.height));
+
+        if transpose {
+            core::mem::swap(&mut width, &mut height);
+        }
+        // mirrored edges have to be made of whole MCUs
+        if flip_x {
+            width -= width % mcu_width;
+        }
+        if flip_y {
+            height -= height % mcu_height;
+        }
+        if width == 0 || height == 0 {
+            return Err(DecodeErrors::FormatStatic(
+                "Image is smaller than an MCU along an edge the transform mirrors",
+            ));
+        }
+        let crop = crop.unwrap_or(CropRegion {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        });
+
+        if !crop.x.is_multiple_of(mcu_width) || !crop.y.is_multiple_of(mcu_height) {
+            return Err(DecodeErrors::Format(format!(
+                "Crop region must start at a multiple of the {mcu_width}x{mcu_height} MCU size"
+            )));
+        }
+        if crop.width == 0
+            || crop.height == 0
+            || crop.x + crop.width > width
+            || crop.y + crop.height > height
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Crop region {crop:?} doesn't fit in the {width}x{height} transformed image"
+            )));
+        }
+        let (out_width, out_height) = (
+            u16::try_from(crop.width).map_err(|_| DecodeErrors::FormatStatic("Image too wide"))?,
+            u16::try_from(crop.height).map_err(|_| DecodeErrors::FormatStatic("Image too high"))?,
+        );
+        let out_mcu_x = crop.width.div_ceil(mcu_width);
+        let out_mcu_y = crop.height.div_ceil(mcu_height);
+
+        let mut components = Vec::with_capacity(self.components.len());
+
+        for (component, input) in self.components.iter().zip(&coefficients) {
+            let (h, v) = sampling(component.horizontal_sample, component.vertical_sample);
+            let in_stride = component.width_stride / 8;
+            let in_rows = input.len() / 64 / in_stride.max(1);
+
+            // extent of the transformed image in blocks of this component,
+            // only used along mirrored axes, where it's made of whole MCUs
+            let extent_x = width / mcu_width * h;
+            let extent_y = height / mcu_height * v;
+            let (crop_x, crop_y) = (crop.x / mcu_width * h, crop.y / mcu_height * v);
+
+            let stride = out_mcu_x * h;
+            let rows = out_mcu_y * v;
+            let mut blocks = vec![0; stride * rows * 64];
+
+            for (y, row) in blocks.chunks_exact_mut(stride * 64).enumerate() {
+                for (x, block) in row.chunks_exact_mut(64).enumerate() {
+                    let (tx, ty) = (x + crop_x, y + crop_y);
+                    let tx = if flip_x { extent_x.checked_sub(tx + 1) } else { Some(tx) };
+                    let ty = if flip_y { extent_y.checked_sub(ty + 1) } else { Some(ty) };
+
+                    let (Some(tx), Some(ty)) = (tx, ty) else {
+                        // padding past the trimmed edge
+                        continue;
+                    };
+                    let (sx, sy) = if transpose { (ty, tx) } else { (tx, ty) };
+
+                    if sx < in_stride && sy < in_rows {
+                        let start = (sy * in_stride + sx) * 64;
+
+                        transform_block(&input[start..start + 64], block, steps);
+                    }
+                }
+            }
+            components.push(FrameComponent {
+                id: component.id,
+                horizontal_sample: h,
+                vertical_sample: v,
+                quantization_table: component.quantization_table_number,
+                stride,
+                blocks,
+            });
+        }
+
+        let mut quantization_tables = Vec::new();
+        let mut written_tables = [false; MAX_COMPONENTS];
+
+        for component in &self.components {
+            let id = usize::from(component.quantization_table_number);
+
+            if !written_tables[id] {
+                written_tables[id] = true;
+                quantization_tables.push((id as u8, component.quantization_table));
+            }
+        }
+        let frame = Frame {
+            width: out_width,
+            height: out_height,
+            colorspace: self.input_colorspace,
+            quantization_tables,
+            components,
+        };
+        let mut out = ZByteWriter::new(Vec::new());
+
+        // coefficients of arbitrary files may not fit the suggested tables
+        frame
+            .write_baseline(&mut out, true)
+            .map_err(|e| DecodeErrors::Format(format!("{e:?}")))?;
+
+        Ok(out.into_inner())
+    }
+}
+
+#[test]
+fn lossless_transforms() {
+    let data = include_bytes!("../../tests/inputs/restart_420.jpg");
+    let original = JpegDecoder::new(&data[..]).decode().unwrap();
+
+    let transform = |data: &[u8], transform| {
+        JpegDecoder::new(data).transform(transform, None).unwrap()
+    };
+    let decode = |data: &[u8]| {
+        let mut decoder = JpegDecoder::new(data);
+        let pixels = decoder.decode().unwrap();
+
+        (pixels, decoder.dimensions().unwrap())
+    };
+
+    // coefficients are kept as they are
+    assert_eq!(decode(&transform(data, JpegTransform::None)).0, original);
+
+    // progressive input is transformed the same way
+    let options = crate::zune_core::options::EncoderOptions::new(
+        96,
+        64,
+        ColorSpace::RGB,
+        crate::zune_core::bit_depth::BitDepth::Eight,
+    )
+    .set_jpeg_encode_progressive(true);
+    let progressive = super::JpegEncoder::new(&original, options).encode().unwrap();
+    assert_eq!(
+        decode(&transform(&progressive, JpegTransform::None)).0,
+        decode(&progressive).0
+    );
+
+    // transforms undoing each other give back the original
+    for (first, second) in [
+        (JpegTransform::FlipHorizontal, JpegTransform::FlipHorizontal),
+        (JpegTransform::FlipVertical, JpegTransform::FlipVertical),
+        (JpegTransform::Transpose, JpegTransform::Transpose),
+        (JpegTransform::Transverse, JpegTransform::Transverse),
+        (JpegTransform::Rotate90, JpegTransform::Rotate270),
+        (JpegTransform::Rotate180, JpegTransform::Rotate180),
+    ] {
+        let once = transform(data, first);
+        let swapped = matches!(
+            first,
+            JpegTransform::Transpose | JpegTransform::Transverse | JpegTransform::Rotate90
+        );
+        assert_eq!(decode(&once).1, if swapped { (64, 96) } else { (96, 64) });
+        assert_eq!(decode(&transform(&once, second)).0, original);
+    }
+
+    // rotating moves pixels to where we expect them, up to rounding
+    let (rotated, _) = decode(&transform(data, JpegTransform::Rotate90));
+
+    for y in 0..96 {
+        for x in 0..64 {
+            for c in 0..3 {
+                let a = i32::from(rotated[(y * 64 + x) * 3 + c]);
+                let b = i32::from(original[((63 - x) * 96 + y) * 3 + c]);
+                assert!((a - b).abs() <= 4);
+            }
+        }
+    }
+
+    // crops keep blocks as they are, pixels away from the crop edges
+    // don't depend on blocks that were cropped away
+    let region = CropRegion {
+        x: 16,
+        y: 16,
+        width: 40,
+        height: 30,
+    };
+    let cropped = JpegDecoder::new(&data[..])
+        .transform(JpegTransform::None, Some(region))
+        .unwrap();
+    let (pixels, dimensions) = decode(&cropped);
+
+    assert_eq!(dimensions, (40, 30));
+
+    for y in 2..28 {
+        for x in 2..38 {
+            let out = (y * 40 + x) * 3;
+            let source = ((y + 16) * 96 + x + 16) * 3;
+
+            assert_eq!(pixels[out..out + 3], original[source..source + 3]);
+        }
+    }
+    let misaligned = CropRegion { x: 8, ..region };
+
+    assert!(JpegDecoder::new(&data[..])
+        .transform(JpegTransform::None, Some(misaligned))
+        .is_err());
+}
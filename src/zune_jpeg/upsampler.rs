@@ -67,22 +67,54 @@
 //! # Horizontal vertical downsampling/chroma quartering.
 //!
 //! Carry out a vertical filter in the first pass, then a horizontal filter in the second pass.
-use super::components::UpSampler;
+//!
+//! # Other layouts
+//! Layouts with other sampling factors (4:1:1, 4:4:0, factors of 3 or 4, or components
+//! with differing factors) use a generic filter that works on whole MCU rows. It places
+//! every output sample at its center in the input grid and blends the two nearest inputs,
+//! which reduces to the filter above for a ratio of two.
+//!
+//! Setting `jpeg_set_fancy_upsampling(false)` replaces all filters with sample replication.
+//!
+//! # Exact mode
+//! libjpeg rounds the filters above differently, and only interpolates ratios of two,
+//! replicating samples otherwise. With `jpeg_set_exact(true)` the exact versions of the filters
+//! are used, and components of other layouts are up-sampled the same way.
+use super::components::{GenericUpSampler, UpSampler};
 
 mod scalar;
 
 // choose best possible implementation for this platform
-pub fn choose_horizontal_samp_function(_use_unsafe: bool) -> UpSampler {
+pub fn choose_horizontal_samp_function(_use_unsafe: bool, exact: bool) -> UpSampler {
+    if exact {
+        return scalar::upsample_horizontal_exact;
+    }
     return scalar::upsample_horizontal;
 }
 
-pub fn choose_hv_samp_function(_use_unsafe: bool) -> UpSampler {
+pub fn choose_hv_samp_function(_use_unsafe: bool, exact: bool) -> UpSampler {
+    if exact {
+        return scalar::upsample_hv_exact;
+    }
     return scalar::upsample_hv;
 }
-pub fn choose_v_samp_function(_use_unsafe: bool) -> UpSampler {
+pub fn choose_v_samp_function(_use_unsafe: bool, exact: bool) -> UpSampler {
+    if exact {
+        return scalar::upsample_vertical_exact;
+    }
     return scalar::upsample_vertical;
 }
 
+pub fn choose_generic_samp_function(fancy_upsampling: bool, exact: bool) -> GenericUpSampler {
+    if fancy_upsampling && exact {
+        return scalar::upsample_generic_exact;
+    }
+    if fancy_upsampling {
+        return scalar::upsample_generic;
+    }
+    return scalar::upsample_generic_nearest;
+}
+
 /// Upsample nothing
 
 pub fn upsample_no_op(
@@ -93,3 +125,137 @@ pub fn upsample_no_op(
     _output: &mut [i16],
 ) {
 }
+
+/// Generic upsample nothing
+pub fn upsample_generic_no_op(
+    _input: &[i16],
+    _row_up: &[i16],
+    _row_down: &[i16],
+    _ratio: (usize, usize),
+    _scratch_space: &mut [i16],
+    _output: &mut [i16],
+) {
+}
+
+/// Decode one of the gradient test images and return the maximum error from
+/// the gradient they were encoded from
+#[cfg(test)]
+#[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+fn gradient_max_error(data: &[u8], fancy_upsampling: bool) -> i32 {
+    use crate::zune_core::options::DecoderOptions;
+    use crate::zune_jpeg::JpegDecoder;
+
+    let options = DecoderOptions::default().jpeg_set_fancy_upsampling(fancy_upsampling);
+    let mut decoder = JpegDecoder::new_with_options(data, options);
+    let pixels = decoder.decode().unwrap();
+    let (w, h) = decoder.dimensions().unwrap();
+    let (w, h) = (usize::from(w), usize::from(h));
+
+    let mut max_error = 0;
+
+    for (pos, pix) in pixels.chunks_exact(3).enumerate() {
+        let (x, y) = (pos % w, pos / w);
+        let expected = [
+            x * 255 / (w - 1),
+            y * 255 / (h - 1),
+            (x + y) * 255 / (w + h - 2),
+        ];
+        for (a, b) in pix.iter().zip(expected) {
+            max_error = max_error.max((i32::from(*a) - b as i32).abs());
+        }
+    }
+    max_error
+}
+
+#[test]
+fn upsample_411() {
+    let data = include_bytes!("../../tests/inputs/sampling_411.jpg");
+    assert!(gradient_max_error(data, true) <= 8);
+    assert!(gradient_max_error(data, false) <= 12);
+}
+
+#[test]
+fn upsample_440() {
+    let data = include_bytes!("../../tests/inputs/sampling_440.jpg");
+    assert!(gradient_max_error(data, true) <= 8);
+    assert!(gradient_max_error(data, false) <= 12);
+}
+
+#[test]
+fn upsample_factor_3() {
+    for data in [
+        &include_bytes!("../../tests/inputs/sampling_31.jpg")[..],
+        &include_bytes!("../../tests/inputs/sampling_32.jpg")[..],
+        &include_bytes!("../../tests/inputs/sampling_33.jpg")[..],
+    ] {
+        assert!(gradient_max_error(data, true) <= 8);
+        assert!(gradient_max_error(data, false) <= 12);
+    }
+}
+
+#[test]
+fn upsample_factor_4() {
+    for data in [
+        &include_bytes!("../../tests/inputs/sampling_42.jpg")[..],
+        &include_bytes!("../../tests/inputs/sampling_14.jpg")[..],
+    ] {
+        assert!(gradient_max_error(data, true) <= 10);
+        assert!(gradient_max_error(data, false) <= 14);
+    }
+}
+
+#[test]
+fn upsample_mixed_component_ratios() {
+    // Y 2x2, Cb 2x1, Cr 1x2 and Y 4x1, Cb 2x1, Cr 1x1
+    for data in [
+        &include_bytes!("../../tests/inputs/sampling_mixed.jpg")[..],
+        &include_bytes!("../../tests/inputs/sampling_mixed2.jpg")[..],
+    ] {
+        assert!(gradient_max_error(data, true) <= 8);
+        assert!(gradient_max_error(data, false) <= 12);
+    }
+}
+
+#[test]
+fn upsample_narrow_image() {
+    // 10x9 image, narrower than a single MCU (Y 4x2)
+    let data = include_bytes!("../../tests/inputs/sampling_small.jpg");
+    assert!(gradient_max_error(data, true) <= 24);
+    assert!(gradient_max_error(data, false) <= 30);
+}
+
+#[test]
+fn upsample_vertical_mcu_boundaries() {
+    use crate::zune_core::bit_depth::BitDepth;
+    use crate::zune_core::colorspace::ColorSpace;
+    use crate::zune_core::options::{EncoderOptions, JpegSubsampling};
+    use crate::zune_jpeg::{JpegDecoder, JpegEncoder};
+
+    // The same luma everywhere, a different chroma in the last chroma row of
+    // every MCU row. Pixel rows 14 and 15 are up-sampled from that chroma row,
+    // interpolated with the chroma rows above and below it, which are the same.
+    let (width, height) = (16, 48);
+    let mut rgb = Vec::with_capacity(width * height * 3);
+
+    for y in 0..height {
+        let pixel = if y % 16 >= 14 { [86, 139, 181] } else { [170, 117, 75] };
+
+        for _ in 0..width {
+            rgb.extend_from_slice(&pixel);
+        }
+    }
+    let options = EncoderOptions::new(width, height, ColorSpace::RGB, BitDepth::Eight)
+        .set_quality(100)
+        .set_jpeg_subsampling(JpegSubsampling::S420);
+    let data = JpegEncoder::new(&rgb, options).encode().unwrap();
+
+    let pixels = JpegDecoder::new(&data).decode().unwrap();
+    let row = |y: usize| &pixels[y * width * 3..(y + 1) * width * 3];
+
+    // the last MCU row has no chroma row below it
+    for y in [14, 30] {
+        for (a, b) in row(y).iter().zip(row(y + 1)) {
+            assert!(a.abs_diff(*b) <= 2, "rows {y} and {}", y + 1);
+        }
+    }
+}
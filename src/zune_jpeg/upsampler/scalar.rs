@@ -1,3 +1,5 @@
+use super::super::components::UpSampler;
+
 pub fn upsample_horizontal(
     input: &[i16], _ref: &[i16], _in_near: &[i16], _scratch: &mut [i16], output: &mut [i16]
 )
@@ -100,3 +102,221 @@ pub fn upsample_hv(
         &mut output[output_half..]
     );
 }
+
+/// Horizontal up-sampling with libjpeg's rounding, `h2v1_fancy_upsample`
+///
+/// libjpeg rounds the left output of every pair down and the right one up,
+/// instead of rounding both up.
+pub fn upsample_horizontal_exact(
+    input: &[i16], _ref: &[i16], _in_near: &[i16], _scratch: &mut [i16], output: &mut [i16]
+)
+{
+    let last = input.len() - 1;
+
+    output[0] = input[0];
+    output[1] = (input[0] * 3 + input[1] + 2) >> 2;
+
+    for (output_window, input_window) in output[2..].chunks_exact_mut(2).zip(input.windows(3))
+    {
+        let sample = 3 * input_window[1];
+
+        output_window[0] = (sample + input_window[0] + 1) >> 2;
+        output_window[1] = (sample + input_window[2] + 2) >> 2;
+    }
+    let out_len = output.len();
+
+    output[out_len - 2] = (input[last] * 3 + input[last - 1] + 1) >> 2;
+    output[out_len - 1] = input[last];
+}
+
+/// Vertical up-sampling with libjpeg's rounding, `h1v2_fancy_upsample`
+pub fn upsample_vertical_exact(
+    input: &[i16], in_near: &[i16], in_far: &[i16], _scratch_space: &mut [i16], output: &mut [i16]
+)
+{
+    let (out_top, out_bottom) = output.split_at_mut(output.len() / 2);
+
+    for ((near, far), x) in input.iter().zip(in_near).zip(out_top)
+    {
+        *x = (3 * near + far + 1) >> 2;
+    }
+    for ((near, far), x) in input.iter().zip(in_far).zip(out_bottom)
+    {
+        *x = (3 * near + far + 2) >> 2;
+    }
+}
+
+/// Horizontal and vertical up-sampling with libjpeg's rounding,
+/// `h2v2_fancy_upsample`
+///
+/// Column sums are kept unrounded between the vertical and horizontal
+/// passes, so this can't reuse the two functions above.
+pub fn upsample_hv_exact(
+    input: &[i16], in_near: &[i16], in_far: &[i16], _scratch_space: &mut [i16], output: &mut [i16]
+)
+{
+    let (out_top, out_bottom) = output.split_at_mut(output.len() / 2);
+
+    for (other, out) in [(in_near, out_top), (in_far, out_bottom)]
+    {
+        let sum = |i: usize| 3 * input[i] + other[i];
+        let last = input.len() - 1;
+        let out_len = out.len();
+
+        out[0] = (sum(0) * 4 + 8) >> 4;
+        out[1] = (sum(0) * 3 + sum(1) + 7) >> 4;
+
+        for (i, pair) in out[2..out_len - 2].chunks_exact_mut(2).enumerate()
+        {
+            let this = sum(i + 1) * 3;
+
+            pair[0] = (this + sum(i) + 8) >> 4;
+            pair[1] = (this + sum(i + 2) + 7) >> 4;
+        }
+
+        out[out_len - 2] = (sum(last) * 3 + sum(last - 1) + 8) >> 4;
+        out[out_len - 1] = (sum(last) * 4 + 7) >> 4;
+    }
+}
+
+/// Filter weights for output sample `pos` when up-sampling by `ratio`
+///
+/// Returns the index of the input sample on the left/top (may be `-1`), and the
+/// weight of the sample after it, out of `2 * ratio`.
+///
+/// Output sample centers are placed at `(2 * pos + 1) / (2 * ratio)` in input
+/// coordinates, so for a ratio of two this reduces to the usual `(3*A+B)/4` filter.
+#[allow(clippy::cast_possible_wrap, clippy::cast_possible_truncation)]
+#[inline(always)]
+fn generic_weights(pos: usize, ratio: usize) -> (isize, i32) {
+    let n = (2 * pos + 1) as isize - ratio as isize;
+    let d = 2 * ratio as isize;
+
+    (n.div_euclid(d), n.rem_euclid(d) as i32)
+}
+
+/// Horizontally up-sample a single row by an arbitrary integer ratio using
+/// the triangle filter
+#[allow(
+    clippy::cast_possible_wrap,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+fn upsample_horizontal_generic(input: &[i16], ratio: usize, output: &mut [i16]) {
+    if ratio == 1 {
+        output.copy_from_slice(input);
+        return;
+    }
+    let last = input.len() as isize - 1;
+    let d = 2 * ratio as i32;
+
+    for (pos, out) in output.iter_mut().enumerate() {
+        let (base, frac) = generic_weights(pos, ratio);
+
+        let a = i32::from(input[base.clamp(0, last) as usize]);
+        let b = i32::from(input[(base + 1).clamp(0, last) as usize]);
+
+        *out = ((a * (d - frac) + b * frac + d / 2) / d) as i16;
+    }
+}
+
+/// Up-sample an MCU row of a component by arbitrary integer ratios using
+/// the triangle filter, vertically first then horizontally.
+#[allow(
+    clippy::cast_possible_wrap,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+pub fn upsample_generic(
+    input: &[i16], row_up: &[i16], row_down: &[i16], (h_ratio, v_ratio): (usize, usize),
+    scratch_space: &mut [i16], output: &mut [i16]
+)
+{
+    let stride = row_up.len();
+    let rows = (input.len() / stride) as isize;
+    let d = 2 * v_ratio as i32;
+
+    let get_row = |pos: isize| -> &[i16] {
+        if pos < 0 {
+            row_up
+        } else if pos >= rows {
+            row_down
+        } else {
+            &input[pos as usize * stride..(pos as usize + 1) * stride]
+        }
+    };
+    let scratch = &mut scratch_space[..stride];
+
+    for (pos, out_row) in output.chunks_exact_mut(stride * h_ratio).enumerate() {
+        let (base, frac) = generic_weights(pos, v_ratio);
+
+        if frac == 0 {
+            upsample_horizontal_generic(get_row(base), h_ratio, out_row);
+            continue;
+        }
+        let (near, far) = (get_row(base), get_row(base + 1));
+
+        for ((a, b), x) in near.iter().zip(far).zip(scratch.iter_mut()) {
+            *x = ((i32::from(*a) * (d - frac) + i32::from(*b) * frac + d / 2) / d) as i16;
+        }
+        upsample_horizontal_generic(scratch, h_ratio, out_row);
+    }
+}
+
+/// Up-sample an MCU row of a component by arbitrary integer ratios by
+/// replicating samples
+pub fn upsample_generic_nearest(
+    input: &[i16], row_up: &[i16], _row_down: &[i16], (h_ratio, v_ratio): (usize, usize),
+    _scratch_space: &mut [i16], output: &mut [i16]
+)
+{
+    let stride = row_up.len();
+
+    for (pos, out_row) in output.chunks_exact_mut(stride * h_ratio).enumerate() {
+        let in_row = &input[(pos / v_ratio) * stride..];
+
+        for (in_sample, out) in in_row.iter().zip(out_row.chunks_exact_mut(h_ratio)) {
+            out.fill(*in_sample);
+        }
+    }
+}
+
+/// Up-sample an MCU row of a component like libjpeg does, interpolating
+/// ratios of two with the exact filters and replicating samples otherwise
+#[allow(clippy::cast_possible_wrap, clippy::cast_sign_loss)]
+pub fn upsample_generic_exact(
+    input: &[i16], row_up: &[i16], row_down: &[i16], ratio: (usize, usize),
+    scratch_space: &mut [i16], output: &mut [i16]
+)
+{
+    let stride = row_up.len();
+    let rows = input.len() / stride;
+
+    let get_row = |pos: isize| -> &[i16] {
+        if pos < 0 {
+            row_up
+        } else if pos as usize >= rows {
+            row_down
+        } else {
+            &input[pos as usize * stride..(pos as usize + 1) * stride]
+        }
+    };
+    let upsampler: UpSampler = match ratio {
+        (2, 1) => upsample_horizontal_exact,
+        (1, 2) => upsample_vertical_exact,
+        (2, 2) => upsample_hv_exact,
+        _ => {
+            upsample_generic_nearest(input, row_up, row_down, ratio, scratch_space, output);
+            return;
+        }
+    };
+    // every input row makes a whole number of output rows
+    for (pos, out) in output
+        .chunks_exact_mut(stride * ratio.0 * ratio.1)
+        .enumerate()
+    {
+        let pos = pos as isize;
+
+        upsampler(get_row(pos), get_row(pos - 1), get_row(pos + 1), &mut [], out);
+    }
+}
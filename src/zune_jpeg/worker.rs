@@ -17,7 +17,11 @@ fn blinn_8x8(in_val: u8, y: u8) -> u8 {
     return ((t + (t >> 8)) >> 8) as u8;
 }
 
-#[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+#[allow(
+    clippy::cast_sign_loss,
+    clippy::cast_possible_truncation,
+    clippy::too_many_arguments
+)]
 pub(crate) fn color_convert_no_sampling(
     unprocessed: &[&[i16]; MAX_COMPONENTS],
     color_convert_16: ColorConvert16Ptr,
@@ -26,19 +30,20 @@ pub(crate) fn color_convert_no_sampling(
     output: &mut [u8],
     width: usize,
     padded_width: usize,
+    output_stride: usize,
 ) -> Result<(), DecodeErrors> // so many parameters..
 {
     // maximum sampling factors are in Y-channel, no need to pass them.
 
     if input_colorspace.num_components() == 3 && input_colorspace == output_colorspace {
         // sort things like RGB to RGB conversion
-        copy_removing_padding(unprocessed, width, padded_width, output);
+        copy_removing_padding(unprocessed, width, padded_width, output, output_stride);
         return Ok(());
     }
     // color convert
     match (input_colorspace, output_colorspace) {
         (ColorSpace::YCbCr | ColorSpace::Luma, ColorSpace::Luma) => {
-            ycbcr_to_grayscale(unprocessed[0], width, padded_width, output);
+            ycbcr_to_grayscale(unprocessed[0], width, padded_width, output, output_stride);
         }
         (
             ColorSpace::YCbCr,
@@ -51,6 +56,7 @@ pub(crate) fn color_convert_no_sampling(
                 output_colorspace,
                 color_convert_16,
                 output,
+                output_stride,
             );
         }
         (ColorSpace::YCCK, ColorSpace::RGB) => {
@@ -61,6 +67,7 @@ pub(crate) fn color_convert_no_sampling(
                 output_colorspace,
                 color_convert_16,
                 output,
+                output_stride,
             );
         }
 
@@ -72,13 +79,14 @@ pub(crate) fn color_convert_no_sampling(
                 output_colorspace,
                 color_convert_16,
                 output,
+                output_stride,
             );
         }
         (ColorSpace::CMYK, ColorSpace::RGB) => {
-            color_convert_cymk_to_rgb::<3>(unprocessed, width, padded_width, output);
+            color_convert_cymk_to_rgb::<3>(unprocessed, width, padded_width, output, output_stride);
         }
         (ColorSpace::CMYK, ColorSpace::RGBA) => {
-            color_convert_cymk_to_rgb::<4>(unprocessed, width, padded_width, output);
+            color_convert_cymk_to_rgb::<4>(unprocessed, width, padded_width, output, output_stride);
         }
         // For the other components we do nothing(currently)
         _ => {
@@ -91,6 +99,19 @@ pub(crate) fn color_convert_no_sampling(
     Ok(())
 }
 
+/// Split `output` into rows of `row_len` bytes starting `stride` bytes apart
+///
+/// Bytes between rows are left alone, and an incomplete last row is dropped.
+pub(crate) fn output_rows(
+    output: &mut [u8],
+    row_len: usize,
+    stride: usize,
+) -> impl Iterator<Item = &mut [u8]> {
+    output
+        .chunks_mut(stride)
+        .map_while(move |row| row.get_mut(..row_len))
+}
+
 /// Copy a block to output removing padding bytes from input
 /// if necessary
 #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
@@ -99,9 +120,9 @@ fn copy_removing_padding(
     width: usize,
     padded_width: usize,
     output: &mut [u8],
+    output_stride: usize,
 ) {
-    for (((pix_w, c_w), m_w), y_w) in output
-        .chunks_exact_mut(width * 3)
+    for (((pix_w, c_w), m_w), y_w) in output_rows(output, width * 3, output_stride)
         .zip(mcu_block[0].chunks_exact(padded_width))
         .zip(mcu_block[1].chunks_exact(padded_width))
         .zip(mcu_block[2].chunks_exact(padded_width))
@@ -123,6 +144,7 @@ fn color_convert_ycck_to_rgb<const NUM_COMPONENTS: usize>(
     output_colorspace: ColorSpace,
     color_convert_16: ColorConvert16Ptr,
     output: &mut [u8],
+    output_stride: usize,
 ) {
     color_convert_ycbcr(
         mcu_block,
@@ -131,9 +153,9 @@ fn color_convert_ycck_to_rgb<const NUM_COMPONENTS: usize>(
         output_colorspace,
         color_convert_16,
         output,
+        output_stride,
     );
-    for (pix_w, m_w) in output
-        .chunks_exact_mut(width * 3)
+    for (pix_w, m_w) in output_rows(output, width * NUM_COMPONENTS, output_stride)
         .zip(mcu_block[3].chunks_exact(padded_width))
     {
         for (pix, m) in pix_w.chunks_exact_mut(NUM_COMPONENTS).zip(m_w) {
@@ -151,16 +173,18 @@ fn color_convert_cymk_to_rgb<const NUM_COMPONENTS: usize>(
     width: usize,
     padded_width: usize,
     output: &mut [u8],
+    output_stride: usize,
 ) {
-    for ((((pix_w, c_w), m_w), y_w), k_w) in output
-        .chunks_exact_mut(width * NUM_COMPONENTS)
+    let rows = output_rows(output, width * NUM_COMPONENTS, output_stride);
+
+    for ((((pix_w, c_w), m_w), y_w), k_w) in rows
         .zip(mcu_block[0].chunks_exact(padded_width))
         .zip(mcu_block[1].chunks_exact(padded_width))
         .zip(mcu_block[2].chunks_exact(padded_width))
         .zip(mcu_block[3].chunks_exact(padded_width))
     {
         for ((((pix, c), m), y), k) in pix_w
-            .chunks_exact_mut(3)
+            .chunks_exact_mut(NUM_COMPONENTS)
             .zip(c_w)
             .zip(m_w)
             .zip(y_w)
@@ -174,6 +198,10 @@ fn color_convert_cymk_to_rgb<const NUM_COMPONENTS: usize>(
             pix[0] = blinn_8x8(c, k);
             pix[1] = blinn_8x8(m, k);
             pix[2] = blinn_8x8(y, k);
+
+            if NUM_COMPONENTS == 4 {
+                pix[3] = 255;
+            }
         }
     }
 }
@@ -192,11 +220,10 @@ fn color_convert_ycbcr(
     output_colorspace: ColorSpace,
     color_convert_16: ColorConvert16Ptr,
     output: &mut [u8],
+    output_stride: usize,
 ) {
     let num_components = output_colorspace.num_components();
 
-    let stride = width * num_components;
-
     // Allocate temporary buffer for small widths less than  16.
     let mut temp = [0; 64];
     // We need to chunk per width to ensure we can discard extra values at the end of the width.
@@ -205,7 +232,7 @@ fn color_convert_ycbcr(
         .chunks_exact(padded_width)
         .zip(mcu_block[1].chunks_exact(padded_width))
         .zip(mcu_block[2].chunks_exact(padded_width))
-        .zip(output.chunks_exact_mut(stride))
+        .zip(output_rows(output, width * num_components, output_stride))
     {
         if width < 16 {
             // allocate temporary buffers for the values received from idct
@@ -213,9 +240,11 @@ fn color_convert_ycbcr(
             let mut cb_out = [0; 16];
             let mut cr_out = [0; 16];
             // copy those small widths to that buffer
-            y_out[0..y_width.len()].copy_from_slice(y_width);
-            cb_out[0..cb_width.len()].copy_from_slice(cb_width);
-            cr_out[0..cr_width.len()].copy_from_slice(cr_width);
+            // (padded widths can exceed 16 for layouts with large sampling factors)
+            let len = y_width.len().min(16);
+            y_out[0..len].copy_from_slice(&y_width[..len]);
+            cb_out[0..len].copy_from_slice(&cb_width[..len]);
+            cr_out[0..len].copy_from_slice(&cr_width[..len]);
             // we handle widths less than 16 a bit differently, allocating a temporary
             // buffer and writing to that and then flushing to the out buffer
             // because of the optimizations applied below,
@@ -277,10 +306,11 @@ pub(crate) fn upsample_and_color_convert_h(
     output: &mut [u8],
     width: usize,
     padded_width: usize,
+    output_stride: usize,
 ) -> Result<(), DecodeErrors> {
     let v_samp = component_data[0].vertical_sample;
 
-    let out_stride = width * output_colorspace.num_components() * v_samp;
+    let out_stride = output_stride * v_samp;
     // Width of image which takes into account fill bytes
     let width_stride = component_data[0].width_stride * v_samp;
 
@@ -322,6 +352,7 @@ pub(crate) fn upsample_and_color_convert_h(
             out,
             width,
             padded_width,
+            output_stride,
         )?;
     }
     Ok(())
@@ -336,6 +367,7 @@ pub(crate) fn upsample_and_color_convert_v(
     output: &mut [u8],
     width: usize,
     padded_width: usize,
+    output_stride: usize,
     pixels_written: &mut usize,
     upsampler_scratch_space: &mut [i16],
     i: usize,
@@ -360,7 +392,7 @@ pub(crate) fn upsample_and_color_convert_v(
     // 3. Before decoding a new  MCU.
     //   We already had saved the last row, we now currently have the row_down
     //   which is the first row of this MCU, so we can upsample the last row of the previous
-    //   MCU.
+    //   MCU. It then becomes the row above this MCU.
     //
     // 4. Decoding a new line for MCU
     //      Previous row is provided by component.prev_row, the rest can be accessed
@@ -375,7 +407,7 @@ pub(crate) fn upsample_and_color_convert_v(
 
     let (y_component, remainder) = component_data.split_at_mut(1);
 
-    let out_stride = width * output_colorspace.num_components() * 2;
+    let out_stride = output_stride * 2;
 
     let (max_h_sample, max_v_sample) = (
         y_component[0].horizontal_sample,
@@ -419,6 +451,8 @@ pub(crate) fn upsample_and_color_convert_v(
 
             // upsample
             (c.up_sampler)(row, row_up, row_down, upsampler_scratch_space, dest);
+            // and it is now the row above the first row of this MCU
+            c.prev_row.copy_from_slice(&c.current_row);
         }
         // by here, each component has been up-sampled, so let's color convert a row(s)
         let cb_stride = &remainder[0].upsample_dest;
@@ -438,6 +472,7 @@ pub(crate) fn upsample_and_color_convert_v(
             &mut output[*pixels_written..*pixels_written + out_stride],
             width,
             padded_width,
+            output_stride,
         )?;
         *pixels_written += out_stride;
     }
@@ -511,10 +546,6 @@ pub(crate) fn upsample_and_color_convert_v(
                 // upsample
                 (c.up_sampler)(row, row_up, row_down, upsampler_scratch_space, dest);
             }
-            if pos == stop_offset - 1 {
-                // copy current last MCU row to be used in the next mcu row
-                c.prev_row.copy_from_slice(row);
-            }
         }
         // if we didn't upsample,means we are in the last row, so then there is no need
         // to color convert
@@ -539,6 +570,7 @@ pub(crate) fn upsample_and_color_convert_v(
             out,
             width,
             padded_width,
+            output_stride,
         )?;
         *pixels_written += out_stride;
     }
@@ -559,3 +591,132 @@ pub(crate) fn upsample_and_color_convert_v(
     }
     Ok(())
 }
+
+/// Up-sample and color convert layouts not handled by the H or V routines
+///
+/// This runs one MCU row behind the decoder, since the last rows of an MCU row
+/// need the first row of the next one before they can be up-sampled.
+/// So for every MCU row `i`, we
+/// 1. Up-sample and color convert MCU row `i-1`, which is now in `pending_coeff`.
+/// 2. Save the last row of MCU row `i-1` as the row above MCU row `i`.
+/// 3. Move MCU row `i` into `pending_coeff`, flushing it if it's the last one.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn upsample_and_color_convert_generic(
+    component_data: &mut [Components],
+    color_convert_16: ColorConvert16Ptr,
+    input_colorspace: ColorSpace,
+    output_colorspace: ColorSpace,
+    output: &mut [u8],
+    width: usize,
+    padded_width: usize,
+    output_stride: usize,
+    pixels_written: &mut usize,
+    upsampler_scratch_space: &mut [i16],
+    i: usize,
+    mcu_height: usize,
+) -> Result<(), DecodeErrors> {
+    if i > 0 {
+        flush_generic_mcu_row(
+            component_data,
+            color_convert_16,
+            input_colorspace,
+            output_colorspace,
+            output,
+            width,
+            padded_width,
+            output_stride,
+            pixels_written,
+            upsampler_scratch_space,
+            false,
+        )?;
+    }
+
+    for c in component_data.iter_mut().filter(|c| c.needed) {
+        let stride = c.width_stride;
+
+        if i == 0 {
+            // first IMAGE row, row above is the first row itself
+            c.prev_row.copy_from_slice(&c.raw_coeff[..stride]);
+        } else {
+            let last_row = c.pending_coeff.len() - stride;
+            c.prev_row.copy_from_slice(&c.pending_coeff[last_row..]);
+        }
+        core::mem::swap(&mut c.raw_coeff, &mut c.pending_coeff);
+    }
+
+    if i == mcu_height - 1 {
+        flush_generic_mcu_row(
+            component_data,
+            color_convert_16,
+            input_colorspace,
+            output_colorspace,
+            output,
+            width,
+            padded_width,
+            output_stride,
+            pixels_written,
+            upsampler_scratch_space,
+            true,
+        )?;
+    }
+    Ok(())
+}
+
+/// Up-sample and color convert the MCU row stored in `pending_coeff`
+///
+/// If `last` is true, there is no row below, so its last row is used instead
+#[allow(clippy::too_many_arguments)]
+fn flush_generic_mcu_row(
+    component_data: &mut [Components],
+    color_convert_16: ColorConvert16Ptr,
+    input_colorspace: ColorSpace,
+    output_colorspace: ColorSpace,
+    output: &mut [u8],
+    width: usize,
+    padded_width: usize,
+    output_stride: usize,
+    pixels_written: &mut usize,
+    upsampler_scratch_space: &mut [i16],
+    last: bool,
+) -> Result<(), DecodeErrors> {
+    for c in component_data.iter_mut().filter(|c| c.needed) {
+        let stride = c.width_stride;
+
+        let row_down = if last {
+            &c.pending_coeff[c.pending_coeff.len() - stride..]
+        } else {
+            &c.raw_coeff[..stride]
+        };
+        (c.generic_up_sampler)(
+            &c.pending_coeff,
+            &c.prev_row,
+            row_down,
+            (c.h_ratio, c.v_ratio),
+            upsampler_scratch_space,
+            &mut c.upsample_dest,
+        );
+    }
+    let mut channels_ref: [&[i16]; MAX_COMPONENTS] = [&[]; MAX_COMPONENTS];
+
+    component_data
+        .iter()
+        .enumerate()
+        .for_each(|(pos, x)| channels_ref[pos] = &x.upsample_dest);
+
+    let rows = component_data[0].upsample_dest.len() / padded_width;
+    let end = output.len().min(*pixels_written + output_stride * rows);
+
+    color_convert_no_sampling(
+        &channels_ref,
+        color_convert_16,
+        input_colorspace,
+        output_colorspace,
+        &mut output[*pixels_written..end],
+        width,
+        padded_width,
+        output_stride,
+    )?;
+    *pixels_written = end;
+
+    Ok(())
+}
@@ -0,0 +1,666 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+//! Writing marker segments and Huffman coded data
+//!
+//! Shared by routines producing JPEG files, blocks are taken as 64 quantized
+//! coefficients in natural order, the order the decoder stores them in.
+#![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+
+use crate::zune_core::bytestream::{ZByteIoError, ZByteWriter, ZWriterTrait};
+use crate::zune_core::colorspace::ColorSpace;
+
+use super::decoder::MAX_COMPONENTS;
+use super::misc::UN_ZIGZAG;
+
+/// Code lengths of the luminance DC table suggested in K.3 of the spec
+pub(crate) const DC_LUMA_BITS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+/// Code lengths of the chrominance DC table suggested in K.3 of the spec
+pub(crate) const DC_CHROMA_BITS: [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+/// Symbols of both suggested DC tables
+pub(crate) const DC_VALUES: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+
+/// Code lengths of the luminance AC table suggested in K.3 of the spec
+pub(crate) const AC_LUMA_BITS: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7D];
+/// Symbols of the suggested luminance AC table
+#[rustfmt::skip]
+pub(crate) const AC_LUMA_VALUES: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51,
+    0x61, 0x07, 0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xA1, 0x08, 0x23, 0x42, 0xB1, 0xC1,
+    0x15, 0x52, 0xD1, 0xF0, 0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0A, 0x16, 0x17, 0x18,
+    0x19, 0x1A, 0x25, 0x26, 0x27, 0x28, 0x29, 0x2A, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39,
+    0x3A, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49, 0x4A, 0x53, 0x54, 0x55, 0x56, 0x57,
+    0x58, 0x59, 0x5A, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6A, 0x73, 0x74, 0x75,
+    0x76, 0x77, 0x78, 0x79, 0x7A, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89, 0x8A, 0x92,
+    0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9A, 0xA2, 0xA3, 0xA4, 0xA5, 0xA6, 0xA7,
+    0xA8, 0xA9, 0xAA, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6, 0xB7, 0xB8, 0xB9, 0xBA, 0xC2, 0xC3,
+    0xC4, 0xC5, 0xC6, 0xC7, 0xC8, 0xC9, 0xCA, 0xD2, 0xD3, 0xD4, 0xD5, 0xD6, 0xD7, 0xD8,
+    0xD9, 0xDA, 0xE1, 0xE2, 0xE3, 0xE4, 0xE5, 0xE6, 0xE7, 0xE8, 0xE9, 0xEA, 0xF1, 0xF2,
+    0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8, 0xF9, 0xFA,
+];
+/// Code lengths of the chrominance AC table suggested in K.3 of the spec
+pub(crate) const AC_CHROMA_BITS: [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+/// Symbols of the suggested chrominance AC table
+#[rustfmt::skip]
+pub(crate) const AC_CHROMA_VALUES: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07,
+    0x61, 0x71, 0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xA1, 0xB1, 0xC1, 0x09,
+    0x23, 0x33, 0x52, 0xF0, 0x15, 0x62, 0x72, 0xD1, 0x0A, 0x16, 0x24, 0x34, 0xE1, 0x25,
+    0xF1, 0x17, 0x18, 0x19, 0x1A, 0x26, 0x27, 0x28, 0x29, 0x2A, 0x35, 0x36, 0x37, 0x38,
+    0x39, 0x3A, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49, 0x4A, 0x53, 0x54, 0x55, 0x56,
+    0x57, 0x58, 0x59, 0x5A, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6A, 0x73, 0x74,
+    0x75, 0x76, 0x77, 0x78, 0x79, 0x7A, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8A, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9A, 0xA2, 0xA3, 0xA4, 0xA5,
+    0xA6, 0xA7, 0xA8, 0xA9, 0xAA, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6, 0xB7, 0xB8, 0xB9, 0xBA,
+    0xC2, 0xC3, 0xC4, 0xC5, 0xC6, 0xC7, 0xC8, 0xC9, 0xCA, 0xD2, 0xD3, 0xD4, 0xD5, 0xD6,
+    0xD7, 0xD8, 0xD9, 0xDA, 0xE2, 0xE3, 0xE4, 0xE5, 0xE6, 0xE7, 0xE8, 0xE9, 0xEA, 0xF2,
+    0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8, 0xF9, 0xFA,
+];
+
+/// The DC and AC tables suggested in K.3 of the spec, luminance first
+pub(crate) fn standard_tables() -> ([HuffmanEncodeTable; 2], [HuffmanEncodeTable; 2]) {
+    (
+        [
+            HuffmanEncodeTable::new(DC_LUMA_BITS, &DC_VALUES),
+            HuffmanEncodeTable::new(DC_CHROMA_BITS, &DC_VALUES),
+        ],
+        [
+            HuffmanEncodeTable::new(AC_LUMA_BITS, &AC_LUMA_VALUES),
+            HuffmanEncodeTable::new(AC_CHROMA_BITS, &AC_CHROMA_VALUES),
+        ],
+    )
+}
+
+/// A Huffman table as written in a DHT segment, plus the code of every symbol
+#[derive(Clone)]
+pub(crate) struct HuffmanEncodeTable {
+    /// Number of codes of each length from 1 to 16 bits
+    pub bits: [u8; 16],
+    /// Symbols in order of increasing code length
+    pub values: Vec<u8>,
+    codes: [u16; 256],
+    sizes: [u8; 256],
+}
+
+impl HuffmanEncodeTable {
+    /// Create a table from the code lengths and symbols of a DHT segment
+    pub(crate) fn new(bits: [u8; 16], values: &[u8]) -> HuffmanEncodeTable {
+        let mut codes = [0; 256];
+        let mut sizes = [0; 256];
+        let mut code = 0_u16;
+        let mut k = 0;
+
+        // codes of a given length are consecutive, see C.2 in the spec
+        for (length, count) in bits.iter().enumerate() {
+            for _ in 0..*count {
+                let symbol = usize::from(values[k]);
+
+                codes[symbol] = code;
+                sizes[symbol] = length as u8 + 1;
+                code = code.wrapping_add(1);
+                k += 1;
+            }
+            code = code.wrapping_shl(1);
+        }
+        HuffmanEncodeTable {
+            bits,
+            values: values.to_vec(),
+            codes,
+            sizes,
+        }
+    }
+
+    /// Create the optimal table for symbols occurring `freq` times,
+    /// following K.2 in the spec
+    ///
+    /// Code lengths are limited to 16 bits and no symbol gets a code of all
+    /// ones.
+    pub(crate) fn optimal(freq: &[u32; 256]) -> HuffmanEncodeTable {
+        // symbol 256 is reserved so no code is all ones
+        let mut freq: Vec<u64> = freq.iter().map(|x| u64::from(*x)).collect();
+        freq.push(1);
+
+        let mut code_size = [0_usize; 257];
+        let mut others = [usize::MAX; 257];
+
+        loop {
+            // the two least frequent symbols, on ties the one with
+            // the largest value
+            let mut c1 = usize::MAX;
+            let mut c2 = usize::MAX;
+
+            for (i, f) in freq.iter().enumerate() {
+                if *f != 0 && (c1 == usize::MAX || *f <= freq[c1]) {
+                    c1 = i;
+                }
+            }
+            for (i, f) in freq.iter().enumerate() {
+                if *f != 0 && i != c1 && (c2 == usize::MAX || *f <= freq[c2]) {
+                    c2 = i;
+                }
+            }
+            if c2 == usize::MAX {
+                break;
+            }
+            freq[c1] += freq[c2];
+            freq[c2] = 0;
+
+            code_size[c1] += 1;
+            while others[c1] != usize::MAX {
+                c1 = others[c1];
+                code_size[c1] += 1;
+            }
+            others[c1] = c2;
+
+            code_size[c2] += 1;
+            while others[c2] != usize::MAX {
+                c2 = others[c2];
+                code_size[c2] += 1;
+            }
+        }
+
+        let mut bits = [0_u32; 33];
+
+        for size in code_size.iter().filter(|x| **x != 0) {
+            bits[(*size).min(32)] += 1;
+        }
+        // move codes longer than 16 bits up the tree, see K.3
+        for i in (17..=32).rev() {
+            while bits[i] > 0 {
+                let mut j = i - 2;
+
+                while bits[j] == 0 {
+                    j -= 1;
+                }
+                bits[i] -= 2;
+                bits[i - 1] += 1;
+                bits[j + 1] += 2;
+                bits[j] -= 1;
+            }
+        }
+        // remove the reserved symbol, it has the longest code
+        let mut i = 16;
+
+        while bits[i] == 0 {
+            i -= 1;
+        }
+        bits[i] -= 1;
+
+        let mut values = Vec::new();
+
+        for size in 1..=32 {
+            for (symbol, _) in code_size[..256].iter().enumerate().filter(|x| *x.1 == size) {
+                values.push(symbol as u8);
+            }
+        }
+        let mut table_bits = [0; 16];
+
+        for (out, count) in table_bits.iter_mut().zip(&bits[1..=16]) {
+            *out = *count as u8;
+        }
+        HuffmanEncodeTable::new(table_bits, &values)
+    }
+}
+
+/// Number of bits needed to store the magnitude of `value`
+pub(crate) fn category(value: i32) -> u8 {
+    (32 - value.unsigned_abs().leading_zeros()) as u8
+}
+
+/// Bits stored after a Huffman symbol for `value`, one's complement for
+/// negative values, see F.1.2.1 in the spec
+pub(crate) fn extra_bits(value: i32, size: u8) -> u32 {
+    let value = if value < 0 { value - 1 } else { value };
+
+    (value as u32) & ((1 << size) - 1)
+}
+
+/// Call `emit` with the symbol and extra bits of every Huffman coded value
+/// of a block, DC first
+///
+/// `emit` receives whether the symbol is DC, the symbol, the extra bits and
+/// their count.
+pub(crate) fn block_symbols<F: FnMut(bool, u8, u32, u8)>(
+    block: &[i16; 64],
+    dc_pred: &mut i32,
+    mut emit: F,
+) {
+    let dc = i32::from(block[0]);
+    let diff = dc - *dc_pred;
+    let size = category(diff);
+
+    *dc_pred = dc;
+    emit(true, size, extra_bits(diff, size), size);
+
+    let mut run = 0;
+
+    for &pos in &UN_ZIGZAG[1..64] {
+        let value = i32::from(block[pos]);
+
+        if value == 0 {
+            run += 1;
+            continue;
+        }
+        while run > 15 {
+            // ZRL, sixteen zeroes
+            emit(false, 0xF0, 0, 0);
+            run -= 16;
+        }
+        let size = category(value);
+
+        emit(false, (run << 4) | size, extra_bits(value, size), size);
+        run = 0;
+    }
+    if run > 0 {
+        // EOB
+        emit(false, 0x00, 0, 0);
+    }
+}
+
+/// Writes Huffman coded data, stuffing a zero after 0xFF bytes
+#[derive(Default)]
+pub(crate) struct BitWriter {
+    buffer: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    /// Write the `size` low bits of `value`, size is at most 16
+    pub(crate) fn put_bits(&mut self, out: &mut Vec<u8>, value: u32, size: u8) {
+        self.buffer = (self.buffer << size) | (value & ((1 << size) - 1));
+        self.bits += size;
+
+        while self.bits >= 8 {
+            let byte = (self.buffer >> (self.bits - 8)) as u8;
+
+            out.push(byte);
+
+            if byte == 0xFF {
+                out.push(0x00);
+            }
+            self.bits -= 8;
+        }
+    }
+
+    /// Write the code of `symbol` followed by `size` extra bits
+    pub(crate) fn put_symbol(
+        &mut self,
+        out: &mut Vec<u8>,
+        table: &HuffmanEncodeTable,
+        symbol: u8,
+        extra: u32,
+        size: u8,
+    ) {
+        let symbol = usize::from(symbol);
+
+        self.put_bits(out, u32::from(table.codes[symbol]), table.sizes[symbol]);
+        self.put_bits(out, extra, size);
+    }
+
+    /// Pad the last byte with one bits
+    pub(crate) fn flush(&mut self, out: &mut Vec<u8>) {
+        if self.bits > 0 {
+            let pad = 8 - self.bits;
+
+            self.put_bits(out, (1 << pad) - 1, pad);
+        }
+        self.buffer = 0;
+    }
+}
+
+/// Number of bytes collected before they are passed to the sink, so sinks
+/// that aren't buffered see few large writes
+pub(crate) const STAGED_BYTES: usize = 4096;
+
+/// Pass the bytes collected in `staged` to `sink` once there are at least
+/// `min` of them
+pub(crate) fn drain_staged<W: ZWriterTrait>(
+    staged: &mut Vec<u8>,
+    sink: &mut ZByteWriter<W>,
+    min: usize,
+) -> Result<(), ZByteIoError> {
+    if staged.len() >= min {
+        sink.write_all(staged)?;
+        staged.clear();
+    }
+    Ok(())
+}
+
+/// Write a marker
+pub(crate) fn write_marker(out: &mut Vec<u8>, marker: u8) {
+    out.extend_from_slice(&[0xFF, marker]);
+}
+
+/// Write a marker segment, the length is computed from `payload`
+pub(crate) fn write_segment(out: &mut Vec<u8>, marker: u8, payload: &[u8]) {
+    write_marker(out, marker);
+    out.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// Write a DQT segment for a table in natural order
+///
+/// Tables with values above 255 are written with 16 bit precision.
+pub(crate) fn write_dqt(out: &mut Vec<u8>, id: u8, table: &[i32; 64]) {
+    let precision = u8::from(table.iter().any(|x| *x > 255));
+    let mut payload = vec![(precision << 4) | id];
+
+    for &pos in &UN_ZIGZAG[..64] {
+        if precision == 0 {
+            payload.push(table[pos] as u8);
+        } else {
+            payload.extend_from_slice(&(table[pos] as u16).to_be_bytes());
+        }
+    }
+    write_segment(out, 0xDB, &payload);
+}
+
+/// Write a DHT segment, `class` is 0 for DC tables and 1 for AC tables
+pub(crate) fn write_dht(out: &mut Vec<u8>, class: u8, id: u8, table: &HuffmanEncodeTable) {
+    let mut payload = vec![(class << 4) | id];
+
+    payload.extend_from_slice(&table.bits);
+    payload.extend_from_slice(&table.values);
+
+    write_segment(out, 0xC4, &payload);
+}
+
+/// Write a SOF segment, components are given as
+/// `(id, horizontal sample, vertical sample, quantization table)`
+pub(crate) fn write_sof(
+    out: &mut Vec<u8>,
+    marker: u8,
+    width: u16,
+    height: u16,
+    components: &[(u8, u8, u8, u8)],
+) {
+    let mut payload = vec![8];
+
+    payload.extend_from_slice(&height.to_be_bytes());
+    payload.extend_from_slice(&width.to_be_bytes());
+    payload.push(components.len() as u8);
+
+    for &(id, h, v, table) in components {
+        payload.extend_from_slice(&[id, (h << 4) | v, table]);
+    }
+    write_segment(out, marker, &payload);
+}
+
+/// Write a SOS segment, components are given as `(id, DC table, AC table)`
+pub(crate) fn write_sos(
+    out: &mut Vec<u8>,
+    components: &[(u8, u8, u8)],
+    spec_start: u8,
+    spec_end: u8,
+    succ_high: u8,
+    succ_low: u8,
+) {
+    let mut payload = vec![components.len() as u8];
+
+    for &(id, dc, ac) in components {
+        payload.extend_from_slice(&[id, (dc << 4) | ac]);
+    }
+    payload.extend_from_slice(&[spec_start, spec_end, (succ_high << 4) | succ_low]);
+
+    write_segment(out, 0xDA, &payload);
+}
+
+/// Write a JFIF APP0 segment with no thumbnail and square pixels
+pub(crate) fn write_jfif(out: &mut Vec<u8>) {
+    write_segment(
+        out,
+        0xE0,
+        &[b'J', b'F', b'I', b'F', 0, 1, 1, 0, 0, 1, 0, 1, 0, 0],
+    );
+}
+
+/// Write an Adobe APP14 segment, with `transform` 0 for RGB or CMYK
+/// components and 2 for YCCK
+pub(crate) fn write_adobe(out: &mut Vec<u8>, transform: u8) {
+    write_segment(
+        out,
+        0xEE,
+        &[
+            b'A', b'd', b'o', b'b', b'e', 0, 100, 0, 0, 0, 0, transform,
+        ],
+    );
+}
+
+/// A component of a frame about to be written
+pub(crate) struct FrameComponent {
+    pub id: u8,
+    pub horizontal_sample: usize,
+    pub vertical_sample: usize,
+    pub quantization_table: u8,
+    /// Blocks per block row, covering whole MCUs
+    pub stride: usize,
+    /// Quantized coefficients in natural order, block after block
+    pub blocks: Vec<i16>,
+}
+
+impl FrameComponent {
+    pub(crate) fn block(&self, index: usize) -> &[i16; 64] {
+        self.blocks[index * 64..index * 64 + 64].try_into().unwrap()
+    }
+}
+
+/// Quantized coefficients of a whole image, ready to be written
+pub(crate) struct Frame {
+    pub width: u16,
+    pub height: u16,
+    /// Colorspace of the components, decides the APP segment written
+    pub colorspace: ColorSpace,
+    /// Quantization tables in natural order, by table number
+    pub quantization_tables: Vec<(u8, [i32; 64])>,
+    pub components: Vec<FrameComponent>,
+}
+
+impl Frame {
+    /// Largest horizontal and vertical sampling factors
+    fn max_sampling(&self) -> (usize, usize) {
+        (
+            self.components.iter().map(|c| c.horizontal_sample).max().unwrap_or(1),
+            self.components.iter().map(|c| c.vertical_sample).max().unwrap_or(1),
+        )
+    }
+
+    /// Number of MCUs in a row and a column
+    pub(crate) fn mcus(&self) -> (usize, usize) {
+        let (h_max, v_max) = self.max_sampling();
+
+        (
+            usize::from(self.width).div_ceil(8 * h_max),
+            usize::from(self.height).div_ceil(8 * v_max),
+        )
+    }
+
+    /// Blocks in the order of a scan of `components`, as
+    /// (component, block index)
+    ///
+    /// Scans of several components are interleaved and cover whole MCUs,
+    /// scans of a single component only cover the blocks its samples touch,
+    /// see A.2 in the spec.
+    pub(crate) fn scan_order(&self, components: &[usize]) -> Vec<(usize, usize)> {
+        let mut order = Vec::new();
+
+        if let [pos] = components {
+            let (h_max, v_max) = self.max_sampling();
+            let c = &self.components[*pos];
+            let width = (usize::from(self.width) * c.horizontal_sample).div_ceil(h_max);
+            let height = (usize::from(self.height) * c.vertical_sample).div_ceil(v_max);
+
+            for y in 0..height.div_ceil(8) {
+                for x in 0..width.div_ceil(8) {
+                    order.push((*pos, y * c.stride + x));
+                }
+            }
+            return order;
+        }
+        let (mcu_x, mcu_y) = self.mcus();
+
+        for my in 0..mcu_y {
+            for mx in 0..mcu_x {
+                for &pos in components {
+                    let c = &self.components[pos];
+
+                    for v in 0..c.vertical_sample {
+                        for h in 0..c.horizontal_sample {
+                            let x = mx * c.horizontal_sample + h;
+                            let y = my * c.vertical_sample + v;
+
+                            order.push((pos, y * c.stride + x));
+                        }
+                    }
+                }
+            }
+        }
+        order
+    }
+
+    /// Write SOI, the APP segment matching the colorspace, the quantization
+    /// tables and the frame header
+    ///
+    /// `sof` is the marker used for 8 bit tables, tables with values above
+    /// 255 switch a baseline frame to extended sequential.
+    pub(crate) fn write_headers(&self, out: &mut Vec<u8>, sof: u8) {
+        write_marker(out, 0xD8);
+
+        match self.colorspace {
+            ColorSpace::YCbCr | ColorSpace::Luma => write_jfif(out),
+            ColorSpace::YCCK => write_adobe(out, 2),
+            _ => write_adobe(out, 0),
+        }
+        let mut sixteen_bit = false;
+
+        for (id, table) in &self.quantization_tables {
+            sixteen_bit |= table.iter().any(|x| *x > 255);
+            write_dqt(out, *id, table);
+        }
+        let components: Vec<_> = self
+            .components
+            .iter()
+            .map(|c| {
+                (
+                    c.id,
+                    c.horizontal_sample as u8,
+                    c.vertical_sample as u8,
+                    c.quantization_table,
+                )
+            })
+            .collect();
+        let marker = if sixteen_bit && sof == 0xC0 { 0xC1 } else { sof };
+
+        write_sof(out, marker, self.width, self.height, &components);
+    }
+
+    /// Write the frame as a baseline JPEG with a single interleaved scan
+    ///
+    /// The first component uses the first pair of Huffman tables and the
+    /// rest the second pair, which are either optimal for the frame or the
+    /// ones suggested in the spec. The suggested tables only cover
+    /// coefficients of 8 bit samples, quantized by tables of at least one.
+    pub(crate) fn write_baseline<W: ZWriterTrait>(
+        &self,
+        sink: &mut ZByteWriter<W>,
+        optimize: bool,
+    ) -> Result<(), ZByteIoError> {
+        let all: Vec<usize> = (0..self.components.len()).collect();
+        let scan_order = self.scan_order(&all);
+        let tables = if self.components.len() == 1 { 1 } else { 2 };
+
+        let (dc_tables, ac_tables): (Vec<_>, Vec<_>) = if optimize {
+            let mut dc_freq = [[0_u32; 256]; 2];
+            let mut ac_freq = [[0_u32; 256]; 2];
+            let mut dc_pred = [0; MAX_COMPONENTS];
+
+            for &(pos, index) in &scan_order {
+                let table = usize::from(pos != 0);
+
+                block_symbols(
+                    self.components[pos].block(index),
+                    &mut dc_pred[pos],
+                    |is_dc, symbol, _, _| {
+                        if is_dc {
+                            dc_freq[table][usize::from(symbol)] += 1;
+                        } else {
+                            ac_freq[table][usize::from(symbol)] += 1;
+                        }
+                    },
+                );
+            }
+            (
+                dc_freq[..tables].iter().map(HuffmanEncodeTable::optimal).collect(),
+                ac_freq[..tables].iter().map(HuffmanEncodeTable::optimal).collect(),
+            )
+        } else {
+            let (dc, ac) = standard_tables();
+
+            (dc[..tables].to_vec(), ac[..tables].to_vec())
+        };
+
+        let mut out = Vec::new();
+
+        self.write_headers(&mut out, 0xC0);
+
+        for (id, (dc, ac)) in dc_tables.iter().zip(&ac_tables).enumerate() {
+            write_dht(&mut out, 0, id as u8, dc);
+            write_dht(&mut out, 1, id as u8, ac);
+        }
+        let sos: Vec<_> = self
+            .components
+            .iter()
+            .enumerate()
+            .map(|(pos, c)| (c.id, u8::from(pos != 0), u8::from(pos != 0)))
+            .collect();
+        write_sos(&mut out, &sos, 0, 63, 0, 0);
+
+        let mut writer = BitWriter::default();
+        let mut dc_pred = [0; MAX_COMPONENTS];
+
+        for &(pos, index) in &scan_order {
+            let table = usize::from(pos != 0);
+            let (dc, ac) = (&dc_tables[table], &ac_tables[table]);
+
+            block_symbols(
+                self.components[pos].block(index),
+                &mut dc_pred[pos],
+                |is_dc, symbol, extra, size| {
+                    let table = if is_dc { dc } else { ac };
+
+                    writer.put_symbol(&mut out, table, symbol, extra, size);
+                },
+            );
+            drain_staged(&mut out, sink, STAGED_BYTES)?;
+        }
+        writer.flush(&mut out);
+        write_marker(&mut out, 0xD9);
+
+        drain_staged(&mut out, sink, 0)
+    }
+}
+
+#[test]
+fn optimal_huffman_table() {
+    let mut freq = [0; 256];
+
+    freq[0] = 100;
+    freq[1] = 50;
+    freq[2] = 25;
+    freq[0x11] = 1;
+
+    let table = HuffmanEncodeTable::optimal(&freq);
+    let lengths: Vec<u8> = table.values.iter().map(|x| table.sizes[usize::from(*x)]).collect();
+
+    assert_eq!(table.values, [0, 1, 2, 0x11]);
+    assert_eq!(lengths, [1, 2, 3, 4]);
+    // the reserved all ones code is left out
+    assert!(table.values.iter().all(|x| {
+        let size = table.sizes[usize::from(*x)];
+        u32::from(table.codes[usize::from(*x)]) != (1 << size) - 1
+    }));
+}
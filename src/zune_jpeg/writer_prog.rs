@@ -0,0 +1,310 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+//! Writing progressive scans
+//!
+//! This follows `jcphuff.c` from libjpeg, see G.1.2 in the spec for
+//! the coding of each kind of scan.
+#![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+
+use crate::zune_core::bytestream::{ZByteIoError, ZByteWriter, ZWriterTrait};
+
+use super::encoder::JpegScan;
+use super::misc::UN_ZIGZAG;
+use super::writer::{
+    category, drain_staged, extra_bits, standard_tables, write_dht, write_marker, write_sos,
+    BitWriter, Frame, HuffmanEncodeTable, STAGED_BYTES,
+};
+
+/// Most correction bits buffered while an EOB run is pending
+const MAX_CORRECTION_BITS: usize = 1000;
+
+/// Longest EOB run an EOBn symbol can code
+const MAX_EOB_RUN: u32 = 0x7FFF;
+
+/// Something written to a scan
+#[derive(Copy, Clone)]
+enum Code {
+    /// A Huffman symbol of the given table
+    Symbol(usize, u8),
+    /// Bits written as they are, value and count
+    Bits(u32, u8),
+}
+
+/// State of a scan being coded, codes are passed to `emit`
+struct ScanCoder<F: FnMut(Code)> {
+    emit: F,
+    /// Longest EOB run allowed, tables without EOBn symbols can only
+    /// code runs of a single block
+    max_eob_run: u32,
+    /// Huffman table of the component of an AC scan
+    table: usize,
+    /// Blocks in the current EOB run
+    eob_run: u32,
+    /// Correction bits of blocks in the current EOB run
+    correction_bits: Vec<u8>,
+}
+
+impl<F: FnMut(Code)> ScanCoder<F> {
+    /// Write the pending EOB run and the correction bits that go with it
+    fn flush_eob_run(&mut self) {
+        if self.eob_run > 0 {
+            let size = self.eob_run.ilog2() as u8;
+
+            (self.emit)(Code::Symbol(self.table, size << 4));
+            (self.emit)(Code::Bits(self.eob_run, size));
+
+            self.eob_run = 0;
+        }
+        for bit in self.correction_bits.drain(..) {
+            (self.emit)(Code::Bits(u32::from(bit), 1));
+        }
+    }
+
+    /// Code the first pass over the AC coefficients `start..=end` of a block,
+    /// see G.1.2.2
+    fn ac_first(&mut self, block: &[i16; 64], start: usize, end: usize, low: u8) {
+        let mut run = 0;
+
+        for &pos in &UN_ZIGZAG[start..=end] {
+            let coeff = i32::from(block[pos]);
+            // shift magnitudes, so rounding is towards zero
+            let value = coeff.abs() >> low;
+
+            if value == 0 {
+                run += 1;
+                continue;
+            }
+            self.flush_eob_run();
+
+            while run > 15 {
+                (self.emit)(Code::Symbol(self.table, 0xF0));
+                run -= 16;
+            }
+            let value = if coeff < 0 { -value } else { value };
+            let size = category(value);
+
+            (self.emit)(Code::Symbol(self.table, (run << 4) | size));
+            (self.emit)(Code::Bits(extra_bits(value, size), size));
+            run = 0;
+        }
+        if run > 0 {
+            self.eob_run += 1;
+
+            if self.eob_run >= self.max_eob_run {
+                self.flush_eob_run();
+            }
+        }
+    }
+
+    /// Code a refinement pass over the AC coefficients `start..=end` of a block,
+    /// see G.1.2.3
+    fn ac_refine(&mut self, block: &[i16; 64], start: usize, end: usize, low: u8) {
+        let mut magnitudes = [0; 64];
+        // last coefficient becoming nonzero in this pass
+        let mut last_new = 0;
+
+        for (k, &pos) in UN_ZIGZAG[..64].iter().enumerate().take(end + 1).skip(start) {
+            magnitudes[k] = i32::from(block[pos]).abs() >> low;
+
+            if magnitudes[k] == 1 {
+                last_new = k;
+            }
+        }
+        let mut run = 0;
+        let mut block_bits = Vec::new();
+
+        for k in start..=end {
+            let value = magnitudes[k];
+
+            if value == 0 {
+                run += 1;
+                continue;
+            }
+            while run > 15 && k <= last_new {
+                self.flush_eob_run();
+                (self.emit)(Code::Symbol(self.table, 0xF0));
+                run -= 16;
+
+                for bit in block_bits.drain(..) {
+                    (self.emit)(Code::Bits(u32::from(bit), 1));
+                }
+            }
+            if value > 1 {
+                // already nonzero, only send a correction bit
+                block_bits.push((value & 1) as u8);
+                continue;
+            }
+            self.flush_eob_run();
+
+            let sign = u32::from(block[UN_ZIGZAG[k]] > 0);
+
+            (self.emit)(Code::Symbol(self.table, (run << 4) | 1));
+            (self.emit)(Code::Bits(sign, 1));
+
+            for bit in block_bits.drain(..) {
+                (self.emit)(Code::Bits(u32::from(bit), 1));
+            }
+            run = 0;
+        }
+        if run > 0 || !block_bits.is_empty() {
+            self.eob_run += 1;
+            self.correction_bits.append(&mut block_bits);
+
+            if self.eob_run >= self.max_eob_run
+                || self.correction_bits.len() > MAX_CORRECTION_BITS - 63
+            {
+                self.flush_eob_run();
+            }
+        }
+    }
+}
+
+impl Frame {
+    /// Pass everything written by `scan` to `emit`, without the headers
+    fn code_scan<F: FnMut(Code)>(&self, scan: &JpegScan, max_eob_run: u32, emit: F) {
+        let order = self.scan_order(&scan.components);
+        let (start, end) = (
+            usize::from(scan.spectral_start),
+            usize::from(scan.spectral_end),
+        );
+        let (high, low) = (scan.successive_high, scan.successive_low);
+
+        let mut coder = ScanCoder {
+            emit,
+            max_eob_run,
+            table: usize::from(scan.components[0] != 0),
+            eob_run: 0,
+            correction_bits: Vec::new(),
+        };
+        let mut dc_pred = vec![0; self.components.len()];
+
+        for (pos, index) in order {
+            let block = self.components[pos].block(index);
+
+            if start == 0 {
+                let dc = i32::from(block[0]) >> low;
+
+                if high == 0 {
+                    let diff = dc - dc_pred[pos];
+                    let size = category(diff);
+
+                    dc_pred[pos] = dc;
+                    (coder.emit)(Code::Symbol(usize::from(pos != 0), size));
+                    (coder.emit)(Code::Bits(extra_bits(diff, size), size));
+                } else {
+                    (coder.emit)(Code::Bits((dc & 1) as u32, 1));
+                }
+            } else if high == 0 {
+                coder.ac_first(block, start, end, low);
+            } else {
+                coder.ac_refine(block, start, end, low);
+            }
+        }
+        coder.flush_eob_run();
+    }
+
+    /// Write the frame as a progressive JPEG following `script`
+    ///
+    /// The script must have been validated. Tables are assigned like in
+    /// [`write_baseline`](Frame::write_baseline), with optimal tables written
+    /// before every scan or the suggested ones written once. The suggested
+    /// AC tables can't code EOB runs, so every block ends with its own EOB.
+    pub(crate) fn write_progressive<W: ZWriterTrait>(
+        &self,
+        sink: &mut ZByteWriter<W>,
+        script: &[JpegScan],
+        optimize: bool,
+    ) -> Result<(), ZByteIoError> {
+        let mut out = Vec::new();
+
+        self.write_headers(&mut out, 0xC2);
+
+        let (standard_dc, standard_ac) = standard_tables();
+        let tables = if self.components.len() == 1 { 1 } else { 2 };
+
+        if !optimize {
+            for id in 0..tables {
+                write_dht(&mut out, 0, id as u8, &standard_dc[id]);
+                write_dht(&mut out, 1, id as u8, &standard_ac[id]);
+            }
+        }
+        let max_eob_run = if optimize { MAX_EOB_RUN } else { 1 };
+
+        for scan in script {
+            let is_dc = scan.spectral_start == 0;
+            let class = u8::from(!is_dc);
+
+            let scan_tables: Vec<HuffmanEncodeTable> = if optimize {
+                let mut freq = [[0_u32; 256]; 2];
+
+                self.code_scan(scan, max_eob_run, |code| {
+                    if let Code::Symbol(table, symbol) = code {
+                        freq[table][usize::from(symbol)] += 1;
+                    }
+                });
+                let mut tables = Vec::with_capacity(2);
+
+                // tables of components missing from the scan are left empty,
+                // and DC refinement scans have no Huffman coded data at all
+                for (id, freq) in freq.iter().enumerate() {
+                    if freq.iter().any(|x| *x != 0) {
+                        let table = HuffmanEncodeTable::optimal(freq);
+
+                        write_dht(&mut out, class, id as u8, &table);
+                        tables.push(table);
+                    } else {
+                        tables.push(HuffmanEncodeTable::new([0; 16], &[]));
+                    }
+                }
+                tables
+            } else if is_dc {
+                standard_dc.to_vec()
+            } else {
+                standard_ac.to_vec()
+            };
+
+            let sos: Vec<_> = scan
+                .components
+                .iter()
+                .map(|pos| {
+                    let table = u8::from(*pos != 0);
+                    (self.components[*pos].id, table, table)
+                })
+                .collect();
+            write_sos(
+                &mut out,
+                &sos,
+                scan.spectral_start,
+                scan.spectral_end,
+                scan.successive_high,
+                scan.successive_low,
+            );
+
+            let mut writer = BitWriter::default();
+            let mut result = Ok(());
+
+            self.code_scan(scan, max_eob_run, |code| {
+                match code {
+                    Code::Symbol(table, symbol) => {
+                        writer.put_symbol(&mut out, &scan_tables[table], symbol, 0, 0);
+                    }
+                    Code::Bits(value, size) => writer.put_bits(&mut out, value, size),
+                }
+                if result.is_ok() {
+                    result = drain_staged(&mut out, sink, STAGED_BYTES);
+                }
+            });
+            result?;
+            writer.flush(&mut out);
+        }
+        write_marker(&mut out, 0xD9);
+
+        drain_staged(&mut out, sink, 0)
+    }
+}